@@ -0,0 +1,7 @@
+//! Reads SQLite database files.
+//!
+//! [`SqliteFile`] is the place to start.
+
+pub mod sqlite;
+
+pub use sqlite::*;
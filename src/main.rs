@@ -1,81 +1,1131 @@
-mod sqlite;
-use sqlite::*;
-
-use anyhow::{bail, Result};
-use sqlite::record::Value;
-use std::fs::File;
-use std::num::NonZeroU64;
-
-fn main() -> Result<()> {
-    // Parse arguments
-    let args = std::env::args().collect::<Vec<_>>();
-    match args.len() {
-        0 | 1 => bail!("Missing <database path> and <command>"),
-        2 => bail!("Missing <command>"),
-        _ => {}
-    }
-
-    // Parse command and act accordingly
-    let command = &args[2];
-
-    match command.as_str() {
-        ".dbinfo" => {
-            let file = SqliteFile::new(File::open(&args[1])?)?;
-            let schema = file.get_page(NonZeroU64::new(1).unwrap())?;
-            let page_size = file.page_size();
-            println!("database page size: {}", page_size);
-            println!("number of tables: {}", schema.header.cell_count);
-        }
-        ".tables" => {
-            let file = SqliteFile::new(File::open(&args[1])?)?;
-            let schema = file.get_page(NonZeroU64::new(1).unwrap())?;
-            let input = &schema[108..];
-            let (_, pointers) = cell_pointers(input, schema.header.cell_count as usize)
-                .expect("tried to read cell pointers");
-            let cells = pointers.iter().map(|ptr| {
-                let (_, cell) = schema
-                    .header
-                    .parse_cell(&schema.data[*ptr as usize..])
-                    .expect("parse cell");
-                cell
-            });
-            for c in cells {
-                match c {
-                    cells::Cell::TableLeaf { payload, .. } => {
-                        let (_, records) = payload.parse().expect("parse records");
-                        println!("{}", records[1]);
-                    }
-                    _ => unimplemented!(),
-                }
-            }
-        }
-        query => {
-            let file = SqliteFile::new(File::open(&args[1])?)?;
-            let schema = file.get_schema();
-            let stmt: Select = query.parse()?;
-            let table = schema
-                .iter()
-                .find(|sch| sch.name == stmt.name)
-                .ok_or_else(|| anyhow::anyhow!("table not found"))?;
-            let create: CreateTable = table.try_into()?;
-            let selected = create.select(&stmt);
-            let pgno = NonZeroU64::new(table.rootpage).unwrap();
-            let page = file.get_page(pgno)?;
-            match &stmt.columns {
-                SelectColumns::Count => println!("{}", page.header.cell_count),
-                _ => {
-                    for cell in page.cells() {
-                        let row: Vec<Value> = cell.try_into()?;
-                        let mut result = vec![];
-                        for s in selected.iter() {
-                            result.push(row[*s].to_string());
-                        }
-                        println!("{}", result.join("|"));
-                    }
-                }
-            }
-        }
-    }
-
-    Ok(())
-}
+use sqlite_starter_rust::*;
+
+use anyhow::{anyhow, bail, Result};
+use json::Json;
+use record::Value;
+use std::collections::HashMap;
+use std::io::{IsTerminal, Write};
+use std::num::NonZeroU64;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+
+fn main() -> Result<()> {
+    #[cfg(feature = "trace")]
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .with_writer(std::io::stderr)
+        .init();
+
+    // `--init FILE` runs FILE instead of ~/.codecrafters-sqliterc,
+    // `--key PASSPHRASE` decrypts an SQLCipher database (sqlcipher feature),
+    // `--param VALUE` or `--param NAME=VALUE` binds a value for the SQL,
+    // `--stage N` prints exactly what CodeCrafters stage N checks for, and
+    // `--timeout SECONDS` stops each command that runs longer than that
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("compare-data") {
+        std::process::exit(compare_data(&args[1..]));
+    }
+    if args.first().map(String::as_str) == Some("wal-changes") {
+        return wal_changes(&args[1..]);
+    }
+    let mut init = None;
+    let mut stage = None;
+    let mut key = None;
+    let mut params = vec![];
+    let mut vars = HashMap::new();
+    let mut timeout = None;
+    while matches!(
+        args.first().map(String::as_str),
+        Some("--init" | "--key" | "--param" | "--stage" | "--timeout")
+    ) {
+        if args.len() < 2 {
+            bail!("Missing value after {}", args[0]);
+        }
+        let value = args.remove(1);
+        let option = args.remove(0);
+        match option.as_str() {
+            "--init" => init = Some(PathBuf::from(value)),
+            "--key" => key = Some(value),
+            "--stage" => match value.parse() {
+                Ok(n) if n > 0 => stage = Some(n),
+                _ => bail!("bad stage number {}", value),
+            },
+            "--timeout" => match value.parse::<f64>() {
+                Ok(seconds) if seconds > 0.0 && seconds.is_finite() => {
+                    timeout = Some(Duration::from_secs_f64(seconds))
+                }
+                _ => bail!("bad timeout {}", value),
+            },
+            _ => match param_name(&value) {
+                Some((name, value)) => {
+                    vars.insert(name.to_owned(), param_value(value));
+                }
+                None => params.push(param_value(&value)),
+            },
+        }
+    }
+    match args.len() {
+        0 => bail!("Missing <database path> and <command>"),
+        1 => bail!("Missing <command>"),
+        _ => {}
+    }
+
+    let mut shell = Shell {
+        file: match &key {
+            #[cfg(feature = "sqlcipher")]
+            Some(key) => OpenOptions::new().key(key).open(&args[0])?,
+            #[cfg(not(feature = "sqlcipher"))]
+            Some(_) => bail!("--key needs a build with the sqlcipher feature"),
+            None => OpenOptions::new().open(&args[0])?,
+        },
+        settings: match stage {
+            Some(stage) => Settings::for_stage(stage),
+            None => Settings::default(),
+        },
+        prepared: HashMap::new(),
+        vars,
+        params,
+        last_table: None,
+    };
+    match init {
+        Some(path) => shell.run_file(&path)?,
+        // the rc file could change how things are printed
+        None if stage.is_some() => {}
+        None => {
+            let rc = std::env::var_os("HOME").map(|home| Path::new(&home).join(RC_FILE));
+            if let Some(rc) = rc.filter(|rc| rc.exists()) {
+                shell.run_file(&rc)?;
+            }
+        }
+    }
+    // Each argument after the database is a command, run in order
+    for command in &args[1..] {
+        let interrupt = shell.file.interrupt_handle();
+        with_timeout(interrupt, timeout, || shell.command(command))?;
+    }
+    Ok(())
+}
+
+/// Run `run`, interrupting the file's queries if it takes longer than
+/// `timeout`.
+fn with_timeout(
+    interrupt: interrupt::Interrupt,
+    timeout: Option<Duration>,
+    run: impl FnOnce() -> Result<()>,
+) -> Result<()> {
+    let Some(timeout) = timeout else {
+        return run();
+    };
+    let (done, wait) = mpsc::channel::<()>();
+    let watchdog = {
+        let interrupt = interrupt.clone();
+        std::thread::spawn(move || {
+            if wait.recv_timeout(timeout) == Err(RecvTimeoutError::Timeout) {
+                interrupt.interrupt();
+            }
+        })
+    };
+    let result = run();
+    drop(done);
+    watchdog.join().expect("watchdog thread panicked");
+    interrupt.reset();
+    result.map_err(|e| {
+        if e.is::<interrupt::Interrupted>() {
+            anyhow!("timed out after {:?}", timeout)
+        } else {
+            e
+        }
+    })
+}
+
+/// `compare-data DB1 DB2 [TABLE...]`: print a line for each table whose data
+/// differs, for scripts to check. Returns the exit code: 0 if they're the
+/// same, 1 if they aren't and 2 if they couldn't be compared, like `cmp`.
+fn compare_data(args: &[String]) -> i32 {
+    let [first, second, tables @ ..] = args else {
+        eprintln!("usage: compare-data DB1 DB2 [TABLE...]");
+        return 2;
+    };
+    let diffs = OpenOptions::new().open(first).and_then(|first| {
+        let second = OpenOptions::new().open(second)?;
+        compare::compare_data(&first, &second, tables)
+    });
+    match diffs {
+        Ok(diffs) if diffs.is_empty() => 0,
+        Ok(diffs) => {
+            for diff in &diffs {
+                println!("{}", diff);
+            }
+            1
+        }
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            2
+        }
+    }
+}
+
+/// `wal-changes DB [--follow]`: print the rows changed by each transaction
+/// in DB's WAL as JSON, one per line. With `--follow` it keeps watching for
+/// more until it's killed.
+fn wal_changes(args: &[String]) -> Result<()> {
+    let (path, follow) = match args {
+        [path] => (path, false),
+        [path, follow] if follow == "--follow" => (path, true),
+        _ => bail!("usage: wal-changes DB [--follow]"),
+    };
+    let mut tail = wal::WalTail::open(path)?;
+    loop {
+        let mut out = std::io::stdout().lock();
+        for change in tail.poll()? {
+            writeln!(out, "{}", Json::from(&change))?;
+        }
+        out.flush()?;
+        if !follow {
+            return Ok(());
+        }
+        std::thread::sleep(std::time::Duration::from_millis(WAL_POLL_MS));
+    }
+}
+
+/// The name in `NAME=VALUE`, if it starts with one.
+fn param_name(arg: &str) -> Option<(&str, &str)> {
+    let (name, value) = arg.split_once('=')?;
+    let name = name.trim_start_matches([':', '@', '$']);
+    let mut chars = name.chars();
+    let ok = chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_');
+    ok.then_some((name, value))
+}
+
+/// A `--param` value: an SQL literal like `42` or `'text'`, or else the text
+/// as it is.
+fn param_value(text: &str) -> Value {
+    match sql::parse_values(text).as_deref() {
+        Ok([value]) => value.clone(),
+        _ => Value::String(text.to_owned()),
+    }
+}
+
+/// Pages on each line of `.map`.
+const MAP_WIDTH: usize = 64;
+
+/// Commands run at startup, usually to set up `.mode`, `.headers` and so on.
+const RC_FILE: &str = ".codecrafters-sqliterc";
+
+/// How often `wal-changes --follow` looks for new commits.
+const WAL_POLL_MS: u64 = 200;
+
+/// How much of a long value is shown at the terminal, until `.preview`.
+const DEFAULT_PREVIEW: usize = 1000;
+
+/// The dot-commands, for completion.
+const COMMANDS: &[&str] = &[
+    ".ar",
+    ".btree",
+    ".check",
+    ".complete",
+    ".dbinfo",
+    ".escape",
+    ".fetch",
+    ".fill",
+    ".headers",
+    ".import",
+    ".map",
+    ".materialize",
+    ".maxrows",
+    ".mode",
+    ".nullvalue",
+    ".page",
+    ".prepare",
+    ".preview",
+    ".rowcount",
+    ".run",
+    ".schema",
+    ".search",
+    ".set",
+    ".spaceused",
+    ".stats",
+    ".tables",
+    ".verify-unique",
+    ".width",
+];
+
+/// The dot-commands that print JSON when given `--json`.
+const JSON_COMMANDS: &[&str] = &[
+    ".check",
+    ".dbinfo",
+    ".fill",
+    ".spaceused",
+    ".stats",
+    ".tables",
+    ".verify-unique",
+];
+
+/// What the shell keeps from one command to the next.
+struct Shell {
+    file: SqliteFile,
+    settings: Settings,
+    /// `.prepare`d statements, by name
+    prepared: HashMap<String, sql::Statement>,
+    /// `.set` and `--param NAME=VALUE` variables, for `$name` in SQL
+    vars: HashMap<String, Value>,
+    /// `--param VALUE`s, for `?` in SQL
+    params: Vec<Value>,
+    /// The table the last query read from, for `.fetch`.
+    last_table: Option<String>,
+}
+
+impl Shell {
+    /// Run the commands in a file, one per line. Blank lines and lines
+    /// starting with `--` are skipped.
+    fn run_file(&mut self, path: &Path) -> Result<()> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("can't read {}: {}", path.display(), e))?;
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("--") {
+                continue;
+            }
+            self.command(line)
+                .map_err(|e| anyhow!("{}:{}: {}", path.display(), n + 1, e))?;
+        }
+        Ok(())
+    }
+
+    /// Run a dot-command or an SQL statement.
+    fn command(&mut self, command: &str) -> Result<()> {
+        let Shell {
+            file,
+            settings,
+            prepared,
+            vars,
+            params,
+            last_table,
+        } = self;
+        let file = &*file;
+        let mut words: Vec<&str> = command.split_whitespace().collect();
+        let json =
+            words.first().is_some_and(|w| JSON_COMMANDS.contains(w)) && words.contains(&"--json");
+        words.retain(|w| *w != "--json" || !json);
+
+        match words.as_slice() {
+            [".dbinfo"] if json => {
+                let schema = file.get_page(NonZeroU64::new(1).unwrap())?;
+                let mismatch = file.page_count_mismatch()?.map(|(in_header, in_file)| {
+                    Json::object([("header", in_header.into()), ("file", in_file.into())])
+                });
+                let info = Json::object([
+                    ("page_size", file.page_size().into()),
+                    (
+                        "number_of_tables",
+                        u32::from(schema.header.cell_count).into(),
+                    ),
+                    ("page_count", file.page_count()?.into()),
+                    ("page_count_mismatch", mismatch.into()),
+                    ("header", (&file.header()?).into()),
+                ]);
+                println!("{}", info);
+            }
+            [".dbinfo"] if settings.stage.is_some() => {
+                let schema = file.get_page(NonZeroU64::new(1).unwrap())?;
+                println!("database page size: {}", file.page_size());
+                if settings.stage >= Some(2) {
+                    println!("number of tables: {}", schema.header.cell_count);
+                }
+            }
+            [".dbinfo"] => {
+                let schema = file.get_page(NonZeroU64::new(1).unwrap())?;
+                let page_size = file.page_size();
+                println!("database page size: {}", page_size);
+                println!("number of tables: {}", schema.header.cell_count);
+                println!("database page count: {}", file.page_count()?);
+                if let Some((in_header, in_file)) = file.page_count_mismatch()? {
+                    println!(
+                        "warning: header says {} pages but the file has {}",
+                        in_header, in_file
+                    );
+                }
+            }
+            [".tables"] if json => {
+                let names: Vec<String> = file.get_schema()?.into_iter().map(|s| s.name).collect();
+                println!("{}", Json::from(names));
+            }
+            // user tables on one line, like the sqlite3 shell
+            [".tables"] if settings.stage.is_some() => {
+                let names: Vec<String> = file
+                    .get_schema()?
+                    .into_iter()
+                    .filter(|s| s.stype == SchemaType::Table && !s.name.starts_with("sqlite_"))
+                    .map(|s| s.name)
+                    .collect();
+                println!("{}", names.join(" "));
+            }
+            [".tables"] => {
+                for schema in file.get_schema()? {
+                    println!("{}", schema.name);
+                }
+            }
+            [".map"] => {
+                let map = pagemap::page_map(file)?;
+                for (i, line) in map.chunks(MAP_WIDTH).enumerate() {
+                    let line: String = line.iter().map(|p| p.symbol()).collect();
+                    println!("{:>8} {}", i * MAP_WIDTH + 1, line);
+                }
+                println!();
+                for (what, n) in pagemap::histogram(&map) {
+                    println!(
+                        "{} {:<15} {:>8} {:>6.1}%",
+                        what.symbol(),
+                        what.name(),
+                        n,
+                        100.0 * n as f64 / map.len() as f64
+                    );
+                }
+            }
+            [".spaceused"] if json => {
+                let usage: Vec<Json> = analyze::space_used(file)?.iter().map(Json::from).collect();
+                let report = Json::object([
+                    ("objects", Json::Array(usage)),
+                    ("freelist_pages", file.freelist_count()?.into()),
+                ]);
+                println!("{}", report);
+            }
+            [".spaceused"] => {
+                println!(
+                    "{:<24} {:>7} {:>7} {:>8} {:>8} {:>10} {:>10} {:>10} {:>6} {:>6}",
+                    "name",
+                    "pages",
+                    "leaf",
+                    "interior",
+                    "overflow",
+                    "payload",
+                    "ovfl bytes",
+                    "free",
+                    "frag",
+                    "fill"
+                );
+                for usage in analyze::space_used(file)? {
+                    println!(
+                        "{:<24} {:>7} {:>7} {:>8} {:>8} {:>10} {:>10} {:>10} {:>6} {:>5.1}%",
+                        usage.name,
+                        usage.pages(),
+                        usage.leaf_pages,
+                        usage.interior_pages,
+                        usage.overflow_pages,
+                        usage.payload_bytes,
+                        usage.overflow_bytes,
+                        usage.free_bytes,
+                        usage.fragmented_bytes,
+                        usage.fill_factor() * 100.0
+                    );
+                }
+                println!("freelist pages: {}", file.freelist_count()?);
+            }
+            [".fill"] if json => {
+                let usage = analyze::space_used(file)?;
+                let free = file.freelist_count()? as u64;
+                let reclaimable = usage.iter().map(|u| u.reclaimable_pages()).sum::<u64>() + free;
+                let report = Json::object([
+                    (
+                        "objects",
+                        Json::Array(usage.iter().map(Json::from).collect()),
+                    ),
+                    ("freelist_pages", free.into()),
+                    ("reclaimable_pages", reclaimable.into()),
+                    (
+                        "reclaimable_bytes",
+                        (reclaimable * file.page_size() as u64).into(),
+                    ),
+                ]);
+                println!("{}", report);
+            }
+            [".fill"] => {
+                println!(
+                    "{:<24} {:>7} {:>8} {:>11} {:>11}",
+                    "name", "leaf", "avg fill", "underfilled", "reclaimable"
+                );
+                let mut reclaimable = 0;
+                for usage in analyze::space_used(file)? {
+                    println!(
+                        "{:<24} {:>7} {:>7.1}% {:>11} {:>11}",
+                        usage.name,
+                        usage.leaf_pages,
+                        usage.leaf_fill() * 100.0,
+                        usage.underfilled_leaves,
+                        usage.reclaimable_pages()
+                    );
+                    reclaimable += usage.reclaimable_pages();
+                }
+                let free = file.freelist_count()? as u64;
+                println!("freelist pages: {}", free);
+                println!(
+                    "VACUUM could reclaim about {} pages ({} bytes)",
+                    reclaimable + free,
+                    (reclaimable + free) * file.page_size() as u64
+                );
+            }
+            [".ar", "list"] => {
+                for entry in sqlar::list(file)? {
+                    println!("{}", entry.name);
+                }
+            }
+            [".ar", "extract", rest @ ..] => {
+                let (dir, names) = match rest {
+                    ["--dir", dir, names @ ..] => (Path::new(*dir), names),
+                    names => (Path::new("."), names),
+                };
+                for entry in sqlar::extract(file, dir, names)? {
+                    println!("{}", entry.name);
+                }
+            }
+            [".ar", ..] => bail!("usage: .ar list | .ar extract [--dir DIR] [NAME...]"),
+            [".page", n] => {
+                let page_id: NonZeroU64 = n.parse()?;
+                inspect::dump_page(&mut std::io::stdout().lock(), file, page_id)?;
+            }
+            [".schema", "--graph"] => {
+                inspect::schema_dot(&mut std::io::stdout().lock(), file)?;
+            }
+            // from the leaf page headers, without reading any rows
+            [".rowcount", table] => println!("{}", file.row_count(table)?),
+            [".rowcount", ..] => bail!("usage: .rowcount TABLE"),
+            [".btree", name] => {
+                inspect::btree_dot(&mut std::io::stdout().lock(), file, name)?;
+            }
+            [".search", table, column, terms @ ..] if !terms.is_empty() => {
+                let terms = terms.join(" ");
+                let terms = terms.trim_matches(|c| c == '\'' || c == '"');
+                for hit in search::search(file, table, column, terms)? {
+                    println!("{}|{:.3}|{}", hit.rowid, hit.score, hit.text);
+                }
+            }
+            [".check", rest @ ..] => {
+                let mut options = integrity::CheckOptions::new();
+                let mut rest = rest.iter();
+                while let Some(word) = rest.next() {
+                    match *word {
+                        "--quick" => {
+                            options.quick(true);
+                        }
+                        "--pages" => {
+                            let Some((first, last)) = rest.next().and_then(|r| r.split_once('-'))
+                            else {
+                                bail!("usage: .check [--quick] [--pages FIRST-LAST] [TABLE]");
+                            };
+                            options.pages(first.parse()?..=last.parse()?);
+                        }
+                        table => {
+                            options.table(table);
+                        }
+                    }
+                }
+                let problems = integrity::check(file, &options)?;
+                if json {
+                    let report = Json::object([
+                        ("ok", problems.is_empty().into()),
+                        ("problems", problems.into()),
+                    ]);
+                    println!("{}", report);
+                } else {
+                    print_problems(problems, usize::MAX);
+                }
+            }
+            [".verify-unique", name] => {
+                let duplicates = integrity::duplicate_keys(file, name)?;
+                if json {
+                    let report = Json::object([
+                        ("ok", duplicates.is_empty().into()),
+                        (
+                            "duplicates",
+                            Json::Array(duplicates.iter().map(Json::from).collect()),
+                        ),
+                    ]);
+                    println!("{}", report);
+                    return Ok(());
+                }
+                if duplicates.is_empty() {
+                    println!("ok");
+                }
+                for dup in duplicates {
+                    let key: Vec<String> = dup.key.iter().map(Value::to_string).collect();
+                    let rowids: Vec<String> = dup.rowids.iter().map(i64::to_string).collect();
+                    println!(
+                        "duplicate key ({}) in rows {}",
+                        key.join(", "),
+                        rowids.join(", ")
+                    );
+                }
+            }
+            [".complete", ..] => {
+                // everything after `.complete `, so a trailing space is kept
+                let line = command.trim_start()[".complete".len()..]
+                    .strip_prefix(' ')
+                    .unwrap_or("");
+                for candidate in complete::complete(file, line, COMMANDS)? {
+                    println!("{}", candidate);
+                }
+            }
+            // the counters since the last query, or since opening the file
+            [".stats"] => settings.print_stats(file, json),
+            [".stats", "on"] => {
+                settings.stats = true;
+                settings.stats_json = json;
+            }
+            [".stats", "off"] => settings.stats = false,
+            [".headers", "on"] => settings.headers = true,
+            [".headers", "off"] => settings.headers = false,
+            [".mode", mode] => {
+                settings.mode = match *mode {
+                    "list" => Mode::List,
+                    "csv" => Mode::Csv,
+                    "tabs" => Mode::Tabs,
+                    "column" => Mode::Column,
+                    "line" => Mode::Line,
+                    "insert" => bail!("usage: .mode insert TABLE"),
+                    _ => bail!(
+                        "unknown mode {}: use list, csv, tabs, column, line or insert",
+                        mode
+                    ),
+                };
+            }
+            [".mode", "insert", table] => settings.mode = Mode::Insert(table.to_string()),
+            [".nullvalue", ..] => {
+                let text = command.trim_start()[".nullvalue".len()..].trim();
+                settings.nullvalue = text.trim_matches(|c| c == '\'' || c == '"').to_owned();
+            }
+            [".escape", escape] => {
+                settings.escape = match *escape {
+                    "ascii" => Escape::Ascii,
+                    "symbol" => Escape::Symbol,
+                    "off" => Escape::Off,
+                    _ => bail!("unknown escape {}: use ascii, symbol or off", escape),
+                };
+            }
+            // 0 turns the limit off
+            [".maxrows", n] => {
+                settings.max_rows = Some(n.parse()?).filter(|&n| n > 0);
+            }
+            // 0 shows values in full
+            [".preview", n] => {
+                settings.preview = Some(n.parse()?).filter(|&n| n > 0);
+            }
+            [".fetch", rowid, column] => {
+                let (table, column) = match column.rsplit_once('.') {
+                    Some((table, column)) => (table.to_owned(), column),
+                    None => match last_table {
+                        Some(table) => (table.clone(), *column),
+                        None => bail!("no query yet: use .fetch ROWID TABLE.COLUMN"),
+                    },
+                };
+                fetch(file, &table, rowid.parse()?, column)?;
+            }
+            [".fetch", ..] => bail!("usage: .fetch ROWID [TABLE.]COLUMN"),
+            [".width", widths @ ..] => {
+                settings.widths = widths.iter().map(|w| w.parse()).collect::<Result<_, _>>()?;
+            }
+            [".prepare", name, ..] => {
+                let sql = command.trim_start()[".prepare".len()..].trim_start();
+                let stmt = sql::Statement::parse_with(&sql[name.len()..], file.limits())?;
+                prepared.insert(name.to_string(), stmt);
+            }
+            [".run", name, ..] => {
+                let Some(stmt) = prepared.get(*name) else {
+                    bail!("no prepared statement named {}", name);
+                };
+                let args = command.trim_start()[".run".len()..].trim_start();
+                let args = sql::parse_values(&args[name.len()..])?;
+                run(file, stmt.bind_vars(vars)?.bind(&args)?, settings)?;
+            }
+            [".materialize", name, as_, ..] if as_.eq_ignore_ascii_case("as") => {
+                let sql = command.trim_start()[".materialize".len()..].trim_start();
+                let sql = sql[name.len()..].trim_start()[as_.len()..].trim_start();
+                let select = bind(
+                    &sql::Statement::parse_with(sql, file.limits())?,
+                    vars,
+                    params,
+                )?;
+                let sql::Statement::Select(select) = select else {
+                    bail!("usage: .materialize NAME AS SELECT ...");
+                };
+                file.materialize(name, &select)?;
+            }
+            [".materialize", ..] => bail!("usage: .materialize NAME AS SELECT ..."),
+            [".import", "--json", path, table] => {
+                let json: Json = std::fs::read_to_string(path)?.parse()?;
+                file.import_json(table, &json)?;
+            }
+            [".import", ..] => bail!("usage: .import --json FILE TABLE"),
+            [".set", name, ..] => {
+                let value = command.trim_start()[".set".len()..].trim_start();
+                let [value] = &sql::parse_values(&value[name.len()..])?[..] else {
+                    bail!("usage: .set NAME VALUE");
+                };
+                let name = name.trim_start_matches([':', '@', '$']);
+                vars.insert(name.to_owned(), value.clone());
+            }
+            _ => {
+                let mut statements = sql::parse_statements(command, file.limits());
+                if let Some((_, Ok(sql::Statement::Select(select)))) = statements.last() {
+                    *last_table = Some(select.name.clone());
+                }
+                if statements.len() == 1 {
+                    let (_, stmt) = statements.remove(0);
+                    return run(file, bind(&stmt?, vars, params)?, settings);
+                }
+                // keep going after an error, so each statement gets a go,
+                // even the ones after one that doesn't parse
+                let mut failed = 0;
+                for (n, (text, stmt)) in statements.iter().enumerate() {
+                    let result = stmt
+                        .as_ref()
+                        .map_err(|e| anyhow!("{}", e))
+                        .and_then(|stmt| run(file, bind(stmt, vars, params)?, settings));
+                    if let Err(e) = result {
+                        eprintln!("Error in statement {} ({}): {}", n + 1, text, e);
+                        failed += 1;
+                    }
+                }
+                if failed > 0 {
+                    bail!("{} of {} statements failed", failed, statements.len());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How `.escape` shows control characters in text, so a value can't move the
+/// cursor or change colours in the terminal. Tabs and newlines are left alone.
+#[derive(Clone, Copy, PartialEq)]
+enum Escape {
+    /// `^A`, `^[` and so on.
+    Ascii,
+    /// The Unicode control pictures, like `␛`.
+    Symbol,
+    /// Write text as it is, for piping into something else.
+    Off,
+}
+
+impl Escape {
+    fn apply(self, s: &str) -> String {
+        let control = |c: char| c.is_ascii_control() && c != '\t' && c != '\n';
+        if self == Escape::Off || !s.contains(control) {
+            return s.to_owned();
+        }
+        let mut out = String::with_capacity(s.len());
+        for c in s.chars() {
+            match (self, c) {
+                (_, c) if !control(c) => out.push(c),
+                (Escape::Symbol, '\x7f') => out.push('\u{2421}'),
+                (Escape::Symbol, c) => out.push(char::from_u32(0x2400 + c as u32).unwrap()),
+                (_, c) => {
+                    out.push('^');
+                    out.push((c as u8 ^ 0x40) as char);
+                }
+            }
+        }
+        out
+    }
+}
+
+/// How `.mode` lays out rows.
+#[derive(Clone, Default, PartialEq)]
+enum Mode {
+    /// Values separated by `|`.
+    #[default]
+    List,
+    Csv,
+    /// Values separated by tabs.
+    Tabs,
+    /// Lined up in columns, as wide as `.width` says or the widest value.
+    Column,
+    /// One `name = value` line per column, with a blank line between rows.
+    Line,
+    /// An `INSERT INTO table VALUES (...);` statement per row, for loading
+    /// the rows into another database.
+    Insert(String),
+}
+
+/// Shell settings that change how results are shown.
+struct Settings {
+    /// `.stats on`: print I/O counters after each query.
+    stats: bool,
+    /// `.stats on --json`: print them as JSON.
+    stats_json: bool,
+    /// `.headers on`: print the column names before the rows.
+    headers: bool,
+    mode: Mode,
+    /// `.nullvalue`: what `NULL` is shown as.
+    nullvalue: String,
+    /// `.escape`: what to do with control characters in text. Escaped by
+    /// default when writing to a terminal.
+    escape: Escape,
+    /// `.maxrows`: show at most this many rows of a query, if set.
+    max_rows: Option<usize>,
+    /// `.preview`: cut text and blobs off after this many characters or
+    /// bytes, if set, so a megabyte blob doesn't flood the screen. `.fetch`
+    /// shows the whole thing. On by default when writing to a terminal.
+    preview: Option<usize>,
+    /// `.width`: column widths for column mode. 0 means as wide as needed, and
+    /// negative means right-aligned.
+    widths: Vec<i32>,
+    /// `--stage N`: print like sqlite3 does, as the CodeCrafters tests
+    /// compare against it.
+    stage: Option<u32>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            stats: false,
+            stats_json: false,
+            headers: false,
+            mode: Mode::default(),
+            nullvalue: "NULL".to_owned(),
+            escape: if std::io::stdout().is_terminal() {
+                Escape::Ascii
+            } else {
+                Escape::Off
+            },
+            max_rows: None,
+            preview: std::io::stdout().is_terminal().then_some(DEFAULT_PREVIEW),
+            widths: vec![],
+            stage: None,
+        }
+    }
+}
+
+impl Settings {
+    fn for_stage(stage: u32) -> Self {
+        Settings {
+            nullvalue: String::new(),
+            escape: Escape::Off,
+            preview: None,
+            stage: Some(stage),
+            ..Settings::default()
+        }
+    }
+
+    fn text(&self, value: &Value) -> String {
+        match value {
+            Value::Null => self.nullvalue.clone(),
+            Value::Float(x) if self.stage.is_some() => cast::real_to_text(*x),
+            Value::String(s) => match self.preview {
+                Some(n) if s.chars().count() > n => {
+                    let end = s.char_indices().nth(n).map_or(s.len(), |(i, _)| i);
+                    let total = s.chars().count();
+                    format!("{}... ({} chars)", self.escape.apply(&s[..end]), total)
+                }
+                _ => self.escape.apply(s),
+            },
+            Value::Blob(b) => match self.preview {
+                Some(n) if b.len() > n => format!("{:?}... ({} bytes)", &b[..n], b.len()),
+                _ => value.to_string(),
+            },
+            v => v.to_string(),
+        }
+    }
+
+    fn print_stats(&self, file: &SqliteFile, json: bool) {
+        if json {
+            println!("{}", Json::from(&file.stats()));
+        } else {
+            println!("{}", file.stats());
+        }
+    }
+
+    /// Print the rows of a query, with `names` for the headers.
+    fn print_rows(&self, names: &[String], rows: &[Vec<Value>]) {
+        if let Mode::Insert(table) = &self.mode {
+            return print_inserts(table, self.headers.then_some(names), rows);
+        }
+        let rows: Vec<Vec<String>> = rows
+            .iter()
+            .map(|row| row.iter().map(|v| self.text(v)).collect())
+            .collect();
+        let separator = match self.mode {
+            Mode::List => "|",
+            Mode::Csv => ",",
+            Mode::Tabs => "\t",
+            Mode::Column => return self.print_columns(names, &rows),
+            Mode::Insert(_) => unreachable!("printed as SQL values above"),
+            Mode::Line => {
+                let width = names.iter().map(|n| n.chars().count()).max().unwrap_or(0);
+                for (i, row) in rows.iter().enumerate() {
+                    if i > 0 {
+                        println!();
+                    }
+                    for (name, value) in names.iter().zip(row) {
+                        println!("{:>width$} = {}", name, value, width = width);
+                    }
+                }
+                return;
+            }
+        };
+        let quote = |s: &String| match self.mode {
+            Mode::Csv if s.contains([',', '"', '\n', '\r']) => {
+                format!("\"{}\"", s.replace('"', "\"\""))
+            }
+            _ => s.clone(),
+        };
+        if self.headers {
+            let names: Vec<String> = names.iter().map(quote).collect();
+            println!("{}", names.join(separator));
+        }
+        for row in &rows {
+            let row: Vec<String> = row.iter().map(quote).collect();
+            println!("{}", row.join(separator));
+        }
+    }
+
+    fn print_columns(&self, names: &[String], rows: &[Vec<String>]) {
+        let widths: Vec<i32> = (0..names.len())
+            .map(|i| match self.widths.get(i) {
+                Some(&w) if w != 0 => w,
+                _ => {
+                    let header = if self.headers {
+                        names[i].chars().count()
+                    } else {
+                        0
+                    };
+                    let widest = rows.iter().map(|r| r[i].chars().count()).max();
+                    widest.unwrap_or(0).max(header) as i32
+                }
+            })
+            .collect();
+        let line = |values: &[String]| {
+            let cells: Vec<String> = values
+                .iter()
+                .zip(&widths)
+                .map(|(v, &w)| {
+                    // like SQLite, a value wider than its column is cut short
+                    let v: String = v.chars().take(w.unsigned_abs() as usize).collect();
+                    match w {
+                        w if w < 0 => format!("{:>1$}", v, w.unsigned_abs() as usize),
+                        w => format!("{:<1$}", v, w as usize),
+                    }
+                })
+                .collect();
+            println!("{}", cells.join("  ").trim_end());
+        };
+        if self.headers {
+            line(names);
+            let dashes: Vec<String> = widths
+                .iter()
+                .map(|w| "-".repeat(w.unsigned_abs() as usize))
+                .collect();
+            line(&dashes);
+        }
+        for row in rows {
+            line(row);
+        }
+    }
+}
+
+/// Print `rows` as `INSERT` statements for `table`, naming the columns if
+/// `names` is given. Values are written as SQL literals, so `.nullvalue`,
+/// `.preview` and `.escape` don't apply.
+fn print_inserts(table: &str, names: Option<&[String]>, rows: &[Vec<Value>]) {
+    let into = match names {
+        Some(names) => {
+            let names: Vec<String> = names.iter().map(|n| identifier(n)).collect();
+            format!("{}({})", identifier(table), names.join(","))
+        }
+        None => identifier(table),
+    };
+    for row in rows {
+        let values: Vec<String> = row.iter().map(literal).collect();
+        println!("INSERT INTO {} VALUES({});", into, values.join(","));
+    }
+}
+
+/// `name` as it has to be written in SQL: as it is if it's a plain word, and
+/// in double quotes otherwise.
+fn identifier(name: &str) -> String {
+    let plain = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if plain {
+        name.to_owned()
+    } else {
+        format!("\"{}\"", name.replace('"', "\"\""))
+    }
+}
+
+/// `value` as an SQL literal that reads back as the same value.
+fn literal(value: &Value) -> String {
+    match value {
+        Value::Null => "NULL".to_owned(),
+        Value::Integer(n) => n.to_string(),
+        Value::Float(x) if x.is_nan() => "NULL".to_owned(),
+        // too big for a double, so it reads back as infinity, like sqlite3
+        Value::Float(x) if x.is_infinite() => {
+            if *x > 0.0 { "9.0e+999" } else { "-9.0e+999" }.to_owned()
+        }
+        // `{:?}` always has a `.` or an exponent and round-trips exactly
+        Value::Float(x) => format!("{:?}", x),
+        Value::String(s) => format!("'{}'", s.replace('\'', "''")),
+        Value::Blob(b) => {
+            let hex: String = b.iter().map(|b| format!("{:02X}", b)).collect();
+            format!("X'{}'", hex)
+        }
+    }
+}
+
+/// Bind the shell's variables and `--param`s. Each statement takes as many of
+/// the `--param`s as it has `?`s.
+fn bind(
+    stmt: &sql::Statement,
+    vars: &HashMap<String, Value>,
+    params: &[Value],
+) -> Result<sql::Statement> {
+    let count = stmt.param_count().min(params.len());
+    stmt.bind_vars(vars)?.bind(&params[..count])
+}
+
+/// Print one value in full, for when `.preview` cut it off. Text and blobs
+/// are written out as they are, so a blob can be redirected to a file.
+fn fetch(file: &SqliteFile, table: &str, rowid: i64, column: &str) -> Result<()> {
+    let quote = |name: &str| format!("\"{}\"", name.replace('"', "\"\""));
+    let table_sql: Vec<String> = table.split('.').map(quote).collect();
+    let sql = format!(
+        "SELECT {} FROM {} WHERE rowid = {}",
+        quote(column),
+        table_sql.join("."),
+        rowid
+    );
+    let sql::Statement::Select(select) = sql::Statement::parse_with(&sql, file.limits())? else {
+        unreachable!()
+    };
+    let Some(value) = query::execute(file, &select)?
+        .pop()
+        .and_then(|mut row| row.pop())
+    else {
+        bail!("no row with rowid {} in {}", rowid, table);
+    };
+    let mut out = std::io::stdout().lock();
+    match value {
+        Value::String(s) => out.write_all(s.as_bytes())?,
+        Value::Blob(b) => out.write_all(&b)?,
+        v => write!(out, "{}", v)?,
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+/// Run an SQL statement and print what it returns.
+fn run(file: &SqliteFile, stmt: sql::Statement, settings: &Settings) -> Result<()> {
+    if let sql::Statement::Pragma { name, arg } = &stmt {
+        file.authorize(&auth::Action::Pragma {
+            name,
+            arg: arg.as_ref(),
+        })?;
+    }
+    match stmt {
+        sql::Statement::Select(select) => {
+            file.reset_stats();
+            let names = query::column_names(file, &select)?;
+            match settings.max_rows {
+                Some(max) => {
+                    let limited = query::execute_limited(file, &select, max)?;
+                    settings.print_rows(&names, &limited.rows);
+                    if limited.truncated {
+                        eprintln!(
+                            "... stopped after {} rows; use .maxrows 0 to show them all",
+                            max
+                        );
+                    }
+                }
+                None => settings.print_rows(&names, &query::execute(file, &select)?),
+            }
+            if settings.stats {
+                settings.print_stats(file, settings.stats_json);
+            }
+        }
+        sql::Statement::ExplainQueryPlan(select) => {
+            for line in query::explain(file, &select)? {
+                println!("{}", line);
+            }
+        }
+        sql::Statement::Pragma { name, arg }
+            if name == "integrity_check" || name == "quick_check" =>
+        {
+            let mut options = integrity::CheckOptions::new();
+            options.quick(name == "quick_check");
+            // a number is how many problems to show, like SQLite
+            let mut limit = 100;
+            match arg {
+                Some(Value::Integer(n)) => limit = n.max(1) as usize,
+                Some(Value::String(table)) => {
+                    options.table(&table);
+                }
+                _ => {}
+            }
+            print_problems(integrity::check(file, &options)?, limit);
+        }
+        sql::Statement::Pragma {
+            name,
+            arg: Some(Value::String(table)),
+        } if name == "table_info" || name == "foreign_key_list" => {
+            // like SQLite, a table that isn't there has no columns or keys
+            let def = match file
+                .get_schema()?
+                .iter()
+                .find(|s| s.stype == SchemaType::Table && s.name.eq_ignore_ascii_case(&table))
+            {
+                Some(entry) => Some(CreateTable::try_from(entry)?),
+                None => None,
+            };
+            let (names, rows) = match name.as_str() {
+                "table_info" => (
+                    &["cid", "name", "type", "notnull", "dflt_value", "pk"][..],
+                    def.map_or(vec![], |def| def.table_info()),
+                ),
+                _ => (
+                    &[
+                        "id",
+                        "seq",
+                        "table",
+                        "from",
+                        "to",
+                        "on_update",
+                        "on_delete",
+                        "match",
+                    ][..],
+                    def.map_or(vec![], |def| def.foreign_key_list()),
+                ),
+            };
+            let names: Vec<String> = names.iter().map(|n| n.to_string()).collect();
+            settings.print_rows(&names, &rows);
+        }
+        sql::Statement::Pragma {
+            name,
+            arg: Some(Value::String(arg)),
+        } if name == "index_list" || name == "index_info" => {
+            let (names, rows) = match name.as_str() {
+                "index_list" => (
+                    &["seq", "name", "unique", "origin", "partial"][..],
+                    file.index_list(&arg)?,
+                ),
+                _ => (&["seqno", "cid", "name"][..], file.index_info(&arg)?),
+            };
+            let names: Vec<String> = names.iter().map(|n| n.to_string()).collect();
+            settings.print_rows(&names, &rows);
+        }
+        sql::Statement::Pragma { name, .. } => bail!("unsupported pragma: {}", name),
+        sql::Statement::Attach { path, name } => file.attach(path, &name)?,
+        sql::Statement::Detach(name) => file.detach(&name)?,
+    }
+    Ok(())
+}
+
+/// Print what an integrity check found, or `ok` if it found nothing.
+fn print_problems(problems: Vec<String>, limit: usize) {
+    if problems.is_empty() {
+        println!("ok");
+    }
+    for problem in problems.into_iter().take(limit) {
+        println!("{}", problem);
+    }
+}
@@ -0,0 +1,169 @@
+//! Space usage statistics, along the lines of `sqlite3_analyzer`.
+
+use std::num::NonZeroU64;
+
+use anyhow::Result;
+
+use super::btree::walk;
+use super::{Page, PageKind, SqliteFile};
+
+/// How much space one table or index takes up.
+#[derive(Debug, Default, Clone)]
+pub struct SpaceUsage {
+    /// Name of the table or index.
+    pub name: String,
+    pub interior_pages: u64,
+    pub leaf_pages: u64,
+    pub overflow_pages: u64,
+    /// Number of rows or index entries.
+    pub entries: u64,
+    /// Payload bytes, including the parts stored on overflow pages.
+    pub payload_bytes: u64,
+    /// Payload bytes stored on overflow pages.
+    pub overflow_bytes: u64,
+    /// Unused bytes: unallocated space, freeblocks, and the unused end of the last overflow page.
+    pub free_bytes: u64,
+    /// Fragmented bytes, which are too small to reuse.
+    pub fragmented_bytes: u64,
+    /// Usable bytes per page.
+    pub usable_size: u64,
+    /// Unused and fragmented bytes on leaf pages only.
+    pub leaf_free_bytes: u64,
+    /// Leaf pages less than [`UNDERFILLED`] full.
+    pub underfilled_leaves: u64,
+}
+
+/// A leaf page filled less than this is counted as underfilled. SQLite
+/// rebalances pages that drop under about a third full, so one that's under
+/// half usually got that way from deletes or random inserts.
+pub const UNDERFILLED: f64 = 0.5;
+
+impl SpaceUsage {
+    /// Total number of pages.
+    pub fn pages(&self) -> u64 {
+        self.interior_pages + self.leaf_pages + self.overflow_pages
+    }
+
+    /// Fraction of the pages' usable space that's in use, from 0 to 1.
+    pub fn fill_factor(&self) -> f64 {
+        let total = self.pages() * self.usable_size;
+        if total == 0 {
+            return 0.0;
+        }
+        1.0 - (self.free_bytes + self.fragmented_bytes) as f64 / total as f64
+    }
+
+    /// Average fill of the leaf pages, from 0 to 1.
+    pub fn leaf_fill(&self) -> f64 {
+        let total = self.leaf_pages * self.usable_size;
+        if total == 0 {
+            return 0.0;
+        }
+        1.0 - self.leaf_free_bytes as f64 / total as f64
+    }
+
+    /// Roughly how many leaf pages a `VACUUM` would save. It rebuilds the
+    /// B-tree in key order, which packs the leaves nearly full.
+    pub fn reclaimable_pages(&self) -> u64 {
+        let used = self.leaf_pages * self.usable_size - self.leaf_free_bytes;
+        self.leaf_pages - used.div_ceil(self.usable_size.max(1))
+    }
+
+    fn add_page(&mut self, page: &Page) {
+        if page.header.kind.is_interior() {
+            self.interior_pages += 1;
+        } else {
+            self.leaf_pages += 1;
+            let free = page.free_bytes() as u64 + page.header.fragmented_free_bytes as u64;
+            self.leaf_free_bytes += free;
+            if (free as f64) > (1.0 - UNDERFILLED) * self.usable_size as f64 {
+                self.underfilled_leaves += 1;
+            }
+        }
+        // index b-trees keep entries on interior pages too
+        if page.header.kind != PageKind::TableInterior {
+            self.entries += page.header.cell_count as u64;
+        }
+        self.free_bytes += page.free_bytes() as u64;
+        self.fragmented_bytes += page.header.fragmented_free_bytes as u64;
+
+        let overflow_capacity = self.usable_size - 4;
+        for cell in page.cells() {
+            if let Some(payload) = cell.get_payload() {
+                self.payload_bytes += payload.size;
+                let overflow = payload.overflow_size();
+                if overflow > 0 {
+                    let pages = overflow.div_ceil(overflow_capacity);
+                    self.overflow_pages += pages;
+                    self.overflow_bytes += overflow;
+                    self.free_bytes += pages * overflow_capacity - overflow;
+                }
+            }
+        }
+    }
+}
+
+impl Page {
+    /// Unallocated space between the cell pointer array and the cell contents,
+    /// plus the space in freeblocks. Doesn't include fragmented bytes.
+    pub fn free_bytes(&self) -> usize {
+        let pointers_end =
+            self.header_offset() + self.header_size() + 2 * self.header.cell_count as usize;
+        let content_start = match self.header.cell_contents {
+            0 => 65536,
+            n => n as usize,
+        };
+        let mut free = content_start.saturating_sub(pointers_end);
+
+        // freeblocks are a linked list: 2 bytes offset of the next one, 2 bytes size
+        let mut offset = self.header.first_freeblock as usize;
+        let mut hops = 0;
+        while offset != 0 && offset + 4 <= self.data.len() && hops < self.data.len() / 4 {
+            let next = u16::from_be_bytes([self.data[offset], self.data[offset + 1]]);
+            let size = u16::from_be_bytes([self.data[offset + 2], self.data[offset + 3]]);
+            free += size as usize;
+            offset = next as usize;
+            hops += 1;
+        }
+        free
+    }
+}
+
+/// Space usage of one B-tree.
+pub fn btree_space(file: &SqliteFile, name: &str, root: NonZeroU64) -> Result<SpaceUsage> {
+    let mut usage = SpaceUsage {
+        name: name.to_owned(),
+        usable_size: file.usable_size() as u64,
+        ..Default::default()
+    };
+    walk(file, root, |page, _| {
+        usage.add_page(page);
+        Ok(())
+    })?;
+    Ok(usage)
+}
+
+/// Space usage of the schema table and every table and index in the database.
+pub fn space_used(file: &SqliteFile) -> Result<Vec<SpaceUsage>> {
+    let mut all = vec![btree_space(file, "sqlite_schema", NonZeroU64::MIN)?];
+    for schema in file.get_schema()? {
+        // views and triggers don't have a b-tree
+        if let Some(root) = NonZeroU64::new(schema.rootpage) {
+            all.push(btree_space(file, &schema.name, root)?);
+        }
+    }
+    Ok(all)
+}
+
+#[test]
+fn reclaimable_leaves() {
+    let usage = SpaceUsage {
+        leaf_pages: 4,
+        usable_size: 1000,
+        // as much data as one and a half full pages
+        leaf_free_bytes: 2500,
+        ..Default::default()
+    };
+    assert_eq!(usage.leaf_fill(), 0.375);
+    assert_eq!(usage.reclaimable_pages(), 2);
+}
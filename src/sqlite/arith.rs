@@ -0,0 +1,232 @@
+//! Arithmetic on [`Value`]s, with SQLite's rules: `NULL` in gives `NULL` out,
+//! text is read as a number, integer overflow turns into a real, and dividing
+//! by zero gives `NULL`.
+
+use std::ops::{Add, Div, Mul, Neg, Rem, Sub};
+
+use super::record::Value;
+
+impl Value {
+    /// The value as a number, the way SQLite converts the operands of
+    /// arithmetic. Text and blobs use the longest prefix that looks like a
+    /// number, or 0 if there isn't one. `NULL` stays `NULL`.
+    pub fn to_numeric(&self) -> Value {
+        match self {
+            Value::Null | Value::Integer(_) | Value::Float(_) => self.clone(),
+            Value::String(s) => numeric_prefix(s),
+            Value::Blob(b) => numeric_prefix(&String::from_utf8_lossy(b)),
+        }
+    }
+}
+
+/// Parse the number at the start of `s`, after any whitespace.
+fn numeric_prefix(s: &str) -> Value {
+    number_prefix(s).map_or(Value::Integer(0), |(n, _)| n)
+}
+
+/// The number `s` is, if there's nothing else in it but whitespace. This is
+/// what text has to look like to be converted by a column's affinity.
+pub(crate) fn parse_number(s: &str) -> Option<Value> {
+    let s = s.trim();
+    match number_prefix(s)? {
+        (n, end) if end == s.len() => Some(n),
+        _ => None,
+    }
+}
+
+/// The number at the start of `s`, after any whitespace, and where it ends.
+fn number_prefix(s: &str) -> Option<(Value, usize)> {
+    let start = s.len() - s.trim_start().len();
+    let s = &s[start..];
+    let bytes = s.as_bytes();
+    let digits = |from: usize| {
+        bytes[from..]
+            .iter()
+            .take_while(|b| b.is_ascii_digit())
+            .count()
+    };
+    let mut end = 0;
+    if matches!(bytes.first(), Some(b'+' | b'-')) {
+        end = 1;
+    }
+    let int_digits = digits(end);
+    end += int_digits;
+    let mut real = false;
+    if bytes.get(end) == Some(&b'.') {
+        let frac_digits = digits(end + 1);
+        if int_digits > 0 || frac_digits > 0 {
+            real = true;
+            end += 1 + frac_digits;
+        }
+    } else if int_digits == 0 {
+        return None;
+    }
+    // an exponent only counts if it has digits
+    if matches!(bytes.get(end), Some(b'e' | b'E')) {
+        let mut exp = end + 1;
+        if matches!(bytes.get(exp), Some(b'+' | b'-')) {
+            exp += 1;
+        }
+        let exp_digits = digits(exp);
+        if exp_digits > 0 {
+            real = true;
+            end = exp + exp_digits;
+        }
+    }
+    let text = &s[..end];
+    if !real {
+        if let Ok(n) = text.parse() {
+            return Some((Value::Integer(n), start + end));
+        }
+    }
+    let n = text.parse().ok()?;
+    Some((Value::Float(n), start + end))
+}
+
+#[derive(Clone, Copy)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+/// Reals that aren't a number, like `inf - inf`, are `NULL`.
+fn real(x: f64) -> Value {
+    if x.is_nan() {
+        Value::Null
+    } else {
+        Value::Float(x)
+    }
+}
+
+fn arith(op: Op, a: &Value, b: &Value) -> Value {
+    let (a, b) = (a.to_numeric(), b.to_numeric());
+    match (&a, &b) {
+        (Value::Null, _) | (_, Value::Null) => Value::Null,
+        (&Value::Integer(x), &Value::Integer(y)) => {
+            let result = match op {
+                Op::Add => x.checked_add(y),
+                Op::Sub => x.checked_sub(y),
+                Op::Mul => x.checked_mul(y),
+                Op::Div if y == 0 => return Value::Null,
+                Op::Div => x.checked_div(y),
+                Op::Rem if y == 0 => return Value::Null,
+                // i64::MIN % -1 overflows, but the answer is still 0
+                Op::Rem => Some(x.checked_rem(y).unwrap_or(0)),
+            };
+            match result {
+                Some(n) => Value::Integer(n),
+                None => arith_real(op, x as f64, y as f64),
+            }
+        }
+        _ => arith_real(op, as_f64(&a), as_f64(&b)),
+    }
+}
+
+fn arith_real(op: Op, x: f64, y: f64) -> Value {
+    match op {
+        Op::Add => real(x + y),
+        Op::Sub => real(x - y),
+        Op::Mul => real(x * y),
+        Op::Div if y == 0.0 => Value::Null,
+        Op::Div => real(x / y),
+        // the remainder is always taken on integers, even for reals
+        Op::Rem => {
+            let (x, y) = (x as i64, y as i64);
+            if y == 0 {
+                return Value::Null;
+            }
+            Value::Float(x.checked_rem(y).unwrap_or(0) as f64)
+        }
+    }
+}
+
+fn as_f64(v: &Value) -> f64 {
+    match v {
+        Value::Integer(n) => *n as f64,
+        Value::Float(x) => *x,
+        _ => 0.0,
+    }
+}
+
+macro_rules! impl_op {
+    ($($trait:ident $method:ident $op:ident),* $(,)?) => {
+        $(
+            impl $trait<&Value> for &Value {
+                type Output = Value;
+
+                fn $method(self, rhs: &Value) -> Value {
+                    arith(Op::$op, self, rhs)
+                }
+            }
+
+            impl $trait for Value {
+                type Output = Value;
+
+                fn $method(self, rhs: Value) -> Value {
+                    arith(Op::$op, &self, &rhs)
+                }
+            }
+        )*
+    };
+}
+
+impl_op!(Add add Add, Sub sub Sub, Mul mul Mul, Div div Div, Rem rem Rem);
+
+impl Neg for &Value {
+    type Output = Value;
+
+    fn neg(self) -> Value {
+        match self.to_numeric() {
+            Value::Integer(n) => n
+                .checked_neg()
+                .map_or(Value::Float(-(n as f64)), Value::Integer),
+            Value::Float(x) => Value::Float(-x),
+            _ => Value::Null,
+        }
+    }
+}
+
+impl Neg for Value {
+    type Output = Value;
+
+    fn neg(self) -> Value {
+        -&self
+    }
+}
+
+#[test]
+fn value_arithmetic() {
+    use Value::*;
+    let text = |s: &str| String(s.to_owned());
+    assert_eq!(Integer(7) / Integer(2), Integer(3));
+    assert_eq!(Float(7.0) / Integer(2), Float(3.5));
+    assert_eq!(Integer(5) / Integer(0), Null);
+    assert_eq!(Integer(5) % Integer(0), Null);
+    assert_eq!(Integer(-7) % Integer(3), Integer(-1));
+    assert_eq!(Float(5.5) % Integer(2), Float(1.0));
+    assert_eq!(Null + Integer(1), Null);
+    // overflow turns into a real
+    assert_eq!(Integer(i64::MAX) + Integer(1), Float(9223372036854775808.0));
+    assert_eq!(
+        Integer(i64::MIN) / Integer(-1),
+        Float(9223372036854775808.0)
+    );
+    assert_eq!(-Integer(i64::MIN), Float(9223372036854775808.0));
+    // text is read as a number
+    assert_eq!(text("12abc") + Integer(1), Integer(13));
+    assert_eq!(text("abc") + Integer(1), Integer(1));
+    assert_eq!(text(" 3.5e1x") * Integer(2), Float(70.0));
+    assert_eq!(text("5.") + Integer(0), Float(5.0));
+    // too big for an integer
+    assert_eq!(
+        text("9223372036854775808") + Integer(0),
+        Float(9223372036854775808.0)
+    );
+    assert_eq!(text("1e") + Integer(0), Integer(1));
+    assert_eq!(text("-") + Integer(1), Integer(1));
+    assert_eq!(Blob(b"12".to_vec()) + Integer(1), Integer(13));
+    assert_eq!(Float(f64::INFINITY) - Float(f64::INFINITY), Null);
+}
@@ -0,0 +1,62 @@
+//! Deciding what SQL is allowed to do, like `sqlite3_set_authorizer()`, for
+//! programs that run queries their users wrote. The authorizer is asked about
+//! each table, column, function and pragma a statement uses while it's being
+//! planned, before any rows are read.
+//!
+//! ```no_run
+//! # use sqlite_starter_rust::{auth::{Action, Authorization}, OpenOptions};
+//! let mut db = OpenOptions::new().open("sample.db")?;
+//! db.set_authorizer(|action| match action {
+//!     Action::Read { table, .. } if table.eq_ignore_ascii_case("secrets") => Authorization::Deny,
+//!     _ => Authorization::Allow,
+//! });
+//! assert!(db.prepare("SELECT * FROM secrets").is_err());
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use std::fmt;
+
+use super::record::Value;
+
+/// Something a statement wants to do.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action<'a> {
+    /// Run a `SELECT` at all. Asked once per statement.
+    Select,
+    /// Read a column of a table, by the names in the schema. `column` is
+    /// empty if the table's rows are used but none of its columns are, like
+    /// for `COUNT(*)`.
+    Read { table: &'a str, column: &'a str },
+    /// Call a function.
+    Function(&'a str),
+    /// Run a pragma.
+    Pragma {
+        name: &'a str,
+        arg: Option<&'a Value>,
+    },
+}
+
+impl fmt::Display for Action<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Select => write!(f, "SELECT"),
+            Action::Read { table, column } => write!(f, "read {}.{}", table, column),
+            Action::Function(name) => write!(f, "call {}()", name),
+            Action::Pragma { name, .. } => write!(f, "PRAGMA {}", name),
+        }
+    }
+}
+
+/// What the authorizer says.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Authorization {
+    Allow,
+    /// Fail the statement.
+    Deny,
+    /// For [`Action::Read`], the column reads as `NULL`. Anything else can't
+    /// be half done, so it's the same as `Deny`.
+    Ignore,
+}
+
+/// The authorizer: told what's going on, says whether it can.
+pub type AuthorizerFn = dyn Fn(&Action) -> Authorization + Send + Sync;
@@ -0,0 +1,111 @@
+//! Transient in-memory indexes, built on the fly when a lookup is repeated on
+//! a column that has no real index, like SQLite's automatic indexes.
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+
+use super::record::{Row, Value};
+
+/// [`Value`] ordered with [`Value::compare`], so it can be a map key.
+#[derive(Debug, Clone)]
+struct Key(Value);
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Key {}
+
+impl PartialOrd for Key {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Key {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.compare(&other.0)
+    }
+}
+
+/// Rows of a table grouped by the value of one key, for the inner side of a
+/// join that would otherwise rescan the whole table for every outer row.
+///
+/// Building one costs a single pass over the table, after which each lookup is
+/// O(log n), so the join as a whole is O(N log N) instead of O(N·M).
+#[derive(Debug, Default)]
+pub struct AutoIndex {
+    entries: BTreeMap<Key, Vec<Row>>,
+    rows: usize,
+}
+
+impl AutoIndex {
+    /// Index `rows` by whatever `key` returns for each of them. Rows with a
+    /// `NULL` key are left out, since `NULL` never equals anything.
+    pub fn build<I, F>(rows: I, key: F) -> Result<Self>
+    where
+        I: IntoIterator<Item = Result<Row>>,
+        F: Fn(&Row) -> Result<Value>,
+    {
+        let mut index = AutoIndex::default();
+        for row in rows {
+            let row = row?;
+            let k = key(&row)?;
+            if k == Value::Null {
+                continue;
+            }
+            index.entries.entry(Key(k)).or_default().push(row);
+            index.rows += 1;
+        }
+        Ok(index)
+    }
+
+    /// The rows whose key equals `key`, in the order they were added.
+    pub fn get(&self, key: &Value) -> &[Row] {
+        if *key == Value::Null {
+            return &[];
+        }
+        self.entries
+            .get(&Key(key.clone()))
+            .map_or(&[], |rows| rows.as_slice())
+    }
+
+    /// Number of rows in the index.
+    pub fn len(&self) -> usize {
+        self.rows
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows == 0
+    }
+}
+
+#[test]
+fn auto_index_groups_rows() -> Result<()> {
+    let rows = [
+        (1, Value::Integer(3)),
+        (2, Value::String("x".into())),
+        (3, Value::Null),
+        (4, Value::Float(3.0)),
+    ]
+    .into_iter()
+    .map(|(rowid, v)| {
+        Ok(Row {
+            rowid,
+            values: vec![v],
+        })
+    });
+    let index = AutoIndex::build(rows, |row| Ok(row.values[0].clone()))?;
+    assert_eq!(index.len(), 3);
+    let rowids = |key| index.get(&key).iter().map(|r| r.rowid).collect::<Vec<_>>();
+    // integers and reals compare as numbers
+    assert_eq!(rowids(Value::Integer(3)), vec![1, 4]);
+    assert_eq!(rowids(Value::String("x".into())), vec![2]);
+    assert!(rowids(Value::Null).is_empty());
+    assert!(rowids(Value::Integer(7)).is_empty());
+    Ok(())
+}
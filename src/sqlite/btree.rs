@@ -0,0 +1,851 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::num::NonZeroU64;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Result};
+
+use super::cells::{Cell, Payload};
+use super::collation::Collation;
+use super::functions::Functions;
+use super::record::{
+    parse_columns, parse_payload, parse_payload_ref, record_prefix_len, Row, RowRef, Value,
+    ValueRef,
+};
+use super::rowset::RowidSet;
+use super::stats;
+use super::{nom_reason, Page, PageKind, SqliteError, SqliteFile};
+
+impl Page {
+    /// Child pages of an interior page, in key order. Leaf pages have none.
+    pub fn children(&self) -> Vec<u32> {
+        let mut children: Vec<u32> = self
+            .cells()
+            .filter_map(|cell| match cell {
+                Cell::TableInterior {
+                    left_child_page, ..
+                }
+                | Cell::IndexInterior {
+                    left_child_page, ..
+                } => Some(left_child_page),
+                _ => None,
+            })
+            .collect();
+        children.extend(self.header.rightmost_pointer);
+        children
+    }
+}
+
+/// Visit every page of the B-tree rooted at `root`, parents before children.
+/// The callback also gets the depth of the page, which is 0 for the root.
+///
+/// All children of an interior page are fetched together with
+/// [`SqliteFile::get_pages`], so sibling pages get read in one go.
+pub fn walk<F>(file: &SqliteFile, root: NonZeroU64, mut f: F) -> Result<()>
+where
+    F: FnMut(&Arc<Page>, usize) -> Result<()>,
+{
+    let mut seen = HashSet::new();
+    let mut fetched = HashMap::new();
+    let mut stack = vec![(root, 0)];
+    while let Some((page_id, depth)) = stack.pop() {
+        if !seen.insert(page_id) {
+            return Err(btree_error(
+                page_id.get(),
+                "appears in the b-tree more than once",
+            ));
+        }
+        let page = match fetched.remove(&page_id.get()) {
+            Some(page) => page,
+            None => file.get_page(page_id)?,
+        };
+        f(&page, depth)?;
+        let mut children = vec![];
+        for child in page.children() {
+            children.push(child_page(&page, child)?);
+        }
+        fetched.extend(file.get_pages(&children)?);
+        // push in reverse so children come off the stack in key order
+        stack.extend(children.into_iter().rev().map(|child| (child, depth + 1)));
+    }
+    Ok(())
+}
+
+impl SqliteFile {
+    /// The whole payload of a cell, following the chain of overflow pages if it
+    /// didn't fit on the page.
+    pub fn read_payload<'a>(&self, payload: &Payload<'a>) -> Result<Cow<'a, [u8]>> {
+        let Some(mut next) = payload.overflow else {
+            return Ok(Cow::Borrowed(payload.payload));
+        };
+        let mut data = Vec::with_capacity(payload.size as usize);
+        data.extend_from_slice(payload.payload);
+        let overflow_size = self.usable_size() - 4;
+        let mut prev = 0;
+        while data.len() < payload.size as usize {
+            let Some(page_id) = NonZeroU64::new(next as u64) else {
+                return Err(SqliteError::Overflow {
+                    page: prev,
+                    reason: format!(
+                        "chain ends {} bytes before the end of the payload",
+                        payload.size as usize - data.len()
+                    ),
+                }
+                .into());
+            };
+            let page = self.read_raw(page_id).map_err(|e| SqliteError::Overflow {
+                page: page_id.get(),
+                reason: e.to_string(),
+            })?;
+            prev = page_id.get();
+            next = u32::from_be_bytes([page[0], page[1], page[2], page[3]]);
+            let want = (payload.size as usize - data.len()).min(overflow_size);
+            data.extend_from_slice(&page[4..4 + want]);
+        }
+        Ok(Cow::Owned(data))
+    }
+
+    /// Decode the record in a cell's payload.
+    pub fn read_record(&self, payload: &Payload) -> Result<Vec<Value>> {
+        let data = self.read_payload(payload)?;
+        let (_, values) = parse_payload(&data).map_err(|e| anyhow!(nom_reason(e)))?;
+        Ok(values)
+    }
+
+    /// Decode only some columns of the record in a cell's payload, leaving the
+    /// rest `NULL`. With no column list this is the same as [`Self::read_record`].
+    pub fn read_columns(&self, payload: &Payload, columns: Option<&[usize]>) -> Result<Vec<Value>> {
+        let data = self.record_data(payload, columns)?;
+        Ok(decode_columns(&data, columns)?
+            .into_iter()
+            .map(ValueRef::to_value)
+            .collect())
+    }
+
+    /// The bytes of a record needed to decode `columns`. Overflow pages aren't
+    /// read if those columns are all on the page.
+    fn record_data<'a>(
+        &self,
+        payload: &Payload<'a>,
+        columns: Option<&[usize]>,
+    ) -> Result<Cow<'a, [u8]>> {
+        let local = columns.is_some_and(|columns| {
+            record_prefix_len(payload.payload, columns)
+                .is_some_and(|len| len <= payload.payload.len())
+        });
+        if local {
+            Ok(Cow::Borrowed(payload.payload))
+        } else {
+            self.read_payload(payload)
+        }
+    }
+}
+
+/// Decode the given columns of a record, or all of them.
+fn decode_columns<'a>(data: &'a [u8], columns: Option<&[usize]>) -> Result<Vec<ValueRef<'a>>> {
+    let parsed = match columns {
+        Some(columns) => parse_columns(data, columns),
+        None => parse_payload_ref(data),
+    };
+    let (_, values) = parsed.map_err(|e| anyhow!(nom_reason(e)))?;
+    Ok(values)
+}
+
+fn btree_error(page: u64, reason: impl Into<String>) -> anyhow::Error {
+    SqliteError::Btree {
+        page,
+        reason: reason.into(),
+    }
+    .into()
+}
+
+/// Say which cell a record came from if decoding it failed.
+fn in_cell(page: &Page, cell: usize) -> impl Fn(anyhow::Error) -> anyhow::Error + '_ {
+    move |e| {
+        if e.is::<SqliteError>() {
+            return e;
+        }
+        SqliteError::Record {
+            page: page.page_id,
+            cell,
+            reason: e.to_string(),
+        }
+        .into()
+    }
+}
+
+/// Iterates over the rows of a table B-tree in rowid order.
+///
+/// It can be limited to a [`RowidSet`], in which case subtrees without any of
+/// the wanted rowids are skipped, and the pages that are needed get fetched
+/// together in page order.
+pub struct TableScan<'f> {
+    file: &'f SqliteFile,
+    rowids: Option<RowidSet>,
+    /// Smallest and biggest rowid wanted.
+    range: (i64, i64),
+    /// Columns to decode, or `None` for all of them.
+    columns: Option<Vec<usize>>,
+    /// Pages still to visit, with the range of rowids they can contain.
+    /// The next one is at the end.
+    pending: Vec<(NonZeroU64, i64, i64)>,
+    fetched: HashMap<u64, Arc<Page>>,
+    /// Leaf page we're in the middle of, and the next cell on it.
+    leaf: Option<(Arc<Page>, usize)>,
+}
+
+impl<'f> TableScan<'f> {
+    /// Scan every row of the table whose B-tree starts at `root`.
+    pub fn new(file: &'f SqliteFile, root: NonZeroU64) -> Self {
+        Self {
+            file,
+            rowids: None,
+            range: (i64::MIN, i64::MAX),
+            columns: None,
+            pending: vec![(root, i64::MIN, i64::MAX)],
+            fetched: HashMap::new(),
+            leaf: None,
+        }
+    }
+
+    /// Only scan the rows with the given rowids.
+    pub fn with_rowids(file: &'f SqliteFile, root: NonZeroU64, rowids: RowidSet) -> Self {
+        let mut scan = Self::new(file, root);
+        if rowids.is_empty() {
+            scan.pending.clear();
+        }
+        scan.rowids = Some(rowids);
+        scan
+    }
+
+    /// Only scan the rows with rowids from `min` to `max`, including both.
+    /// Pages outside the range don't get read, and the scan stops as soon as
+    /// it's past `max`.
+    pub fn range(mut self, min: i64, max: i64) -> Self {
+        self.range = (self.range.0.max(min), self.range.1.min(max));
+        if self.range.0 > self.range.1 {
+            self.pending.clear();
+        }
+        self
+    }
+
+    /// Only scan the rows after `rowid`, without reading the pages before it.
+    pub fn after(self, rowid: i64) -> Self {
+        match rowid.checked_add(1) {
+            Some(min) => self.range(min, i64::MAX),
+            // nothing comes after the biggest rowid
+            None => self.range(i64::MAX, i64::MIN),
+        }
+    }
+
+    /// Only decode the given columns, leaving the others `NULL`.
+    pub fn columns(mut self, columns: Vec<usize>) -> Self {
+        self.columns = Some(columns);
+        self
+    }
+
+    fn wanted(&self, lo: i64, hi: i64) -> bool {
+        hi >= self.range.0
+            && lo <= self.range.1
+            && self.rowids.as_ref().is_none_or(|r| r.any_in(lo, hi))
+    }
+
+    /// Queue up the children of an interior page that might have rows we want.
+    fn descend(&mut self, page: &Page, lo: i64, hi: i64) -> Result<()> {
+        let mut children = vec![];
+        let mut prev = lo;
+        for i in 0..page.header.cell_count as usize {
+            if let Cell::TableInterior {
+                left_child_page,
+                rowid,
+            } = page.cell(i)?
+            {
+                // the left child has rowids up to and including the key
+                let key = rowid as i64;
+                if self.wanted(prev, key) {
+                    children.push((child_page(page, left_child_page)?, prev, key));
+                }
+                prev = key.saturating_add(1);
+            }
+        }
+        if let Some(right) = page.header.rightmost_pointer {
+            if self.wanted(prev, hi) {
+                children.push((child_page(page, right)?, prev, hi));
+            }
+        }
+        trace!(page = page.page_id, children = children.len(), "descend");
+        let cells = page.header.cell_count as u64;
+        stats::add(&self.file.counters().cells_parsed, cells);
+        let ids: Vec<NonZeroU64> = children.iter().map(|c| c.0).collect();
+        self.fetched.extend(self.file.get_pages(&ids)?);
+        self.pending.extend(children.into_iter().rev());
+        Ok(())
+    }
+
+    /// The next leaf cell with a row we want, as the page and cell index.
+    fn next_cell(&mut self) -> Result<Option<(Arc<Page>, usize, i64)>> {
+        loop {
+            if let Some((page, i)) = &mut self.leaf {
+                if *i >= page.header.cell_count as usize {
+                    self.leaf = None;
+                    continue;
+                }
+                let cell = page.cell(*i)?;
+                *i += 1;
+                stats::add(&self.file.counters().cells_parsed, 1);
+                if let Cell::TableLeaf { rowid, .. } = cell {
+                    let rowid = rowid as i64;
+                    if rowid > self.range.1 {
+                        // rows come in order, so that's all of them
+                        self.leaf = None;
+                        self.pending.clear();
+                        return Ok(None);
+                    }
+                    if rowid < self.range.0
+                        || self.rowids.as_ref().is_some_and(|r| !r.contains(rowid))
+                    {
+                        continue;
+                    }
+                    return Ok(Some((Arc::clone(page), *i - 1, rowid)));
+                }
+                continue;
+            }
+            let Some((page_id, lo, hi)) = self.pending.pop() else {
+                return Ok(None);
+            };
+            self.file.check_interrupt()?;
+            let page = match self.fetched.remove(&page_id.get()) {
+                Some(page) => page,
+                None => self.file.get_page(page_id)?,
+            };
+            match page.header.kind {
+                PageKind::TableLeaf => self.leaf = Some((page, 0)),
+                PageKind::TableInterior => self.descend(&page, lo, hi)?,
+                kind => {
+                    let reason = format!("{:?} page in a table b-tree", kind);
+                    return Err(btree_error(page_id.get(), reason));
+                }
+            }
+        }
+    }
+
+    fn next_row(&mut self) -> Result<Option<Row>> {
+        let Some((page, i, rowid)) = self.next_cell()? else {
+            return Ok(None);
+        };
+        let cell = page.cell(i)?;
+        let payload = cell.get_payload().unwrap();
+        let values = self
+            .file
+            .read_columns(payload, self.columns.as_deref())
+            .map_err(in_cell(&page, i))?;
+        Ok(Some(Row { rowid, values }))
+    }
+
+    /// Call `f` on each row without copying it out of the page.
+    pub fn for_each_ref<F>(mut self, mut f: F) -> Result<()>
+    where
+        F: FnMut(RowRef<'_>) -> Result<()>,
+    {
+        while let Some((page, i, rowid)) = self.next_cell()? {
+            let cell = page.cell(i)?;
+            let payload = cell.get_payload().unwrap();
+            let columns = self.columns.as_deref();
+            let data = self
+                .file
+                .record_data(payload, columns)
+                .map_err(in_cell(&page, i))?;
+            let values = decode_columns(&data, columns).map_err(in_cell(&page, i))?;
+            f(RowRef { rowid, values })?;
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for TableScan<'_> {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_row() {
+            Ok(row) => row.map(Ok),
+            Err(e) => {
+                // don't keep going after running into something broken
+                self.pending.clear();
+                self.leaf = None;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// The biggest rowid in a table, or `None` if it's empty. Only the pages
+/// down the right edge of the tree get read.
+pub fn max_rowid(file: &SqliteFile, root: NonZeroU64) -> Result<Option<i64>> {
+    let mut page_id = root;
+    loop {
+        let page = file.get_page(page_id)?;
+        if let Some(right) = page.header.rightmost_pointer {
+            page_id = child_page(&page, right)?;
+            continue;
+        }
+        let Some(last) = (page.header.cell_count as usize).checked_sub(1) else {
+            return Ok(None);
+        };
+        return match page.cell(last)? {
+            Cell::TableLeaf { rowid, .. } => Ok(Some(rowid as i64)),
+            _ => Err(btree_error(page_id.get(), "index page in a table b-tree")),
+        };
+    }
+}
+
+/// The number of rows in a table: the cell counts in the headers of its leaf
+/// pages added up. No cells get parsed.
+pub fn count_rows(file: &SqliteFile, root: NonZeroU64) -> Result<u64> {
+    let leaves = leaf_pages(file, root)?;
+    let pages = file.get_pages(&leaves)?;
+    let mut count = 0;
+    for page_id in &leaves {
+        let page = &pages[&page_id.get()];
+        if page.header.kind != PageKind::TableLeaf {
+            let reason = format!("{:?} page in a table b-tree", page.header.kind);
+            return Err(btree_error(page_id.get(), reason));
+        }
+        count += page.header.cell_count as u64;
+    }
+    Ok(count)
+}
+
+/// Tables with fewer leaf pages than this are scanned on one thread.
+const PARALLEL_MIN_LEAVES: usize = 64;
+
+/// The leaf pages of a B-tree, in key order.
+///
+/// Only interior pages get read: the tree is balanced, so once one page of a
+/// level turns out to be a leaf, the whole level is leaves.
+pub fn leaf_pages(file: &SqliteFile, root: NonZeroU64) -> Result<Vec<NonZeroU64>> {
+    let mut level = vec![root];
+    loop {
+        if !file.get_page(level[0])?.header.kind.is_interior() {
+            return Ok(level);
+        }
+        trace!(pages = level.len(), "descend level");
+        let pages = file.get_pages(&level)?;
+        let mut next = vec![];
+        for page_id in &level {
+            let page = &pages[&page_id.get()];
+            if !page.header.kind.is_interior() {
+                return Err(btree_error(page_id.get(), "leaf page among interior pages"));
+            }
+            for child in page.children() {
+                next.push(child_page(page, child)?);
+            }
+        }
+        if next.is_empty() {
+            return Err(btree_error(
+                level[0].get(),
+                "interior page without children",
+            ));
+        }
+        level = next;
+    }
+}
+
+/// Scan a whole table on several threads, passing each row through `f`, which
+/// can filter it out by returning `None`. Results come back in rowid order.
+/// Like [`TableScan::columns`], `columns` limits which columns get decoded.
+///
+/// The leaf pages are split into one contiguous run per thread, so each thread
+/// reads its pages in order and the runs just need to be put back together.
+pub fn parallel_scan<T, F>(
+    file: &SqliteFile,
+    root: NonZeroU64,
+    columns: Option<&[usize]>,
+    f: F,
+) -> Result<Vec<T>>
+where
+    T: Send,
+    F: Fn(RowRef<'_>) -> Result<Option<T>> + Sync,
+{
+    let leaves = leaf_pages(file, root)?;
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    if threads == 1 || leaves.len() < PARALLEL_MIN_LEAVES {
+        let mut scan = TableScan::new(file, root);
+        scan.columns = columns.map(<[usize]>::to_vec);
+        let mut out = vec![];
+        scan.for_each_ref(|row| {
+            out.extend(f(row)?);
+            Ok(())
+        })?;
+        return Ok(out);
+    }
+
+    let per_thread = leaves.len().div_ceil(threads);
+    let results: Vec<Result<Vec<T>>> = std::thread::scope(|scope| {
+        let handles: Vec<_> = leaves
+            .chunks(per_thread)
+            .map(|chunk| {
+                let f = &f;
+                scope.spawn(move || {
+                    let mut out = vec![];
+                    for &page_id in chunk {
+                        file.check_interrupt()?;
+                        let page = file.get_page(page_id)?;
+                        if page.header.kind != PageKind::TableLeaf {
+                            let reason = format!("{:?} page in a table b-tree", page.header.kind);
+                            return Err(btree_error(page_id.get(), reason));
+                        }
+                        let cells = page.header.cell_count as u64;
+                        stats::add(&file.counters().cells_parsed, cells);
+                        for i in 0..page.header.cell_count as usize {
+                            if let Cell::TableLeaf { rowid, payload } = page.cell(i)? {
+                                let data = file
+                                    .record_data(&payload, columns)
+                                    .map_err(in_cell(&page, i))?;
+                                let values =
+                                    decode_columns(&data, columns).map_err(in_cell(&page, i))?;
+                                out.extend(f(RowRef {
+                                    rowid: rowid as i64,
+                                    values,
+                                })?);
+                            }
+                        }
+                    }
+                    Ok(out)
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| {
+                h.join()
+                    .unwrap_or_else(|_| Err(anyhow!("scan thread panicked")))
+            })
+            .collect()
+    });
+    let mut out = vec![];
+    for part in results {
+        out.extend(part?);
+    }
+    Ok(out)
+}
+
+fn child_page(parent: &Page, child: u32) -> Result<NonZeroU64> {
+    NonZeroU64::new(child as u64)
+        .ok_or_else(|| btree_error(parent.page_id, "child pointer to page 0"))
+}
+
+/// Compare the first `key.len()` columns of an index record with `key`, each
+/// with its collation from `collations`, or `BINARY` past the end of them,
+/// and the other way round for the columns that are `descending`.
+pub(crate) fn compare_prefix(
+    record: &[Value],
+    key: &[Value],
+    collations: &[Collation],
+    descending: &[bool],
+    functions: &Functions,
+) -> Ordering {
+    for (i, (a, b)) in record.iter().zip(key).enumerate() {
+        let collation = collations.get(i).cloned().unwrap_or_default();
+        match collation.compare(&a.as_value_ref(), &b.as_value_ref(), functions) {
+            Ordering::Equal => {}
+            ord if descending.get(i) == Some(&true) => return ord.reverse(),
+            ord => return ord,
+        }
+    }
+    Ordering::Equal
+}
+
+/// The rowid at the end of an index record.
+fn index_rowid(record: &[Value]) -> Result<i64> {
+    match record.last() {
+        Some(Value::Integer(n)) => Ok(*n),
+        other => bail!("index record should end with a rowid, found {:?}", other),
+    }
+}
+
+/// Find the rowids of all index entries whose leading columns equal `key`,
+/// compared with the index's `collations`, and in reverse for the columns
+/// that are `descending`.
+pub fn index_lookup(
+    file: &SqliteFile,
+    root: NonZeroU64,
+    key: &[Value],
+    collations: &[Collation],
+    descending: &[bool],
+) -> Result<RowidSet> {
+    trace_span!("index lookup", root = root.get());
+    let mut rowids = RowidSet::new();
+    let order = (collations, descending);
+    index_lookup_page(file, root, key, order, &mut rowids)?;
+    Ok(rowids)
+}
+
+fn index_lookup_page(
+    file: &SqliteFile,
+    page_id: NonZeroU64,
+    key: &[Value],
+    order: (&[Collation], &[bool]),
+    rowids: &mut RowidSet,
+) -> Result<()> {
+    file.check_interrupt()?;
+    let page = file.get_page(page_id)?;
+    for i in 0..page.header.cell_count as usize {
+        let cell = page.cell(i)?;
+        stats::add(&file.counters().cells_parsed, 1);
+        let payload = cell
+            .get_payload()
+            .ok_or_else(|| btree_error(page_id.get(), "table page in an index b-tree"))?;
+        let record = file.read_record(payload).map_err(in_cell(&page, i))?;
+        let ord = compare_prefix(&record, key, order.0, order.1, file.functions());
+        if let Cell::IndexInterior {
+            left_child_page, ..
+        } = cell
+        {
+            // entries before this one are in the left child
+            if ord != Ordering::Less {
+                trace!(page = page_id.get(), child = left_child_page, "descend");
+                let child = child_page(&page, left_child_page)?;
+                index_lookup_page(file, child, key, order, rowids)?;
+            }
+        }
+        match ord {
+            Ordering::Less => {}
+            Ordering::Equal => {
+                rowids.insert(index_rowid(&record).map_err(in_cell(&page, i))?);
+            }
+            // everything after this is bigger too
+            Ordering::Greater => return Ok(()),
+        }
+    }
+    if let Some(right) = page.header.rightmost_pointer {
+        trace!(page = page_id.get(), child = right, "descend");
+        index_lookup_page(file, child_page(&page, right)?, key, order, rowids)?;
+    }
+    Ok(())
+}
+
+/// The rowid of the first entry of an index, or the last one if `last`,
+/// whose first column isn't `NULL`. `NULL`s sort first, or last if the
+/// column is `descending`, and the parts of the b-tree with only `NULL`s in
+/// them are skipped.
+pub fn index_edge(
+    file: &SqliteFile,
+    root: NonZeroU64,
+    last: bool,
+    descending: bool,
+) -> Result<Option<i64>> {
+    trace_span!("index edge", root = root.get(), last);
+    index_edge_page(file, root, last, last == descending)
+}
+
+/// [`index_edge`] for the entries under one page, where `nulls_first` is
+/// whether the `NULL`s are before the others in the order they're gone
+/// through.
+fn index_edge_page(
+    file: &SqliteFile,
+    page_id: NonZeroU64,
+    last: bool,
+    nulls_first: bool,
+) -> Result<Option<i64>> {
+    file.check_interrupt()?;
+    let page = file.get_page(page_id)?;
+    let n = page.header.cell_count as usize;
+    let mut entries = vec![];
+    let mut children = vec![];
+    for i in 0..n {
+        let cell = page.cell(i)?;
+        stats::add(&file.counters().cells_parsed, 1);
+        let payload = cell
+            .get_payload()
+            .ok_or_else(|| btree_error(page_id.get(), "table page in an index b-tree"))?;
+        entries.push(file.read_record(payload).map_err(in_cell(&page, i))?);
+        if let Cell::IndexInterior {
+            left_child_page, ..
+        } = cell
+        {
+            children.push(left_child_page);
+        }
+    }
+    let is_null = |i: usize| entries[i].first().is_none_or(|v| *v == Value::Null);
+    let rowid = |i: usize| index_rowid(&entries[i]).map_err(in_cell(&page, i));
+    let Some(right) = page.header.rightmost_pointer else {
+        let mut order: Box<dyn Iterator<Item = usize>> = match last {
+            true => Box::new((0..n).rev()),
+            false => Box::new(0..n),
+        };
+        let found = match nulls_first {
+            true => order.find(|&i| !is_null(i)),
+            // with the NULLs last, the first entry is the one or there isn't one
+            false => order.next().filter(|&i| !is_null(i)),
+        };
+        return found.map(rowid).transpose();
+    };
+    children.push(right);
+    // child `j` comes before cell `j`
+    let order: Box<dyn Iterator<Item = usize>> = match last {
+        true => Box::new((0..=n).rev()),
+        false => Box::new(0..=n),
+    };
+    for j in order {
+        // the cell after the child, going this way
+        let after = match last {
+            true => j.checked_sub(1),
+            false => Some(j).filter(|&j| j < n),
+        };
+        let after_null = after.is_some_and(is_null);
+        // with the NULLs first, a child before a NULL only has NULLs in it
+        if !(nulls_first && after_null) {
+            let child = child_page(&page, children[j])?;
+            trace!(page = page_id.get(), child = children[j], "descend");
+            let found = index_edge_page(file, child, last, nulls_first)?;
+            // with the NULLs last, there's nothing past a NULL
+            if found.is_some() || !nulls_first {
+                return Ok(found);
+            }
+        }
+        if let Some(i) = after.filter(|_| !after_null) {
+            return rowid(i).map(Some);
+        }
+    }
+    Ok(None)
+}
+
+/// One end of a range of index entries: a value for the column after the
+/// equal ones, and whether entries equal to it are in the range.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bound {
+    pub value: Value,
+    pub inclusive: bool,
+}
+
+/// Find the rowids of all index entries whose leading columns equal `prefix`
+/// and whose next column is between `min` and `max`, compared with the
+/// index's `collations`, and in reverse for the columns that are
+/// `descending`. With no `min`, `NULL`s are left out, as nothing compares
+/// with them.
+pub fn index_range(
+    file: &SqliteFile,
+    root: NonZeroU64,
+    prefix: &[Value],
+    min: Option<&Bound>,
+    max: Option<&Bound>,
+    collations: &[Collation],
+    descending: &[bool],
+) -> Result<RowidSet> {
+    trace_span!("index range", root = root.get());
+    let after_nulls = Bound {
+        value: Value::Null,
+        inclusive: false,
+    };
+    let min = match (min, max) {
+        (None, Some(_)) => Some(&after_nulls),
+        (min, _) => min,
+    };
+    let range = IndexRange {
+        prefix,
+        min,
+        max,
+        collations,
+        descending,
+    };
+    let mut rowids = RowidSet::new();
+    index_range_page(file, root, &range, &mut rowids)?;
+    Ok(rowids)
+}
+
+struct IndexRange<'a> {
+    prefix: &'a [Value],
+    min: Option<&'a Bound>,
+    max: Option<&'a Bound>,
+    collations: &'a [Collation],
+    descending: &'a [bool],
+}
+
+impl IndexRange<'_> {
+    /// Where an index record is compared with the range: `Less` if it comes
+    /// before it in the index, `Greater` if it comes after it.
+    fn compare(&self, record: &[Value], functions: &Functions) -> Ordering {
+        let prefix = compare_prefix(
+            record,
+            self.prefix,
+            self.collations,
+            self.descending,
+            functions,
+        );
+        if prefix != Ordering::Equal {
+            return prefix;
+        }
+        let n = self.prefix.len();
+        let column = record.get(n).unwrap_or(&Value::Null);
+        let collation = self.collations.get(n).cloned().unwrap_or_default();
+        let compare = |bound: &Bound| {
+            let ord = collation.compare(
+                &column.as_value_ref(),
+                &bound.value.as_value_ref(),
+                functions,
+            );
+            (ord, bound.inclusive)
+        };
+        let below = matches!(
+            self.min.map(compare),
+            Some((Ordering::Less, _) | (Ordering::Equal, false))
+        );
+        let above = matches!(
+            self.max.map(compare),
+            Some((Ordering::Greater, _) | (Ordering::Equal, false))
+        );
+        let ord = match (below, above) {
+            (true, _) => Ordering::Less,
+            (_, true) => Ordering::Greater,
+            _ => Ordering::Equal,
+        };
+        // a descending column has its biggest values first
+        match self.descending.get(n) {
+            Some(true) => ord.reverse(),
+            _ => ord,
+        }
+    }
+}
+
+fn index_range_page(
+    file: &SqliteFile,
+    page_id: NonZeroU64,
+    range: &IndexRange,
+    rowids: &mut RowidSet,
+) -> Result<()> {
+    file.check_interrupt()?;
+    let page = file.get_page(page_id)?;
+    for i in 0..page.header.cell_count as usize {
+        let cell = page.cell(i)?;
+        stats::add(&file.counters().cells_parsed, 1);
+        let payload = cell
+            .get_payload()
+            .ok_or_else(|| btree_error(page_id.get(), "table page in an index b-tree"))?;
+        let record = file.read_record(payload).map_err(in_cell(&page, i))?;
+        let ord = range.compare(&record, file.functions());
+        if let Cell::IndexInterior {
+            left_child_page, ..
+        } = cell
+        {
+            // entries before this one are in the left child
+            if ord != Ordering::Less {
+                trace!(page = page_id.get(), child = left_child_page, "descend");
+                index_range_page(file, child_page(&page, left_child_page)?, range, rowids)?;
+            }
+        }
+        match ord {
+            Ordering::Less => {}
+            Ordering::Equal => {
+                rowids.insert(index_rowid(&record).map_err(in_cell(&page, i))?);
+            }
+            // everything after this is past the end of the range too
+            Ordering::Greater => return Ok(()),
+        }
+    }
+    if let Some(right) = page.header.rightmost_pointer {
+        trace!(page = page_id.get(), child = right, "descend");
+        index_range_page(file, child_page(&page, right)?, range, rowids)?;
+    }
+    Ok(())
+}
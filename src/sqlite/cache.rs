@@ -0,0 +1,211 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use super::Page;
+
+/// Default number of pages kept in the cache.
+pub const DEFAULT_CACHE_SIZE: usize = 2000;
+
+struct CacheEntry {
+    page: Arc<Page>,
+    last_used: u64,
+}
+
+/// Keeps recently read pages around so we don't hit the disk for them again.
+///
+/// Pages are evicted least recently used first, either when there are more than
+/// `capacity` pages or when the cache uses more than the soft heap limit.
+pub struct PageCache {
+    entries: HashMap<u64, CacheEntry>,
+    /// Page ids by when they were last used, oldest first.
+    order: BTreeMap<u64, u64>,
+    /// Heap bytes of the cached pages, kept up to date as they come and go.
+    bytes: usize,
+    tick: u64,
+    capacity: usize,
+    soft_heap_limit: Option<usize>,
+}
+
+impl Default for PageCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CACHE_SIZE)
+    }
+}
+
+impl PageCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            bytes: 0,
+            tick: 0,
+            capacity,
+            soft_heap_limit: None,
+        }
+    }
+
+    /// Look up a page, marking it as recently used.
+    pub fn get(&mut self, page_id: u64) -> Option<Arc<Page>> {
+        self.tick += 1;
+        let entry = self.entries.get_mut(&page_id)?;
+        self.order.remove(&entry.last_used);
+        self.order.insert(self.tick, page_id);
+        entry.last_used = self.tick;
+        Some(Arc::clone(&entry.page))
+    }
+
+    /// Add a page to the cache, evicting old pages if it's over the limits.
+    pub fn insert(&mut self, page: Arc<Page>) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.tick += 1;
+        let page_id = page.page_id;
+        self.bytes += page.data.heap_size();
+        self.order.insert(self.tick, page_id);
+        let entry = CacheEntry {
+            page,
+            last_used: self.tick,
+        };
+        if let Some(old) = self.entries.insert(page_id, entry) {
+            self.order.remove(&old.last_used);
+            self.bytes -= old.page.data.heap_size();
+        }
+        self.enforce_limits();
+    }
+
+    /// Number of pages in the cache.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Bytes of heap memory held by the cache. Memory mapped pages don't count.
+    pub fn memory_used(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Change the maximum number of cached pages.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.enforce_limits();
+    }
+
+    pub fn soft_heap_limit(&self) -> Option<usize> {
+        self.soft_heap_limit
+    }
+
+    /// Limit the bytes of page data the cache holds on to. `None` means no limit.
+    pub fn set_soft_heap_limit(&mut self, limit: Option<usize>) {
+        self.soft_heap_limit = limit;
+        self.enforce_limits();
+    }
+
+    /// Evict least recently used pages until the cache uses at most `bytes`.
+    /// Returns the number of bytes freed.
+    pub fn shrink_to(&mut self, bytes: usize) -> usize {
+        let before = self.memory_used();
+        let mut used = before;
+        while used > bytes {
+            match self.evict_one() {
+                Some(freed) => used -= freed,
+                None => break,
+            }
+        }
+        if self.is_empty() {
+            // give the table's allocation back too
+            self.entries.shrink_to_fit();
+        }
+        before - used
+    }
+
+    /// Drop every cached page.
+    pub fn clear(&mut self) {
+        self.entries = HashMap::new();
+        self.order = BTreeMap::new();
+        self.bytes = 0;
+    }
+
+    fn evict_one(&mut self) -> Option<usize> {
+        let (_, oldest) = self.order.pop_first()?;
+        let freed = self.entries.remove(&oldest)?.page.data.heap_size();
+        self.bytes -= freed;
+        Some(freed)
+    }
+
+    fn enforce_limits(&mut self) {
+        while self.entries.len() > self.capacity {
+            self.evict_one();
+        }
+        if let Some(limit) = self.soft_heap_limit {
+            self.shrink_to(limit);
+        }
+    }
+}
+
+#[cfg(test)]
+fn test_page(page_id: u64) -> Arc<Page> {
+    use super::{BtreeHeader, PageData, PageKind};
+    Arc::new(Page {
+        page_id,
+        data: PageData::Owned(vec![0u8; 1024]),
+        header: BtreeHeader {
+            kind: PageKind::TableLeaf,
+            first_freeblock: 0,
+            cell_count: 0,
+            cell_contents: 0,
+            fragmented_free_bytes: 0,
+            rightmost_pointer: None,
+        },
+        usable_size: 1024,
+    })
+}
+
+#[test]
+fn cache_evicts_least_recently_used() {
+    let mut cache = PageCache::new(2);
+    cache.insert(test_page(1));
+    cache.insert(test_page(2));
+    assert!(cache.get(1).is_some());
+    cache.insert(test_page(3));
+    assert_eq!(cache.len(), 2);
+    assert!(cache.get(2).is_none());
+    assert!(cache.get(1).is_some());
+    assert!(cache.get(3).is_some());
+}
+
+#[test]
+fn cache_reinserted_pages_count_once() {
+    let mut cache = PageCache::new(3);
+    for id in [1, 2, 1, 3, 1, 4] {
+        cache.insert(test_page(id));
+    }
+    assert_eq!(cache.len(), 3);
+    assert_eq!(cache.memory_used(), 3 * 1024);
+    // 2 was used longest ago, then 3
+    assert!(cache.get(2).is_none());
+    assert_eq!(cache.shrink_to(1024), 2048);
+    assert!(cache.get(1).is_none());
+    assert!(cache.get(4).is_some());
+}
+
+#[test]
+fn cache_soft_heap_limit() {
+    let mut cache = PageCache::new(10);
+    for id in 1..=5 {
+        cache.insert(test_page(id));
+    }
+    assert_eq!(cache.memory_used(), 5 * 1024);
+    cache.set_soft_heap_limit(Some(2048));
+    assert_eq!(cache.len(), 2);
+    assert!(cache.get(5).is_some());
+    assert_eq!(cache.shrink_to(0), 2048);
+    assert_eq!(cache.len(), 0);
+}
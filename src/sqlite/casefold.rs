@@ -0,0 +1,76 @@
+//! Upper and lower case, for `upper()`, `lower()` and the `NOCASE` collation.
+//!
+//! SQLite only folds ASCII letters, so that's the default here too: Rust's
+//! `to_uppercase` and friends know all of Unicode, and would give different
+//! answers for text like `straße`.
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+/// Which letters change case. Set per database with
+/// [`OpenOptions::case_folding`][super::OpenOptions::case_folding].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CaseFolding {
+    /// Only `A`-`Z` and `a`-`z`, like SQLite without ICU.
+    #[default]
+    Ascii,
+    /// Every letter Unicode has a case mapping for, like SQLite's ICU extension.
+    #[cfg(feature = "unicode-case")]
+    Unicode,
+}
+
+impl CaseFolding {
+    pub fn upper(self, s: &str) -> Cow<'_, str> {
+        match self {
+            CaseFolding::Ascii if !s.bytes().any(|b| b.is_ascii_lowercase()) => Cow::Borrowed(s),
+            CaseFolding::Ascii => Cow::Owned(s.to_ascii_uppercase()),
+            #[cfg(feature = "unicode-case")]
+            CaseFolding::Unicode => Cow::Owned(s.to_uppercase()),
+        }
+    }
+
+    pub fn lower(self, s: &str) -> Cow<'_, str> {
+        match self {
+            CaseFolding::Ascii if !s.bytes().any(|b| b.is_ascii_uppercase()) => Cow::Borrowed(s),
+            CaseFolding::Ascii => Cow::Owned(s.to_ascii_lowercase()),
+            #[cfg(feature = "unicode-case")]
+            CaseFolding::Unicode => Cow::Owned(s.to_lowercase()),
+        }
+    }
+
+    /// Compare text ignoring case, the way the `NOCASE` collation does.
+    pub fn compare(self, a: &[u8], b: &[u8]) -> Ordering {
+        match self {
+            CaseFolding::Ascii => a
+                .iter()
+                .map(u8::to_ascii_lowercase)
+                .cmp(b.iter().map(u8::to_ascii_lowercase)),
+            #[cfg(feature = "unicode-case")]
+            CaseFolding::Unicode => {
+                let fold = |s: &[u8]| String::from_utf8_lossy(s).to_lowercase();
+                fold(a).cmp(&fold(b))
+            }
+        }
+    }
+}
+
+#[test]
+fn ascii_folding_leaves_other_letters_alone() {
+    let ascii = CaseFolding::Ascii;
+    assert_eq!(ascii.upper("straße é"), "STRAßE é");
+    assert_eq!(ascii.lower("ÉCOLE"), "École");
+    assert_eq!(ascii.compare(b"Apple", b"aPPLE"), Ordering::Equal);
+    assert_eq!(
+        ascii.compare("É".as_bytes(), "é".as_bytes()),
+        Ordering::Less
+    );
+    #[cfg(feature = "unicode-case")]
+    {
+        let unicode = CaseFolding::Unicode;
+        assert_eq!(unicode.upper("straße é"), "STRASSE É");
+        assert_eq!(
+            unicode.compare("É".as_bytes(), "é".as_bytes()),
+            Ordering::Equal
+        );
+    }
+}
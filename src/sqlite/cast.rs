@@ -0,0 +1,215 @@
+//! `CAST(x AS type)`, with SQLite's rules for turning one kind of value into
+//! another. See <https://www.sqlite.org/lang_expr.html#castexpr>.
+//!
+//! Affinity also decides how values are converted before they're compared:
+//! see <https://www.sqlite.org/datatype3.html#type_conversions_prior_to_comparison>.
+
+use super::arith::parse_number;
+use super::record::Value;
+
+/// The kind of value a column or a `CAST` prefers, worked out from a type
+/// name. See <https://www.sqlite.org/datatype3.html#determination_of_column_affinity>.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Affinity {
+    Text,
+    Numeric,
+    Integer,
+    Real,
+    /// Also called `NONE`: values are left as they are.
+    Blob,
+}
+
+impl Affinity {
+    /// The affinity for a declared type like `VARCHAR(10)` or `BIGINT`. The
+    /// rules only look for bits of the name, in this order, so `FLOATING
+    /// POINT` is an integer because of the `INT`.
+    pub fn from_type_name(name: &str) -> Self {
+        let name = name.to_ascii_uppercase();
+        if name.contains("INT") {
+            Affinity::Integer
+        } else if ["CHAR", "CLOB", "TEXT"].iter().any(|s| name.contains(s)) {
+            Affinity::Text
+        } else if name.contains("BLOB") || name.trim().is_empty() {
+            Affinity::Blob
+        } else if ["REAL", "FLOA", "DOUB"].iter().any(|s| name.contains(s)) {
+            Affinity::Real
+        } else {
+            Affinity::Numeric
+        }
+    }
+
+    /// `INTEGER`, `REAL` or `NUMERIC`.
+    pub fn is_numeric(self) -> bool {
+        matches!(self, Affinity::Numeric | Affinity::Integer | Affinity::Real)
+    }
+}
+
+impl Value {
+    /// Convert the value like `CAST(value AS affinity)`. `NULL` stays `NULL`.
+    pub fn cast(&self, to: Affinity) -> Value {
+        match (to, self) {
+            (_, Value::Null) => Value::Null,
+            (Affinity::Blob, Value::Blob(_)) => self.clone(),
+            (Affinity::Blob, Value::String(s)) => Value::Blob(s.clone().into_bytes()),
+            (Affinity::Blob, v) => Value::Blob(v.cast(Affinity::Text).to_string().into_bytes()),
+            (Affinity::Text, Value::Float(x)) => Value::String(real_to_text(*x)),
+            (Affinity::Text, Value::Blob(b)) => {
+                Value::String(String::from_utf8_lossy(b).into_owned())
+            }
+            (Affinity::Text, v) => Value::String(v.to_string()),
+            (Affinity::Integer, Value::Integer(_)) => self.clone(),
+            // `as` truncates toward zero and saturates, which is what SQLite does
+            (Affinity::Integer, Value::Float(x)) => Value::Integer(*x as i64),
+            (Affinity::Integer, Value::String(s)) => Value::Integer(integer_prefix(s)),
+            (Affinity::Integer, Value::Blob(b)) => {
+                Value::Integer(integer_prefix(&String::from_utf8_lossy(b)))
+            }
+            (Affinity::Real, v) => match v.to_numeric() {
+                Value::Integer(n) => Value::Float(n as f64),
+                // adding zero turns -0.0 into 0.0, which SQLite doesn't have
+                Value::Float(x) => Value::Float(x + 0.0),
+                _ => Value::Float(0.0),
+            },
+            (Affinity::Numeric, Value::Integer(_) | Value::Float(_)) => self.clone(),
+            // text that reads as a whole number becomes an integer, even `1.0`
+            (Affinity::Numeric, v) => match v.to_numeric() {
+                Value::Float(x)
+                    if x.fract() == 0.0 && x >= -(2f64.powi(63)) && x < 2f64.powi(63) =>
+                {
+                    Value::Integer(x as i64)
+                }
+                n => n,
+            },
+        }
+    }
+}
+
+impl Value {
+    /// Convert the value the way a column with this affinity would store it.
+    /// Unlike [`Value::cast`], text only becomes a number if all of it reads
+    /// as one, and blobs are never converted.
+    pub fn apply_affinity(&self, affinity: Affinity) -> Value {
+        match (affinity, self) {
+            (Affinity::Blob, _) | (_, Value::Null | Value::Blob(_)) => self.clone(),
+            (Affinity::Text, Value::String(_)) => self.clone(),
+            (Affinity::Text, v) => v.cast(Affinity::Text),
+            (_, Value::String(s)) => match parse_number(s) {
+                Some(n) => n.apply_affinity(affinity),
+                None => self.clone(),
+            },
+            (Affinity::Real, Value::Integer(n)) => Value::Float(*n as f64),
+            (Affinity::Integer | Affinity::Numeric, Value::Float(x))
+                if x.fract() == 0.0 && *x >= -(2f64.powi(63)) && *x < 2f64.powi(63) =>
+            {
+                Value::Integer(*x as i64)
+            }
+            _ => self.clone(),
+        }
+    }
+}
+
+/// The integer at the start of `s`, ignoring anything after it, even a
+/// decimal point. Too big or too small gives the largest or smallest integer.
+fn integer_prefix(s: &str) -> i64 {
+    let s = s.trim_start();
+    let (negative, digits) = match s.as_bytes().first() {
+        Some(b'-') => (true, &s[1..]),
+        Some(b'+') => (false, &s[1..]),
+        _ => (false, s),
+    };
+    let mut n: i128 = 0;
+    for d in digits.bytes().take_while(u8::is_ascii_digit) {
+        // stop before it can overflow an i128; it's saturated below anyway
+        n = (n * 10 + (d - b'0') as i128).min(i64::MAX as i128 + 1);
+    }
+    let n = if negative { -n } else { n };
+    n.clamp(i64::MIN as i128, i64::MAX as i128) as i64
+}
+
+/// A real as text, the way SQLite writes it: 15 significant digits, always
+/// with a decimal point, and an exponent for very big or small numbers.
+pub fn real_to_text(x: f64) -> String {
+    if !x.is_finite() {
+        return match x {
+            x if x.is_nan() => "NaN".to_owned(),
+            x if x > 0.0 => "Inf".to_owned(),
+            _ => "-Inf".to_owned(),
+        };
+    }
+    if x == 0.0 {
+        return "0.0".to_owned();
+    }
+    // round to 15 significant digits first, then decide how to show it
+    let sci = format!("{:.14e}", x);
+    let (mantissa, exp) = sci.split_once('e').unwrap();
+    let exp: i32 = exp.parse().unwrap();
+    let with_point = |s: &str| {
+        let s = if s.contains('.') {
+            s.trim_end_matches('0')
+        } else {
+            s
+        };
+        match s.strip_suffix('.') {
+            Some(s) => format!("{}.0", s),
+            None if s.contains('.') => s.to_owned(),
+            None => format!("{}.0", s),
+        }
+    };
+    if !(-4..15).contains(&exp) {
+        let sign = if exp < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", with_point(mantissa), sign, exp.abs())
+    } else {
+        with_point(&format!("{:.*}", (14 - exp) as usize, x))
+    }
+}
+
+#[test]
+fn apply_affinities() {
+    use Value::*;
+    let text = |s: &str| String(s.to_owned());
+    assert_eq!(text(" 3 ").apply_affinity(Affinity::Integer), Integer(3));
+    assert_eq!(text("3.0").apply_affinity(Affinity::Numeric), Integer(3));
+    assert_eq!(text("3.5").apply_affinity(Affinity::Integer), Float(3.5));
+    assert_eq!(text("3").apply_affinity(Affinity::Real), Float(3.0));
+    // only text that's all number is converted
+    assert_eq!(text("3abc").apply_affinity(Affinity::Numeric), text("3abc"));
+    assert_eq!(text("").apply_affinity(Affinity::Numeric), text(""));
+    assert_eq!(Integer(3).apply_affinity(Affinity::Text), text("3"));
+    assert_eq!(Float(0.5).apply_affinity(Affinity::Text), text("0.5"));
+    assert_eq!(
+        Blob(b"3".to_vec()).apply_affinity(Affinity::Integer),
+        Blob(b"3".to_vec())
+    );
+    assert_eq!(text("3").apply_affinity(Affinity::Blob), text("3"));
+}
+
+#[test]
+fn cast_values() {
+    use Value::*;
+    let text = |s: &str| String(s.to_owned());
+    assert_eq!(text("12.7abc").cast(Affinity::Integer), Integer(12));
+    assert_eq!(text("1e3").cast(Affinity::Integer), Integer(1));
+    assert_eq!(
+        text("-9999999999999999999").cast(Affinity::Integer),
+        Integer(i64::MIN)
+    );
+    assert_eq!(Float(-3.9).cast(Affinity::Integer), Integer(-3));
+    assert_eq!(text("1.5e2x").cast(Affinity::Real), Float(150.0));
+    assert_eq!(text("1.0").cast(Affinity::Numeric), Integer(1));
+    assert_eq!(Float(1.0).cast(Affinity::Numeric), Float(1.0));
+    assert_eq!(Float(1.0).cast(Affinity::Text), text("1.0"));
+    assert_eq!(Float(1e20).cast(Affinity::Text), text("1.0e+20"));
+    assert_eq!(
+        Float(1.0 / 3.0).cast(Affinity::Text),
+        text("0.333333333333333")
+    );
+    assert_eq!(Float(1e-5).cast(Affinity::Text), text("1.0e-05"));
+    assert_eq!(Integer(5).cast(Affinity::Blob), Blob(b"5".to_vec()));
+    assert_eq!(Null.cast(Affinity::Text), Null);
+    assert_eq!(
+        Affinity::from_type_name("FLOATING POINT"),
+        Affinity::Integer
+    );
+    assert_eq!(Affinity::from_type_name("VARCHAR"), Affinity::Text);
+    assert_eq!(Affinity::from_type_name("STRING"), Affinity::Numeric);
+}
@@ -1,126 +1,171 @@
-use crate::record::{parse_payload, Value};
-use crate::varint::varint;
-use crate::BtreeHeader;
-use crate::PageKind;
-
-use nom::bytes::complete::take;
-use nom::number::complete::be_u32;
-use nom::sequence::tuple;
-use nom::IResult;
-
-/// Contains the payload part of the [Cell].
-pub struct Payload<'a> {
-    pub size: u64,
-    pub payload: &'a [u8],
-    pub overflow: Option<u32>,
-}
-
-impl<'a> Payload<'a> {
-    pub fn parse(&'a self) -> IResult<&'a [u8], Vec<Value>> {
-        parse_payload(self.payload)
-    }
-}
-
-impl<'a> std::fmt::Debug for Payload<'a> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Payload")
-            .field("size", &self.size)
-            .field("payload", &String::from_utf8_lossy(&self.payload))
-            .finish()
-    }
-}
-
-#[derive(Debug)]
-/// Represents a cell in a table or index.
-pub enum Cell<'a> {
-    /// Table Leaf cell
-    TableLeaf { rowid: u64, payload: Payload<'a> },
-    /// Table Interior cell
-    TableInterior { left_child_page: u32, rowid: u64 },
-    /// Index Leaf cell
-    IndexLeaf { payload: Payload<'a> },
-    /// Index Interior cell
-    IndexInterior {
-        left_child_page: u32,
-        payload: Payload<'a>,
-    },
-}
-
-impl<'a> Cell<'a> {
-    pub fn get_payload(&self) -> Option<&Payload<'a>> {
-        match self {
-            Cell::TableLeaf { ref payload, .. } => Some(payload),
-            Cell::TableInterior { .. } => None,
-            Cell::IndexLeaf { ref payload, .. } => Some(payload),
-            Cell::IndexInterior { ref payload, .. } => Some(payload),
-        }
-    }
-}
-
-impl<'a> TryFrom<Cell<'a>> for Vec<Value> {
-    type Error = anyhow::Error;
-
-    fn try_from(value: Cell<'a>) -> Result<Self, Self::Error> {
-        let pl = value
-            .get_payload()
-            .ok_or_else(|| anyhow::anyhow!("Table Interior cells have no payload"))?;
-        let (_, row) = pl
-            .parse()
-            .map_err(|e| anyhow::anyhow!("parse payload error: {}", e.to_string()))?;
-        Ok(row)
-    }
-}
-
-impl<'a> BtreeHeader {
-    /// Parse a cell based on the type of Btree.
-    pub fn parse_cell(&'a self, input: &'a [u8]) -> IResult<&[u8], Cell<'a>> {
-        match self.kind {
-            PageKind::TableLeaf => {
-                let (input, (size, rowid)) = tuple((varint, varint))(input)?;
-                let (input, payload) = take(size)(input)?;
-                let payload = Payload {
-                    size,
-                    payload,
-                    overflow: None,
-                };
-                Ok((input, Cell::TableLeaf { rowid, payload }))
-            }
-            PageKind::TableInterior => {
-                let (input, (left_child_page, rowid)) = tuple((be_u32, varint))(input)?;
-                Ok((
-                    input,
-                    Cell::TableInterior {
-                        left_child_page,
-                        rowid,
-                    },
-                ))
-            }
-            PageKind::IndexLeaf => {
-                let (input, size) = varint(input)?;
-                let (input, payload) = take(size)(input)?;
-                let payload = Payload {
-                    size,
-                    payload,
-                    overflow: None,
-                };
-                Ok((input, Cell::IndexLeaf { payload }))
-            }
-            PageKind::IndexInterior => {
-                let (input, (left_child_page, size)) = tuple((be_u32, varint))(input)?;
-                let (input, payload) = take(size)(input)?;
-                let payload = Payload {
-                    size,
-                    payload,
-                    overflow: None,
-                };
-                Ok((
-                    input,
-                    Cell::IndexInterior {
-                        left_child_page,
-                        payload,
-                    },
-                ))
-            }
-        }
-    }
-}
+use crate::record::{parse_payload, Value};
+use crate::varint::varint;
+use crate::BtreeHeader;
+use crate::PageKind;
+
+use nom::bytes::complete::take;
+use nom::number::complete::be_u32;
+use nom::sequence::tuple;
+use nom::IResult;
+
+/// Contains the payload part of the [Cell].
+pub struct Payload<'a> {
+    /// Total size of the payload, including the part on overflow pages.
+    pub size: u64,
+    /// The part of the payload stored on this page.
+    pub payload: &'a [u8],
+    /// First overflow page, if the payload didn't fit on this page.
+    pub overflow: Option<u32>,
+}
+
+impl<'a> Payload<'a> {
+    /// Number of payload bytes stored on overflow pages.
+    pub fn overflow_size(&self) -> u64 {
+        self.size - self.payload.len() as u64
+    }
+
+    pub fn parse(&'a self) -> IResult<&'a [u8], Vec<Value>> {
+        parse_payload(self.payload)
+    }
+}
+
+impl<'a> std::fmt::Debug for Payload<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Payload")
+            .field("size", &self.size)
+            .field("payload", &String::from_utf8_lossy(self.payload))
+            .field("overflow", &self.overflow)
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+/// Represents a cell in a table or index.
+pub enum Cell<'a> {
+    /// Table Leaf cell
+    TableLeaf { rowid: u64, payload: Payload<'a> },
+    /// Table Interior cell
+    TableInterior { left_child_page: u32, rowid: u64 },
+    /// Index Leaf cell
+    IndexLeaf { payload: Payload<'a> },
+    /// Index Interior cell
+    IndexInterior {
+        left_child_page: u32,
+        payload: Payload<'a>,
+    },
+}
+
+impl<'a> Cell<'a> {
+    pub fn get_payload(&self) -> Option<&Payload<'a>> {
+        match self {
+            Cell::TableLeaf { ref payload, .. } => Some(payload),
+            Cell::TableInterior { .. } => None,
+            Cell::IndexLeaf { ref payload, .. } => Some(payload),
+            Cell::IndexInterior { ref payload, .. } => Some(payload),
+        }
+    }
+}
+
+impl<'a> TryFrom<Cell<'a>> for Vec<Value> {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Cell<'a>) -> Result<Self, Self::Error> {
+        let pl = value
+            .get_payload()
+            .ok_or_else(|| anyhow::anyhow!("Table Interior cells have no payload"))?;
+        let (_, row) = pl
+            .parse()
+            .map_err(|e| anyhow::anyhow!("parse payload error: {}", e.to_string()))?;
+        Ok(row)
+    }
+}
+
+/// How many bytes of a payload are stored on the page itself. The rest spills
+/// onto overflow pages. See <https://www.sqlite.org/fileformat.html#cellformat>.
+pub fn local_payload_size(kind: PageKind, size: u64, usable_size: usize) -> usize {
+    let u = usable_size as u64;
+    let x = if kind == PageKind::TableLeaf {
+        u - 35
+    } else {
+        ((u - 12) * 64 / 255) - 23
+    };
+    if size <= x {
+        return size as usize;
+    }
+    let m = ((u - 12) * 32 / 255) - 23;
+    let k = m + ((size - m) % (u - 4));
+    if k <= x {
+        k as usize
+    } else {
+        m as usize
+    }
+}
+
+/// Parse a payload of `size` bytes, followed by an overflow page number if it doesn't fit.
+fn payload(
+    kind: PageKind,
+    size: u64,
+    usable_size: usize,
+) -> impl Fn(&[u8]) -> IResult<&[u8], Payload<'_>> {
+    move |input| {
+        let local = local_payload_size(kind, size, usable_size);
+        let (input, payload) = take(local)(input)?;
+        let (input, overflow) = if (local as u64) < size {
+            let (input, page) = be_u32(input)?;
+            (input, Some(page))
+        } else {
+            (input, None)
+        };
+        Ok((
+            input,
+            Payload {
+                size,
+                payload,
+                overflow,
+            },
+        ))
+    }
+}
+
+impl<'a> BtreeHeader {
+    /// Parse a cell based on the type of Btree.
+    pub fn parse_cell(
+        &'a self,
+        input: &'a [u8],
+        usable_size: usize,
+    ) -> IResult<&'a [u8], Cell<'a>> {
+        match self.kind {
+            PageKind::TableLeaf => {
+                let (input, (size, rowid)) = tuple((varint, varint))(input)?;
+                let (input, payload) = payload(self.kind, size, usable_size)(input)?;
+                Ok((input, Cell::TableLeaf { rowid, payload }))
+            }
+            PageKind::TableInterior => {
+                let (input, (left_child_page, rowid)) = tuple((be_u32, varint))(input)?;
+                Ok((
+                    input,
+                    Cell::TableInterior {
+                        left_child_page,
+                        rowid,
+                    },
+                ))
+            }
+            PageKind::IndexLeaf => {
+                let (input, size) = varint(input)?;
+                let (input, payload) = payload(self.kind, size, usable_size)(input)?;
+                Ok((input, Cell::IndexLeaf { payload }))
+            }
+            PageKind::IndexInterior => {
+                let (input, (left_child_page, size)) = tuple((be_u32, varint))(input)?;
+                let (input, payload) = payload(self.kind, size, usable_size)(input)?;
+                Ok((
+                    input,
+                    Cell::IndexInterior {
+                        left_child_page,
+                        payload,
+                    },
+                ))
+            }
+        }
+    }
+}
@@ -0,0 +1,209 @@
+//! Reading SQLCipher 4 databases. Every page but the first 16 bytes of the
+//! file is encrypted with AES-256 in CBC mode, and the last 80 bytes of each
+//! page, which the header reserves, hold its IV and an HMAC-SHA512 of it.
+//! Pages are decrypted as they're read, below the page cache, so nothing
+//! above [CipherFile] knows the file is encrypted.
+//!
+//! See <https://www.zetetic.net/sqlcipher/design/>. Only the SQLCipher 4
+//! defaults are supported, apart from the number of key derivation rounds.
+//! Needs the `sqlcipher` feature, which brings in the RustCrypto crates for
+//! AES, HMAC, SHA-512 and PBKDF2.
+
+use std::fmt;
+use std::io;
+
+use aes::cipher::block_padding::NoPadding;
+use aes::cipher::{BlockDecryptMut, KeyIvInit};
+use anyhow::{bail, Result};
+use hmac::{Hmac, Mac};
+use sha2::Sha512;
+
+use super::header::MAGIC;
+use super::vfs::VfsFile;
+
+/// How many rounds of PBKDF2 turn a passphrase into a key, by default.
+pub const DEFAULT_KDF_ITER: u32 = 256_000;
+
+/// The start of the file, which takes the place of the header's magic
+/// string, and is salt for the key.
+const SALT_SIZE: usize = 16;
+const IV_SIZE: usize = 16;
+const HMAC_SIZE: usize = 64;
+/// IV and HMAC, rounded up to a whole AES block.
+const RESERVE: usize = 80;
+/// Flipped into the salt to derive the HMAC key from the encryption key.
+const HMAC_SALT_MASK: u8 = 0x3a;
+
+type HmacSha512 = Hmac<Sha512>;
+type Aes256CbcDec = cbc::Decryptor<aes::Aes256>;
+
+/// A passphrase, kept out of `Debug` output.
+#[derive(Clone)]
+pub struct Passphrase(pub Vec<u8>);
+
+impl fmt::Debug for Passphrase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Passphrase(..)")
+    }
+}
+
+/// An encrypted database, read through another [VfsFile].
+pub struct CipherFile {
+    inner: Box<dyn VfsFile>,
+    page_size: usize,
+    key: [u8; 32],
+    /// Keyed, and cloned for each page.
+    hmac: HmacSha512,
+}
+
+impl CipherFile {
+    /// Derive the keys from `passphrase` and the file's salt. The page size
+    /// isn't stored anywhere readable, so it's whichever size page 1 checks
+    /// out with.
+    pub fn new(inner: Box<dyn VfsFile>, passphrase: &[u8], kdf_iter: u32) -> Result<Self> {
+        let mut salt = [0; SALT_SIZE];
+        inner.read_exact_at(&mut salt, 0)?;
+        if salt == *MAGIC {
+            bail!("database isn't encrypted");
+        }
+        let mut key = [0; 32];
+        pbkdf2::pbkdf2_hmac::<Sha512>(passphrase, &salt, kdf_iter, &mut key);
+        let hmac_salt = salt.map(|b| b ^ HMAC_SALT_MASK);
+        let mut hmac_key = [0; 32];
+        pbkdf2::pbkdf2_hmac::<Sha512>(&key, &hmac_salt, 2, &mut hmac_key);
+        let mut file = CipherFile {
+            inner,
+            page_size: 0,
+            key,
+            hmac: HmacSha512::new_from_slice(&hmac_key)?,
+        };
+        let len = file.inner.len()?;
+        for page_size in [4096, 1024, 2048, 8192, 16384, 32768, 65536, 512] {
+            if page_size as u64 > len {
+                continue;
+            }
+            let mut page = vec![0; page_size];
+            file.inner.read_exact_at(&mut page, 0)?;
+            file.page_size = page_size;
+            if file.decrypt_page(1, &page).is_some() {
+                return Ok(file);
+            }
+        }
+        bail!("wrong key, or not an SQLCipher 4 database")
+    }
+
+    /// The plain text of a page, or `None` if its HMAC doesn't match, because
+    /// the key is wrong or the page was changed. Pages of zeros are left alone,
+    /// like SQLCipher does, since they were never written.
+    fn decrypt_page(&self, page: u64, raw: &[u8]) -> Option<Vec<u8>> {
+        let start = if page == 1 { SALT_SIZE } else { 0 };
+        let end = self.page_size - RESERVE;
+        let mut mac = self.hmac.clone();
+        mac.update(&raw[start..end + IV_SIZE]);
+        mac.update(&(page as u32).to_le_bytes());
+        // compared in constant time
+        if mac
+            .verify_slice(&raw[end + IV_SIZE..end + IV_SIZE + HMAC_SIZE])
+            .is_err()
+        {
+            return raw.iter().all(|&b| b == 0).then(|| raw.to_vec());
+        }
+        let mut data = raw.to_vec();
+        let iv = &raw[end..end + IV_SIZE];
+        Aes256CbcDec::new(&self.key.into(), iv.into())
+            .decrypt_padded_mut::<NoPadding>(&mut data[start..end])
+            .ok()?;
+        if page == 1 {
+            data[..SALT_SIZE].copy_from_slice(MAGIC);
+        }
+        Some(data)
+    }
+}
+
+impl VfsFile for CipherFile {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        if buf.is_empty() {
+            return Ok(());
+        }
+        let page_size = self.page_size as u64;
+        let first = offset / page_size;
+        let last = (offset + buf.len() as u64 - 1) / page_size;
+        let mut raw = vec![0; ((last - first + 1) * page_size) as usize];
+        self.inner.read_exact_at(&mut raw, first * page_size)?;
+        let mut plain = Vec::with_capacity(raw.len());
+        for (i, page) in raw.chunks_exact(self.page_size).enumerate() {
+            let page_no = first + i as u64 + 1;
+            let data = self.decrypt_page(page_no, page).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("page {} doesn't match its HMAC", page_no),
+                )
+            })?;
+            plain.extend(data);
+        }
+        let start = (offset - first * page_size) as usize;
+        buf.copy_from_slice(&plain[start..start + buf.len()]);
+        Ok(())
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        self.inner.len()
+    }
+}
+
+#[test]
+fn encrypted_database() -> Result<()> {
+    use super::record::Value;
+    use super::vfs::MemoryFile;
+    use aes::cipher::BlockEncryptMut;
+
+    let plain = super::fixtures::Fixture::new()
+        .page_size(1024)
+        .reserved_bytes(RESERVE as u8)
+        .table(
+            "CREATE TABLE secrets (id INTEGER PRIMARY KEY, secret)",
+            (1..=50)
+                .map(|i| vec![i.into(), format!("secret {}", i).into()])
+                .collect(),
+        )
+        .build()?;
+
+    // encrypt it the way SQLCipher does
+    let salt: [u8; 16] = std::array::from_fn(|i| i as u8 * 7 + 1);
+    let mut key = [0; 32];
+    pbkdf2::pbkdf2_hmac::<Sha512>(b"hunter2", &salt, 10, &mut key);
+    let mut hmac_key = [0; 32];
+    let hmac_salt = salt.map(|b| b ^ HMAC_SALT_MASK);
+    pbkdf2::pbkdf2_hmac::<Sha512>(&key, &hmac_salt, 2, &mut hmac_key);
+    let mut data = plain.clone();
+    for (i, page) in data.chunks_exact_mut(1024).enumerate() {
+        let start = if i == 0 { SALT_SIZE } else { 0 };
+        let end = 1024 - RESERVE;
+        let iv: [u8; 16] = std::array::from_fn(|j| (i * 16 + j) as u8);
+        page[end..end + IV_SIZE].copy_from_slice(&iv);
+        cbc::Encryptor::<aes::Aes256>::new(&key.into(), &iv.into())
+            .encrypt_padded_mut::<NoPadding>(&mut page[start..end], end - start)
+            .unwrap();
+        let mut mac = HmacSha512::new_from_slice(&hmac_key)?;
+        mac.update(&page[start..end + IV_SIZE]);
+        mac.update(&(i as u32 + 1).to_le_bytes());
+        page[end + IV_SIZE..].copy_from_slice(&mac.finalize().into_bytes());
+    }
+    data[..SALT_SIZE].copy_from_slice(&salt);
+    assert!(!data.windows(6).any(|w| w == b"secret"));
+
+    let open = |passphrase: &[u8]| -> Result<super::SqliteFile> {
+        let file = CipherFile::new(Box::new(MemoryFile::new(data.clone())), passphrase, 10)?;
+        super::SqliteFile::from_vfs_file(Box::new(file))
+    };
+    let db = open(b"hunter2")?;
+    assert_eq!(db.page_size(), 1024);
+    assert_eq!(
+        db.prepare("SELECT secret FROM secrets WHERE id = 42")?
+            .rows()?,
+        [vec![Value::String("secret 42".into())]]
+    );
+    assert!(open(b"hunter3").is_err());
+    assert!(CipherFile::new(Box::new(MemoryFile::new(plain)), b"hunter2", 10).is_err());
+    Ok(())
+}
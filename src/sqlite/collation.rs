@@ -0,0 +1,119 @@
+//! Collations: how text is compared, for `=`, `<` and the like, and for the
+//! keys of indexes.
+//!
+//! A comparison uses the collation given with `COLLATE` on either side, the
+//! left one first, and otherwise the one a column on either side was declared
+//! with. Numbers, blobs and `NULL`s compare the same whatever the collation.
+//!
+//! Besides the built-in ones, collations can be added with
+//! [`SqliteFile::create_collation`][super::SqliteFile::create_collation].
+//! Any name parses, but comparing with one that hasn't been added is an error.
+
+use std::cmp::Ordering;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+
+use super::functions::Functions;
+use super::record::ValueRef;
+
+/// The body of a collation added by the user.
+pub type CollationFn = dyn Fn(&str, &str) -> Ordering + Send + Sync;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Collation {
+    /// Byte by byte.
+    #[default]
+    Binary,
+    /// Ignoring case, as far as [`CaseFolding`] goes.
+    NoCase,
+    /// Ignoring spaces at the end.
+    Rtrim,
+    /// One added with [`Functions::register_collation`], by its name in
+    /// lower case.
+    Custom(String),
+}
+
+impl Collation {
+    /// Compare two values, like [`ValueRef::compare`] but with text compared
+    /// this way. `NOCASE` folds case like `functions` does, and custom
+    /// collations are looked up there too. One that isn't there compares
+    /// like `BINARY`: [`Collation::check`] says so first.
+    pub fn compare(&self, a: &ValueRef, b: &ValueRef, functions: &Functions) -> Ordering {
+        let (ValueRef::String(a), ValueRef::String(b)) = (a, b) else {
+            return a.compare(b);
+        };
+        match self {
+            Collation::Binary => a.cmp(b),
+            Collation::NoCase => functions.case_folding().compare(a, b),
+            Collation::Rtrim => trim_end(a).cmp(trim_end(b)),
+            Collation::Custom(name) => match functions.collation(name) {
+                Some(f) => f(&String::from_utf8_lossy(a), &String::from_utf8_lossy(b)),
+                None => a.cmp(b),
+            },
+        }
+    }
+
+    /// Fail if it's a custom collation that `functions` doesn't have.
+    pub fn check(&self, functions: &Functions) -> Result<()> {
+        match self {
+            Collation::Custom(name) if functions.collation(name).is_none() => {
+                Err(anyhow!("no such collation sequence: {}", name))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn trim_end(s: &[u8]) -> &[u8] {
+    let end = s.iter().rposition(|&b| b != b' ').map_or(0, |i| i + 1);
+    &s[..end]
+}
+
+impl FromStr for Collation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s.to_ascii_uppercase().as_str() {
+            "BINARY" => Collation::Binary,
+            "NOCASE" => Collation::NoCase,
+            "RTRIM" => Collation::Rtrim,
+            _ => Collation::Custom(s.to_ascii_lowercase()),
+        })
+    }
+}
+
+impl Display for Collation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Collation::Binary => "BINARY",
+            Collation::NoCase => "NOCASE",
+            Collation::Rtrim => "RTRIM",
+            Collation::Custom(name) => name,
+        })
+    }
+}
+
+#[test]
+fn compare_with_collations() -> Result<()> {
+    let mut functions = Functions::default();
+    functions.register_collation("length", |a, b| a.len().cmp(&b.len()));
+    let cmp = |c: &str, a: &str, b: &str| -> Result<Ordering> {
+        let (a, b) = (a.as_bytes(), b.as_bytes());
+        let (a, b) = (ValueRef::String(a), ValueRef::String(b));
+        Ok(c.parse::<Collation>()?.compare(&a, &b, &functions))
+    };
+    assert!(cmp("nocase", "Apple", "aPPLE")?.is_eq());
+    assert!(cmp("BINARY", "Apple", "apple")?.is_lt());
+    assert!(cmp("rtrim", "a  ", "a")?.is_eq());
+    assert!(cmp("rtrim", " a", "a")?.is_lt());
+    assert!(cmp("LENGTH", "bb", "a")?.is_gt());
+    // only text is affected
+    let (n, text) = (ValueRef::Integer(1), ValueRef::String(b"1"));
+    assert!(Collation::NoCase.compare(&n, &text, &functions).is_lt());
+    let klingon: Collation = "klingon".parse()?;
+    assert!(klingon.check(&functions).is_err());
+    assert!(Collation::Custom("length".into()).check(&functions).is_ok());
+    Ok(())
+}
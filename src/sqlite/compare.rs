@@ -0,0 +1,235 @@
+//! Comparing the data in two files table by table, for checking that a
+//! pipeline wrote what it was supposed to. Rows are matched up by rowid, and
+//! both tables are scanned in rowid order together, so nothing is held in
+//! memory but the current row of each.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::num::NonZeroU64;
+
+use anyhow::{anyhow, Result};
+
+use super::btree::TableScan;
+use super::record::{Row, Value};
+use super::{CreateTable, Schema, SchemaType, SqliteFile};
+
+/// How a table differs between the two files.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Difference {
+    OnlyInFirst,
+    OnlyInSecond,
+    /// The tables don't have the same columns, so the rows weren't compared.
+    Columns {
+        first: Vec<String>,
+        second: Vec<String>,
+    },
+    Rows {
+        /// Rowids only in the second file.
+        added: usize,
+        /// Rowids only in the first file.
+        deleted: usize,
+        /// Rowids in both with different values.
+        changed: usize,
+        /// The smallest rowid that differs, to start looking from.
+        first_rowid: i64,
+    },
+}
+
+/// A table that isn't the same in both files.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableDiff {
+    pub table: String,
+    pub difference: Difference,
+}
+
+impl fmt::Display for TableDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ", self.table)?;
+        match &self.difference {
+            Difference::OnlyInFirst => write!(f, "only in the first file"),
+            Difference::OnlyInSecond => write!(f, "only in the second file"),
+            Difference::Columns { first, second } => write!(
+                f,
+                "columns differ: ({}) vs ({})",
+                first.join(", "),
+                second.join(", ")
+            ),
+            Difference::Rows {
+                added,
+                deleted,
+                changed,
+                first_rowid,
+            } => write!(
+                f,
+                "{} added, {} deleted, {} changed, first at rowid {}",
+                added, deleted, changed, first_rowid
+            ),
+        }
+    }
+}
+
+/// The tables whose data differs between `first` and `second`, in the order
+/// of the first file's schema and then the second's. `tables` limits it to
+/// those tables, and is all of them if it's empty, except SQLite's own
+/// `sqlite_` ones and virtual tables, which have no rows of their own.
+pub fn compare_data(
+    first: &SqliteFile,
+    second: &SqliteFile,
+    tables: &[String],
+) -> Result<Vec<TableDiff>> {
+    let schemas = [first.get_schema()?, second.get_schema()?];
+    let mut names: Vec<String> = vec![];
+    if tables.is_empty() {
+        for schema in &schemas {
+            for s in schema {
+                let internal = s.name.to_ascii_lowercase().starts_with("sqlite_");
+                if s.stype == SchemaType::Table
+                    && s.rootpage != 0
+                    && !internal
+                    && !names.iter().any(|n| n.eq_ignore_ascii_case(&s.name))
+                {
+                    names.push(s.name.clone());
+                }
+            }
+        }
+    } else {
+        names = tables.to_vec();
+    }
+    let mut diffs = vec![];
+    for name in names {
+        let find = |schema| Schema::find(schema, SchemaType::Table, &name);
+        let difference = match (find(&schemas[0]), find(&schemas[1])) {
+            (None, None) => return Err(anyhow!("no such table: {}", name)),
+            (Some(_), None) => Some(Difference::OnlyInFirst),
+            (None, Some(_)) => Some(Difference::OnlyInSecond),
+            (Some(a), Some(b)) => compare_table(first, a, second, b)?,
+        };
+        if let Some(difference) = difference {
+            diffs.push(TableDiff {
+                table: name,
+                difference,
+            });
+        }
+    }
+    Ok(diffs)
+}
+
+/// How one table differs, or `None` if it's the same.
+fn compare_table(
+    first: &SqliteFile,
+    a: &Schema,
+    second: &SqliteFile,
+    b: &Schema,
+) -> Result<Option<Difference>> {
+    let columns = |s: &Schema| -> Result<Vec<String>> { Ok(s.sql.parse::<CreateTable>()?.columns) };
+    let (first_columns, second_columns) = (columns(a)?, columns(b)?);
+    if !first_columns
+        .iter()
+        .map(|c| c.to_ascii_lowercase())
+        .eq(second_columns.iter().map(|c| c.to_ascii_lowercase()))
+    {
+        return Ok(Some(Difference::Columns {
+            first: first_columns,
+            second: second_columns,
+        }));
+    }
+    let root = |s: &Schema| {
+        NonZeroU64::new(s.rootpage).ok_or_else(|| anyhow!("table {} has no root page", s.name))
+    };
+    let mut rows_a = TableScan::new(first, root(a)?);
+    let mut rows_b = TableScan::new(second, root(b)?);
+    let (mut added, mut deleted, mut changed) = (0, 0, 0);
+    let mut first_rowid = None;
+    let mut next_a = rows_a.next().transpose()?;
+    let mut next_b = rows_b.next().transpose()?;
+    // rowids come in order, so the first difference found is the smallest
+    loop {
+        let order = match (&next_a, &next_b) {
+            (None, None) => break,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(x), Some(y)) => x.rowid.cmp(&y.rowid),
+        };
+        let rowid = match order {
+            Ordering::Less => {
+                let rowid = next_a.as_ref().map(|row| row.rowid);
+                next_a = rows_a.next().transpose()?;
+                deleted += 1;
+                rowid
+            }
+            Ordering::Greater => {
+                let rowid = next_b.as_ref().map(|row| row.rowid);
+                next_b = rows_b.next().transpose()?;
+                added += 1;
+                rowid
+            }
+            Ordering::Equal => {
+                let (Some(x), Some(y)) = (&next_a, &next_b) else {
+                    unreachable!()
+                };
+                let rowid = (!same_values(x, y, first_columns.len())).then_some(x.rowid);
+                changed += rowid.is_some() as usize;
+                next_a = rows_a.next().transpose()?;
+                next_b = rows_b.next().transpose()?;
+                rowid
+            }
+        };
+        if first_rowid.is_none() {
+            first_rowid = rowid;
+        }
+    }
+    Ok(first_rowid.map(|first_rowid| Difference::Rows {
+        added,
+        deleted,
+        changed,
+        first_rowid,
+    }))
+}
+
+/// Rows written before a column was added leave it off the end, so missing
+/// values count as `NULL`.
+fn same_values(a: &Row, b: &Row, columns: usize) -> bool {
+    let value = |row: &Row, i: usize| row.values.get(i).cloned().unwrap_or(Value::Null);
+    (0..columns.max(a.values.len()).max(b.values.len())).all(|i| value(a, i) == value(b, i))
+}
+
+#[test]
+fn compare_files() -> Result<()> {
+    use super::fixtures::Fixture;
+    let rows = |rows: &[(i64, &str)]| {
+        rows.iter()
+            .map(|&(id, name)| vec![id.into(), name.into()])
+            .collect::<Vec<_>>()
+    };
+    let table = "CREATE TABLE t (id INTEGER PRIMARY KEY, name)";
+    let first = Fixture::new()
+        .table(table, rows(&[(1, "a"), (2, "b"), (3, "c"), (4, "d")]))
+        .table("CREATE TABLE same (x)", vec![vec!["x".into()]])
+        .table("CREATE TABLE gone (x)", vec![])
+        .table("CREATE TABLE cols (x)", vec![])
+        .open()?;
+    let second = Fixture::new()
+        .table(
+            table,
+            rows(&[(1, "a"), (3, "C"), (4, "d"), (5, "e"), (6, "f")]),
+        )
+        .table("CREATE TABLE same (x)", vec![vec!["x".into()]])
+        .table("CREATE TABLE cols (x, y)", vec![])
+        .table("CREATE TABLE new (x)", vec![])
+        .open()?;
+    let diffs = compare_data(&first, &second, &[])?;
+    let lines: Vec<_> = diffs.iter().map(TableDiff::to_string).collect();
+    assert_eq!(
+        lines,
+        [
+            "t: 2 added, 1 deleted, 1 changed, first at rowid 2",
+            "gone: only in the first file",
+            "cols: columns differ: (x) vs (x, y)",
+            "new: only in the second file",
+        ]
+    );
+    assert!(compare_data(&first, &second, &["same".to_owned()])?.is_empty());
+    assert!(compare_data(&first, &first, &[])?.is_empty());
+    assert!(compare_data(&first, &second, &["nope".to_owned()]).is_err());
+    Ok(())
+}
@@ -0,0 +1,114 @@
+//! Tab completion for the shell: dot-commands, table names, and the columns
+//! of tables the statement mentions. It doesn't know about any particular
+//! line editor; give it the text before the cursor and it gives back what the
+//! last word could be.
+
+use anyhow::Result;
+
+use super::{CreateTable, SchemaType, SqliteFile};
+
+/// Ways to finish the last word of `line`, sorted and without repeats.
+/// `commands` are the dot-commands to offer when the line starts with `.`.
+pub fn complete(file: &SqliteFile, line: &str, commands: &[&str]) -> Result<Vec<String>> {
+    let word_start = line
+        .rfind(|c: char| c.is_whitespace() || "(),=<>!+-*/%|".contains(c))
+        .map_or(0, |i| i + 1);
+    let word = &line[word_start..];
+    if word_start == 0 && word.starts_with('.') {
+        return Ok(matching(commands.iter().map(|c| c.to_string()), word));
+    }
+
+    let schema = file.get_schema()?;
+    let tables: Vec<CreateTable> = schema
+        .iter()
+        .filter(|s| s.stype == SchemaType::Table)
+        .filter_map(|s| s.try_into().ok())
+        .collect();
+    // tables the statement names, with their aliases
+    let words: Vec<&str> = line[..word_start]
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|w| !w.is_empty())
+        .collect();
+    let mut mentioned: Vec<(&CreateTable, Option<&str>)> = vec![];
+    for (i, w) in words.iter().enumerate() {
+        let after_keyword = i > 0
+            && ["FROM", "JOIN"]
+                .iter()
+                .any(|kw| words[i - 1].eq_ignore_ascii_case(kw));
+        let Some(table) = tables.iter().find(|t| t.name.eq_ignore_ascii_case(w)) else {
+            continue;
+        };
+        if !after_keyword {
+            continue;
+        }
+        let alias = match words.get(i + 1) {
+            Some(kw) if kw.eq_ignore_ascii_case("AS") => words.get(i + 2).copied(),
+            Some(&a) if !is_keyword(a) => Some(a),
+            _ => None,
+        };
+        mentioned.push((table, alias));
+    }
+
+    // `table.col` or `alias.col`
+    if let Some((qualifier, column)) = word.split_once('.') {
+        let Some(table) = mentioned
+            .iter()
+            .find(|(t, alias)| alias.unwrap_or(&t.name).eq_ignore_ascii_case(qualifier))
+            .map(|(t, _)| *t)
+            .or_else(|| {
+                tables
+                    .iter()
+                    .find(|t| t.name.eq_ignore_ascii_case(qualifier))
+            })
+        else {
+            return Ok(vec![]);
+        };
+        let names = table.columns.iter().map(|c| format!("{}.{}", qualifier, c));
+        return Ok(matching(names, &format!("{}.{}", qualifier, column)));
+    }
+
+    let names = tables.iter().map(|t| t.name.clone()).chain(
+        mentioned
+            .iter()
+            .flat_map(|(t, _)| t.columns.iter().cloned()),
+    );
+    Ok(matching(names, word))
+}
+
+fn is_keyword(word: &str) -> bool {
+    ["WHERE", "JOIN", "INNER", "ON", "ORDER", "GROUP", "LIMIT"]
+        .iter()
+        .any(|kw| word.eq_ignore_ascii_case(kw))
+}
+
+/// The names that start with `prefix`, ignoring case.
+fn matching(names: impl Iterator<Item = String>, prefix: &str) -> Vec<String> {
+    let prefix = prefix.to_lowercase();
+    let mut found: Vec<String> = names
+        .filter(|n| n.to_lowercase().starts_with(&prefix))
+        .collect();
+    found.sort_unstable();
+    found.dedup();
+    found
+}
+
+#[test]
+fn completions() -> Result<()> {
+    let file = super::OpenOptions::new().open("sample.db")?;
+    let commands = [".tables", ".schema", ".stats"];
+    assert_eq!(complete(&file, ".st", &commands)?, [".stats"]);
+    assert_eq!(complete(&file, "SELECT * FROM ap", &commands)?, ["apples"]);
+    assert_eq!(
+        complete(&file, "SELECT na", &commands)?,
+        Vec::<String>::new()
+    );
+    assert_eq!(
+        complete(&file, "SELECT id FROM oranges WHERE de", &commands)?,
+        ["description"]
+    );
+    assert_eq!(
+        complete(&file, "SELECT id FROM apples a WHERE a.c", &commands)?,
+        ["a.color"]
+    );
+    Ok(())
+}
@@ -0,0 +1,340 @@
+//! Exact decimal arithmetic on numbers stored as text, like SQLite's `decimal`
+//! extension. Money and other numbers that don't fit in a double are usually
+//! kept in TEXT columns, and going through `f64` would round them.
+
+use std::cmp::Ordering;
+use std::fmt::Display;
+use std::num::IntErrorKind;
+use std::ops::{Add, Mul, Sub};
+use std::str::FromStr;
+
+use super::record::Value;
+
+/// How far an exponent can move the decimal point, either way. `1e999999999`
+/// would otherwise need a billion digits.
+pub const MAX_EXPONENT: u64 = 10_000;
+
+/// The error for a number with an exponent past [`MAX_EXPONENT`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("exponent is more than {MAX_EXPONENT} either way")]
+pub struct ExponentTooLarge;
+
+/// A decimal number of any size. Keeps the number of digits after the point it
+/// was written with, so `1.50` stays `1.50`.
+#[derive(Debug, Clone)]
+pub struct Decimal {
+    negative: bool,
+    /// Digits, least significant first.
+    digits: Vec<u8>,
+    /// How many of the digits are after the decimal point.
+    scale: usize,
+}
+
+impl Decimal {
+    /// Read a value as a decimal. Integers and reals go through their text
+    /// form. `NULL` isn't a number.
+    pub fn from_value(value: &Value) -> Result<Option<Self>, ExponentTooLarge> {
+        Ok(match value {
+            Value::Null => None,
+            Value::Integer(n) => Some(n.to_string().parse()?),
+            Value::Float(x) => Some(x.to_string().parse()?),
+            Value::String(s) => Some(s.parse()?),
+            Value::Blob(b) => Some(String::from_utf8_lossy(b).parse()?),
+        })
+    }
+
+    fn is_zero(&self) -> bool {
+        self.digits.iter().all(|&d| d == 0)
+    }
+
+    /// Give both numbers the same scale, padding with zeros.
+    fn align(mut a: Self, mut b: Self) -> (Self, Self) {
+        let scale = a.scale.max(b.scale);
+        for x in [&mut a, &mut b] {
+            let pad = scale - x.scale;
+            x.digits.splice(0..0, std::iter::repeat_n(0, pad));
+            x.scale = scale;
+        }
+        (a, b)
+    }
+
+    fn normalize(mut self) -> Self {
+        while self.digits.len() > self.scale && self.digits.last() == Some(&0) {
+            self.digits.pop();
+        }
+        if self.is_zero() {
+            self.negative = false;
+        }
+        self
+    }
+
+    fn plus(self, other: Self) -> Self {
+        let (a, b) = Self::align(self, other);
+        let scale = a.scale;
+        let (negative, digits) = if a.negative == b.negative {
+            (a.negative, add_digits(&a.digits, &b.digits))
+        } else {
+            match cmp_digits(&a.digits, &b.digits) {
+                Ordering::Less => (b.negative, sub_digits(&b.digits, &a.digits)),
+                _ => (a.negative, sub_digits(&a.digits, &b.digits)),
+            }
+        };
+        Decimal {
+            negative,
+            digits,
+            scale,
+        }
+        .normalize()
+    }
+
+    fn times(self, other: Self) -> Self {
+        let mut digits = vec![0u8; self.digits.len() + other.digits.len()];
+        for (i, &a) in self.digits.iter().enumerate() {
+            let mut carry = 0;
+            for (j, &b) in other.digits.iter().enumerate() {
+                let n = digits[i + j] as u32 + a as u32 * b as u32 + carry;
+                digits[i + j] = (n % 10) as u8;
+                carry = n / 10;
+            }
+            let mut k = i + other.digits.len();
+            while carry > 0 {
+                let n = digits[k] as u32 + carry;
+                digits[k] = (n % 10) as u8;
+                carry = n / 10;
+                k += 1;
+            }
+        }
+        let mut product = Decimal {
+            negative: self.negative != other.negative,
+            digits,
+            scale: self.scale + other.scale,
+        };
+        // drop the extra zeros the multiplication adds to the end
+        let min_scale = self.scale.min(other.scale);
+        while product.scale > min_scale && product.digits.first() == Some(&0) {
+            product.digits.remove(0);
+            product.scale -= 1;
+        }
+        product.normalize()
+    }
+}
+
+impl Add for Decimal {
+    type Output = Decimal;
+
+    fn add(self, rhs: Decimal) -> Decimal {
+        self.plus(rhs)
+    }
+}
+
+impl Sub for Decimal {
+    type Output = Decimal;
+
+    fn sub(self, mut rhs: Decimal) -> Decimal {
+        rhs.negative = !rhs.negative;
+        self.plus(rhs)
+    }
+}
+
+impl Mul for Decimal {
+    type Output = Decimal;
+
+    fn mul(self, rhs: Decimal) -> Decimal {
+        self.times(rhs)
+    }
+}
+
+fn add_digits(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(a.len().max(b.len()) + 1);
+    let mut carry = 0;
+    for i in 0..a.len().max(b.len()) {
+        let n = a.get(i).unwrap_or(&0) + b.get(i).unwrap_or(&0) + carry;
+        out.push(n % 10);
+        carry = n / 10;
+    }
+    if carry > 0 {
+        out.push(carry);
+    }
+    out
+}
+
+/// `a - b`, where `a >= b`.
+fn sub_digits(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(a.len());
+    let mut borrow = 0;
+    for (i, &x) in a.iter().enumerate() {
+        let y = b.get(i).unwrap_or(&0) + borrow;
+        if x >= y {
+            out.push(x - y);
+            borrow = 0;
+        } else {
+            out.push(x + 10 - y);
+            borrow = 1;
+        }
+    }
+    out
+}
+
+fn cmp_digits(a: &[u8], b: &[u8]) -> Ordering {
+    let len = a.len().max(b.len());
+    for i in (0..len).rev() {
+        match a.get(i).unwrap_or(&0).cmp(b.get(i).unwrap_or(&0)) {
+            Ordering::Equal => {}
+            ord => return ord,
+        }
+    }
+    Ordering::Equal
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Decimal {}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Compares by value, so `1.0` equals `1`.
+impl Ord for Decimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let (a, b) = Decimal::align(self.clone().normalize(), other.clone().normalize());
+        match (a.negative, b.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_digits(&a.digits, &b.digits),
+            (true, true) => cmp_digits(&b.digits, &a.digits),
+        }
+    }
+}
+
+/// Parses as much of the text as looks like a number, like SQLite does, so
+/// text that isn't a number at all is 0. This only fails when the exponent is
+/// too big to write the number out.
+impl FromStr for Decimal {
+    type Err = ExponentTooLarge;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().as_bytes();
+        let mut i = 0;
+        let mut negative = false;
+        if let Some(&c @ (b'+' | b'-')) = s.first() {
+            negative = c == b'-';
+            i += 1;
+        }
+        let mut digits = vec![];
+        let mut scale = 0;
+        let mut point = false;
+        while let Some(&c) = s.get(i) {
+            match c {
+                b'0'..=b'9' => {
+                    digits.push(c - b'0');
+                    if point {
+                        scale += 1;
+                    }
+                }
+                b'.' if !point => point = true,
+                _ => break,
+            }
+            i += 1;
+        }
+        if matches!(s.get(i), Some(b'e' | b'E')) {
+            let exp = match std::str::from_utf8(&s[i + 1..]).map(str::parse::<i64>) {
+                Ok(Ok(exp)) => exp,
+                Ok(Err(e))
+                    if matches!(
+                        e.kind(),
+                        IntErrorKind::PosOverflow | IntErrorKind::NegOverflow
+                    ) =>
+                {
+                    return Err(ExponentTooLarge)
+                }
+                _ => 0,
+            };
+            if exp.unsigned_abs() > MAX_EXPONENT {
+                return Err(ExponentTooLarge);
+            }
+            if exp >= 0 {
+                let exp = exp as usize;
+                let shift = exp.min(scale);
+                scale -= shift;
+                digits.extend(std::iter::repeat_n(0, exp - shift));
+            } else {
+                scale += exp.unsigned_abs() as usize;
+            }
+        }
+        digits.reverse();
+        // enough leading zeros for a digit before the point
+        while digits.len() <= scale {
+            digits.push(0);
+        }
+        Ok(Decimal {
+            negative,
+            digits,
+            scale,
+        }
+        .normalize())
+    }
+}
+
+impl Display for Decimal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.negative && !self.is_zero() {
+            write!(f, "-")?;
+        }
+        let int = &self.digits[self.scale..];
+        if int.is_empty() {
+            write!(f, "0")?;
+        }
+        for d in int.iter().rev() {
+            write!(f, "{}", d)?;
+        }
+        if self.scale > 0 {
+            write!(f, ".")?;
+            for d in self.digits[..self.scale].iter().rev() {
+                write!(f, "{}", d)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<Decimal> for Value {
+    fn from(value: Decimal) -> Self {
+        Value::String(value.to_string())
+    }
+}
+
+#[test]
+fn decimal_matches_sqlite() {
+    let d = |s: &str| s.parse::<Decimal>().unwrap();
+    assert_eq!(d("1.50").to_string(), "1.50");
+    assert_eq!(d("  -00012.3400e2").to_string(), "-1234.00");
+    assert_eq!(d("1e-3").to_string(), "0.001");
+    assert_eq!(d("abc").to_string(), "0");
+    assert_eq!(d("0.000").to_string(), "0.000");
+    assert_eq!((d("0.1") + d("0.2")).to_string(), "0.3");
+    assert_eq!((d("1") - d("1.000")).to_string(), "0.000");
+    assert_eq!(
+        (d("12345678901234567890") + d("1")).to_string(),
+        "12345678901234567891"
+    );
+    assert_eq!((d("1.25") * d("-0.4")).to_string(), "-0.5");
+    assert_eq!((d("1.50") * d("2.0")).to_string(), "3.0");
+    assert_eq!((d("1.5") * d("2")).to_string(), "3");
+    assert!(d("10.0") > d("9.99"));
+    assert_eq!(d("1.0"), d("1"));
+    assert_eq!(d("-0"), d("0"));
+    assert_eq!(d("1e10000").to_string().len(), 10_001);
+    assert_eq!("1e10001".parse::<Decimal>(), Err(ExponentTooLarge));
+    assert_eq!("1e-999999999999".parse::<Decimal>(), Err(ExponentTooLarge));
+    assert_eq!(
+        "1e99999999999999999999".parse::<Decimal>(),
+        Err(ExponentTooLarge)
+    );
+}
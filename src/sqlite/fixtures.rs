@@ -0,0 +1,590 @@
+//! Build small SQLite databases from tables and their rows, for tests that
+//! need a particular layout without running `sqlite3`.
+//!
+//! Pages are packed full and there are no overflow pages, freelist or
+//! automatic indexes, so each row has to fit on a page. Small pages make deep
+//! b-trees out of a few rows.
+//!
+//! ```no_run
+//! # use sqlite_starter_rust::{fixtures::Fixture, record::Value};
+//! let file = Fixture::new()
+//!     .page_size(512)
+//!     .table(
+//!         "CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT)",
+//!         vec![vec![Value::Integer(1), Value::String("one".into())]],
+//!     )
+//!     .index("CREATE INDEX t_name ON t (name)")
+//!     .open()?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use std::borrow::Cow;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+
+use super::btree::compare_prefix;
+use super::collation::Collation;
+use super::fts::CreateFts5;
+use super::functions::Functions;
+use super::header::DbHeader;
+use super::query;
+use super::record::{encode_record, Value, ValueRef};
+use super::varint::put_varint;
+use super::{CreateIndex, CreateTable, CreateView, SqliteFile};
+
+/// A database to build. Tables and indexes are given as their `CREATE`
+/// statements, which go into `sqlite_schema` as they are.
+#[derive(Clone, Debug)]
+pub struct Fixture {
+    page_size: u32,
+    reserved_bytes: u8,
+    tables: Vec<(String, TableRows)>,
+    indexes: Vec<String>,
+    views: Vec<String>,
+    virtual_tables: Vec<String>,
+    /// For sorting indexes, and given to the file when it's opened.
+    functions: Functions,
+}
+
+#[derive(Clone, Debug)]
+enum TableRows {
+    /// As given to [`Fixture::table`].
+    Values(Vec<Vec<Value>>),
+    /// Rowids and records in rowid order, from [`Fixture::bulk_table`].
+    Encoded(Vec<(i64, Vec<u8>)>),
+}
+
+impl Default for Fixture {
+    fn default() -> Self {
+        Fixture {
+            page_size: 4096,
+            reserved_bytes: 0,
+            tables: vec![],
+            indexes: vec![],
+            views: vec![],
+            virtual_tables: vec![],
+            functions: Functions::default(),
+        }
+    }
+}
+
+impl Fixture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bytes per page: a power of two from 512 to 65536. 4096 by default.
+    pub fn page_size(&mut self, page_size: u32) -> &mut Self {
+        self.page_size = page_size;
+        self
+    }
+
+    /// Bytes left unused at the end of every page, for extensions like
+    /// encryption to keep their own data in. None by default.
+    pub fn reserved_bytes(&mut self, reserved_bytes: u8) -> &mut Self {
+        self.reserved_bytes = reserved_bytes;
+        self
+    }
+
+    /// Add a table and its rows. An `INTEGER PRIMARY KEY` column gives each
+    /// row its rowid, otherwise rows are numbered from 1 in order. Rows can
+    /// leave off columns at the end, like ones written before those columns
+    /// were added with `ALTER TABLE`.
+    pub fn table(&mut self, sql: &str, rows: Vec<Vec<Value>>) -> &mut Self {
+        self.tables.push((sql.to_owned(), TableRows::Values(rows)));
+        self
+    }
+
+    /// Add a table whose rows already come in rowid order, like a dump of
+    /// another table. Each row is encoded as it comes instead of all of them
+    /// being kept, copied and sorted, so big tables are a lot quicker to
+    /// build. A row out of order is an error.
+    pub fn bulk_table(
+        &mut self,
+        sql: &str,
+        rows: impl IntoIterator<Item = Vec<Value>>,
+    ) -> Result<&mut Self> {
+        let def: CreateTable = sql.parse()?;
+        let mut encoded: Vec<(i64, Vec<u8>)> = vec![];
+        for (i, row) in rows.into_iter().enumerate() {
+            let (rowid, record) = encode_row(&def, i, row)?;
+            if let Some((last, _)) = encoded.last() {
+                if rowid <= *last {
+                    bail!(
+                        "rows of table {} aren't in rowid order: {} came after {}",
+                        def.name,
+                        rowid,
+                        last
+                    );
+                }
+            }
+            encoded.push((rowid, record));
+        }
+        self.tables
+            .push((sql.to_owned(), TableRows::Encoded(encoded)));
+        Ok(self)
+    }
+
+    /// Add an index on one of the tables. Entries are sorted by each column's
+    /// collation, with ASCII case folding for `NOCASE`.
+    pub fn index(&mut self, sql: &str) -> &mut Self {
+        self.indexes.push(sql.to_owned());
+        self
+    }
+
+    /// Add a view. Its `SELECT` isn't checked until it's used.
+    pub fn view(&mut self, sql: &str) -> &mut Self {
+        self.views.push(sql.to_owned());
+        self
+    }
+
+    /// Add an fts5 table. Only its `CREATE VIRTUAL TABLE` goes in the
+    /// schema, so its shadow tables have to be added with [`Fixture::table`].
+    pub fn virtual_table(&mut self, sql: &str) -> &mut Self {
+        self.virtual_tables.push(sql.to_owned());
+        self
+    }
+
+    /// Add a collation for indexes to be sorted with. [`Fixture::open`] adds
+    /// it to the file too.
+    pub fn collation<F>(&mut self, name: &str, f: F) -> &mut Self
+    where
+        F: Fn(&str, &str) -> std::cmp::Ordering + Send + Sync + 'static,
+    {
+        self.functions.register_collation(name, f);
+        self
+    }
+
+    /// The bytes of the database file.
+    pub fn build(&self) -> Result<Vec<u8>> {
+        if !(512..=65536).contains(&self.page_size) || !self.page_size.is_power_of_two() {
+            bail!("bad page size {}", self.page_size);
+        }
+        let mut pages = Pages {
+            page_size: self.page_size as usize,
+            usable_size: self.page_size as usize - self.reserved_bytes as usize,
+            // page 1 is filled in last, with the schema
+            pages: vec![vec![0; self.page_size as usize]],
+        };
+
+        let mut schema = vec![];
+        let mut tables = vec![];
+        for (sql, rows) in &self.tables {
+            let def: CreateTable = sql.parse()?;
+            let rows = match rows {
+                TableRows::Values(rows) => Cow::Owned(table_rows(&def, rows)?),
+                TableRows::Encoded(rows) => Cow::Borrowed(rows),
+            };
+            let entries = rows
+                .iter()
+                .map(|(rowid, record)| table_entry(*rowid, record))
+                .collect();
+            let root = pages.tree(Tree::Table, entries, false)?;
+            schema.push(("table", def.name.clone(), def.name.clone(), root, sql));
+            tables.push((def, rows));
+        }
+        for sql in &self.indexes {
+            let index: CreateIndex = sql.parse()?;
+            let Some((def, rows)) = tables
+                .iter()
+                .find(|(def, _)| def.name.eq_ignore_ascii_case(&index.table))
+            else {
+                bail!("no such table: {}", index.table);
+            };
+            let entries = index_entries(def, rows, &index, &self.functions)?;
+            let root = pages.tree(Tree::Index, entries, false)?;
+            schema.push(("index", index.name, def.name.clone(), root, sql));
+        }
+        for sql in &self.views {
+            let view: CreateView = sql.parse()?;
+            schema.push(("view", view.name.clone(), view.name, 0, sql));
+        }
+        for sql in &self.virtual_tables {
+            let fts: CreateFts5 = sql.parse()?;
+            schema.push(("table", fts.name.clone(), fts.name, 0, sql));
+        }
+
+        let entries = schema
+            .iter()
+            .enumerate()
+            .map(|(i, (kind, name, table, root, sql))| {
+                let record = encode_record(&[
+                    Value::String(kind.to_string()),
+                    Value::String(name.clone()),
+                    Value::String(table.clone()),
+                    Value::Integer((*root).into()),
+                    Value::String(sql.to_string()),
+                ]);
+                table_entry(i as i64 + 1, &record)
+            })
+            .collect();
+        pages.tree(Tree::Table, entries, true)?;
+
+        let page_count = pages.pages.len() as u32;
+        pages.pages[0][..100].copy_from_slice(DbHeader::new(self.page_size, page_count).raw());
+        pages.pages[0][20] = self.reserved_bytes;
+        Ok(pages.pages.concat())
+    }
+
+    /// Build the database and save it at `path`. Every page is built in
+    /// memory first, so the file is written front to back in one go.
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<()> {
+        Ok(std::fs::write(path, self.build()?)?)
+    }
+
+    /// Build the database and open it in memory, without a file.
+    pub fn open(&self) -> Result<SqliteFile> {
+        let mut file = SqliteFile::from_bytes(self.build()?)?;
+        *file.functions_mut() = self.functions.clone();
+        Ok(file)
+    }
+}
+
+/// The rowid and record of the `i`th row of a table, counting from 0.
+fn encode_row(def: &CreateTable, i: usize, mut row: Vec<Value>) -> Result<(i64, Vec<u8>)> {
+    if row.len() > def.columns.len() {
+        bail!(
+            "table {} has {} columns but a row has {} values",
+            def.name,
+            def.columns.len(),
+            row.len()
+        );
+    }
+    let key = def.key.as_deref().and_then(|key| def.column_index(key));
+    let rowid = match key {
+        // the rowid alias is stored as NULL, since it's the rowid
+        Some(k) if k >= row.len() => bail!("a row of table {} has no rowid", def.name),
+        Some(k) => match std::mem::replace(&mut row[k], Value::Null) {
+            Value::Integer(n) => n,
+            v => bail!("rowid of table {} must be an integer, not {}", def.name, v),
+        },
+        None => i as i64 + 1,
+    };
+    Ok((rowid, encode_record(&row)))
+}
+
+/// Each row's rowid and record, in rowid order.
+fn table_rows(def: &CreateTable, rows: &[Vec<Value>]) -> Result<Vec<(i64, Vec<u8>)>> {
+    let mut out = rows
+        .iter()
+        .enumerate()
+        .map(|(i, row)| encode_row(def, i, row.clone()))
+        .collect::<Result<Vec<_>>>()?;
+    out.sort_by_key(|(rowid, _)| *rowid);
+    if let Some(w) = out.windows(2).find(|w| w[0].0 == w[1].0) {
+        bail!("duplicate rowid {} in table {}", w[0].0, def.name);
+    }
+    Ok(out)
+}
+
+/// The index entries for a table's rows, in order.
+fn index_entries(
+    def: &CreateTable,
+    rows: &[(i64, Vec<u8>)],
+    index: &CreateIndex,
+    functions: &Functions,
+) -> Result<Vec<Entry>> {
+    let columns = index
+        .columns
+        .iter()
+        .map(|c| {
+            def.column_index(c)
+                .ok_or_else(|| anyhow!("no such column: {}.{}", def.name, c))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let collations: Vec<Collation> = columns
+        .iter()
+        .zip(&index.collations)
+        .map(|(&c, collation)| {
+            collation
+                .clone()
+                .unwrap_or_else(|| def.collations[c].clone())
+        })
+        .collect();
+    let key = def.key.as_deref().and_then(|key| def.column_index(key));
+    let filter = index.filter_expr()?;
+    let mut keys = vec![];
+    for (rowid, record) in rows {
+        let (_, values) =
+            super::record::parse_payload(record).map_err(|e| anyhow!("bad record: {:?}", e))?;
+        // a partial index only has the rows its WHERE is true for
+        if let Some(filter) = &filter {
+            let column = |name: &str| -> Result<ValueRef> {
+                let c = def
+                    .column_index(name)
+                    .ok_or_else(|| anyhow!("no such column: {}", name))?;
+                Ok(match key {
+                    Some(k) if k == c => ValueRef::Integer(*rowid),
+                    _ => values.get(c).map_or(ValueRef::Null, Value::as_value_ref),
+                })
+            };
+            if !query::is_true(filter, &column, functions)? {
+                continue;
+            }
+        }
+        let mut key_values: Vec<Value> = columns
+            .iter()
+            .map(|&c| match key {
+                Some(k) if k == c => Value::Integer(*rowid),
+                _ => values.get(c).cloned().unwrap_or(Value::Null),
+            })
+            .collect();
+        key_values.push(Value::Integer(*rowid));
+        keys.push(key_values);
+    }
+    // the rowid on the end sorts as BINARY
+    keys.sort_by(|a, b| compare_prefix(a, b, &collations, &index.descending, functions));
+    Ok(keys
+        .iter()
+        .map(|k| index_entry(&encode_record(k)))
+        .collect())
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Tree {
+    Table,
+    Index,
+}
+
+/// A cell for a leaf page, and what it puts in an interior cell when it's
+/// the last one on its page: the rowid for a table, the whole cell for an
+/// index.
+struct Entry {
+    cell: Vec<u8>,
+    divider: Vec<u8>,
+}
+
+fn table_entry(rowid: i64, record: &[u8]) -> Entry {
+    let mut cell = vec![];
+    put_varint(&mut cell, record.len() as u64);
+    put_varint(&mut cell, rowid as u64);
+    cell.extend_from_slice(record);
+    let mut divider = vec![];
+    put_varint(&mut divider, rowid as u64);
+    Entry { cell, divider }
+}
+
+fn index_entry(record: &[u8]) -> Entry {
+    let mut cell = vec![];
+    put_varint(&mut cell, record.len() as u64);
+    cell.extend_from_slice(record);
+    Entry {
+        divider: cell.clone(),
+        cell,
+    }
+}
+
+/// The pages built so far. Page 1 is at index 0.
+struct Pages {
+    page_size: usize,
+    /// The page size less the reserved bytes, which cells stay out of.
+    usable_size: usize,
+    pages: Vec<Vec<u8>>,
+}
+
+/// A page in a level of a b-tree being built, and the divider that goes
+/// above it in its parent. The last page of a level has none.
+struct Node {
+    page: u32,
+    divider: Option<Vec<u8>>,
+}
+
+impl Pages {
+    /// Build a b-tree out of entries in order and return its root page. The
+    /// schema's root has to be page 1, after the file header, so all its
+    /// pages leave room for that.
+    fn tree(&mut self, tree: Tree, mut entries: Vec<Entry>, page_one: bool) -> Result<u32> {
+        let offset = if page_one { 100 } else { 0 };
+        let room = self.usable_size - offset;
+        let max_payload = match tree {
+            Tree::Table => self.usable_size - 35,
+            Tree::Index => (self.usable_size - 12) * 64 / 255 - 23,
+        };
+        if let Some(e) = entries
+            .iter()
+            .find(|e| e.cell.len() > max_payload || e.cell.len() + 10 > room)
+        {
+            bail!(
+                "a {}-byte cell needs overflow pages, which aren't supported",
+                e.cell.len()
+            );
+        }
+
+        // fill leaves in order
+        let mut leaves: Vec<Vec<Entry>> = vec![vec![]];
+        let mut used = 8;
+        for entry in entries.drain(..) {
+            let size = entry.cell.len() + 2;
+            if used + size > room && used > 8 {
+                leaves.push(vec![]);
+                used = 8;
+            }
+            used += size;
+            leaves.last_mut().unwrap().push(entry);
+        }
+        let (leaf_kind, interior_kind) = match tree {
+            Tree::Table => (0x0d, 0x05),
+            Tree::Index => (0x0a, 0x02),
+        };
+        let count = leaves.len();
+        let mut level = vec![];
+        for (i, mut cells) in leaves.into_iter().enumerate() {
+            let last = i + 1 == count;
+            // an index's divider is moved up out of the leaf, a table's copied
+            let divider = match (last, tree) {
+                (true, _) => None,
+                (false, Tree::Table) => cells.last().map(|e| e.divider.clone()),
+                (false, Tree::Index) => cells.pop().map(|e| e.divider),
+            };
+            let cells: Vec<Vec<u8>> = cells.into_iter().map(|e| e.cell).collect();
+            let page = self.page(page_one && count == 1, leaf_kind, &cells, None);
+            level.push(Node { page, divider });
+        }
+
+        while level.len() > 1 {
+            // pages of consecutive nodes: all but the last become cells, and
+            // the last is the right pointer
+            let mut groups: Vec<Vec<Node>> = vec![];
+            let mut nodes = level.into_iter().peekable();
+            while let Some(first) = nodes.next() {
+                let mut group = vec![first];
+                let mut used = 12;
+                while nodes.peek().is_some() {
+                    let prev = group.last().unwrap().divider.as_ref().unwrap();
+                    if used + 4 + prev.len() + 2 > room {
+                        break;
+                    }
+                    used += 4 + prev.len() + 2;
+                    group.push(nodes.next().unwrap());
+                }
+                if group.len() == 1 && group[0].divider.is_some() {
+                    bail!("b-tree cells are too big for the page size");
+                }
+                groups.push(group);
+            }
+            // a page of just a right pointer isn't allowed, so take a node
+            // from the page before
+            if let [.., before, last] = &mut groups[..] {
+                if last.len() == 1 {
+                    last.insert(0, before.pop().unwrap());
+                }
+            }
+            let count = groups.len();
+            level = vec![];
+            for mut group in groups {
+                let right = group.pop().unwrap();
+                let cells: Vec<Vec<u8>> = group
+                    .into_iter()
+                    .map(|node| {
+                        let mut cell = node.page.to_be_bytes().to_vec();
+                        cell.extend(node.divider.unwrap());
+                        cell
+                    })
+                    .collect();
+                let page = self.page(
+                    page_one && count == 1,
+                    interior_kind,
+                    &cells,
+                    Some(right.page),
+                );
+                level.push(Node {
+                    page,
+                    divider: right.divider,
+                });
+            }
+        }
+        Ok(level[0].page)
+    }
+
+    /// Write a b-tree page, on page 1 or a new page, and return its number.
+    fn page(&mut self, page_one: bool, kind: u8, cells: &[Vec<u8>], right: Option<u32>) -> u32 {
+        let (number, offset) = if page_one {
+            (1, 100)
+        } else {
+            self.pages.push(vec![0; self.page_size]);
+            (self.pages.len() as u32, 0)
+        };
+        let data = &mut self.pages[number as usize - 1];
+        let mut end = self.usable_size;
+        let mut pointers = vec![];
+        for cell in cells {
+            end -= cell.len();
+            data[end..end + cell.len()].copy_from_slice(cell);
+            pointers.push(end as u16);
+        }
+        data[offset] = kind;
+        data[offset + 3..offset + 5].copy_from_slice(&(cells.len() as u16).to_be_bytes());
+        // 65536 doesn't fit, and is written as 0
+        data[offset + 5..offset + 7].copy_from_slice(&(end as u16).to_be_bytes());
+        let mut at = offset + 8;
+        if let Some(right) = right {
+            data[at..at + 4].copy_from_slice(&right.to_be_bytes());
+            at += 4;
+        }
+        for p in pointers {
+            data[at..at + 2].copy_from_slice(&p.to_be_bytes());
+            at += 2;
+        }
+        number
+    }
+}
+
+#[test]
+fn build_deep_btrees() -> Result<()> {
+    use super::integrity;
+
+    let rows: Vec<Vec<Value>> = (1..=2000)
+        .map(|i| {
+            vec![
+                Value::Integer(i * 3),
+                Value::String(format!("name {}", i % 97)),
+                Value::Float(i as f64 / 2.0),
+            ]
+        })
+        .collect();
+    let file = Fixture::new()
+        .page_size(512)
+        .table(
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT, x REAL)",
+            rows,
+        )
+        .table("CREATE TABLE empty (a)", vec![])
+        .index("CREATE INDEX t_name ON t (name)")
+        .open()?;
+    assert!(integrity::check(&file, &integrity::CheckOptions::new())?.is_empty());
+
+    let value =
+        |sql: &str| -> Result<Value> { Ok(query::execute(&file, &sql.parse()?)?[0][0].clone()) };
+    assert_eq!(value("SELECT COUNT(*) FROM t")?, Value::Integer(2000));
+    assert_eq!(value("SELECT COUNT(*) FROM empty")?, Value::Integer(0));
+    assert_eq!(
+        value("SELECT name FROM t WHERE id = 3000")?,
+        Value::String("name 30".into())
+    );
+    assert_eq!(
+        value("SELECT COUNT(*) FROM t WHERE name = 'name 30'")?,
+        Value::Integer(21)
+    );
+    Ok(())
+}
+
+#[test]
+fn bulk_load_sorted_rows() -> Result<()> {
+    let sql = "CREATE TABLE t (id INTEGER PRIMARY KEY, n)";
+    let rows = || (1..=3000).map(|i| vec![Value::Integer(i * 2), Value::Integer(i % 7)]);
+    let mut bulk = Fixture::new();
+    bulk.page_size(512)
+        .bulk_table(sql, rows())?
+        .index("CREATE INDEX t_n ON t (n)");
+    let mut plain = Fixture::new();
+    plain
+        .page_size(512)
+        .table(sql, rows().collect())
+        .index("CREATE INDEX t_n ON t (n)");
+    assert_eq!(bulk.build()?, plain.build()?);
+
+    let backwards = rows().collect::<Vec<_>>().into_iter().rev();
+    assert!(Fixture::new().bulk_table(sql, backwards).is_err());
+    Ok(())
+}
@@ -0,0 +1,204 @@
+//! fts5 full-text tables.
+//!
+//! They're virtual tables, so they have no b-tree of their own. SQLite keeps
+//! the documents in a `NAME_content` shadow table, or in another table named
+//! by `content=`, and the full-text index in `NAME_data` and `NAME_idx`. The
+//! documents are read from wherever they're kept, so an fts5 table can be
+//! queried like any other table. The index isn't read: [`search`] finds
+//! words by reading every row instead.
+//!
+//! [`search`]: super::search
+
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Error, Result};
+use regex::RegexBuilder;
+
+use super::{leading_name, sql, unquote, Select, NAME};
+
+/// Where an fts5 table's documents are kept.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Content {
+    /// In the `NAME_content` shadow table, with the columns as `c0`, `c1`
+    /// and so on.
+    Shadow,
+    /// In another table, `content='table'`, with the same column names and
+    /// `content_rowid` as the rowid.
+    External { table: String, rowid: String },
+    /// Nowhere, `content=''`. Only the rowids are known, from the
+    /// `NAME_docsize` shadow table, and the columns are all `NULL`.
+    Contentless,
+}
+
+/// Compiled `CREATE VIRTUAL TABLE ... USING fts5(...)` statement
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateFts5 {
+    pub name: String,
+    pub columns: Vec<String>,
+    pub content: Content,
+    /// `columnsize=0`: there's no `NAME_docsize` table.
+    pub no_docsize: bool,
+}
+
+impl FromStr for CreateFts5 {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = &sql::strip_comments(s);
+        let rx = RegexBuilder::new(&format!(
+            r"^\s*create\s+virtual\s+table\s+(?:if\s+not\s+exists\s+)?(?P<name>{NAME})\s+using\s+(?P<module>{NAME})\s*(?:\((?P<args>.*)\))?\s*;?\s*$",
+        ))
+        .case_insensitive(true)
+        .dot_matches_new_line(true)
+        .build()?;
+        let caps = rx
+            .captures(s)
+            .ok_or_else(|| anyhow!("failed to parse CREATE VIRTUAL TABLE"))?;
+        let name = unquote(&caps["name"]);
+        let module = unquote(&caps["module"]);
+        if !module.eq_ignore_ascii_case("fts5") {
+            bail!(
+                "virtual table {} uses module {}, and only fts5 tables can be read",
+                name,
+                module
+            );
+        }
+        let mut columns = vec![];
+        let mut content = Content::Shadow;
+        let mut content_rowid = "rowid".to_owned();
+        let mut no_docsize = false;
+        for arg in split_args(caps.name("args").map_or("", |m| m.as_str())) {
+            let Some((option, value)) = arg.split_once('=') else {
+                // a column, maybe followed by UNINDEXED
+                let (column, _) =
+                    leading_name(arg).ok_or_else(|| anyhow!("bad fts5 argument: {}", arg))?;
+                columns.push(column);
+                continue;
+            };
+            let value = unquote_value(value.trim());
+            match option.trim().to_ascii_lowercase().as_str() {
+                "content" if value.is_empty() => content = Content::Contentless,
+                "content" => {
+                    content = Content::External {
+                        table: value,
+                        rowid: String::new(),
+                    }
+                }
+                "content_rowid" => content_rowid = value,
+                "columnsize" => no_docsize = value == "0",
+                // how it's tokenized and indexed doesn't change the documents
+                _ => {}
+            }
+        }
+        if let Content::External { rowid, .. } = &mut content {
+            *rowid = content_rowid;
+        }
+        if columns.is_empty() {
+            bail!("fts5 table {} has no columns", name);
+        }
+        Ok(CreateFts5 {
+            name,
+            columns,
+            content,
+            no_docsize,
+        })
+    }
+}
+
+impl CreateFts5 {
+    /// A query for each document's rowid followed by its columns.
+    pub fn documents(&self) -> Result<Select> {
+        let quote = |name: &str| format!("\"{}\"", name.replace('"', "\"\""));
+        let (rowid, columns, table) = match &self.content {
+            Content::Shadow => (
+                "id".to_owned(),
+                (0..self.columns.len()).map(|i| format!("c{}", i)).collect(),
+                format!("{}_content", self.name),
+            ),
+            Content::External { table, rowid } => (
+                quote(rowid),
+                self.columns.iter().map(|c| quote(c)).collect(),
+                table.clone(),
+            ),
+            Content::Contentless if self.no_docsize => bail!(
+                "fts5 table {} is contentless and has columnsize=0, so there's nothing to read",
+                self.name
+            ),
+            Content::Contentless => (
+                "id".to_owned(),
+                vec!["NULL".to_owned(); self.columns.len()],
+                format!("{}_docsize", self.name),
+            ),
+        };
+        let sql = format!(
+            "SELECT {}, {} FROM {}",
+            rowid,
+            columns.join(", "),
+            quote(&table)
+        );
+        sql.parse()
+    }
+}
+
+/// The arguments in the brackets after `fts5`, split at the commas that
+/// aren't quoted.
+fn split_args(args: &str) -> Vec<&str> {
+    let mut out = vec![];
+    let mut quote = None;
+    let mut start = 0;
+    for (i, c) in args.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"' | '`') => quote = Some(c),
+            (None, '[') => quote = Some(']'),
+            (None, ',') => {
+                out.push(args[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    out.push(args[start..].trim());
+    out.retain(|arg| !arg.is_empty());
+    out
+}
+
+/// An option's value without its quotes, which can be `'...'` as well as a
+/// name's quotes.
+fn unquote_value(value: &str) -> String {
+    match value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')) {
+        Some(inner) => inner.replace("''", "'"),
+        None => unquote(value),
+    }
+}
+
+#[test]
+fn parse_fts5() -> Result<()> {
+    let fts: CreateFts5 =
+        "CREATE VIRTUAL TABLE docs USING fts5(title, \"body text\" UNINDEXED, tokenize = 'porter unicode61')"
+            .parse()?;
+    assert_eq!(fts.name, "docs");
+    assert_eq!(fts.columns, ["title", "body text"]);
+    assert_eq!(fts.content, Content::Shadow);
+
+    let fts: CreateFts5 =
+        "create virtual table f using FTS5(a, content='notes', content_rowid='id')".parse()?;
+    assert_eq!(
+        fts.content,
+        Content::External {
+            table: "notes".to_owned(),
+            rowid: "id".to_owned()
+        }
+    );
+    let fts: CreateFts5 = "CREATE VIRTUAL TABLE f USING fts5(a, content='')".parse()?;
+    assert_eq!(fts.content, Content::Contentless);
+
+    assert!("CREATE VIRTUAL TABLE r USING rtree(id, x0, x1)"
+        .parse::<CreateFts5>()
+        .is_err());
+    assert!("CREATE VIRTUAL TABLE f USING fts5()"
+        .parse::<CreateFts5>()
+        .is_err());
+    Ok(())
+}
@@ -0,0 +1,457 @@
+//! Scalar SQL functions, like `length()` and `upper()`.
+//!
+//! The built-in ones follow SQLite's rules for `NULL`s and for converting
+//! between text and numbers. `json_extract()`, `json_type()`,
+//! `json_array_length()` and `json_valid()` look inside JSON kept as text.
+//! More can be added with [`Functions::register`].
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Result};
+
+use super::casefold::CaseFolding;
+use super::collation::CollationFn;
+use super::json::Json;
+use super::record::{Value, ValueRef};
+use super::search;
+
+/// What a function knows about the database it's called on.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Context {
+    pub case_folding: CaseFolding,
+}
+
+/// The body of a scalar function.
+pub type ScalarFn = dyn Fn(&Context, &[ValueRef]) -> Result<Value> + Send + Sync;
+
+#[derive(Clone)]
+struct Function {
+    /// How many arguments it takes.
+    args: RangeInclusive<usize>,
+    f: Arc<ScalarFn>,
+}
+
+/// The functions SQL can call, by name, and the collations it can compare
+/// with besides the built-in ones.
+#[derive(Clone)]
+pub struct Functions {
+    context: Context,
+    functions: HashMap<String, Function>,
+    collations: HashMap<String, Arc<CollationFn>>,
+}
+
+impl Default for Functions {
+    fn default() -> Self {
+        Self::new(CaseFolding::default())
+    }
+}
+
+impl std::fmt::Debug for Functions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut names: Vec<&str> = self.functions.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        let mut collations: Vec<&str> = self.collations.keys().map(String::as_str).collect();
+        collations.sort_unstable();
+        f.debug_struct("Functions")
+            .field("context", &self.context)
+            .field("functions", &names)
+            .field("collations", &collations)
+            .finish()
+    }
+}
+
+impl Functions {
+    /// The built-in functions.
+    pub fn new(case_folding: CaseFolding) -> Self {
+        let mut functions = Functions {
+            context: Context { case_folding },
+            functions: HashMap::new(),
+            collations: HashMap::new(),
+        };
+        functions.register("length", 1..=1, length);
+        functions.register("upper", 1..=1, |cx, args| {
+            text_fn(args, |s| cx.case_folding.upper(s).into_owned())
+        });
+        functions.register("lower", 1..=1, |cx, args| {
+            text_fn(args, |s| cx.case_folding.lower(s).into_owned())
+        });
+        functions.register("substr", 2..=3, substr);
+        functions.register("substring", 2..=3, substr);
+        functions.register("trim", 1..=2, |_, args| trim(args, true, true));
+        functions.register("ltrim", 1..=2, |_, args| trim(args, true, false));
+        functions.register("rtrim", 1..=2, |_, args| trim(args, false, true));
+        functions.register("abs", 1..=1, abs);
+        functions.register("round", 1..=2, round);
+        functions.register("coalesce", 2..=usize::MAX, coalesce);
+        functions.register("ifnull", 2..=2, coalesce);
+        functions.register("typeof", 1..=1, |_, args| {
+            let name = match args[0] {
+                ValueRef::Null => "null",
+                ValueRef::Integer(_) => "integer",
+                ValueRef::Float(_) => "real",
+                ValueRef::String(_) => "text",
+                ValueRef::Blob(_) => "blob",
+            };
+            Ok(Value::String(name.to_owned()))
+        });
+        functions.register("hex", 1..=1, |_, args| {
+            Ok(Value::String(hex(&bytes(args[0]))))
+        });
+        functions.register("quote", 1..=1, |_, args| Ok(quote(args[0])));
+        functions.register("json_valid", 1..=1, |_, args| {
+            Ok(match args[0] {
+                ValueRef::Null => Value::Null,
+                v => Value::Integer(v.to_text().parse::<Json>().is_ok() as i64),
+            })
+        });
+        functions.register("json_extract", 2..=usize::MAX, json_extract);
+        functions.register("json_type", 1..=2, |_, args| {
+            json_at(args, |json| Value::String(json.type_name().to_owned()))
+        });
+        functions.register("json_array_length", 1..=2, |_, args| {
+            json_at(args, |json| match json {
+                Json::Array(items) => Value::Integer(items.len() as i64),
+                _ => Value::Integer(0),
+            })
+        });
+        functions.register("match_all", 2..=2, |_, args| match args {
+            [ValueRef::Null, _] | [_, ValueRef::Null] => Ok(Value::Null),
+            [text, terms] => Ok(Value::Integer(
+                search::match_all(&text.to_text(), &terms.to_text()) as i64,
+            )),
+            _ => unreachable!(),
+        });
+        functions
+    }
+
+    /// Add a function, or replace the one with the same name. Names are case
+    /// insensitive. `args` is how many arguments it takes, which is checked
+    /// before it's called.
+    pub fn register<F>(&mut self, name: &str, args: RangeInclusive<usize>, f: F)
+    where
+        F: Fn(&Context, &[ValueRef]) -> Result<Value> + Send + Sync + 'static,
+    {
+        let function = Function {
+            args,
+            f: Arc::new(f),
+        };
+        self.functions.insert(name.to_ascii_lowercase(), function);
+    }
+
+    /// Add a collation, or replace the one with the same name, for
+    /// `COLLATE name` in queries and schemas. Names are case insensitive, and
+    /// the built-in `BINARY`, `NOCASE` and `RTRIM` can't be replaced.
+    pub fn register_collation<F>(&mut self, name: &str, f: F)
+    where
+        F: Fn(&str, &str) -> Ordering + Send + Sync + 'static,
+    {
+        self.collations
+            .insert(name.to_ascii_lowercase(), Arc::new(f));
+    }
+
+    /// A collation added with [`Functions::register_collation`].
+    pub fn collation(&self, name: &str) -> Option<&CollationFn> {
+        self.collations
+            .get(&name.to_ascii_lowercase())
+            .map(Arc::as_ref)
+    }
+
+    /// How `upper()`, `lower()` and `NOCASE` change the case of letters.
+    pub fn case_folding(&self) -> CaseFolding {
+        self.context.case_folding
+    }
+
+    pub fn call(&self, name: &str, args: &[ValueRef]) -> Result<Value> {
+        let Some(function) = self.functions.get(&name.to_ascii_lowercase()) else {
+            bail!("no such function: {}", name);
+        };
+        if !function.args.contains(&args.len()) {
+            bail!("wrong number of arguments to function {}()", name);
+        }
+        (function.f)(&self.context, args)
+    }
+}
+
+/// A function of one text argument, which gives `NULL` for `NULL`.
+fn text_fn(args: &[ValueRef], f: impl Fn(&str) -> String) -> Result<Value> {
+    Ok(match args[0] {
+        ValueRef::Null => Value::Null,
+        v => Value::String(f(&v.to_text())),
+    })
+}
+
+/// The bytes of a value, as `hex()` and blob functions see it.
+fn bytes(v: ValueRef) -> Vec<u8> {
+    match v {
+        ValueRef::Blob(b) | ValueRef::String(b) => b.to_vec(),
+        v => v.to_text().into_owned().into_bytes(),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        write!(s, "{:02X}", b).unwrap();
+    }
+    s
+}
+
+/// The value as an integer, for arguments like the start of `substr()`.
+fn to_integer(v: ValueRef) -> i64 {
+    match v.to_value().to_numeric() {
+        Value::Integer(n) => n,
+        Value::Float(x) => x as i64,
+        _ => 0,
+    }
+}
+
+/// Characters in text, bytes in a blob.
+fn length(_: &Context, args: &[ValueRef]) -> Result<Value> {
+    Ok(match args[0] {
+        ValueRef::Null => Value::Null,
+        ValueRef::Blob(b) => Value::Integer(b.len() as i64),
+        v => {
+            let text = v.to_text();
+            // like SQLite, text ends at the first NUL
+            let text = text.split('\0').next().unwrap_or("");
+            Value::Integer(text.chars().count() as i64)
+        }
+    })
+}
+
+/// `substr(x, start, len)`, counting from 1. A negative start counts from the
+/// end, and a negative length takes the characters before the start.
+fn substr(_: &Context, args: &[ValueRef]) -> Result<Value> {
+    if args.contains(&ValueRef::Null) {
+        return Ok(Value::Null);
+    }
+    let blob = matches!(args[0], ValueRef::Blob(_));
+    let chars: Vec<char> = if blob {
+        vec![]
+    } else {
+        args[0].to_text().chars().collect()
+    };
+    let bytes = bytes(args[0]);
+    let len = if blob { bytes.len() } else { chars.len() } as i64;
+
+    // the same steps as SQLite's substrFunc
+    let mut start = to_integer(args[1]);
+    let (mut count, negative) = match args.get(2) {
+        Some(&v) => {
+            let n = to_integer(v);
+            (n.saturating_abs(), n < 0)
+        }
+        None => (i64::MAX, false),
+    };
+    if start < 0 {
+        start += len;
+        if start < 0 {
+            count = (count + start).max(0);
+            start = 0;
+        }
+    } else if start > 0 {
+        start -= 1;
+    } else if count > 0 {
+        count -= 1;
+    }
+    if negative {
+        start -= count;
+        if start < 0 {
+            count += start;
+            start = 0;
+        }
+    }
+    let from = start.min(len) as usize;
+    let to = start.saturating_add(count).min(len) as usize;
+    Ok(if blob {
+        Value::Blob(bytes[from..to.max(from)].to_vec())
+    } else {
+        Value::String(chars[from..to.max(from)].iter().collect())
+    })
+}
+
+/// Take characters off the ends of the text: spaces, or any of the
+/// characters in the second argument.
+fn trim(args: &[ValueRef], left: bool, right: bool) -> Result<Value> {
+    if args.contains(&ValueRef::Null) {
+        return Ok(Value::Null);
+    }
+    let text = args[0].to_text();
+    let chars: Vec<char> = match args.get(1) {
+        Some(v) => v.to_text().chars().collect(),
+        None => vec![' '],
+    };
+    let mut s: &str = &text;
+    if left {
+        s = s.trim_start_matches(chars.as_slice());
+    }
+    if right {
+        s = s.trim_end_matches(chars.as_slice());
+    }
+    Ok(Value::String(s.to_owned()))
+}
+
+fn abs(_: &Context, args: &[ValueRef]) -> Result<Value> {
+    Ok(match args[0] {
+        ValueRef::Null => Value::Null,
+        ValueRef::Integer(n) => match n.checked_abs() {
+            Some(n) => Value::Integer(n),
+            None => bail!("integer overflow"),
+        },
+        ValueRef::Float(x) => Value::Float(x.abs()),
+        // text and blobs are converted to a real
+        v => match v.to_value().to_numeric() {
+            Value::Integer(n) => Value::Float((n as f64).abs()),
+            Value::Float(x) => Value::Float(x.abs()),
+            _ => Value::Float(0.0),
+        },
+    })
+}
+
+/// `round(x, digits)`, always giving a real. Halves round away from zero.
+fn round(_: &Context, args: &[ValueRef]) -> Result<Value> {
+    if args.contains(&ValueRef::Null) {
+        return Ok(Value::Null);
+    }
+    let digits = args.get(1).map_or(0, |&d| to_integer(d)).clamp(0, 30) as usize;
+    let x = match args[0].to_value().to_numeric() {
+        Value::Integer(n) => n as f64,
+        Value::Float(x) => x,
+        _ => 0.0,
+    };
+    if digits == 0 {
+        return Ok(Value::Float(x.round()));
+    }
+    let rounded = format!("{:.*}", digits, x);
+    Ok(Value::Float(rounded.parse().unwrap_or(x)))
+}
+
+/// The first argument that isn't `NULL`.
+fn coalesce(_: &Context, args: &[ValueRef]) -> Result<Value> {
+    Ok(args
+        .iter()
+        .find(|v| **v != ValueRef::Null)
+        .map_or(Value::Null, |v| v.to_value()))
+}
+
+/// Parse a JSON argument.
+fn json_arg(v: ValueRef) -> Result<Json> {
+    v.to_text().parse().map_err(|_| anyhow!("malformed JSON"))
+}
+
+/// `f` of what's at the path in the second argument of the JSON in the first,
+/// or of the whole thing without one. `NULL` if either is `NULL` or there's
+/// nothing at the path.
+fn json_at(args: &[ValueRef], f: impl Fn(&Json) -> Value) -> Result<Value> {
+    if args.contains(&ValueRef::Null) {
+        return Ok(Value::Null);
+    }
+    let json = json_arg(args[0])?;
+    let found = match args.get(1) {
+        Some(path) => json.path(&path.to_text())?,
+        None => Some(&json),
+    };
+    Ok(found.map_or(Value::Null, f))
+}
+
+/// `json_extract(json, path, ...)`: what's at the path, as an SQL value, with
+/// arrays and objects as JSON text. With more than one path, a JSON array of
+/// what's at each.
+fn json_extract(_: &Context, args: &[ValueRef]) -> Result<Value> {
+    if let [json, path] = args {
+        return json_at(&[*json, *path], Json::to_value);
+    }
+    if args.contains(&ValueRef::Null) {
+        return Ok(Value::Null);
+    }
+    let json = json_arg(args[0])?;
+    let items = args[1..]
+        .iter()
+        .map(|path| Ok(json.path(&path.to_text())?.cloned().unwrap_or(Json::Null)))
+        .collect::<Result<Vec<Json>>>()?;
+    Ok(Value::String(Json::Array(items).to_string()))
+}
+
+/// The value as an SQL literal.
+fn quote(v: ValueRef) -> Value {
+    Value::String(match v {
+        ValueRef::Null => "NULL".to_owned(),
+        ValueRef::String(_) => format!("'{}'", v.to_text().replace('\'', "''")),
+        ValueRef::Blob(b) => format!("X'{}'", hex(b)),
+        v => v.to_value().to_string(),
+    })
+}
+
+#[test]
+fn builtin_functions() -> Result<()> {
+    let functions = Functions::default();
+    let call = |name: &str, args: &[ValueRef]| functions.call(name, args);
+    let text = |s: &'static str| ValueRef::String(s.as_bytes());
+    let string = |s: &str| Value::String(s.to_owned());
+
+    assert_eq!(call("length", &[text("héllo")])?, Value::Integer(5));
+    assert_eq!(call("UPPER", &[text("straße")])?, string("STRAßE"));
+    assert_eq!(
+        call(
+            "substr",
+            &[text("abcdef"), ValueRef::Integer(2), ValueRef::Integer(3)]
+        )?,
+        string("bcd")
+    );
+    assert_eq!(
+        call("substr", &[text("abcdef"), ValueRef::Integer(-2)])?,
+        string("ef")
+    );
+    assert_eq!(
+        call(
+            "substr",
+            &[text("abcdef"), ValueRef::Integer(4), ValueRef::Integer(-2)]
+        )?,
+        string("bc")
+    );
+    assert_eq!(call("trim", &[text("xxhixx"), text("x")])?, string("hi"));
+    assert_eq!(call("abs", &[ValueRef::Integer(-3)])?, Value::Integer(3));
+    assert!(call("abs", &[ValueRef::Integer(i64::MIN)]).is_err());
+    assert_eq!(call("round", &[ValueRef::Float(2.5)])?, Value::Float(3.0));
+    assert_eq!(
+        call("coalesce", &[ValueRef::Null, ValueRef::Integer(1)])?,
+        Value::Integer(1)
+    );
+    assert_eq!(call("typeof", &[ValueRef::Float(1.0)])?, string("real"));
+    assert_eq!(call("hex", &[text("hi")])?, string("6869"));
+    assert_eq!(call("quote", &[text("it's")])?, string("'it''s'"));
+    let doc = text(r#"{"a": [1, {"b": "x"}], "n": 2.5, "t": true}"#);
+    assert_eq!(call("json_extract", &[doc, text("$.a[1].b")])?, string("x"));
+    assert_eq!(
+        call("json_extract", &[doc, text("$.t")])?,
+        Value::Integer(1)
+    );
+    assert_eq!(
+        call("json_extract", &[doc, text("$.a[1]")])?,
+        string(r#"{"b":"x"}"#)
+    );
+    assert_eq!(call("json_extract", &[doc, text("$.nope")])?, Value::Null);
+    assert_eq!(
+        call("json_extract", &[doc, text("$.n"), text("$.nope")])?,
+        string("[2.5,null]")
+    );
+    assert!(call("json_extract", &[text("{"), text("$")]).is_err());
+    assert!(call("json_extract", &[doc, text("a")]).is_err());
+    assert_eq!(call("json_type", &[doc])?, string("object"));
+    assert_eq!(call("json_type", &[doc, text("$.n")])?, string("real"));
+    assert_eq!(
+        call("json_array_length", &[doc, text("$.a")])?,
+        Value::Integer(2)
+    );
+    assert_eq!(call("json_array_length", &[doc])?, Value::Integer(0));
+    assert_eq!(call("json_valid", &[doc])?, Value::Integer(1));
+    assert_eq!(call("json_valid", &[text("[1,")])?, Value::Integer(0));
+    assert_eq!(call("json_valid", &[ValueRef::Null])?, Value::Null);
+    assert!(call("ifnull", &[ValueRef::Null]).is_err());
+    assert!(call("nope", &[]).is_err());
+    Ok(())
+}
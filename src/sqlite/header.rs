@@ -0,0 +1,225 @@
+//! The 100 byte header at the start of every database file. All the numbers in
+//! it are big-endian, whatever machine wrote the file.
+
+use anyhow::Result;
+
+use super::SqliteError;
+
+pub(crate) const MAGIC: &[u8; 16] = b"SQLite format 3\0";
+
+/// How text is stored in the database.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextEncoding {
+    Utf8,
+    Utf16le,
+    Utf16be,
+}
+
+/// The database file header, with a getter for each field.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DbHeader {
+    data: [u8; 100],
+}
+
+impl DbHeader {
+    /// The header for a new file with `page_count` pages: UTF-8, no reserved
+    /// bytes and the usual payload fractions.
+    pub fn new(page_size: u32, page_count: u32) -> Self {
+        let mut h = [0u8; 100];
+        h[..16].copy_from_slice(MAGIC);
+        // 65536 is written as 1
+        let size = if page_size == 65536 {
+            1
+        } else {
+            page_size as u16
+        };
+        h[16..18].copy_from_slice(&size.to_be_bytes());
+        h[18] = 1; // write version: rollback journal
+        h[19] = 1; // read version
+        h[21] = 64; // max embedded payload fraction
+        h[22] = 32; // min embedded payload fraction
+        h[23] = 32; // leaf payload fraction
+        h[24..28].copy_from_slice(&1u32.to_be_bytes()); // change counter
+        h[28..32].copy_from_slice(&page_count.to_be_bytes());
+        h[40..44].copy_from_slice(&1u32.to_be_bytes()); // schema cookie
+        h[44..48].copy_from_slice(&4u32.to_be_bytes()); // schema format
+        h[56..60].copy_from_slice(&1u32.to_be_bytes()); // UTF-8
+        h[92..96].copy_from_slice(&1u32.to_be_bytes()); // version valid for
+        h[96..100].copy_from_slice(&3045000u32.to_be_bytes());
+        DbHeader { data: h }
+    }
+
+    /// Check the magic string and page size, which everything else depends on.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let bad = |reason: &str| SqliteError::FileHeader {
+            reason: reason.to_owned(),
+        };
+        let data: [u8; 100] = data
+            .get(..100)
+            .and_then(|d| d.try_into().ok())
+            .ok_or_else(|| bad("file is shorter than the header"))?;
+        if &data[..16] != MAGIC {
+            return Err(bad("not an SQLite database, or it's encrypted").into());
+        }
+        let header = DbHeader { data };
+        let page_size = header.page_size();
+        if !page_size.is_power_of_two() || !(512..=65536).contains(&page_size) {
+            return Err(bad(&format!("bad page size {}", page_size)).into());
+        }
+        if header.reserved_bytes() as u32 >= page_size - 480 {
+            return Err(bad("too many reserved bytes").into());
+        }
+        Ok(header)
+    }
+
+    /// The header exactly as it is in the file.
+    pub fn raw(&self) -> &[u8; 100] {
+        &self.data
+    }
+
+    fn u32_at(&self, offset: usize) -> u32 {
+        u32::from_be_bytes(self.data[offset..offset + 4].try_into().unwrap())
+    }
+
+    /// Bytes per page. 65536 is stored as 1, since it doesn't fit in 16 bits.
+    pub fn page_size(&self) -> u32 {
+        match u16::from_be_bytes([self.data[16], self.data[17]]) {
+            1 => 65536,
+            n => n as u32,
+        }
+    }
+
+    /// 1 for a rollback journal, 2 for WAL.
+    pub fn write_version(&self) -> u8 {
+        self.data[18]
+    }
+
+    /// 1 for a rollback journal, 2 for WAL.
+    pub fn read_version(&self) -> u8 {
+        self.data[19]
+    }
+
+    /// Unused bytes at the end of each page, for extensions like encryption.
+    pub fn reserved_bytes(&self) -> u8 {
+        self.data[20]
+    }
+
+    /// Always 64.
+    pub fn max_payload_fraction(&self) -> u8 {
+        self.data[21]
+    }
+
+    /// Always 32.
+    pub fn min_payload_fraction(&self) -> u8 {
+        self.data[22]
+    }
+
+    /// Always 32.
+    pub fn leaf_payload_fraction(&self) -> u8 {
+        self.data[23]
+    }
+
+    /// Bumped by every transaction that changes the file.
+    pub fn change_counter(&self) -> u32 {
+        self.u32_at(24)
+    }
+
+    /// Size of the database in pages, as written in the header. Only to be
+    /// trusted if [`page_count_valid`][Self::page_count_valid].
+    pub fn page_count(&self) -> u32 {
+        self.u32_at(28)
+    }
+
+    /// Versions of SQLite before 3.7.0 didn't keep the page count up to date,
+    /// which shows up as the change counter not matching
+    /// [`version_valid_for`][Self::version_valid_for].
+    pub fn page_count_valid(&self) -> bool {
+        self.page_count() != 0 && self.change_counter() == self.version_valid_for()
+    }
+
+    /// First freelist trunk page, or 0 if the freelist is empty.
+    pub fn first_freelist_trunk(&self) -> u32 {
+        self.u32_at(32)
+    }
+
+    pub fn freelist_count(&self) -> u32 {
+        self.u32_at(36)
+    }
+
+    /// Bumped whenever the schema changes.
+    pub fn schema_cookie(&self) -> u32 {
+        self.u32_at(40)
+    }
+
+    /// 1 to 4. Newer formats allow things like descending indexes.
+    pub fn schema_format(&self) -> u32 {
+        self.u32_at(44)
+    }
+
+    /// The suggested cache size, from `PRAGMA default_cache_size`.
+    pub fn default_cache_size(&self) -> i32 {
+        self.u32_at(48) as i32
+    }
+
+    /// Root page of the largest B-tree in auto-vacuum mode, or 0 if
+    /// auto-vacuum is off.
+    pub fn largest_root_page(&self) -> u32 {
+        self.u32_at(52)
+    }
+
+    /// `None` for an encoding number SQLite doesn't know.
+    pub fn text_encoding(&self) -> Option<TextEncoding> {
+        match self.u32_at(56) {
+            1 => Some(TextEncoding::Utf8),
+            2 => Some(TextEncoding::Utf16le),
+            3 => Some(TextEncoding::Utf16be),
+            _ => None,
+        }
+    }
+
+    /// `PRAGMA user_version`
+    pub fn user_version(&self) -> i32 {
+        self.u32_at(60) as i32
+    }
+
+    /// Whether auto-vacuum is in incremental mode.
+    pub fn incremental_vacuum(&self) -> bool {
+        self.u32_at(64) != 0
+    }
+
+    /// `PRAGMA application_id`
+    pub fn application_id(&self) -> i32 {
+        self.u32_at(68) as i32
+    }
+
+    /// The change counter as of when [`sqlite_version`][Self::sqlite_version]
+    /// was written.
+    pub fn version_valid_for(&self) -> u32 {
+        self.u32_at(92)
+    }
+
+    /// Version of the SQLite library that last wrote the file, like 3045001
+    /// for 3.45.1.
+    pub fn sqlite_version(&self) -> u32 {
+        self.u32_at(96)
+    }
+}
+
+#[test]
+fn header_fields() -> Result<()> {
+    let data = std::fs::read("sample.db")?;
+    let header = DbHeader::parse(&data)?;
+    assert_eq!(header.page_size(), 4096);
+    assert_eq!(header.text_encoding(), Some(TextEncoding::Utf8));
+    assert_eq!(header.max_payload_fraction(), 64);
+    assert!(header.page_count_valid());
+    assert_eq!(header.page_count() as usize * 4096, data.len());
+
+    let mut big = data[..100].to_vec();
+    big[16..18].copy_from_slice(&1u16.to_be_bytes());
+    assert_eq!(DbHeader::parse(&big)?.page_size(), 65536);
+    big[16..18].copy_from_slice(&1000u16.to_be_bytes());
+    assert!(DbHeader::parse(&big).is_err());
+    assert!(DbHeader::parse(b"SQLite format 2\0").is_err());
+    Ok(())
+}
@@ -0,0 +1,282 @@
+//! Reading a database off a web server with HTTP range requests, like
+//! sql.js-httpvfs, so a query only downloads the pages it touches instead of
+//! the whole file. Pages land in the usual page cache, so each is fetched
+//! once. Only plain `http://` is spoken, since there's no TLS here.
+//!
+//! Every query checks the header for changes, which is a request of its
+//! own; open with [`immutable`][super::OpenOptions::immutable] to skip it.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use super::vfs::{Vfs, VfsFile};
+
+/// Lowercase header names and their values, then the body.
+type Response = (Vec<(String, String)>, Vec<u8>);
+
+/// How long to wait on a server that stops answering before giving up.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Opens `http://` URLs.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HttpVfs;
+
+impl Vfs for HttpVfs {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        let url = path
+            .to_str()
+            .ok_or_else(|| io::Error::other("URL isn't UTF-8"))?;
+        Ok(Box::new(HttpFile::open(url)?))
+    }
+}
+
+/// A file on a web server that supports range requests.
+pub struct HttpFile {
+    /// `host:port`
+    host: String,
+    path: String,
+    len: u64,
+    /// Kept open between requests, unless the server closes it.
+    conn: Mutex<Option<BufReader<TcpStream>>>,
+    requests: AtomicU64,
+}
+
+impl HttpFile {
+    /// Connect and find out how big the file is.
+    pub fn open(url: &str) -> io::Result<Self> {
+        let Some(rest) = url.strip_prefix("http://") else {
+            return Err(io::Error::other(format!("not an http:// URL: {}", url)));
+        };
+        let (host, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        let host = match host.contains(':') {
+            true => host.to_owned(),
+            false => format!("{}:80", host),
+        };
+        let mut file = HttpFile {
+            host,
+            path: if path.is_empty() { "/" } else { path }.to_owned(),
+            len: 0,
+            conn: Mutex::new(None),
+            requests: AtomicU64::new(0),
+        };
+        let (headers, _) = file.get(0, 0)?;
+        // Content-Range: bytes 0-0/12345
+        let total = header(&headers, "content-range")
+            .and_then(|range| range.rsplit_once('/'))
+            .and_then(|(_, total)| total.trim().parse().ok());
+        file.len = total.ok_or_else(|| io::Error::other("no file size in Content-Range"))?;
+        Ok(file)
+    }
+
+    /// How many requests have been made, to see what a query cost.
+    pub fn requests(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    /// Bytes `first..=last` of the file, and the response headers. A
+    /// connection the server dropped since last time is retried once.
+    fn get(&self, first: u64, last: u64) -> io::Result<Response> {
+        let mut conn = self.conn.lock().unwrap();
+        let reused = conn.is_some();
+        match self.request(&mut conn, first, last) {
+            Err(_) if reused => {
+                *conn = None;
+                self.request(&mut conn, first, last)
+            }
+            result => result,
+        }
+    }
+
+    fn request(
+        &self,
+        conn: &mut Option<BufReader<TcpStream>>,
+        first: u64,
+        last: u64,
+    ) -> io::Result<Response> {
+        if conn.is_none() {
+            let stream = TcpStream::connect(&self.host)?;
+            stream.set_read_timeout(Some(TIMEOUT))?;
+            stream.set_write_timeout(Some(TIMEOUT))?;
+            *conn = Some(BufReader::new(stream));
+        }
+        let stream = conn.as_mut().unwrap();
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        write!(
+            stream.get_mut(),
+            "GET {} HTTP/1.1\r\nHost: {}\r\nRange: bytes={}-{}\r\n\r\n",
+            self.path,
+            self.host,
+            first,
+            last
+        )?;
+
+        let mut line = String::new();
+        stream.read_line(&mut line)?;
+        let status = line.split_whitespace().nth(1).unwrap_or("").to_owned();
+        let mut headers = vec![];
+        loop {
+            line.clear();
+            if stream.read_line(&mut line)? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let Some((name, value)) = line.split_once(':') else {
+                break;
+            };
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_owned()));
+        }
+        // the body isn't read on an error, so the connection can't be reused
+        match status.as_str() {
+            "206" => {}
+            "200" => {
+                *conn = None;
+                return Err(io::Error::other("server doesn't support range requests"));
+            }
+            _ => {
+                *conn = None;
+                return Err(io::Error::other(format!(
+                    "GET {} failed with status {}",
+                    self.path, status
+                )));
+            }
+        }
+        // Content-Range: bytes 0-99/12345, which can stop short at the end
+        let range = header(&headers, "content-range")
+            .and_then(|r| r.strip_prefix("bytes "))
+            .and_then(|r| r.split_once('/'))
+            .and_then(|(r, _)| r.split_once('-'))
+            .and_then(|(a, b)| Some((a.trim().parse::<u64>().ok()?, b.trim().parse().ok()?)));
+        let len = match range {
+            Some((a, b)) if a == first && a <= b && b <= last => b - a + 1,
+            _ => {
+                *conn = None;
+                return Err(io::Error::other(format!(
+                    "asked for bytes {}-{} but the Content-Range doesn't match",
+                    first, last
+                )));
+            }
+        };
+        if header(&headers, "content-length").is_some_and(|n| n.parse() != Ok(len)) {
+            *conn = None;
+            return Err(io::Error::other(
+                "Content-Length doesn't match Content-Range",
+            ));
+        }
+        let mut body = vec![0; len as usize];
+        stream.read_exact(&mut body)?;
+        if header(&headers, "connection").is_some_and(|c| c.eq_ignore_ascii_case("close")) {
+            *conn = None;
+        }
+        Ok((headers, body))
+    }
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(n, _)| n == name)
+        .map(|(_, v)| v.as_str())
+}
+
+impl VfsFile for HttpFile {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        if buf.is_empty() {
+            return Ok(());
+        }
+        if offset + buf.len() as u64 > self.len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let (_, body) = self.get(offset, offset + buf.len() as u64 - 1)?;
+        if body.len() != buf.len() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        buf.copy_from_slice(&body);
+        Ok(())
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.len)
+    }
+}
+
+#[test]
+fn read_over_http() -> anyhow::Result<()> {
+    use super::record::Value;
+    use std::net::TcpListener;
+
+    // serves sample.db with range requests, a thread per connection
+    let data = std::sync::Arc::new(std::fs::read("sample.db")?);
+    let size = data.len() as u64;
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let url = format!("http://{}/sample.db", listener.local_addr()?);
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let data = data.clone();
+            std::thread::spawn(move || {
+                let mut reader = BufReader::new(stream);
+                let mut range = None;
+                let mut line = String::new();
+                while reader.read_line(&mut line).is_ok_and(|n| n > 0) {
+                    if let Some(r) = line.trim().strip_prefix("Range: bytes=") {
+                        let (first, last) = r.split_once('-').unwrap();
+                        range = Some((first.parse::<usize>().unwrap(), last.parse().unwrap()));
+                    }
+                    if line == "\r\n" {
+                        let (first, last) = range.take().unwrap();
+                        let last = std::cmp::min(last, data.len() - 1);
+                        let body = &data[first..=last];
+                        let head = format!(
+                            "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\nContent-Length: {}\r\n\r\n",
+                            first,
+                            last,
+                            data.len(),
+                            body.len()
+                        );
+                        let stream = reader.get_mut();
+                        let _ = stream.write_all(head.as_bytes());
+                        let _ = stream.write_all(body);
+                    }
+                    line.clear();
+                }
+            });
+        }
+    });
+
+    let file = HttpFile::open(&url)?;
+    assert_eq!(file.len()?, size);
+    assert_eq!(file.requests(), 1);
+    let db = super::OpenOptions::new().immutable(true).open(&url)?;
+    assert_eq!(
+        db.prepare("SELECT name FROM apples WHERE id = 3")?.rows()?,
+        [vec![Value::String("Honeycrisp".into())]]
+    );
+    assert!(HttpFile::open("https://example.com/x.db").is_err());
+    Ok(())
+}
+
+#[test]
+fn refuse_bodies_bigger_than_asked_for() -> anyhow::Result<()> {
+    use std::net::TcpListener;
+
+    // claims to send far more than the byte that was asked for
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let url = format!("http://{}/x.db", listener.local_addr()?);
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let mut reader = BufReader::new(stream);
+            let mut line = String::new();
+            while reader.read_line(&mut line).is_ok_and(|n| n > 0) && line != "\r\n" {
+                line.clear();
+            }
+            let _ = reader.get_mut().write_all(
+                b"HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 0-999999999999/999999999999\r\nContent-Length: 999999999999\r\n\r\n",
+            );
+        }
+    });
+    let err = HttpFile::open(&url).err().unwrap();
+    assert!(err.to_string().contains("Content-Range"), "{}", err);
+    Ok(())
+}
@@ -0,0 +1,258 @@
+//! Tools for looking at the raw structure of a database file.
+
+use std::io::Write;
+use std::num::NonZeroU64;
+
+use anyhow::{anyhow, Result};
+
+use super::btree::walk;
+use super::cells::{Cell, Payload};
+use super::{cell_pointers, parse_btree_header, CreateTable, PageKind, SchemaType, SqliteFile};
+
+/// How many bytes of each payload to show in cell summaries.
+const PREVIEW_BYTES: usize = 16;
+
+/// Write `data` as a hexdump, 16 bytes per line with an ASCII column.
+/// Runs of identical lines are collapsed into a `*`, like `hexdump -C`.
+pub fn hexdump<W: Write>(out: &mut W, data: &[u8]) -> Result<()> {
+    let mut prev: Option<&[u8]> = None;
+    let mut collapsed = false;
+    for (i, line) in data.chunks(16).enumerate() {
+        if prev == Some(line) {
+            if !collapsed {
+                writeln!(out, "*")?;
+                collapsed = true;
+            }
+            continue;
+        }
+        prev = Some(line);
+        collapsed = false;
+        write!(out, "{:06x}: ", i * 16)?;
+        for j in 0..16 {
+            match line.get(j) {
+                Some(b) => write!(out, "{:02x} ", b)?,
+                None => write!(out, "   ")?,
+            }
+            if j == 7 {
+                write!(out, " ")?;
+            }
+        }
+        let ascii: String = line
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        writeln!(out, " |{}|", ascii)?;
+    }
+    writeln!(out, "{:06x}", data.len())?;
+    Ok(())
+}
+
+fn preview(bytes: &[u8]) -> String {
+    let shown = &bytes[..bytes.len().min(PREVIEW_BYTES)];
+    let mut hex: Vec<String> = shown.iter().map(|b| format!("{:02x}", b)).collect();
+    if bytes.len() > PREVIEW_BYTES {
+        hex.push("..".to_owned());
+    }
+    hex.join(" ")
+}
+
+fn describe_payload(payload: &Payload) -> String {
+    let mut s = format!("payload {} bytes", payload.size);
+    if let Some(page) = payload.overflow {
+        s += &format!(" ({} local, overflow page {})", payload.payload.len(), page);
+    }
+    s + &format!(": {}", preview(payload.payload))
+}
+
+/// Summary of a cell on one line.
+pub fn describe_cell(cell: &Cell) -> String {
+    match cell {
+        Cell::TableLeaf { rowid, payload } => {
+            format!("rowid {}, {}", rowid, describe_payload(payload))
+        }
+        Cell::TableInterior {
+            left_child_page,
+            rowid,
+        } => format!("left child {}, rowid {}", left_child_page, rowid),
+        Cell::IndexLeaf { payload } => describe_payload(payload),
+        Cell::IndexInterior {
+            left_child_page,
+            payload,
+        } => format!(
+            "left child {}, {}",
+            left_child_page,
+            describe_payload(payload)
+        ),
+    }
+}
+
+/// Write a hexdump of a page, followed by its decoded B-tree header and cells.
+///
+/// This tries hard not to fail on broken pages: anything that can't be decoded
+/// is reported and skipped, and pages that aren't B-tree pages only get the hexdump.
+pub fn dump_page<W: Write>(out: &mut W, file: &SqliteFile, page_id: NonZeroU64) -> Result<()> {
+    let data = file.read_raw(page_id)?;
+    writeln!(out, "page {} ({} bytes)", page_id, data.len())?;
+    hexdump(out, &data)?;
+
+    let header_offset = if page_id.get() == 1 { 100 } else { 0 };
+    let header = match parse_btree_header(&data[header_offset..]) {
+        Ok((_, header)) => header,
+        Err(_) => {
+            writeln!(
+                out,
+                "not a b-tree page (overflow, freelist or pointer map page?)"
+            )?;
+            return Ok(());
+        }
+    };
+    writeln!(out, "{:#?}", header)?;
+
+    let header_size = if header.kind.is_interior() { 12 } else { 8 };
+    let start = header_offset + header_size;
+    let pointers = match cell_pointers(&data[start..], header.cell_count as usize) {
+        Ok((_, pointers)) => pointers,
+        Err(_) => {
+            writeln!(
+                out,
+                "cell pointer array runs off the end of the page ({} cells)",
+                header.cell_count
+            )?;
+            return Ok(());
+        }
+    };
+    writeln!(out, "cell pointers: {:?}", pointers)?;
+    for (i, ptr) in pointers.iter().enumerate() {
+        let ptr = *ptr as usize;
+        if ptr >= data.len() {
+            writeln!(out, "cell {} @ {}: pointer past end of page", i, ptr)?;
+            continue;
+        }
+        match header.parse_cell(&data[ptr..], file.usable_size()) {
+            Ok((_, cell)) => writeln!(out, "cell {} @ {}: {}", i, ptr, describe_cell(&cell))?,
+            Err(e) => writeln!(out, "cell {} @ {}: can't parse: {:?}", i, ptr, e)?,
+        }
+    }
+    Ok(())
+}
+
+fn kind_name(kind: PageKind) -> &'static str {
+    match kind {
+        PageKind::IndexInterior => "index interior",
+        PageKind::TableInterior => "table interior",
+        PageKind::IndexLeaf => "index leaf",
+        PageKind::TableLeaf => "table leaf",
+    }
+}
+
+/// Escape text for a Graphviz record label.
+fn record_label(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "{}|<>\"\\".contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Write the tables of the database as a Graphviz `dot` graph: one node per
+/// table listing its columns, with the primary key marked, and an edge for
+/// each foreign key.
+pub fn schema_dot<W: Write>(out: &mut W, file: &SqliteFile) -> Result<()> {
+    let tables: Vec<CreateTable> = file
+        .get_schema()?
+        .iter()
+        .filter(|s| s.stype == SchemaType::Table)
+        .map(CreateTable::try_from)
+        .collect::<Result<_>>()?;
+    // ports are column numbers, since names could be anything
+    let port = |table: &str, column: &str| {
+        tables
+            .iter()
+            .find(|t| t.name.eq_ignore_ascii_case(table))
+            .and_then(|t| t.column_index(column))
+            .map_or_else(String::new, |i| format!(":c{}", i))
+    };
+    writeln!(out, "digraph schema {{")?;
+    writeln!(out, "    rankdir=LR;")?;
+    writeln!(out, "    node [shape=record];")?;
+    for table in &tables {
+        let mut label = record_label(&table.name);
+        for (i, column) in table.columns.iter().enumerate() {
+            let pk = table
+                .primary_key
+                .iter()
+                .any(|c| c.eq_ignore_ascii_case(column));
+            let mark = if pk { " (PK)" } else { "" };
+            label += &format!("|<c{}> {}{}\\l", i, record_label(column), mark);
+        }
+        writeln!(out, "    {:?} [label=\"{{{}}}\"];", table.name, label)?;
+    }
+    for table in &tables {
+        for fk in &table.foreign_keys {
+            let parent = tables
+                .iter()
+                .find(|t| t.name.eq_ignore_ascii_case(&fk.table));
+            // no columns means the parent's primary key
+            let references = match (fk.references.is_empty(), parent) {
+                (true, Some(parent)) => &parent.primary_key,
+                _ => &fk.references,
+            };
+            for (i, column) in fk.columns.iter().enumerate() {
+                let to = references
+                    .get(i)
+                    .map_or_else(String::new, |c| port(&fk.table, c));
+                writeln!(
+                    out,
+                    "    {:?}{} -> {:?}{};",
+                    table.name,
+                    port(&table.name, column),
+                    fk.table,
+                    to
+                )?;
+            }
+        }
+    }
+    writeln!(out, "}}")?;
+    Ok(())
+}
+
+/// Write the B-tree of a table or index as a Graphviz `dot` graph, with one node per page.
+pub fn btree_dot<W: Write>(out: &mut W, file: &SqliteFile, name: &str) -> Result<()> {
+    let root = if name == "sqlite_schema" || name == "sqlite_master" {
+        NonZeroU64::MIN
+    } else {
+        file.get_schema()?
+            .iter()
+            .find(|s| s.name == name)
+            .and_then(|s| NonZeroU64::new(s.rootpage))
+            .ok_or_else(|| anyhow!("no table or index named {}", name))?
+    };
+    writeln!(out, "digraph {:?} {{", name)?;
+    writeln!(out, "    node [shape=record];")?;
+    walk(file, root, |page, depth| {
+        writeln!(
+            out,
+            "    p{} [label=\"{{page {}|{}|{} cells|depth {}}}\"];",
+            page.page_id,
+            page.page_id,
+            kind_name(page.header.kind),
+            page.header.cell_count,
+            depth
+        )?;
+        for child in page.children() {
+            writeln!(out, "    p{} -> p{};", page.page_id, child)?;
+        }
+        Ok(())
+    })?;
+    writeln!(out, "}}")?;
+    Ok(())
+}
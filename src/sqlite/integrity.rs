@@ -0,0 +1,628 @@
+//! `PRAGMA integrity_check` and `PRAGMA quick_check`: looking for corruption
+//! in the structure of the file.
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::num::NonZeroU64;
+use std::ops::RangeInclusive;
+
+use anyhow::{bail, Result};
+
+use super::btree::{compare_prefix, walk};
+use super::cells::Cell;
+use super::ptrmap::{PtrmapEntry, PtrmapKind};
+use super::record::Value;
+use super::rowset::RowidSet;
+use super::{CreateIndex, CreateTable, SchemaType, SqliteFile};
+
+/// What to check, and how thoroughly.
+#[derive(Clone, Debug, Default)]
+pub struct CheckOptions {
+    quick: bool,
+    table: Option<String>,
+    pages: Option<RangeInclusive<u64>>,
+}
+
+impl CheckOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only check that each index has as many entries as its table has rows,
+    /// not that they're the same rows, which is most of the work. Like
+    /// `PRAGMA quick_check`.
+    pub fn quick(&mut self, quick: bool) -> &mut Self {
+        self.quick = quick;
+        self
+    }
+
+    /// Only check one table and its indexes.
+    pub fn table(&mut self, table: &str) -> &mut Self {
+        self.table = Some(table.to_owned());
+        self
+    }
+
+    /// Only read the leaf pages in this range. Interior pages still get read
+    /// to find them, but those are a small part of a big file, so a huge file
+    /// can be checked a piece at a time.
+    pub fn pages(&mut self, pages: RangeInclusive<u64>) -> &mut Self {
+        self.pages = Some(pages);
+        self
+    }
+
+    /// Whether every page gets looked at, so anything left over is a problem.
+    fn whole_file(&self) -> bool {
+        self.table.is_none() && self.pages.is_none()
+    }
+}
+
+/// The rows of a table, or the entries of an index.
+struct Entries {
+    count: u64,
+    /// The table's rowids, or the rowid each index entry points to. Left
+    /// empty by a quick check.
+    rowids: RowidSet,
+}
+
+/// Walks every B-tree, overflow chain and the freelist, keeping track of which
+/// pages have been seen so far.
+struct Checker<'f> {
+    file: &'f SqliteFile,
+    options: &'f CheckOptions,
+    page_count: u64,
+    used: HashSet<u64>,
+    /// What the pointer map should say about each page, in an auto-vacuum
+    /// database.
+    ptrmap: Option<Vec<(u64, PtrmapEntry)>>,
+    problems: Vec<String>,
+}
+
+impl<'f> Checker<'f> {
+    /// Note that `page` is in use, complaining if it can't be or it already
+    /// was. Returns whether it's worth looking at.
+    fn claim(&mut self, page: u64, what: &str) -> bool {
+        if page == 0 || page > self.page_count {
+            self.problems
+                .push(format!("{}: invalid page number {}", what, page));
+            return false;
+        }
+        if !self.used.insert(page) {
+            let problem = if self.file.is_ptrmap_page(page).unwrap_or(false) {
+                format!("{}: page {} is a pointer map page", what, page)
+            } else {
+                format!("{}: 2nd reference to page {}", what, page)
+            };
+            self.problems.push(problem);
+            return false;
+        }
+        true
+    }
+
+    /// Note what the pointer map should say about `page`, to check later.
+    fn expect_ptrmap(&mut self, page: u64, kind: PtrmapKind, parent: u64) {
+        if let Some(expected) = &mut self.ptrmap {
+            expected.push((page, PtrmapEntry::new(kind, parent as u32)));
+        }
+    }
+
+    /// Compare the pointer map with what was found. Vacuuming moves pages
+    /// around and updates the map as it goes, so a file copied halfway
+    /// through can have entries that point at the old places.
+    fn check_ptrmap(&mut self) {
+        for (page, expected) in self.ptrmap.take().unwrap_or_default() {
+            match self.file.ptrmap_entry(page) {
+                Ok(got) if got == expected => {}
+                Ok(got) => self.problems.push(format!(
+                    "Bad ptr map entry key={} expected={} got={}",
+                    page, expected, got
+                )),
+                Err(e) => self
+                    .problems
+                    .push(format!("Failed to read ptrmap key={}: {}", page, e)),
+            }
+        }
+    }
+
+    fn in_range(&self, page: u64) -> bool {
+        self.options
+            .pages
+            .as_ref()
+            .is_none_or(|r| r.contains(&page))
+    }
+
+    /// Check a B-tree, also counting its entries if `entries` is set. `None`
+    /// if they weren't wanted, or some were skipped because of the page range.
+    fn check_btree(&mut self, name: &str, root: NonZeroU64, entries: bool) -> Option<Entries> {
+        let what = format!("Tree {} ({})", root, name);
+        let mut found = (entries && self.options.pages.is_none()).then(|| Entries {
+            count: 0,
+            rowids: RowidSet::new(),
+        });
+        // every leaf is at the same depth, which is known once we've been down
+        // the leftmost side
+        let mut leaf_depth = None;
+        let mut stack = vec![(root, 0, 0)];
+        while let Some((page_id, depth, parent)) = stack.pop() {
+            if !self.claim(page_id.get(), &what) {
+                continue;
+            }
+            match parent {
+                // page 1 is the only root without an entry
+                0 if page_id != NonZeroU64::MIN => {
+                    self.expect_ptrmap(page_id.get(), PtrmapKind::RootPage, 0)
+                }
+                0 => {}
+                _ => self.expect_ptrmap(page_id.get(), PtrmapKind::Btree, parent),
+            }
+            if leaf_depth == Some(depth) && !self.in_range(page_id.get()) {
+                continue;
+            }
+            let page = match self.file.get_page(page_id) {
+                Ok(page) => page,
+                Err(e) => {
+                    self.problems.push(format!("{}: {}", what, e));
+                    continue;
+                }
+            };
+            if !page.header.kind.is_interior() {
+                leaf_depth.get_or_insert(depth);
+                if leaf_depth != Some(depth) {
+                    self.problems.push(format!(
+                        "{}: leaf page {} at the wrong depth",
+                        what, page_id
+                    ));
+                }
+            }
+            let mut children = vec![];
+            for cell in page.cells() {
+                if let Some(payload) = cell.get_payload() {
+                    if let Some(first) = payload.overflow {
+                        self.check_overflow(&what, first, payload.overflow_size(), page_id.get());
+                    }
+                }
+                match (&cell, found.as_mut()) {
+                    (Cell::TableLeaf { rowid, .. }, Some(found)) => {
+                        found.count += 1;
+                        if !self.options.quick {
+                            found.rowids.insert(*rowid as i64);
+                        }
+                    }
+                    (Cell::IndexLeaf { .. } | Cell::IndexInterior { .. }, Some(found))
+                        if self.options.quick =>
+                    {
+                        found.count += 1;
+                    }
+                    (
+                        Cell::IndexLeaf { payload } | Cell::IndexInterior { payload, .. },
+                        Some(found),
+                    ) => match self.file.read_record(payload).as_deref() {
+                        Ok([.., Value::Integer(rowid)]) => {
+                            found.count += 1;
+                            found.rowids.insert(*rowid);
+                        }
+                        _ => self.problems.push(format!(
+                            "{}: index entry on page {} doesn't end with a rowid",
+                            what, page_id
+                        )),
+                    },
+                    _ => {}
+                }
+                if let Cell::TableInterior {
+                    left_child_page, ..
+                }
+                | Cell::IndexInterior {
+                    left_child_page, ..
+                } = cell
+                {
+                    children.push(left_child_page);
+                }
+            }
+            children.extend(page.header.rightmost_pointer);
+            // reversed, so the leftmost child comes off the stack first
+            for child in children.into_iter().rev() {
+                match NonZeroU64::new(child as u64) {
+                    Some(child) => stack.push((child, depth + 1, page_id.get())),
+                    None => self
+                        .problems
+                        .push(format!("{}: invalid page number 0", what)),
+                }
+            }
+        }
+        found
+    }
+
+    /// Check that the rows of a `STRICT` table only hold values of their
+    /// columns' types, which SQLite makes sure of when it writes them.
+    fn check_strict(&mut self, table: &CreateTable, root: NonZeroU64) {
+        let file = self.file;
+        let mut problems = vec![];
+        let result = walk(file, root, |page, _| {
+            for cell in page.cells() {
+                let Cell::TableLeaf { payload, .. } = &cell else {
+                    continue;
+                };
+                let record = file.read_record(payload)?;
+                for (column, value) in table.column_defs.iter().zip(&record) {
+                    // the INTEGER PRIMARY KEY is the rowid, and stored as NULL
+                    if column.strict_allows(value) || table.is_rowid(&column.name) {
+                        continue;
+                    }
+                    let kind = match value {
+                        Value::Null => "NULL".to_owned(),
+                        _ => format!("non-{}", column.declared_type.to_ascii_uppercase()),
+                    };
+                    problems.push(format!("{} value in {}.{}", kind, table.name, column.name));
+                }
+            }
+            Ok(())
+        });
+        if let Err(e) = result {
+            problems.push(format!("Tree {} ({}): {}", root, table.name, e));
+        }
+        self.problems.extend(problems);
+    }
+
+    /// Follow a chain of overflow pages holding `size` bytes, for a cell on
+    /// page `cell_page`.
+    fn check_overflow(&mut self, what: &str, first: u32, size: u64, cell_page: u64) {
+        let per_page = self.file.usable_size() as u64 - 4;
+        let mut expected = size.div_ceil(per_page);
+        let mut next = first;
+        let mut prev = 0;
+        while next != 0 {
+            if expected == 0 {
+                self.problems
+                    .push(format!("{}: extends off end of overflow list", what));
+                return;
+            }
+            if !self.claim(next as u64, what) {
+                return;
+            }
+            match prev {
+                0 => self.expect_ptrmap(next as u64, PtrmapKind::Overflow1, cell_page),
+                _ => self.expect_ptrmap(next as u64, PtrmapKind::Overflow2, prev as u64),
+            }
+            prev = next;
+            match self.file.read_raw(NonZeroU64::new(next as u64).unwrap()) {
+                Ok(data) => next = u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+                Err(e) => {
+                    self.problems.push(format!("{}: {}", what, e));
+                    return;
+                }
+            }
+            expected -= 1;
+        }
+        if expected > 0 {
+            self.problems.push(format!(
+                "{}: {} of {} overflow pages missing",
+                what,
+                expected,
+                size.div_ceil(per_page)
+            ));
+        }
+    }
+
+    /// Trunk pages hold the next trunk, a count, then that many leaf pages.
+    fn check_freelist(&mut self, first_trunk: u32, count: u32) {
+        let mut found = 0u64;
+        let mut trunk = first_trunk;
+        while trunk != 0 {
+            if !self.claim(trunk as u64, "Freelist") {
+                break;
+            }
+            self.expect_ptrmap(trunk as u64, PtrmapKind::FreePage, 0);
+            found += 1;
+            let data = match self.file.read_raw(NonZeroU64::new(trunk as u64).unwrap()) {
+                Ok(data) => data,
+                Err(e) => {
+                    self.problems.push(format!("Freelist: {}", e));
+                    break;
+                }
+            };
+            let u32_at =
+                |i: usize| u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+            let leaves = u32_at(4) as usize;
+            if 8 + leaves * 4 > data.len() {
+                self.problems.push(format!(
+                    "Freelist: trunk page {} has too many leaves",
+                    trunk
+                ));
+                break;
+            }
+            for i in 0..leaves {
+                let leaf = u32_at(8 + i * 4) as u64;
+                if self.claim(leaf, "Freelist") {
+                    self.expect_ptrmap(leaf, PtrmapKind::FreePage, 0);
+                    found += 1;
+                }
+            }
+            trunk = u32_at(0);
+        }
+        if found != count as u64 {
+            self.problems.push(format!(
+                "Freelist: size is {} but should be {}",
+                found, count
+            ));
+        }
+    }
+
+    /// Pointer map pages of an auto-vacuum database aren't in any tree.
+    fn claim_pointer_maps(&mut self) -> Result<()> {
+        for page in 2..=self.page_count {
+            if self.file.is_ptrmap_page(page)? {
+                self.used.insert(page);
+            }
+        }
+        Ok(())
+    }
+
+    /// Complain about index entries without a row, and rows without one. A
+    /// quick check only compares the counts.
+    fn compare_index(&mut self, index: &str, table: &Entries, entries: &Entries) {
+        for rowid in entries.rowids.iter().filter(|&r| !table.rowids.contains(r)) {
+            self.problems.push(format!(
+                "index {} has an entry for missing row {}",
+                index, rowid
+            ));
+        }
+        for rowid in table.rowids.iter().filter(|&r| !entries.rowids.contains(r)) {
+            self.problems
+                .push(format!("row {} missing from index {}", rowid, index));
+        }
+        if table.count != entries.count {
+            self.problems
+                .push(format!("wrong # of entries in index {}", index));
+        }
+    }
+}
+
+/// Check the parts of the file picked out by `options`. Every page should
+/// belong to exactly one B-tree, overflow chain or the freelist, and the
+/// header should agree with the size of the file. Returns the problems found,
+/// so no problems means the file looks fine.
+pub fn check(file: &SqliteFile, options: &CheckOptions) -> Result<Vec<String>> {
+    let header = file.header()?;
+    let schema = file.get_schema()?;
+    if let Some(table) = &options.table {
+        if !schema
+            .iter()
+            .any(|s| s.stype == SchemaType::Table && s.name.eq_ignore_ascii_case(table))
+        {
+            bail!("no such table: {}", table);
+        }
+    }
+    let mut checker = Checker {
+        file,
+        options,
+        page_count: file.page_count()?,
+        used: HashSet::new(),
+        ptrmap: file.has_ptrmap()?.then(Vec::new),
+        problems: vec![],
+    };
+    if let Some((in_header, in_file)) = file.page_count_mismatch()? {
+        checker.problems.push(format!(
+            "Page count in header is {} but the file has {} pages",
+            in_header, in_file
+        ));
+    }
+    if file.has_ptrmap()? {
+        checker.claim_pointer_maps()?;
+    } else if header.incremental_vacuum() {
+        checker
+            .problems
+            .push("Incremental vacuum is on but there's no pointer map".to_owned());
+    }
+    let wanted = |name: &str| {
+        options
+            .table
+            .as_ref()
+            .is_none_or(|table| table.eq_ignore_ascii_case(name))
+    };
+    if options.table.is_none() {
+        checker.check_btree("sqlite_schema", NonZeroU64::MIN, false);
+    }
+    let mut rowids = HashMap::new();
+    for table in schema.iter().filter(|s| s.stype == SchemaType::Table) {
+        if let Some(root) = NonZeroU64::new(table.rootpage).filter(|_| wanted(&table.name)) {
+            // only rowid tables have rowids for indexes to point at
+            let has_rowids = file
+                .get_page(root)
+                .is_ok_and(|page| page.header.kind.is_table());
+            if let Some(found) = checker.check_btree(&table.name, root, has_rowids) {
+                rowids.insert(table.name.to_ascii_lowercase(), found);
+            }
+            if options.quick || options.pages.is_some() {
+                continue;
+            }
+            match CreateTable::try_from(table) {
+                Ok(def) if def.strict => checker.check_strict(&def, root),
+                _ => {}
+            }
+        }
+    }
+    for index in schema.iter().filter(|s| s.stype == SchemaType::Index) {
+        if let Some(root) = NonZeroU64::new(index.rootpage).filter(|_| wanted(&index.table_name)) {
+            let table = rowids.get(&index.table_name.to_ascii_lowercase());
+            let partial = CreateIndex::try_from(index).is_ok_and(|i| i.filter.is_some());
+            if let Some(entries) = checker.check_btree(&index.name, root, table.is_some()) {
+                // a partial index only has some of the rows
+                if !partial {
+                    checker.compare_index(&index.name, table.unwrap(), &entries);
+                }
+            }
+        }
+    }
+    if options.whole_file() {
+        checker.check_freelist(header.first_freelist_trunk(), header.freelist_count());
+        for page in 1..=checker.page_count {
+            if checker.used.contains(&page) {
+                continue;
+            }
+            // the pointer map might know what it used to be
+            let problem = match checker.ptrmap.is_some().then(|| file.ptrmap_entry(page)) {
+                Some(Ok(entry)) => format!(
+                    "Page {} is never used, though the pointer map says it's {}",
+                    page,
+                    entry.describe()
+                ),
+                _ => format!("Page {} is never used", page),
+            };
+            checker.problems.push(problem);
+        }
+    }
+    checker.check_ptrmap();
+    Ok(checker.problems)
+}
+
+/// Check the whole file, like `PRAGMA integrity_check`.
+pub fn integrity_check(file: &SqliteFile) -> Result<Vec<String>> {
+    check(file, &CheckOptions::new())
+}
+
+/// Check the whole file, but only count index entries rather than matching
+/// them up with rows, like `PRAGMA quick_check`.
+pub fn quick_check(file: &SqliteFile) -> Result<Vec<String>> {
+    check(file, CheckOptions::new().quick(true))
+}
+
+/// Entries of an index that have the same key.
+#[derive(Debug, PartialEq)]
+pub struct DuplicateKey {
+    pub key: Vec<Value>,
+    pub rowids: Vec<i64>,
+}
+
+/// Look for entries with the same key in the index called `name`. A unique
+/// index shouldn't have any, so finding some means the file is corrupt or
+/// whatever wrote it has a bug. Keys with a `NULL` in them don't count, as
+/// `NULL`s are never equal to each other. Other indexes can repeat keys, so
+/// there's nothing to look for in them.
+pub fn duplicate_keys(file: &SqliteFile, name: &str) -> Result<Vec<DuplicateKey>> {
+    let Some(index) = file
+        .get_schema()?
+        .into_iter()
+        .find(|s| s.stype == SchemaType::Index && s.name.eq_ignore_ascii_case(name))
+    else {
+        bail!("no such index: {}", name);
+    };
+    let Some(root) = NonZeroU64::new(index.rootpage) else {
+        bail!("index {} has no root page", name);
+    };
+    // the automatic indexes of PRIMARY KEY and UNIQUE constraints have no SQL
+    if !index.sql.is_empty() && !index.sql.parse::<CreateIndex>()?.unique {
+        return Ok(vec![]);
+    }
+
+    let mut records = vec![];
+    walk(file, root, |page, _| {
+        for cell in page.cells() {
+            let Some(payload) = cell.get_payload() else {
+                bail!("page {} is a table page in index {}", page.page_id, name);
+            };
+            records.push(file.read_record(payload)?);
+        }
+        Ok(())
+    })?;
+    // the last column is the rowid, the rest are the key
+    let key = |record: &[Value]| record.len().saturating_sub(1);
+    let compare = |a: &[Value], b: &[Value]| compare_prefix(a, b, &[], &[], file.functions());
+    records.sort_by(|a, b| compare(&a[..key(a)], &b[..key(b)]));
+
+    let mut duplicates: Vec<DuplicateKey> = vec![];
+    for pair in records.windows(2) {
+        let (a, b) = (&pair[0][..key(&pair[0])], &pair[1][..key(&pair[1])]);
+        if a.len() != b.len() || a.contains(&Value::Null) || compare(a, b) != Ordering::Equal {
+            continue;
+        }
+        let rowid = |record: &[Value]| match record.last() {
+            Some(Value::Integer(n)) => *n,
+            _ => 0,
+        };
+        match duplicates.last_mut() {
+            Some(dup) if dup.key == a => dup.rowids.push(rowid(&pair[1])),
+            _ => duplicates.push(DuplicateKey {
+                key: a.to_vec(),
+                rowids: vec![rowid(&pair[0]), rowid(&pair[1])],
+            }),
+        }
+    }
+    for dup in &mut duplicates {
+        dup.rowids.sort_unstable();
+    }
+    Ok(duplicates)
+}
+
+#[test]
+fn sample_is_intact() -> Result<()> {
+    let file = super::OpenOptions::new().open("sample.db")?;
+    assert_eq!(integrity_check(&file)?, Vec::<String>::new());
+    assert_eq!(quick_check(&file)?, Vec::<String>::new());
+    let options = CheckOptions::new().table("apples").pages(2..=2).clone();
+    assert_eq!(check(&file, &options)?, Vec::<String>::new());
+    Ok(())
+}
+
+#[test]
+fn strict_table_types() -> Result<()> {
+    let rows = vec![
+        vec![
+            Value::Integer(1),
+            Value::Integer(2),
+            Value::Integer(3),
+            "x".into(),
+        ],
+        vec![
+            Value::Integer(2),
+            "two".into(),
+            Value::Float(1.5),
+            Value::Null,
+        ],
+        vec![
+            Value::Integer(3),
+            Value::Null,
+            Value::Blob(vec![1]),
+            Value::Integer(4),
+        ],
+    ];
+    let file = super::fixtures::Fixture::new()
+        .table(
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, n INT, r REAL, s TEXT NOT NULL) STRICT",
+            rows,
+        )
+        .open()?;
+    assert_eq!(
+        integrity_check(&file)?,
+        [
+            "non-INT value in t.n",
+            "NULL value in t.s",
+            "non-REAL value in t.r",
+            "non-TEXT value in t.s",
+        ]
+    );
+    // quick_check only looks at the structure
+    assert!(quick_check(&file)?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn duplicate_keys_in_unique_indexes() -> Result<()> {
+    let rows = ["a", "b", "a"]
+        .iter()
+        .enumerate()
+        .map(|(i, name)| vec![Value::Integer(i as i64 + 1), (*name).into()])
+        .collect();
+    let file = super::fixtures::Fixture::new()
+        .table("CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT)", rows)
+        .index("CREATE INDEX t_name ON t (name)")
+        .index("CREATE UNIQUE INDEX t_unique_name ON t (name)")
+        .open()?;
+    assert!(duplicate_keys(&file, "t_name")?.is_empty());
+    assert_eq!(
+        duplicate_keys(&file, "t_unique_name")?,
+        [DuplicateKey {
+            key: vec!["a".into()],
+            rowids: vec![1, 3],
+        }]
+    );
+    Ok(())
+}
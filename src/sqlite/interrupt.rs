@@ -0,0 +1,102 @@
+//! Stopping queries from another thread, like `sqlite3_interrupt()`, and
+//! hearing from them as they go, like `sqlite3_progress_handler()`.
+//!
+//! Scans check the flag at each page they read, and joins at each row, so a
+//! query over a huge file stops soon after it's set rather than when it's
+//! done. Each of those checks is a step for the progress handler.
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use sqlite_starter_rust::{interrupt::Interrupted, OpenOptions};
+//! let db = OpenOptions::new().open("huge.db")?;
+//! let interrupt = db.interrupt_handle();
+//! std::thread::spawn(move || {
+//!     std::thread::sleep(Duration::from_secs(5));
+//!     interrupt.interrupt();
+//! });
+//! let result = db.prepare("SELECT * FROM a JOIN b ON a.x = b.y")?.rows();
+//! assert!(result.is_err_and(|e| e.is::<Interrupted>()));
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use anyhow::Result;
+
+/// A flag that makes the queries of a [`SqliteFile`][super::SqliteFile] fail
+/// with [`Interrupted`]. Clones share the flag, so one can be handed to
+/// another thread.
+#[derive(Debug, Clone, Default)]
+pub struct Interrupt(Arc<AtomicBool>);
+
+impl Interrupt {
+    /// Stop the queries that are running. The flag stays set, so queries
+    /// started after this fail too, until [`reset`][Self::reset].
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Let queries run again.
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Fail with [`Interrupted`] if the flag is set.
+    pub fn check(&self) -> Result<()> {
+        if self.is_interrupted() {
+            return Err(Interrupted.into());
+        }
+        Ok(())
+    }
+}
+
+/// The error a query fails with when it's interrupted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("interrupted")]
+pub struct Interrupted;
+
+/// Type of the handlers for
+/// [`set_progress_handler`][super::SqliteFile::set_progress_handler]. They
+/// return `true` to interrupt the query.
+pub type ProgressFn = dyn Fn() -> bool + Send + Sync;
+
+/// The progress handler, if there is one. Clones share it, so attached files
+/// call the same handler as the file they're attached to.
+#[derive(Clone, Default)]
+pub(crate) struct Progress(Arc<RwLock<Option<Handler>>>);
+
+struct Handler {
+    every: u64,
+    steps: AtomicU64,
+    f: Box<ProgressFn>,
+}
+
+impl Progress {
+    /// Call `f` every `every` steps from now on, or never if `every` is 0.
+    pub(crate) fn set(&self, every: u64, f: Box<ProgressFn>) {
+        *self.0.write().unwrap() = (every > 0).then(|| Handler {
+            every,
+            steps: AtomicU64::new(0),
+            f,
+        });
+    }
+
+    /// Count a step, calling the handler if it's due. Fails with
+    /// [`Interrupted`] if it says to stop.
+    pub(crate) fn step(&self) -> Result<()> {
+        let handler = self.0.read().unwrap();
+        let Some(handler) = &*handler else {
+            return Ok(());
+        };
+        let steps = handler.steps.fetch_add(1, Ordering::Relaxed) + 1;
+        if steps % handler.every == 0 && (handler.f)() {
+            return Err(Interrupted.into());
+        }
+        Ok(())
+    }
+}
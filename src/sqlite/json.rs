@@ -0,0 +1,611 @@
+//! Just enough JSON to write out what the informational commands report, for
+//! tools that would otherwise have to pick apart the text, to read back what
+//! `.import --json` is given, and for the `json_*()` SQL functions to look
+//! inside JSON kept in text columns.
+
+use std::fmt::{Display, Write};
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Error, Result};
+
+use super::analyze::SpaceUsage;
+use super::header::{DbHeader, TextEncoding};
+use super::integrity::DuplicateKey;
+use super::record::Value;
+use super::stats::Stats;
+use super::wal::{Change, ChangeKind};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Array(Vec<Json>),
+    /// Keys stay in the order they were given.
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// An object from `(key, value)` pairs.
+    pub fn object<'a>(fields: impl IntoIterator<Item = (&'a str, Json)>) -> Json {
+        Json::Object(fields.into_iter().map(|(k, v)| (k.to_owned(), v)).collect())
+    }
+    /// The value to store for this in a column. Booleans are 1 and 0 like in
+    /// SQL, and arrays and objects are kept as JSON text.
+    pub fn to_value(&self) -> Value {
+        match self {
+            Json::Null => Value::Null,
+            Json::Bool(b) => Value::Integer(*b as i64),
+            Json::Integer(n) => Value::Integer(*n),
+            Json::Float(x) => Value::Float(*x),
+            Json::String(s) => Value::String(s.clone()),
+            Json::Array(_) | Json::Object(_) => Value::String(self.to_string()),
+        }
+    }
+
+    /// What `json_type()` calls this.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Json::Null => "null",
+            Json::Bool(true) => "true",
+            Json::Bool(false) => "false",
+            Json::Integer(_) => "integer",
+            Json::Float(_) => "real",
+            Json::String(_) => "text",
+            Json::Array(_) => "array",
+            Json::Object(_) => "object",
+        }
+    }
+
+    /// Follow an SQLite JSON path like `$.a."b c"[2][#-1]` to what's there,
+    /// if anything. `[#-N]` counts from the end of an array. A path that
+    /// isn't well formed is an error.
+    pub fn path(&self, path: &str) -> Result<Option<&Json>> {
+        let bad = || anyhow!("bad JSON path: {:?}", path);
+        let mut rest = path.strip_prefix('$').ok_or_else(bad)?;
+        let mut json = self;
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix('.') {
+                let (key, after) = match after.strip_prefix('"') {
+                    Some(quoted) => {
+                        let end = quoted.find('"').ok_or_else(bad)?;
+                        (&quoted[..end], &quoted[end + 1..])
+                    }
+                    None => {
+                        let end = after.find(['.', '[']).unwrap_or(after.len());
+                        if end == 0 {
+                            return Err(bad());
+                        }
+                        after.split_at(end)
+                    }
+                };
+                rest = after;
+                let Json::Object(fields) = json else {
+                    return Ok(None);
+                };
+                match fields.iter().find(|(k, _)| k == key) {
+                    Some((_, value)) => json = value,
+                    None => return Ok(None),
+                }
+            } else if let Some(after) = rest.strip_prefix('[') {
+                let (index, after) = after.split_once(']').ok_or_else(bad)?;
+                rest = after;
+                // how far from the front, or with `#`, from the back
+                let (from_back, n): (bool, usize) = match index.strip_prefix('#') {
+                    Some("") => (true, 0),
+                    Some(back) => {
+                        let n = back.strip_prefix('-').and_then(|n| n.trim().parse().ok());
+                        (true, n.ok_or_else(bad)?)
+                    }
+                    None => (false, index.trim().parse().map_err(|_| bad())?),
+                };
+                let Json::Array(items) = json else {
+                    return Ok(None);
+                };
+                let i = match from_back {
+                    true => items.len().checked_sub(n),
+                    false => Some(n),
+                };
+                match i.and_then(|i| items.get(i)) {
+                    Some(item) => json = item,
+                    None => return Ok(None),
+                }
+            } else {
+                return Err(bad());
+            }
+        }
+        Ok(Some(json))
+    }
+}
+
+fn write_string(f: &mut std::fmt::Formatter<'_>, s: &str) -> std::fmt::Result {
+    f.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+/// Compact JSON, all on one line.
+impl Display for Json {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Json::Null => f.write_str("null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Integer(n) => write!(f, "{}", n),
+            // JSON has no NaN or infinity
+            Json::Float(x) if !x.is_finite() => f.write_str("null"),
+            Json::Float(x) => write!(f, "{:?}", x),
+            Json::String(s) => write_string(f, s),
+            Json::Array(items) => {
+                f.write_char('[')?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                f.write_char(']')
+            }
+            Json::Object(fields) => {
+                f.write_char('{')?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+impl From<bool> for Json {
+    fn from(b: bool) -> Self {
+        Json::Bool(b)
+    }
+}
+
+impl From<i64> for Json {
+    fn from(n: i64) -> Self {
+        Json::Integer(n)
+    }
+}
+
+impl From<u64> for Json {
+    fn from(n: u64) -> Self {
+        i64::try_from(n).map_or(Json::Float(n as f64), Json::Integer)
+    }
+}
+
+impl From<i32> for Json {
+    fn from(n: i32) -> Self {
+        Json::Integer(n.into())
+    }
+}
+
+impl From<u32> for Json {
+    fn from(n: u32) -> Self {
+        Json::Integer(n.into())
+    }
+}
+
+impl From<usize> for Json {
+    fn from(n: usize) -> Self {
+        (n as u64).into()
+    }
+}
+
+impl From<f64> for Json {
+    fn from(x: f64) -> Self {
+        Json::Float(x)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(s: &str) -> Self {
+        Json::String(s.to_owned())
+    }
+}
+
+impl From<String> for Json {
+    fn from(s: String) -> Self {
+        Json::String(s)
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(v: Option<T>) -> Self {
+        v.map_or(Json::Null, Into::into)
+    }
+}
+
+impl<T: Into<Json>> From<Vec<T>> for Json {
+    fn from(items: Vec<T>) -> Self {
+        Json::Array(items.into_iter().map(Into::into).collect())
+    }
+}
+
+/// Blobs become hex strings.
+impl From<&Value> for Json {
+    fn from(v: &Value) -> Self {
+        match v {
+            Value::Null => Json::Null,
+            Value::Integer(n) => Json::Integer(*n),
+            Value::Float(x) => Json::Float(*x),
+            Value::String(s) => Json::String(s.clone()),
+            Value::Blob(b) => Json::String(b.iter().map(|b| format!("{:02x}", b)).collect()),
+        }
+    }
+}
+
+/// `old` and `new` are objects keyed by column name, and left out when the
+/// row wasn't there.
+impl From<&Change> for Json {
+    fn from(c: &Change) -> Self {
+        let row = |values: &[Value]| {
+            Json::object(
+                c.columns
+                    .iter()
+                    .zip(values)
+                    .map(|(k, v)| (k.as_str(), v.into())),
+            )
+        };
+        let mut fields = vec![
+            ("table", c.table.as_str().into()),
+            ("op", c.kind.name().into()),
+            ("rowid", c.rowid.into()),
+        ];
+        if c.kind != ChangeKind::Insert {
+            fields.push(("old", row(&c.old)));
+        }
+        if c.kind != ChangeKind::Delete {
+            fields.push(("new", row(&c.new)));
+        }
+        Json::object(fields)
+    }
+}
+
+impl From<&Stats> for Json {
+    fn from(s: &Stats) -> Self {
+        Json::object([
+            ("pages_read", s.pages_read.into()),
+            ("cache_hits", s.cache_hits.into()),
+            ("cache_misses", s.cache_misses.into()),
+            ("cells_parsed", s.cells_parsed.into()),
+            ("rows_emitted", s.rows_emitted.into()),
+            ("bytes_read", s.bytes_read.into()),
+        ])
+    }
+}
+
+impl From<&SpaceUsage> for Json {
+    fn from(u: &SpaceUsage) -> Self {
+        Json::object([
+            ("name", u.name.as_str().into()),
+            ("pages", u.pages().into()),
+            ("interior_pages", u.interior_pages.into()),
+            ("leaf_pages", u.leaf_pages.into()),
+            ("overflow_pages", u.overflow_pages.into()),
+            ("entries", u.entries.into()),
+            ("payload_bytes", u.payload_bytes.into()),
+            ("overflow_bytes", u.overflow_bytes.into()),
+            ("free_bytes", u.free_bytes.into()),
+            ("fragmented_bytes", u.fragmented_bytes.into()),
+            ("fill_factor", u.fill_factor().into()),
+            ("leaf_fill", u.leaf_fill().into()),
+            ("underfilled_leaves", u.underfilled_leaves.into()),
+            ("reclaimable_pages", u.reclaimable_pages().into()),
+        ])
+    }
+}
+
+impl From<&DbHeader> for Json {
+    fn from(h: &DbHeader) -> Self {
+        let encoding = h.text_encoding().map(|e| match e {
+            TextEncoding::Utf8 => "UTF-8",
+            TextEncoding::Utf16le => "UTF-16le",
+            TextEncoding::Utf16be => "UTF-16be",
+        });
+        Json::object([
+            ("page_size", h.page_size().into()),
+            ("write_version", u32::from(h.write_version()).into()),
+            ("read_version", u32::from(h.read_version()).into()),
+            ("reserved_bytes", u32::from(h.reserved_bytes()).into()),
+            ("change_counter", h.change_counter().into()),
+            ("page_count", h.page_count().into()),
+            ("first_freelist_trunk", h.first_freelist_trunk().into()),
+            ("freelist_count", h.freelist_count().into()),
+            ("schema_cookie", h.schema_cookie().into()),
+            ("schema_format", h.schema_format().into()),
+            ("default_cache_size", h.default_cache_size().into()),
+            ("largest_root_page", h.largest_root_page().into()),
+            ("text_encoding", encoding.into()),
+            ("user_version", h.user_version().into()),
+            ("incremental_vacuum", h.incremental_vacuum().into()),
+            ("application_id", h.application_id().into()),
+            ("version_valid_for", h.version_valid_for().into()),
+            ("sqlite_version", h.sqlite_version().into()),
+        ])
+    }
+}
+
+impl From<&DuplicateKey> for Json {
+    fn from(d: &DuplicateKey) -> Self {
+        Json::object([
+            ("key", Json::Array(d.key.iter().map(Json::from).collect())),
+            ("rowids", d.rowids.clone().into()),
+        ])
+    }
+}
+
+/// How deep arrays and objects can go before parsing gives up, so a hostile
+/// file can't run the stack out.
+const MAX_DEPTH: usize = 1000;
+
+impl FromStr for Json {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parser = JsonParser { s, pos: 0 };
+        let json = parser.value(0)?;
+        parser.skip_space();
+        if parser.pos < s.len() {
+            bail!(
+                "unexpected {:?} after JSON at {}",
+                parser.rest(),
+                parser.pos
+            );
+        }
+        Ok(json)
+    }
+}
+
+struct JsonParser<'a> {
+    s: &'a str,
+    pos: usize,
+}
+
+impl JsonParser<'_> {
+    fn rest(&self) -> &str {
+        let rest = &self.s[self.pos..];
+        &rest[..rest.char_indices().nth(10).map_or(rest.len(), |(i, _)| i)]
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.s.as_bytes().get(self.pos).copied()
+    }
+
+    fn skip_space(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, c: u8) -> Result<()> {
+        self.skip_space();
+        if self.peek() != Some(c) {
+            bail!("expected '{}' at {} in JSON", c as char, self.pos);
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Json> {
+        if depth > MAX_DEPTH {
+            bail!("JSON nested too deeply");
+        }
+        self.skip_space();
+        let json = match self.peek() {
+            Some(b'{') => {
+                self.pos += 1;
+                let mut fields = vec![];
+                self.skip_space();
+                if self.peek() == Some(b'}') {
+                    self.pos += 1;
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    self.skip_space();
+                    let key = self.string()?;
+                    self.expect(b':')?;
+                    fields.push((key, self.value(depth + 1)?));
+                    self.skip_space();
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b'}') => break,
+                        _ => bail!("expected ',' or '}}' at {} in JSON", self.pos),
+                    }
+                }
+                self.pos += 1;
+                Json::Object(fields)
+            }
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = vec![];
+                self.skip_space();
+                if self.peek() == Some(b']') {
+                    self.pos += 1;
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value(depth + 1)?);
+                    self.skip_space();
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => break,
+                        _ => bail!("expected ',' or ']' at {} in JSON", self.pos),
+                    }
+                }
+                self.pos += 1;
+                Json::Array(items)
+            }
+            Some(b'"') => Json::String(self.string()?),
+            Some(b'-' | b'0'..=b'9') => self.number()?,
+            _ => {
+                for (word, json) in [
+                    ("null", Json::Null),
+                    ("true", Json::Bool(true)),
+                    ("false", Json::Bool(false)),
+                ] {
+                    if self.s[self.pos..].starts_with(word) {
+                        self.pos += word.len();
+                        return Ok(json);
+                    }
+                }
+                if self.pos == self.s.len() {
+                    bail!("unexpected end of JSON");
+                }
+                bail!("unexpected {:?} at {} in JSON", self.rest(), self.pos);
+            }
+        };
+        Ok(json)
+    }
+
+    fn number(&mut self) -> Result<Json> {
+        let start = self.pos;
+        let mut float = false;
+        while let Some(c) = self.peek() {
+            match c {
+                b'0'..=b'9' | b'-' | b'+' => {}
+                b'.' | b'e' | b'E' => float = true,
+                _ => break,
+            }
+            self.pos += 1;
+        }
+        let text = &self.s[start..self.pos];
+        if !float {
+            if let Ok(n) = text.parse() {
+                return Ok(Json::Integer(n));
+            }
+        }
+        text.parse()
+            .map(Json::Float)
+            .map_err(|_| anyhow!("bad number {:?} in JSON", text))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        if self.peek() != Some(b'"') {
+            bail!("expected a string at {} in JSON", self.pos);
+        }
+        self.pos += 1;
+        let mut out = String::new();
+        loop {
+            let rest = &self.s[self.pos..];
+            let Some(end) = rest.find(['"', '\\']) else {
+                bail!("unterminated string in JSON");
+            };
+            out.push_str(&rest[..end]);
+            self.pos += end + 1;
+            if rest.as_bytes()[end] == b'"' {
+                return Ok(out);
+            }
+            let Some(c) = self.peek() else {
+                bail!("unterminated string in JSON");
+            };
+            self.pos += 1;
+            match c {
+                b'"' | b'\\' | b'/' => out.push(c as char),
+                b'b' => out.push('\u{8}'),
+                b'f' => out.push('\u{c}'),
+                b'n' => out.push('\n'),
+                b'r' => out.push('\r'),
+                b't' => out.push('\t'),
+                b'u' => {
+                    let mut c = self.hex4()?;
+                    if (0xd800..0xdc00).contains(&c) && self.s[self.pos..].starts_with("\\u") {
+                        self.pos += 2;
+                        let low = self.hex4()?;
+                        c = match low {
+                            0xdc00..=0xdfff => 0x10000 + ((c - 0xd800) << 10) + (low - 0xdc00),
+                            _ => 0xfffd,
+                        };
+                    }
+                    out.push(char::from_u32(c).unwrap_or(char::REPLACEMENT_CHARACTER));
+                }
+                _ => bail!("bad escape '\\{}' in JSON", c as char),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32> {
+        let hex = self.s.get(self.pos..self.pos + 4).unwrap_or_default();
+        let c = u32::from_str_radix(hex, 16)
+            .ok()
+            .filter(|_| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+            .ok_or_else(|| anyhow!("bad \\u escape at {} in JSON", self.pos))?;
+        self.pos += 4;
+        Ok(c)
+    }
+}
+
+#[test]
+fn write_json() {
+    let json = Json::object([
+        ("name", "a \"b\"\n\u{1}".into()),
+        ("n", 3i64.into()),
+        ("x", 1.0.into()),
+        ("none", Json::from(None::<i64>)),
+        ("list", vec![true, false].into()),
+        ("nan", f64::NAN.into()),
+    ]);
+    assert_eq!(
+        json.to_string(),
+        r#"{"name":"a \"b\"\n\u0001","n":3,"x":1.0,"none":null,"list":[true,false],"nan":null}"#
+    );
+}
+
+#[test]
+fn read_json() -> Result<()> {
+    let json: Json =
+        r#" [{"a": -1.5e2, "b": [true, null], "c": "x\"\u00e9\ud83d\ude00"}, {}] "#.parse()?;
+    assert_eq!(
+        json,
+        Json::Array(vec![
+            Json::object([
+                ("a", Json::Float(-150.0)),
+                ("b", vec![Json::Bool(true), Json::Null].into()),
+                ("c", "x\"é😀".into()),
+            ]),
+            Json::Object(vec![]),
+        ])
+    );
+    assert_eq!(
+        "12345678901234567890".parse::<Json>()?,
+        Json::Float(1.2345678901234567e19)
+    );
+    for bad in ["", "[1,]", "{\"a\" 1}", "\"abc", "[1] 2", "tru"] {
+        assert!(bad.parse::<Json>().is_err(), "{}", bad);
+    }
+    Ok(())
+}
+
+#[test]
+fn json_paths() -> Result<()> {
+    let json: Json = r#"{"a": {"b c": [1, 2.5, "x"]}, "d": true}"#.parse()?;
+    let at = |path: &str| json.path(path).map(|j| j.cloned());
+    assert_eq!(at("$")?, Some(json.clone()));
+    assert_eq!(at("$.a.\"b c\"[1]")?, Some(Json::Float(2.5)));
+    assert_eq!(at("$.a.\"b c\"[#-1]")?, Some("x".into()));
+    assert_eq!(at("$.a.\"b c\"[#-4]")?, None);
+    assert_eq!(at("$.a.\"b c\"[3]")?, None);
+    assert_eq!(at("$.d.e")?, None);
+    assert_eq!(at("$.nope")?, None);
+    assert_eq!(json.path("$.d")?.map(Json::type_name), Some("true"));
+    for bad in ["", "a", "$.", "$[x]", "$[1", "$.\"a", "$a"] {
+        assert!(json.path(bad).is_err(), "{}", bad);
+    }
+    Ok(())
+}
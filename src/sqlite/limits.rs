@@ -0,0 +1,72 @@
+//! Limits on how big SQL can get, like `sqlite3_limit()`, for programs that
+//! run queries they didn't write. Past them, SQL fails to parse or plan
+//! instead of taking all the memory or stack there is.
+//!
+//! ```no_run
+//! # use sqlite_starter_rust::{limits::Limits, OpenOptions};
+//! let limits = Limits {
+//!     sql_length: 10_000,
+//!     expr_depth: 100,
+//!     ..Limits::default()
+//! };
+//! let db = OpenOptions::new().limits(limits).open("sample.db")?;
+//! let nested = format!("{}1{}", "(".repeat(200), ")".repeat(200));
+//! assert!(db.prepare(&format!("SELECT {} FROM apples", nested)).is_err());
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+/// The limits, which default to SQLite's.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// Bytes in one statement.
+    pub sql_length: usize,
+    /// Levels of an expression tree. `a + b + c` has three, and brackets
+    /// count as a level while they're parsed, so `((a))` does too.
+    pub expr_depth: usize,
+    /// Columns in a result, with `*` spelled out.
+    pub columns: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            sql_length: 1_000_000_000,
+            expr_depth: 1000,
+            columns: 2000,
+        }
+    }
+}
+
+#[test]
+fn enforce_limits() -> anyhow::Result<()> {
+    use super::sql::Statement;
+    let limits = Limits {
+        sql_length: 1_000_000,
+        expr_depth: 10,
+        columns: 3,
+    };
+    let parse = |sql: &str| Statement::parse_with(sql, &limits);
+    let chain = |n: usize| vec!["1"; n].join(" + ");
+    // a chain of n terms is n levels deep
+    assert!(parse(&format!("SELECT {} FROM t", chain(10))).is_ok());
+    assert!(parse(&format!("SELECT {} FROM t", chain(11))).is_err());
+    assert!(parse("SELECT (((((((((1))))))))) FROM t").is_ok());
+    assert!(parse("SELECT ((((((((((1)))))))))) FROM t").is_err());
+    assert!(parse("SELECT NOT NOT NOT NOT NOT NOT NOT NOT NOT NOT 1 FROM t").is_err());
+    assert!(parse(&format!("SELECT a FROM t WHERE {}", "x".repeat(1_000_000))).is_err());
+    assert!(parse("SELECT a, b, c FROM t").is_ok());
+    assert!(parse("SELECT a, b, c, d FROM t").is_err());
+    // runaway input fails rather than overflowing the stack
+    let deep = format!("SELECT {}1 FROM t", "-(".repeat(100_000));
+    assert!(parse(&deep).is_err());
+
+    // `*` is counted once it's spelled out
+    let limits = Limits {
+        columns: 2,
+        ..limits
+    };
+    let file = super::OpenOptions::new().limits(limits).open("sample.db")?;
+    assert!(file.prepare("SELECT * FROM apples").is_err());
+    assert_eq!(file.prepare("SELECT id FROM apples")?.rows()?.len(), 4);
+    Ok(())
+}
@@ -0,0 +1,188 @@
+//! The POSIX advisory locks SQLite takes on a database file, so we take part
+//! in its locking instead of locking the whole file some other way that
+//! sqlite3 never looks at.
+//!
+//! SQLite locks bytes past the 1 GiB mark, which no page ever uses: a reader
+//! holds a read lock on one of the "shared" bytes, and a writer takes a write
+//! lock on the "reserved" byte when it starts, then on all the shared bytes
+//! when it writes to the file.
+//!
+//! These are per process locks, so they don't keep our own connections apart,
+//! and closing any handle on the file lets go of them, just like in SQLite.
+
+use std::fs::File;
+use std::io;
+use std::os::raw::{c_int, c_short};
+use std::os::unix::io::AsRawFd;
+
+/// The byte a writer locks while waiting for readers to finish.
+pub const PENDING_BYTE: i64 = 0x4000_0000;
+/// The byte a writer locks while it has changes that aren't in the file yet.
+pub const RESERVED_BYTE: i64 = PENDING_BYTE + 1;
+/// The bytes readers lock.
+pub const SHARED_FIRST: i64 = PENDING_BYTE + 2;
+pub const SHARED_SIZE: i64 = 510;
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::os::raw::{c_int, c_short};
+
+    pub const F_GETLK: c_int = 5;
+    pub const F_SETLK: c_int = 6;
+    pub const F_SETLKW: c_int = 7;
+    pub const F_RDLCK: c_short = 0;
+    pub const F_WRLCK: c_short = 1;
+    pub const F_UNLCK: c_short = 2;
+
+    #[repr(C)]
+    pub struct Flock {
+        pub l_type: c_short,
+        pub l_whence: c_short,
+        pub l_start: i64,
+        pub l_len: i64,
+        pub l_pid: c_int,
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod sys {
+    use std::os::raw::{c_int, c_short};
+
+    pub const F_GETLK: c_int = 7;
+    pub const F_SETLK: c_int = 8;
+    pub const F_SETLKW: c_int = 9;
+    pub const F_RDLCK: c_short = 1;
+    pub const F_WRLCK: c_short = 3;
+    pub const F_UNLCK: c_short = 2;
+
+    #[repr(C)]
+    pub struct Flock {
+        pub l_start: i64,
+        pub l_len: i64,
+        pub l_pid: c_int,
+        pub l_type: c_short,
+        pub l_whence: c_short,
+    }
+}
+
+use sys::{Flock, F_GETLK, F_SETLK, F_SETLKW};
+pub use sys::{F_RDLCK, F_UNLCK, F_WRLCK};
+
+const SEEK_SET: c_short = 0;
+
+extern "C" {
+    fn fcntl(fd: c_int, cmd: c_int, ...) -> c_int;
+}
+
+fn flock(kind: c_short, start: i64, len: i64) -> Flock {
+    Flock {
+        l_type: kind,
+        l_whence: SEEK_SET,
+        l_start: start,
+        l_len: len,
+        l_pid: 0,
+    }
+}
+
+/// Lock `len` bytes from `start` with `cmd`. A lock someone else holds fails
+/// with [`io::ErrorKind::WouldBlock`].
+fn set_lock(file: &File, cmd: c_int, kind: c_short, start: i64, len: i64) -> io::Result<()> {
+    let lock = flock(kind, start, len);
+    loop {
+        // SAFETY: `lock` is a valid flock for the call to read, and the file
+        // descriptor is open for as long as `file` is.
+        if unsafe { fcntl(file.as_raw_fd(), cmd, &lock as *const Flock) } == 0 {
+            return Ok(());
+        }
+        let e = io::Error::last_os_error();
+        match e.kind() {
+            io::ErrorKind::Interrupted => {}
+            // some systems say EACCES rather than EAGAIN
+            io::ErrorKind::PermissionDenied => return Err(io::ErrorKind::WouldBlock.into()),
+            _ => return Err(e),
+        }
+    }
+}
+
+/// Take a read lock on the shared bytes the way SQLite does, going through
+/// the pending byte so a writer waiting for readers to finish isn't starved.
+/// Without `wait`, fails with [`io::ErrorKind::WouldBlock`] if a writer has
+/// the file locked.
+pub fn lock_shared(file: &File, wait: bool) -> io::Result<()> {
+    let cmd = if wait { F_SETLKW } else { F_SETLK };
+    set_lock(file, cmd, F_RDLCK, PENDING_BYTE, 1)?;
+    let shared = set_lock(file, cmd, F_RDLCK, SHARED_FIRST, SHARED_SIZE);
+    set_lock(file, F_SETLK, F_UNLCK, PENDING_BYTE, 1)?;
+    shared
+}
+
+/// Whether another process holds the reserved byte, i.e. is in the middle of
+/// a transaction.
+pub fn is_reserved(file: &File) -> io::Result<bool> {
+    let mut lock = flock(F_WRLCK, RESERVED_BYTE, 1);
+    // SAFETY: F_GETLK writes the lock that's in the way, if any, into `lock`,
+    // which lives until the call returns.
+    if unsafe { fcntl(file.as_raw_fd(), F_GETLK, &mut lock as *mut Flock) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(lock.l_type != F_UNLCK)
+}
+
+/// Lock bytes through an open file description lock, which conflicts with
+/// our own process's locks, unlike plain fcntl locks, so it can stand in
+/// for sqlite3 in another process.
+#[cfg(all(test, target_os = "linux"))]
+fn hold(file: &File, kind: c_short, start: i64, len: i64) {
+    const F_OFD_SETLK: c_int = 37;
+    let lock = flock(kind, start, len);
+    // SAFETY: as in `set_lock`
+    assert_eq!(
+        unsafe { fcntl(file.as_raw_fd(), F_OFD_SETLK, &lock as *const Flock) },
+        0
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn writers_block_opening() -> anyhow::Result<()> {
+    use super::{OpenError, OpenOptions};
+    let path = std::env::temp_dir().join(format!("locked-{}.db", std::process::id()));
+    std::fs::copy("sample.db", &path)?;
+    let writer = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)?;
+    hold(&writer, F_WRLCK, SHARED_FIRST, SHARED_SIZE);
+    let locked = OpenOptions::new().open(&path);
+    hold(&writer, F_RDLCK, SHARED_FIRST, SHARED_SIZE);
+    let shared = OpenOptions::new().open(&path);
+    let _ = std::fs::remove_file(&path);
+    let e = locked.err().expect("opened a file being written");
+    assert!(matches!(e.downcast_ref(), Some(OpenError::WouldBlock)));
+    // other readers don't get in the way
+    shared?;
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn journals_of_open_transactions_are_not_hot() -> anyhow::Result<()> {
+    use super::{OpenError, OpenOptions};
+    let path = std::env::temp_dir().join(format!("reserved-{}.db", std::process::id()));
+    let journal = path.with_extension("db-journal");
+    std::fs::copy("sample.db", &path)?;
+    std::fs::write(&journal, [0xd9, 0xd5, 0x05, 0xf9, 0x20, 0xa1, 0x63, 0xd7])?;
+    let hot = OpenOptions::new().open(&path);
+    let writer = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)?;
+    hold(&writer, F_WRLCK, RESERVED_BYTE, 1);
+    let reserved = OpenOptions::new().open(&path);
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_file(&journal);
+    let e = hot.err().expect("opened a file with a hot journal");
+    assert!(matches!(e.downcast_ref(), Some(OpenError::HotJournal)));
+    reserved?;
+    Ok(())
+}
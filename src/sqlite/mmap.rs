@@ -0,0 +1,88 @@
+//! Read-only memory maps, so pages can be used straight out of the file
+//! instead of being copied into a buffer first.
+
+use std::ffi::c_void;
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+use std::os::raw::c_int;
+use std::os::unix::io::AsRawFd;
+
+const PROT_READ: c_int = 1;
+const MAP_SHARED: c_int = 1;
+
+extern "C" {
+    fn mmap(
+        addr: *mut c_void,
+        len: usize,
+        prot: c_int,
+        flags: c_int,
+        fd: c_int,
+        offset: i64,
+    ) -> *mut c_void;
+    fn munmap(addr: *mut c_void, len: usize) -> c_int;
+}
+
+/// A whole file mapped into memory, read only.
+pub struct Mmap {
+    ptr: *mut c_void,
+    len: usize,
+}
+
+impl Mmap {
+    /// Map all of `file`. Returns `None` for an empty file, which can't be mapped.
+    ///
+    /// # Safety
+    ///
+    /// The map is shared with the file, so the caller has to make sure nothing
+    /// truncates the file while the map is alive. Reading a page that's been
+    /// cut off raises `SIGBUS` instead of returning an error. Writes by other
+    /// processes don't break anything, but show up in the map as they happen.
+    pub unsafe fn map(file: &File) -> io::Result<Option<Self>> {
+        let len = file.metadata()?.len() as usize;
+        if len == 0 {
+            return Ok(None);
+        }
+        // SAFETY: we ask for a fresh read-only mapping of a file descriptor we own,
+        // and check for failure before using the pointer. Our caller promised
+        // the file won't shrink under it.
+        let ptr = unsafe {
+            mmap(
+                std::ptr::null_mut(),
+                len,
+                PROT_READ,
+                MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr as isize == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Some(Self { ptr, len }))
+    }
+}
+
+// SAFETY: the mapping is read only, so sharing it between threads is no
+// different from sharing a `&[u8]`.
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: the mapping is `len` bytes long and lives until we're dropped,
+        // and the contract of `map` keeps the file at least that long.
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        // SAFETY: ptr and len came from a successful mmap call.
+        unsafe {
+            munmap(self.ptr, self.len);
+        }
+    }
+}
@@ -1,428 +1,2873 @@
-use anyhow::{anyhow, bail, Error, Result};
-use nom::IResult;
-use nom::{
-    combinator::map_res,
-    multi::count,
-    number::complete::{be_u16, be_u32, u8},
-    sequence::tuple,
-};
-use regex::RegexBuilder;
-use std::cell::RefCell;
-use std::io::{Read, Seek, SeekFrom};
-use std::num::NonZeroU64;
-use std::str::FromStr;
-use std::{fs::File, ops::Deref};
-
-use self::cells::Cell;
-
-pub(crate) mod cells;
-pub(crate) mod record;
-pub(crate) mod varint;
-
-/// An SQLite database file. Top level thingy that gets everything else.
-pub struct SqliteFile {
-    file: RefCell<File>,
-    page_size: u16,
-    page1: Page,
-}
-
-impl SqliteFile {
-    /// Create an SQLite file from a regular [File][std::fs::File].
-    pub fn new(mut file: File) -> Result<Self> {
-        file.seek(SeekFrom::Start(16))?;
-        let page_size = {
-            let mut buf = [0u8; 2];
-            file.read_exact(&mut buf[..])?;
-            u16::from_be_bytes(buf)
-        };
-        file.seek(SeekFrom::Start(0))?;
-        let mut data = vec![0u8; page_size as usize];
-        file.by_ref().read_exact(&mut data)?;
-        let (_, header) = parse_btree_header(&data[100..]).map_err(|_| anyhow!("parse header"))?;
-
-        Ok(Self {
-            file: RefCell::new(file),
-            page_size,
-            page1: Page {
-                page_id: 1,
-                data,
-                header,
-            },
-        })
-    }
-
-    /// Get the page size.
-    pub fn page_size(&self) -> u16 {
-        self.page_size
-    }
-
-    /// Get a page. `page_id` starts at 1.
-    pub fn get_page(&self, page_id: NonZeroU64) -> Result<Page> {
-        let page_id = page_id.get();
-        let mut data = vec![0u8; self.page_size as usize];
-        self.file.borrow_mut().seek(SeekFrom::Start(
-            ((page_id - 1) * self.page_size as u64) as u64,
-        ))?;
-        self.file.borrow_mut().read_exact(&mut data[..])?;
-        let hdata = if page_id == 1 {
-            &data[100..]
-        } else {
-            &data[..]
-        };
-        let (_, header) =
-            parse_btree_header(hdata).map_err(|e| anyhow!("parse header: {:?}", e))?;
-        Ok(Page {
-            page_id,
-            data,
-            header,
-        })
-    }
-
-    pub fn get_schema(&self) -> Vec<Schema> {
-        self.page1
-            .cells()
-            .map(|c| {
-                let row = c.get_payload().unwrap().parse().unwrap().1;
-                Schema {
-                    stype: row[0].to_string().parse().unwrap(),
-                    name: row[1].to_string(),
-                    table_name: row[2].to_string(),
-                    rootpage: u64::from(row[3].clone()),
-                    sql: row[4].to_string(),
-                }
-            })
-            .collect()
-    }
-}
-
-#[derive(Clone, Copy, Debug)]
-pub enum SchemaType {
-    Table,
-    Index,
-    View,
-    Trigger,
-}
-
-impl FromStr for SchemaType {
-    type Err = anyhow::Error;
-
-    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
-        use SchemaType::*;
-        match s {
-            "table" => Ok(Table),
-            "index" => Ok(Index),
-            "view" => Ok(View),
-            "trigger" => Ok(Trigger),
-            _ => bail!("schema type must be table, index, view or trigger"),
-        }
-    }
-}
-
-#[derive(Debug)]
-pub struct Schema {
-    pub stype: SchemaType,
-    pub name: String,
-    pub table_name: String,
-    pub rootpage: u64,
-    pub sql: String,
-}
-
-pub struct Page {
-    pub page_id: u64,
-    pub data: Vec<u8>,
-    pub header: BtreeHeader,
-}
-
-/// Iterates over the cells in a page.
-pub struct CellIter<'p> {
-    page: &'p Page,
-    ptr_array: &'p [u8],
-}
-
-impl<'p> Iterator for CellIter<'p> {
-    type Item = Cell<'p>;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        let (input, ptr) = be_u16::<&[u8], ()>(self.ptr_array).ok()?;
-        let data = &self.page[ptr as usize..];
-        let (_, cell) = self.page.header.parse_cell(data).ok()?;
-        self.ptr_array = input;
-        Some(cell)
-    }
-}
-
-impl Page {
-    pub fn cells<'p>(&'p self) -> CellIter<'p> {
-        // start of cell pointer array.
-        // First page contains 100 byte file header.
-        // Page header is 8 bytes if a leaf page or 12 bytes if interior.
-        // I assume the first page is a leaf page, which is usually true unless you have a crapload of tables.
-        let start = if self.page_id == 1 {
-            108
-        } else if self.header.kind.is_interior() {
-            12
-        } else {
-            8
-        };
-        let count = self.header.cell_count as usize;
-        let ptr_array = &self[start..count * 2 + start];
-        CellIter {
-            page: self,
-            ptr_array,
-        }
-    }
-}
-
-impl Deref for Page {
-    type Target = [u8];
-
-    fn deref(&self) -> &Self::Target {
-        &self.data
-    }
-}
-
-/// B-Tree page type
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum PageKind {
-    IndexInterior,
-    TableInterior,
-    IndexLeaf,
-    TableLeaf,
-}
-
-impl PageKind {
-    const fn is_interior(self) -> bool {
-        match self {
-            Self::IndexInterior | Self::TableInterior => true,
-            _ => false,
-        }
-    }
-}
-
-impl TryFrom<u8> for PageKind {
-    type Error = Error;
-
-    fn try_from(value: u8) -> std::result::Result<Self, Self::Error> {
-        use PageKind::*;
-        match value {
-            2 => Ok(IndexInterior),
-            5 => Ok(TableInterior),
-            10 => Ok(IndexLeaf),
-            13 => Ok(TableLeaf),
-            _ => bail!("invalid b-tree page type: {}", value),
-        }
-    }
-}
-
-/// Header of a B-tree page.
-pub struct BtreeHeader {
-    /// Page type
-    pub kind: PageKind,
-    /// Offset to first freeblock in the page, or 0 if none.
-    pub first_freeblock: u16,
-    /// Number of cells on the page.
-    pub cell_count: u16,
-    /// Start of cell content area
-    pub cell_contents: u16,
-    /// Number of fragmented free bytes.
-    pub fragmented_free_bytes: u8,
-    /// Child page whose keys are greater than the keys on this page.
-    /// Only exists if it's an internal page.
-    pub rightmost_pointer: Option<u32>,
-}
-
-fn parse_btree_header(input: &[u8]) -> IResult<&[u8], BtreeHeader> {
-    let (
-        input,
-        (
-            kind,
-            first_freeblock,
-            cell_count,
-            cell_contents,
-            fragmented_free_bytes,
-            rightmost_pointer,
-        ),
-    ) = tuple((
-        map_res(u8, |n| PageKind::try_from(n)),
-        be_u16,
-        be_u16,
-        be_u16,
-        u8,
-        be_u32,
-    ))(input)?;
-    let rightmost_pointer = kind.is_interior().then_some(rightmost_pointer);
-    Ok((
-        input,
-        BtreeHeader {
-            kind,
-            first_freeblock,
-            cell_contents,
-            cell_count,
-            fragmented_free_bytes,
-            rightmost_pointer,
-        },
-    ))
-}
-
-pub fn cell_pointers(input: &[u8], n: usize) -> IResult<&[u8], Vec<u16>> {
-    count(be_u16, n)(input)
-}
-
-/// Compiled `SELECT` statement
-#[derive(Debug, PartialEq)]
-pub struct Select {
-    pub name: String,
-    pub columns: SelectColumns,
-}
-
-#[derive(Debug, PartialEq)]
-pub enum SelectColumns {
-    Columns(Vec<String>),
-    Count,
-}
-
-/// Compiled `CREATE TABLE` statement
-#[derive(Debug, PartialEq)]
-pub struct CreateTable {
-    pub name: String,
-    pub columns: Vec<String>,
-    pub key: Option<String>,
-}
-
-impl CreateTable {
-    /// Get index of corresponding columns in a [`Select`]
-    pub fn select(&self, sel: &Select) -> Vec<usize> {
-        match &sel.columns {
-            SelectColumns::Columns(cols) => cols
-                .iter()
-                .flat_map(|sc| self.columns.iter().position(|cc| cc == sc))
-                .collect(),
-            SelectColumns::Count => Vec::new(),
-        }
-    }
-}
-
-impl TryFrom<&Schema> for CreateTable {
-    type Error = Error;
-
-    fn try_from(value: &Schema) -> std::result::Result<Self, Self::Error> {
-        value.sql.parse()
-    }
-}
-
-impl FromStr for Select {
-    type Err = anyhow::Error;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let rx = RegexBuilder::new(r"SELECT ([A-Za-z, \(\)\*]+) FROM ([A-Za-z]+)")
-            .case_insensitive(true)
-            .build()?;
-        let caps = rx
-            .captures(s)
-            .ok_or_else(|| anyhow!("failed to parse SELECT: {:?}", s))?;
-        let name = caps.get(2).unwrap().as_str().to_owned();
-        let columns = caps.get(1).unwrap();
-        if columns.as_str().eq_ignore_ascii_case("count(*)") {
-            return Ok(Select {
-                name,
-                columns: SelectColumns::Count,
-            });
-        }
-        let columns: Vec<String> = columns.as_str().split(", ").map(String::from).collect();
-        Ok(Select {
-            name,
-            columns: SelectColumns::Columns(columns),
-        })
-    }
-}
-
-impl FromStr for CreateTable {
-    type Err = anyhow::Error;
-
-    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
-        let rx = RegexBuilder::new(r"create\s+table\s+(?P<name>\w+)\s*\(\s*(?P<columns>[^\)]*)\)")
-            .case_insensitive(true)
-            .build()?;
-        let caps = rx
-            .captures(s)
-            .ok_or_else(|| anyhow!("failed to parse CREATE TABLE"))?;
-        let name = caps.name("name").unwrap().as_str().to_owned();
-        let columns: Vec<_> = caps
-            .name("columns")
-            .unwrap()
-            .as_str()
-            .split(",")
-            .map(|s| s.trim())
-            .collect();
-        let colnames: Vec<_> = columns
-            .iter()
-            .map(|s| s.split(" ").next().unwrap().to_string())
-            .collect();
-        let mut table = CreateTable {
-            name,
-            columns: colnames,
-            key: None,
-        };
-        for (i, col) in columns.iter().enumerate() {
-            if col.contains("primary key") {
-                table.key = Some(table.columns[i].clone());
-                break;
-            }
-        }
-        Ok(table)
-    }
-}
-
-#[test]
-fn sql_create_table() -> Result<()> {
-    let sql = "CREATE TABLE apples
-    (
-            id integer primary key autoincrement,
-            name text,
-            color text
-    )";
-    let table: CreateTable = sql.parse()?;
-    let expected = CreateTable {
-        name: "apples".to_string(),
-        columns: vec!["id".to_owned(), "name".to_owned(), "color".to_owned()],
-        key: Some("id".to_owned()),
-    };
-    assert_eq!(table, expected);
-    Ok(())
-}
-
-#[test]
-fn sql_select() -> Result<()> {
-    let sql = "SELECT name FROM apples";
-    let sel: Select = sql.parse()?;
-    let expected = Select {
-        name: "apples".to_owned(),
-        columns: SelectColumns::Columns(vec!["name".to_owned()]),
-    };
-    assert_eq!(sel, expected);
-    Ok(())
-}
-
-#[test]
-fn sql_multi_select() -> Result<()> {
-    let sql = "SELECT name, description FROM apples";
-    let sel: Select = sql.parse()?;
-    let expected = Select {
-        name: "apples".to_owned(),
-        columns: SelectColumns::Columns(vec!["name".to_owned(), "description".to_owned()]),
-    };
-    assert_eq!(sel, expected);
-    Ok(())
-}
-
-#[test]
-fn sql_select_count() -> Result<()> {
-    let sql = "SELECT COUNT(*) FROM apples";
-    let sel: Select = sql.parse()?;
-    let expected = Select {
-        name: "apples".to_owned(),
-        columns: SelectColumns::Count,
-    };
-    assert_eq!(sel, expected);
-    Ok(())
-}
+use anyhow::{anyhow, bail, Error, Result};
+use nom::IResult;
+use nom::{
+    combinator::map_res,
+    multi::count,
+    number::complete::{be_u16, be_u32, u8},
+    sequence::tuple,
+};
+use regex::RegexBuilder;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::num::NonZeroU64;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::{fs::File, ops::Deref};
+
+use self::auth::{Action, Authorization, AuthorizerFn};
+use self::cache::PageCache;
+use self::casefold::CaseFolding;
+use self::cast::Affinity;
+use self::cells::Cell;
+use self::collation::Collation;
+use self::functions::Functions;
+use self::header::DbHeader;
+use self::interrupt::{Interrupt, Progress};
+use self::json::Json;
+use self::limits::Limits;
+use self::record::Value;
+use self::sql::{Expr, Parser};
+use self::stats::{Counters, Stats};
+use self::temp::TempTable;
+use self::vfs::{MemoryFile, OsVfs, Vfs, VfsFile};
+
+/// A `tracing` event at trace level. Compiles to nothing without the `trace`
+/// feature.
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "trace")]
+        tracing::trace!($($arg)*);
+    };
+}
+
+/// Enter a trace level span until the end of the enclosing block.
+macro_rules! trace_span {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "trace")]
+        let _span = tracing::trace_span!($($arg)*).entered();
+    };
+}
+
+pub mod analyze;
+pub mod arith;
+pub mod auth;
+pub mod autoindex;
+pub mod btree;
+pub mod cache;
+pub mod casefold;
+pub mod cast;
+pub mod cells;
+#[cfg(feature = "sqlcipher")]
+pub mod cipher;
+pub mod collation;
+pub mod compare;
+pub mod complete;
+#[cfg(feature = "decimal")]
+pub mod decimal;
+#[cfg(any(test, feature = "fixtures"))]
+pub mod fixtures;
+pub mod fts;
+pub mod functions;
+pub mod header;
+pub mod http;
+pub mod inspect;
+pub mod integrity;
+pub mod interrupt;
+pub mod json;
+pub mod limits;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub mod lock;
+#[cfg(all(feature = "mmap", unix, target_pointer_width = "64"))]
+pub mod mmap;
+pub mod pagemap;
+pub mod prepared;
+pub mod ptrmap;
+pub mod query;
+pub mod record;
+pub mod rowset;
+pub mod search;
+pub mod sql;
+pub mod sqlar;
+pub mod stats;
+pub mod temp;
+pub mod unicode61;
+pub mod varint;
+pub mod vfs;
+pub mod wal;
+
+/// An SQLite database file. Top level thingy that gets everything else.
+///
+/// It can be shared between threads: pages are read with positioned reads,
+/// so nothing needs the file's cursor, and the cache is behind a lock.
+pub struct SqliteFile {
+    file: Box<dyn VfsFile>,
+    page_size: u32,
+    usable_size: usize,
+    cache: Mutex<PageCache>,
+    /// File change counter as of the last time we checked.
+    change_counter: AtomicU32,
+    /// The file can't change, so there's nothing to check.
+    immutable: bool,
+    case_folding: CaseFolding,
+    functions: Functions,
+    limits: Limits,
+    authorizer: Option<Arc<AuthorizerFn>>,
+    /// Tables made with [`materialize`][Self::materialize], by lowercase name.
+    temp_tables: RwLock<HashMap<String, Arc<TempTable>>>,
+    /// Tables queries have used, parsed from the schema. Thrown away along
+    /// with the cached pages.
+    tables: Mutex<query::TableCache>,
+    /// Files added with [`attach`][Self::attach], by lowercase schema name.
+    attached: RwLock<HashMap<String, Arc<SqliteFile>>>,
+    stats: Counters,
+    interrupt: Interrupt,
+    progress: Progress,
+    /// The whole file mapped into memory, if we're using mmap.
+    #[cfg(all(feature = "mmap", unix, target_pointer_width = "64"))]
+    map: std::sync::RwLock<Option<Arc<mmap::Mmap>>>,
+}
+
+impl SqliteFile {
+    /// Create an SQLite file from a regular [File][std::fs::File].
+    pub fn new(file: File) -> Result<Self> {
+        Self::from_vfs_file(Box::new(file))
+    }
+
+    /// A database held in memory instead of a file, from the bytes a file
+    /// would have.
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self> {
+        Self::from_vfs_file(Box::new(MemoryFile::new(data)))
+    }
+
+    /// Read a whole database into memory from anywhere, like a socket or a
+    /// decompressor, and open it like [from_bytes][Self::from_bytes].
+    pub fn from_reader(mut reader: impl io::Read) -> Result<Self> {
+        let mut data = vec![];
+        reader.read_to_end(&mut data)?;
+        Self::from_bytes(data)
+    }
+
+    /// Open a database kept wherever `file` keeps it. See [`vfs`].
+    pub fn from_vfs_file(file: Box<dyn VfsFile>) -> Result<Self> {
+        let mut header = [0u8; 100];
+        file.read_exact_at(&mut header, 0)?;
+        let header = DbHeader::parse(&header)?;
+        let page_size = header.page_size();
+        let usable_size = page_size as usize - header.reserved_bytes() as usize;
+
+        let file = Self {
+            file,
+            page_size,
+            usable_size,
+            cache: Mutex::new(PageCache::default()),
+            change_counter: AtomicU32::new(header.change_counter()),
+            immutable: false,
+            case_folding: CaseFolding::default(),
+            functions: Functions::default(),
+            limits: Limits::default(),
+            authorizer: None,
+            temp_tables: RwLock::default(),
+            tables: Mutex::default(),
+            attached: RwLock::default(),
+            stats: Counters::default(),
+            interrupt: Interrupt::default(),
+            progress: Progress::default(),
+            #[cfg(all(feature = "mmap", unix, target_pointer_width = "64"))]
+            map: std::sync::RwLock::new(None),
+        };
+        // make sure page 1 parses
+        file.get_page(NonZeroU64::MIN)?;
+        Ok(file)
+    }
+
+    /// Get the page size.
+    pub fn page_size(&self) -> u32 {
+        self.page_size
+    }
+
+    /// Page size minus the bytes reserved at the end of each page.
+    pub fn usable_size(&self) -> usize {
+        self.usable_size
+    }
+
+    /// Number of pages in the database. Comes from the header, unless it was
+    /// written by an old version of SQLite that didn't keep it up to date.
+    pub fn page_count(&self) -> Result<u64> {
+        let header = self.header()?;
+        if header.page_count_valid() {
+            return Ok(header.page_count() as u64);
+        }
+        self.file_page_count()
+    }
+
+    /// Number of pages the file is long enough for.
+    pub fn file_page_count(&self) -> Result<u64> {
+        let len = self.file.len()?;
+        Ok(len / self.page_size as u64)
+    }
+
+    /// The page counts from the header and from the size of the file, if the
+    /// header's count is meant to be up to date but they still disagree. That
+    /// happens when a file gets copied halfway through a write, and
+    /// [`page_count`](Self::page_count) goes with the header.
+    pub fn page_count_mismatch(&self) -> Result<Option<(u64, u64)>> {
+        let header = self.header()?;
+        let in_file = self.file_page_count()?;
+        if header.page_count_valid() && header.page_count() as u64 != in_file {
+            return Ok(Some((header.page_count() as u64, in_file)));
+        }
+        Ok(None)
+    }
+
+    /// Number of pages on the freelist, from the file header.
+    pub fn freelist_count(&self) -> Result<u32> {
+        Ok(self.header()?.freelist_count())
+    }
+
+    /// The file header, as of the last time page 1 was read.
+    pub fn header(&self) -> Result<DbHeader> {
+        let page1 = self.get_page(NonZeroU64::MIN)?;
+        DbHeader::parse(&page1.data)
+    }
+
+    /// Whether the file was opened as immutable.
+    pub fn is_immutable(&self) -> bool {
+        self.immutable
+    }
+
+    /// How `upper()`, `lower()` and `NOCASE` change the case of letters.
+    pub fn case_folding(&self) -> CaseFolding {
+        self.case_folding
+    }
+
+    /// The SQL functions queries can call.
+    pub fn functions(&self) -> &Functions {
+        &self.functions
+    }
+
+    /// Add your own functions with [`Functions::register`].
+    pub fn functions_mut(&mut self) -> &mut Functions {
+        // a column's DEFAULT could call one
+        self.tables().clear();
+        &mut self.functions
+    }
+
+    /// How big the SQL given to [`prepare`][Self::prepare] can get.
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Add a collation for `COLLATE name`, in queries or in the schema. Until
+    /// it's added, anything that compares with it fails.
+    pub fn create_collation<F>(&mut self, name: &str, f: F) -> &mut Self
+    where
+        F: Fn(&str, &str) -> std::cmp::Ordering + Send + Sync + 'static,
+    {
+        self.functions.register_collation(name, f);
+        self.tables().clear();
+        self
+    }
+
+    /// Have `f` say what statements can do from now on. See [`auth`].
+    pub fn set_authorizer<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(&Action) -> Authorization + Send + Sync + 'static,
+    {
+        self.authorizer = Some(Arc::new(f));
+        self
+    }
+
+    /// Call `f` every `n_ops` steps of a query: a page read by a scan or a
+    /// row tried by a join. If it returns `true`, the query fails with
+    /// [`Interrupted`][interrupt::Interrupted]. An `n_ops` of 0 removes the
+    /// handler. See [`interrupt`].
+    pub fn set_progress_handler<F>(&mut self, n_ops: u64, f: F) -> &mut Self
+    where
+        F: Fn() -> bool + Send + Sync + 'static,
+    {
+        self.progress.set(n_ops, Box::new(f));
+        self
+    }
+
+    /// Whether there's an authorizer to ask.
+    pub fn has_authorizer(&self) -> bool {
+        self.authorizer.is_some()
+    }
+
+    /// Ask the authorizer about `action`, failing if it's denied. Without an
+    /// authorizer, everything is allowed.
+    pub fn authorize(&self, action: &Action) -> Result<Authorization> {
+        let Some(authorizer) = &self.authorizer else {
+            return Ok(Authorization::Allow);
+        };
+        match (authorizer(action), action) {
+            (Authorization::Allow, _) => Ok(Authorization::Allow),
+            (Authorization::Ignore, Action::Read { .. }) => Ok(Authorization::Ignore),
+            (_, Action::Read { table, column }) => {
+                bail!("access to {}.{} is prohibited", table, column)
+            }
+            _ => bail!("not authorized: {}", action),
+        }
+    }
+
+    /// Run `select` and keep its results as a temp table called `name`, which
+    /// queries can use until the file is closed. Returns how many rows it has.
+    pub fn materialize(&self, name: &str, select: &Select) -> Result<usize> {
+        if self.temp_table(name).is_some() || self.has_table(name)? {
+            bail!("table {} already exists", name);
+        }
+        let columns = query::column_names(self, select)?;
+        let rows = query::execute(self, select)?;
+        let count = rows.len();
+        let table = TempTable::new(name, &columns, rows)?;
+        self.temp_tables
+            .write()
+            .unwrap()
+            .insert(name.to_ascii_lowercase(), Arc::new(table));
+        Ok(count)
+    }
+
+    /// Load a JSON array of objects into the temp table `name`, adding to it
+    /// if it's there and making it from the objects' keys if not. Returns how
+    /// many rows were added. Tables in the file can't be written to.
+    pub fn import_json(&self, name: &str, json: &Json) -> Result<usize> {
+        let mut tables = self.temp_tables.write().unwrap();
+        let key = name.to_ascii_lowercase();
+        let (table, count) = match tables.get(&key) {
+            Some(table) => {
+                let appended = table.append_json(json)?;
+                let count = appended.rows.len() - table.rows.len();
+                (appended, count)
+            }
+            None if self.has_table(name)? => {
+                bail!(
+                    "can't import into {}: only temp tables can be written",
+                    name
+                )
+            }
+            None => {
+                let table = TempTable::from_json(name, json)?;
+                let count = table.rows.len();
+                (table, count)
+            }
+        };
+        tables.insert(key, Arc::new(table));
+        Ok(count)
+    }
+
+    /// Whether the file has a table called `name`.
+    fn has_table(&self, name: &str) -> Result<bool> {
+        Ok(self
+            .get_schema()?
+            .iter()
+            .any(|s| s.stype == SchemaType::Table && s.name.eq_ignore_ascii_case(name)))
+    }
+
+    /// What `PRAGMA index_list` gives for a table: for each of its indexes,
+    /// newest first, a number, its name, whether it's unique, where it came
+    /// from (`c` for `CREATE INDEX`, `u` for a `UNIQUE` constraint or `pk` for
+    /// the `PRIMARY KEY`) and whether it's partial. A table that isn't there
+    /// has none.
+    pub fn index_list(&self, table: &str) -> Result<Vec<Vec<Value>>> {
+        let schema = self.get_schema()?;
+        let Some(def) = Schema::find(&schema, SchemaType::Table, table) else {
+            return Ok(vec![]);
+        };
+        let def = CreateTable::try_from(def)?;
+        let automatic = def.automatic_indexes();
+        let mut rows = vec![];
+        let indexes = schema
+            .iter()
+            .rev()
+            .filter(|s| s.stype == SchemaType::Index && s.table_name.eq_ignore_ascii_case(table));
+        for entry in indexes {
+            let (unique, origin, partial) = match entry.automatic_index() {
+                Some(n) if automatic.get(n - 1).is_some_and(|(_, pk)| *pk) => (true, "pk", false),
+                Some(_) => (true, "u", false),
+                None => {
+                    let index = CreateIndex::try_from(entry)?;
+                    (index.unique, "c", index.filter.is_some())
+                }
+            };
+            rows.push(vec![
+                Value::Integer(rows.len() as i64),
+                entry.name.as_str().into(),
+                Value::Integer(unique as i64),
+                origin.into(),
+                Value::Integer(partial as i64),
+            ]);
+        }
+        Ok(rows)
+    }
+
+    /// What `PRAGMA index_info` gives for an index: for each of its columns,
+    /// where it is in the index, where it is in the table (-1 for the rowid)
+    /// and its name. An index that isn't there has no columns.
+    pub fn index_info(&self, index: &str) -> Result<Vec<Vec<Value>>> {
+        let schema = self.get_schema()?;
+        let Some(entry) = Schema::find(&schema, SchemaType::Index, index) else {
+            return Ok(vec![]);
+        };
+        let table = Schema::find(&schema, SchemaType::Table, &entry.table_name)
+            .ok_or_else(|| anyhow!("no such table: {}", entry.table_name))?;
+        let table = CreateTable::try_from(table)?;
+        let columns = match entry.automatic_index() {
+            Some(n) => table
+                .automatic_indexes()
+                .get(n - 1)
+                .map_or(vec![], |(columns, _)| columns.to_vec()),
+            None => CreateIndex::try_from(entry)?.columns,
+        };
+        Ok(columns
+            .into_iter()
+            .enumerate()
+            .map(|(i, column)| {
+                let cid = match table.column_index(&column) {
+                    Some(cid) => cid as i64,
+                    None if table.is_rowid(&column) => -1,
+                    None => -2,
+                };
+                vec![Value::Integer(i as i64), Value::Integer(cid), column.into()]
+            })
+            .collect())
+    }
+
+    /// What's in `sqlite_sequence`: for each `AUTOINCREMENT` table that's had
+    /// a row, the biggest rowid it's ever used. Empty if there aren't any.
+    pub fn sequences(&self) -> Result<Vec<(String, i64)>> {
+        let schema = self.get_schema()?;
+        let Some(entry) = Schema::find(&schema, SchemaType::Table, "sqlite_sequence") else {
+            return Ok(vec![]);
+        };
+        let root = NonZeroU64::new(entry.rootpage)
+            .ok_or_else(|| anyhow!("sqlite_sequence has no root page"))?;
+        let mut sequences = vec![];
+        for row in btree::TableScan::new(self, root) {
+            let row = row?;
+            let (Some(Value::String(name)), Some(&Value::Integer(seq))) =
+                (row.values.first(), row.values.get(1))
+            else {
+                bail!("bad sqlite_sequence row {}", row.rowid);
+            };
+            sequences.push((name.clone(), seq));
+        }
+        Ok(sequences)
+    }
+
+    /// The number of rows in `table`, counted from the headers of its leaf
+    /// pages without reading the rows.
+    pub fn row_count(&self, table: &str) -> Result<u64> {
+        if let Some(temp) = self.temp_table(table) {
+            return Ok(temp.rows.len() as u64);
+        }
+        let schema = self.get_schema()?;
+        let Some(entry) = Schema::find(&schema, SchemaType::Table, table) else {
+            bail!("no such table: {}", table);
+        };
+        let root = NonZeroU64::new(entry.rootpage)
+            .ok_or_else(|| anyhow!("table {} has no root page", table))?;
+        btree::count_rows(self, root)
+    }
+
+    /// The rowid a row added to `table` would get if it didn't give one: one
+    /// more than the biggest there is, and for an `AUTOINCREMENT` table, more
+    /// than any it's had before as well, going by `sqlite_sequence`. The file
+    /// can't be written, so nothing is updated.
+    pub fn next_rowid(&self, table: &str) -> Result<i64> {
+        if let Some(temp) = self.temp_table(table) {
+            let last = temp.rows.last().map_or(0, |row| row.rowid);
+            return last
+                .checked_add(1)
+                .ok_or_else(|| anyhow!("no rowids left in {}", table));
+        }
+        let schema = self.get_schema()?;
+        let Some(entry) = Schema::find(&schema, SchemaType::Table, table) else {
+            bail!("no such table: {}", table);
+        };
+        let def: CreateTable = entry.sql.parse()?;
+        let root = NonZeroU64::new(entry.rootpage)
+            .ok_or_else(|| anyhow!("table {} has no root page", table))?;
+        let mut last = btree::max_rowid(self, root)?.unwrap_or(0);
+        if def.autoincrement {
+            for (name, seq) in self.sequences()? {
+                if name.eq_ignore_ascii_case(&def.name) {
+                    last = last.max(seq);
+                }
+            }
+        }
+        match last.checked_add(1) {
+            Some(rowid) => Ok(rowid),
+            // SQLite gives up here for AUTOINCREMENT, and otherwise tries
+            // random rowids until it finds one that's free
+            None if def.autoincrement => bail!("database or disk is full"),
+            None => bail!("no rowids left in {} after the biggest one", table),
+        }
+    }
+
+    /// Open another file so queries can read its tables as `name.table`, like
+    /// `ATTACH 'path' AS name`.
+    pub fn attach(&self, path: impl AsRef<Path>, name: &str) -> Result<()> {
+        let key = name.to_ascii_lowercase();
+        if key == "main" || key == "temp" || self.attached(name).is_some() {
+            bail!("database {} is already in use", name);
+        }
+        let mut file = OpenOptions::new()
+            .case_folding(self.case_folding)
+            .limits(self.limits)
+            .open(path)?;
+        // interrupting a query stops it in every file it reads
+        file.interrupt = self.interrupt.clone();
+        file.progress = self.progress.clone();
+        self.attached.write().unwrap().insert(key, Arc::new(file));
+        Ok(())
+    }
+
+    /// Close a file opened with [`attach`][Self::attach].
+    pub fn detach(&self, name: &str) -> Result<()> {
+        let mut attached = self.attached.write().unwrap();
+        match attached.remove(&name.to_ascii_lowercase()) {
+            Some(_) => Ok(()),
+            None => bail!("no such database: {}", name),
+        }
+    }
+
+    /// The file attached as `name`, if there is one.
+    pub fn attached(&self, name: &str) -> Option<Arc<SqliteFile>> {
+        let attached = self.attached.read().unwrap();
+        attached.get(&name.to_ascii_lowercase()).cloned()
+    }
+
+    /// The temp table called `name`, if there is one.
+    pub fn temp_table(&self, name: &str) -> Option<Arc<TempTable>> {
+        let tables = self.temp_tables.read().unwrap();
+        tables.get(&name.to_ascii_lowercase()).cloned()
+    }
+
+    /// Counts of pages read, cache hits and so on since the file was opened or
+    /// the last [`reset_stats`][Self::reset_stats].
+    pub fn stats(&self) -> Stats {
+        self.stats.snapshot()
+    }
+
+    pub fn reset_stats(&self) {
+        self.stats.reset();
+    }
+
+    pub(crate) fn counters(&self) -> &Counters {
+        &self.stats
+    }
+
+    /// A handle for stopping this file's queries from another thread.
+    pub fn interrupt_handle(&self) -> Interrupt {
+        self.interrupt.clone()
+    }
+
+    /// Fail if the queries were interrupted, or the progress handler says to
+    /// stop. Loops that could go on for a while call this as they go.
+    pub(crate) fn check_interrupt(&self) -> Result<()> {
+        self.interrupt.check()?;
+        self.progress.step()
+    }
+
+    /// Check whether another process changed the file since we last looked,
+    /// and if it did, throw away cached pages. Call this before starting a read.
+    ///
+    /// Returns `true` if the file changed. Immutable files are never checked.
+    pub fn check_for_changes(&self) -> Result<bool> {
+        if self.immutable {
+            return Ok(false);
+        }
+        let mut header = [0u8; 100];
+        self.file.read_exact_at(&mut header, 0)?;
+        stats::add(&self.stats.bytes_read, header.len() as u64);
+        let counter = DbHeader::parse(&header)?.change_counter();
+        if self.change_counter.swap(counter, Ordering::Relaxed) == counter {
+            return Ok(false);
+        }
+        self.cache().clear();
+        self.tables().clear();
+        // the file might have grown
+        #[cfg(all(feature = "mmap", unix, target_pointer_width = "64"))]
+        if self.map.read().unwrap().is_some() {
+            // SAFETY: whoever mapped the file first took on keeping it whole
+            unsafe { self.enable_mmap()? };
+        }
+        Ok(true)
+    }
+
+    /// Map the file into memory, so pages are used right out of the map
+    /// instead of being read into buffers. Pages past the end of the map, if
+    /// the file grows, are still read normally.
+    ///
+    /// # Safety
+    ///
+    /// Nothing may truncate the file while it's open, or reading a page that
+    /// was cut off crashes with `SIGBUS`. See [`mmap::Mmap::map`].
+    #[cfg(all(feature = "mmap", unix, target_pointer_width = "64"))]
+    pub unsafe fn enable_mmap(&self) -> Result<()> {
+        let Some(file) = self.file.as_file() else {
+            // already in memory, or not a local file
+            return Ok(());
+        };
+        // SAFETY: passed on to our caller
+        let map = unsafe { mmap::Mmap::map(file)? };
+        *self.map.write().unwrap() = map.map(Arc::new);
+        self.cache().clear();
+        Ok(())
+    }
+
+    /// Read a page without parsing it. Overflow and freelist pages don't have a
+    /// B-tree header, so they have to be read this way.
+    pub fn read_raw(&self, page_id: NonZeroU64) -> Result<Vec<u8>> {
+        trace!(page = page_id.get(), "read page");
+        let mut data = vec![0u8; self.page_size as usize];
+        self.file
+            .read_exact_at(&mut data, self.page_offset(page_id))?;
+        stats::add(&self.stats.pages_read, 1);
+        stats::add(&self.stats.bytes_read, data.len() as u64);
+        Ok(data)
+    }
+
+    /// Read `count` consecutive pages starting at `first` with a single read.
+    pub fn read_raw_pages(&self, first: NonZeroU64, count: usize) -> Result<Vec<Vec<u8>>> {
+        trace!(page = first.get(), count, "read pages");
+        let page_size = self.page_size as usize;
+        let mut data = vec![0u8; page_size * count];
+        self.file
+            .read_exact_at(&mut data, self.page_offset(first))?;
+        stats::add(&self.stats.pages_read, count as u64);
+        stats::add(&self.stats.bytes_read, data.len() as u64);
+        Ok(data.chunks(page_size).map(|c| c.to_vec()).collect())
+    }
+
+    fn page_offset(&self, page_id: NonZeroU64) -> u64 {
+        (page_id.get() - 1) * self.page_size as u64
+    }
+
+    /// Get a page. `page_id` starts at 1.
+    pub fn get_page(&self, page_id: NonZeroU64) -> Result<Arc<Page>> {
+        if let Some(page) = self.cache().get(page_id.get()) {
+            trace!(page = page_id.get(), "cache hit");
+            stats::add(&self.stats.cache_hits, 1);
+            return Ok(page);
+        }
+        stats::add(&self.stats.cache_misses, 1);
+        #[cfg(all(feature = "mmap", unix, target_pointer_width = "64"))]
+        if let Some(data) = self.mapped_page(page_id) {
+            stats::add(&self.stats.pages_read, 1);
+            return self.add_page(page_id, data);
+        }
+        let data = self.read_raw(page_id)?;
+        self.add_page(page_id, PageData::Owned(data))
+    }
+
+    #[cfg(all(feature = "mmap", unix, target_pointer_width = "64"))]
+    fn mapped_page(&self, page_id: NonZeroU64) -> Option<PageData> {
+        let map = self.map.read().unwrap();
+        let map = map.as_ref()?;
+        let offset = self.page_offset(page_id) as usize;
+        let len = self.page_size as usize;
+        (offset + len <= map.len()).then(|| PageData::Mapped {
+            map: Arc::clone(map),
+            offset,
+            len,
+        })
+    }
+
+    /// Get several pages at once. Pages that aren't cached are read in page
+    /// order, and runs of adjacent pages are read with a single read, which is
+    /// a lot faster than reading them one at a time in random order.
+    pub fn get_pages(&self, page_ids: &[NonZeroU64]) -> Result<BTreeMap<u64, Arc<Page>>> {
+        let mut pages = BTreeMap::new();
+        let mut missing = vec![];
+        for &page_id in page_ids {
+            #[cfg(all(feature = "mmap", unix, target_pointer_width = "64"))]
+            if self.map.read().unwrap().is_some() {
+                pages.insert(page_id.get(), self.get_page(page_id)?);
+                continue;
+            }
+            match self.cache().get(page_id.get()) {
+                Some(page) => {
+                    trace!(page = page_id.get(), "cache hit");
+                    stats::add(&self.stats.cache_hits, 1);
+                    pages.insert(page_id.get(), page);
+                }
+                None => missing.push(page_id),
+            }
+        }
+        missing.sort_unstable();
+        missing.dedup();
+        stats::add(&self.stats.cache_misses, missing.len() as u64);
+
+        let mut i = 0;
+        while i < missing.len() {
+            let first = missing[i];
+            let mut count = 1;
+            while i + count < missing.len()
+                && missing[i + count].get() == first.get() + count as u64
+            {
+                count += 1;
+            }
+            for (n, data) in self.read_raw_pages(first, count)?.into_iter().enumerate() {
+                let page_id = first.checked_add(n as u64).unwrap();
+                pages.insert(
+                    page_id.get(),
+                    self.add_page(page_id, PageData::Owned(data))?,
+                );
+            }
+            i += count;
+        }
+        Ok(pages)
+    }
+
+    fn cache(&self) -> MutexGuard<'_, PageCache> {
+        // the cache is still usable if another thread panicked while holding it
+        self.cache.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn tables(&self) -> MutexGuard<'_, query::TableCache> {
+        self.tables.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Parse the B-tree header of a freshly read page and put it in the cache.
+    fn add_page(&self, page_id: NonZeroU64, data: PageData) -> Result<Arc<Page>> {
+        let page_id = page_id.get();
+        let hdata = if page_id == 1 {
+            &data[100..]
+        } else {
+            &data[..]
+        };
+        let header = match parse_btree_header(hdata) {
+            Ok((_, header)) => header,
+            Err(e) => {
+                return Err(SqliteError::PageHeader {
+                    page: page_id,
+                    reason: self
+                        .not_a_btree_page(page_id)
+                        .unwrap_or_else(|| nom_reason(e)),
+                }
+                .into())
+            }
+        };
+        let page = Arc::new(Page {
+            page_id,
+            data,
+            header,
+            usable_size: self.usable_size(),
+        });
+        self.cache().insert(Arc::clone(&page));
+        Ok(page)
+    }
+
+    /// Why a page that should be in a B-tree isn't, if the pointer map knows.
+    /// In an auto-vacuum database a stale pointer to a page that was since
+    /// moved or freed can end up at any kind of page.
+    fn not_a_btree_page(&self, page_id: u64) -> Option<String> {
+        if page_id < 2 || !self.has_ptrmap().ok()? {
+            return None;
+        }
+        if self.is_ptrmap_page(page_id).ok()? {
+            return Some("not a b-tree page, it's a pointer map page".to_owned());
+        }
+        let entry = self.ptrmap_entry(page_id).ok()?;
+        Some(format!(
+            "not a b-tree page, the pointer map says it's {}",
+            entry.describe()
+        ))
+    }
+
+    /// Set the maximum number of pages kept in the page cache.
+    pub fn set_cache_size(&self, pages: usize) {
+        self.cache().set_capacity(pages);
+    }
+
+    /// Set the maximum number of tables kept parsed, for files with more of
+    /// them than is worth holding on to. The least recently used go first.
+    pub fn set_table_cache_size(&self, tables: usize) {
+        self.tables().set_capacity(tables);
+    }
+
+    /// Set a soft limit on the bytes of page data the cache holds. Pages are
+    /// evicted as soon as the cache goes over it. `None` removes the limit.
+    pub fn set_soft_heap_limit(&self, bytes: Option<usize>) {
+        self.cache().set_soft_heap_limit(bytes);
+    }
+
+    /// Bytes of page data currently held by the page cache.
+    pub fn memory_used(&self) -> usize {
+        self.cache().memory_used()
+    }
+
+    /// Free as much cached memory as possible. Returns the number of bytes freed.
+    ///
+    /// Pages still referenced elsewhere stay alive until those references are dropped.
+    pub fn release_memory(&self) -> usize {
+        self.cache().shrink_to(0)
+    }
+
+    /// Parse a `SELECT` to run once its parameters are bound.
+    pub fn prepare(&self, sql: &str) -> Result<prepared::Prepared<'_>> {
+        prepared::Prepared::new(self, Select::parse_with(sql, &self.limits)?)
+    }
+
+    /// Read the `sqlite_schema` table, which starts on page 1.
+    pub fn get_schema(&self) -> Result<Vec<Schema>> {
+        let mut schema = vec![];
+        for row in btree::TableScan::new(self, NonZeroU64::MIN) {
+            let row = row?;
+            let error = |reason: &str| SqliteError::Schema {
+                rowid: row.rowid,
+                reason: reason.to_owned(),
+            };
+            let [stype, name, table_name, rootpage, sql] = &row.values[..] else {
+                return Err(error("expected 5 columns").into());
+            };
+            schema.push(Schema {
+                stype: stype
+                    .to_string()
+                    .parse()
+                    .map_err(|_| error("unknown type"))?,
+                name: name.to_string(),
+                table_name: table_name.to_string(),
+                rootpage: u64::from(rootpage.clone()),
+                sql: sql.to_string(),
+            });
+        }
+        Ok(schema)
+    }
+}
+
+/// What went wrong in a nom parser, for error messages.
+pub(crate) fn nom_reason(e: nom::Err<nom::error::Error<&[u8]>>) -> String {
+    match e {
+        nom::Err::Incomplete(_) => "ends too soon".to_owned(),
+        nom::Err::Error(e) | nom::Err::Failure(e) => {
+            format!("{:?} failed with {} bytes left", e.code, e.input.len())
+        }
+    }
+}
+
+/// A database with nothing in it: page 1, with the header and an empty
+/// `sqlite_schema`.
+fn empty_database(page_size: u32) -> Vec<u8> {
+    let mut data = vec![0; page_size as usize];
+    data[..100].copy_from_slice(DbHeader::new(page_size, 1).raw());
+    data[100] = 0x0d; // table leaf
+
+    // cells start at the end of the page, where 65536 is written as 0
+    data[105..107].copy_from_slice(&(page_size as u16).to_be_bytes());
+    data
+}
+
+/// Options for opening a database file, like [`std::fs::OpenOptions`].
+///
+/// Opening takes a shared lock on the file, the same one sqlite3 takes, and
+/// fails if someone is writing to it. It also refuses files with a hot
+/// rollback journal, or changes in a WAL that haven't been checkpointed,
+/// since we can't read those correctly.
+///
+/// `open` also takes `file:` URIs. The `immutable=1` query parameter is supported.
+#[derive(Clone, Debug, Default)]
+pub struct OpenOptions {
+    wait_for_lock: bool,
+    immutable: bool,
+    case_folding: CaseFolding,
+    limits: Limits,
+    vfs: Option<Arc<dyn Vfs>>,
+    #[cfg(feature = "sqlcipher")]
+    key: Option<cipher::Passphrase>,
+    #[cfg(feature = "sqlcipher")]
+    kdf_iter: Option<u32>,
+    #[cfg(all(feature = "mmap", unix, target_pointer_width = "64"))]
+    mmap: bool,
+}
+
+impl OpenOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether to fail right away with [`OpenError::WouldBlock`] if someone
+    /// is writing to the file, which is the default, or wait for them to
+    /// finish.
+    pub fn try_lock(&mut self, try_lock: bool) -> &mut Self {
+        self.wait_for_lock = !try_lock;
+        self
+    }
+
+    /// Promise that nobody will change the file while it's open, e.g. because
+    /// it's on read-only media. Skips locking, change detection and journal checks.
+    pub fn immutable(&mut self, immutable: bool) -> &mut Self {
+        self.immutable = immutable;
+        self
+    }
+
+    /// Which letters `upper()`, `lower()` and `NOCASE` know the case of.
+    /// Only ASCII by default, like SQLite.
+    pub fn case_folding(&mut self, case_folding: CaseFolding) -> &mut Self {
+        self.case_folding = case_folding;
+        self
+    }
+
+    /// Limits on the SQL queries can use. SQLite's by default.
+    pub fn limits(&mut self, limits: Limits) -> &mut Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Where to open files from. Files on disk, with [`OsVfs`], by default,
+    /// or a web server for `http://` URLs.
+    pub fn vfs(&mut self, vfs: Arc<dyn Vfs>) -> &mut Self {
+        self.vfs = Some(vfs);
+        self
+    }
+
+    /// Decrypt an SQLCipher database with this passphrase.
+    #[cfg(feature = "sqlcipher")]
+    pub fn key(&mut self, passphrase: &str) -> &mut Self {
+        self.key = Some(cipher::Passphrase(passphrase.as_bytes().to_vec()));
+        self
+    }
+
+    /// How many rounds of PBKDF2 the [`key`][Self::key] went through, if the
+    /// database was made with something other than SQLCipher's
+    /// [default][cipher::DEFAULT_KDF_ITER], like `PRAGMA kdf_iter`.
+    #[cfg(feature = "sqlcipher")]
+    pub fn kdf_iter(&mut self, kdf_iter: u32) -> &mut Self {
+        self.kdf_iter = Some(kdf_iter);
+        self
+    }
+
+    /// Memory map the file instead of reading pages into buffers.
+    ///
+    /// # Safety
+    ///
+    /// With `mmap` set, nothing may truncate the file while it's open. See
+    /// [`SqliteFile::enable_mmap`].
+    #[cfg(all(feature = "mmap", unix, target_pointer_width = "64"))]
+    pub unsafe fn mmap(&mut self, mmap: bool) -> &mut Self {
+        self.mmap = mmap;
+        self
+    }
+
+    /// Open the database at `path`.
+    pub fn open<P: AsRef<Path>>(&self, path: P) -> Result<SqliteFile> {
+        let mut options = self.clone();
+        let path = match path.as_ref().to_str() {
+            Some(uri) if uri.starts_with("file:") => options.parse_uri(uri)?,
+            _ => path.as_ref().to_owned(),
+        };
+        let mut db = if path == Path::new(":memory:") {
+            SqliteFile::from_bytes(empty_database(4096))?
+        } else {
+            let vfs: Arc<dyn Vfs> = match &options.vfs {
+                Some(vfs) => vfs.clone(),
+                None if path.to_str().is_some_and(|p| p.starts_with("http://")) => {
+                    Arc::new(http::HttpVfs)
+                }
+                None => Arc::new(OsVfs),
+            };
+            let file = vfs.open(&path)?;
+            if !options.immutable {
+                file.lock_shared(options.wait_for_lock)?;
+                if vfs.has_journal() {
+                    check_journal(&path, file.as_file())?;
+                }
+            }
+            #[cfg(feature = "sqlcipher")]
+            let file: Box<dyn VfsFile> = match &options.key {
+                Some(key) => {
+                    let kdf_iter = options.kdf_iter.unwrap_or(cipher::DEFAULT_KDF_ITER);
+                    Box::new(cipher::CipherFile::new(file, &key.0, kdf_iter)?)
+                }
+                None => file,
+            };
+            SqliteFile::from_vfs_file(file)?
+        };
+        db.immutable = options.immutable;
+        db.case_folding = options.case_folding;
+        db.functions = Functions::new(options.case_folding);
+        db.limits = options.limits;
+        #[cfg(all(feature = "mmap", unix, target_pointer_width = "64"))]
+        if options.mmap {
+            // SAFETY: the caller of `OpenOptions::mmap` took this on
+            unsafe { db.enable_mmap()? };
+        }
+        Ok(db)
+    }
+
+    /// Apply the query parameters of a `file:` URI and return the path.
+    fn parse_uri(&mut self, uri: &str) -> Result<PathBuf> {
+        let rest = uri.trim_start_matches("file:");
+        let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+        // file:///path and file://localhost/path have an authority we don't care about
+        let path = match path.strip_prefix("//") {
+            Some(p) => &p[p.find('/').unwrap_or(p.len())..],
+            None => path,
+        };
+        for param in query.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            match key {
+                "immutable" => self.immutable = uri_bool(value)?,
+                // the file is only ever opened for reading anyway
+                "mode" if value == "ro" => {}
+                _ => bail!("unsupported URI parameter: {}", param),
+            }
+        }
+        Ok(PathBuf::from(path))
+    }
+}
+
+fn uri_bool(value: &str) -> Result<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "yes" | "true" | "on" => Ok(true),
+        "0" | "no" | "false" | "off" => Ok(false),
+        _ => bail!("expected a boolean in URI, got {:?}", value),
+    }
+}
+
+/// Fail if there's a hot rollback journal next to the database, or a WAL
+/// with changes we'd miss. `file` is the database, if it's a local file.
+fn check_journal(path: &Path, file: Option<&File>) -> Result<()> {
+    if hot_journal(path, file)? {
+        return Err(OpenError::HotJournal.into());
+    }
+    if wal::uncheckpointed(path)? {
+        return Err(OpenError::Wal.into());
+    }
+    Ok(())
+}
+
+/// Whether the rollback journal has to be played back before the database
+/// can be read, like SQLite decides: it has a header, so it isn't a committed
+/// journal that was zeroed or truncated, and nobody holds the reserved lock,
+/// so it isn't the journal of a transaction still going on.
+fn hot_journal(path: &Path, file: Option<&File>) -> Result<bool> {
+    let mut journal = path.as_os_str().to_owned();
+    journal.push("-journal");
+    let mut header = [0; 8];
+    let read = match File::open(journal) {
+        Ok(journal) => vfs::VfsFile::read_exact_at(&journal, &mut header, 0),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+    match read {
+        Ok(()) if header != [0; 8] => {}
+        Err(e) if e.kind() != std::io::ErrorKind::UnexpectedEof => return Err(e.into()),
+        _ => return Ok(false),
+    }
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    if let Some(file) = file {
+        return Ok(!lock::is_reserved(file)?);
+    }
+    let _ = file;
+    Ok(true)
+}
+
+/// Errors from [`OpenOptions::open`] that callers might want to handle.
+#[derive(Debug, thiserror::Error)]
+pub enum OpenError {
+    /// Another process holds a lock on the file.
+    #[error("database is locked")]
+    WouldBlock,
+    /// A rollback journal exists, so the file might be in the middle of a transaction.
+    #[error("database has a hot journal that needs to be rolled back")]
+    HotJournal,
+    /// The WAL has changes that aren't in the database file, and reading
+    /// them from the WAL isn't supported.
+    #[error(
+        "database has changes in its write-ahead log, which can't be read yet; checkpoint it first"
+    )]
+    Wal,
+}
+
+/// Something in the file isn't the way it should be. Says where, so a corrupt
+/// file can be looked into with `.page`.
+#[derive(Debug, thiserror::Error)]
+pub enum SqliteError {
+    #[error("page {page}: bad b-tree page header: {reason}")]
+    PageHeader { page: u64, reason: String },
+    #[error("page {page}, cell {cell} at offset {offset}: {reason}")]
+    Cell {
+        page: u64,
+        cell: usize,
+        offset: usize,
+        reason: String,
+    },
+    #[error("page {page}, cell {cell}: bad record: {reason}")]
+    Record {
+        page: u64,
+        cell: usize,
+        reason: String,
+    },
+    /// The pages don't fit together into a proper B-tree.
+    #[error("page {page}: {reason}")]
+    Btree { page: u64, reason: String },
+    #[error("overflow page {page}: {reason}")]
+    Overflow { page: u64, reason: String },
+    #[error("sqlite_schema row {rowid}: {reason}")]
+    Schema { rowid: i64, reason: String },
+    #[error("bad file header: {reason}")]
+    FileHeader { reason: String },
+    #[error("page {page}: {reason}")]
+    Ptrmap { page: u64, reason: String },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SchemaType {
+    Table,
+    Index,
+    View,
+    Trigger,
+}
+
+impl FromStr for SchemaType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        use SchemaType::*;
+        match s {
+            "table" => Ok(Table),
+            "index" => Ok(Index),
+            "view" => Ok(View),
+            "trigger" => Ok(Trigger),
+            _ => bail!("schema type must be table, index, view or trigger"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Schema {
+    pub stype: SchemaType,
+    pub name: String,
+    pub table_name: String,
+    pub rootpage: u64,
+    pub sql: String,
+}
+
+impl Schema {
+    /// The table or index called `name`.
+    pub fn find<'s>(schema: &'s [Schema], stype: SchemaType, name: &str) -> Option<&'s Schema> {
+        schema
+            .iter()
+            .find(|s| s.stype == stype && s.name.eq_ignore_ascii_case(name))
+    }
+
+    /// The `N` of an automatic index called `sqlite_autoindex_TABLE_N`, which
+    /// SQLite makes for a `UNIQUE` or `PRIMARY KEY` constraint. Names starting
+    /// with `sqlite_` are kept for SQLite, so that's all there is to check.
+    pub fn automatic_index(&self) -> Option<usize> {
+        if self.stype != SchemaType::Index {
+            return None;
+        }
+        let rest = self.name.strip_prefix("sqlite_autoindex_")?;
+        rest.rsplit('_').next()?.parse().ok().filter(|&n| n > 0)
+    }
+}
+
+/// The bytes of a page, either read into a buffer or borrowed from a memory map.
+pub enum PageData {
+    Owned(Vec<u8>),
+    #[cfg(all(feature = "mmap", unix, target_pointer_width = "64"))]
+    Mapped {
+        map: Arc<mmap::Mmap>,
+        offset: usize,
+        len: usize,
+    },
+}
+
+impl PageData {
+    /// Bytes of heap memory used. Mapped pages don't use any.
+    pub fn heap_size(&self) -> usize {
+        match self {
+            PageData::Owned(data) => data.len(),
+            #[cfg(all(feature = "mmap", unix, target_pointer_width = "64"))]
+            PageData::Mapped { .. } => 0,
+        }
+    }
+}
+
+impl Deref for PageData {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            PageData::Owned(data) => data,
+            #[cfg(all(feature = "mmap", unix, target_pointer_width = "64"))]
+            PageData::Mapped { map, offset, len } => &map[*offset..*offset + *len],
+        }
+    }
+}
+
+pub struct Page {
+    pub page_id: u64,
+    pub data: PageData,
+    pub header: BtreeHeader,
+    /// Page size minus reserved space at the end of the page.
+    pub usable_size: usize,
+}
+
+/// Iterates over the cells in a page.
+pub struct CellIter<'p> {
+    page: &'p Page,
+    ptr_array: &'p [u8],
+}
+
+impl<'p> Iterator for CellIter<'p> {
+    type Item = Cell<'p>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (input, ptr) = be_u16::<&[u8], ()>(self.ptr_array).ok()?;
+        let data = &self.page[ptr as usize..];
+        let (_, cell) = self
+            .page
+            .header
+            .parse_cell(data, self.page.usable_size)
+            .ok()?;
+        self.ptr_array = input;
+        Some(cell)
+    }
+}
+
+impl Page {
+    /// Where the B-tree header starts. First page contains 100 byte file header.
+    pub fn header_offset(&self) -> usize {
+        if self.page_id == 1 {
+            100
+        } else {
+            0
+        }
+    }
+
+    /// Page header is 8 bytes if a leaf page or 12 bytes if interior.
+    pub fn header_size(&self) -> usize {
+        if self.header.kind.is_interior() {
+            12
+        } else {
+            8
+        }
+    }
+
+    pub fn cells<'p>(&'p self) -> CellIter<'p> {
+        // start of cell pointer array.
+        let start = self.header_offset() + self.header_size();
+        let count = self.header.cell_count as usize;
+        let ptr_array = &self[start..count * 2 + start];
+        CellIter {
+            page: self,
+            ptr_array,
+        }
+    }
+
+    /// Parse the `i`th cell on the page.
+    pub fn cell(&self, i: usize) -> Result<Cell<'_>> {
+        let at = self.header_offset() + self.header_size() + 2 * i;
+        let error = |offset, reason: String| SqliteError::Cell {
+            page: self.page_id,
+            cell: i,
+            offset,
+            reason,
+        };
+        if i >= self.header.cell_count as usize {
+            let reason = format!("only {} cells on the page", self.header.cell_count);
+            return Err(error(at, reason).into());
+        }
+        if at + 2 > self.len() {
+            return Err(error(at, "cell pointer array runs off the page".into()).into());
+        }
+        let ptr = u16::from_be_bytes([self[at], self[at + 1]]) as usize;
+        if ptr >= self.len() {
+            return Err(error(ptr, "cell pointer is past the end of the page".into()).into());
+        }
+        let (_, cell) = self
+            .header
+            .parse_cell(&self[ptr..], self.usable_size)
+            .map_err(|e| error(ptr, nom_reason(e)))?;
+        Ok(cell)
+    }
+}
+
+impl Deref for Page {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.data
+    }
+}
+
+/// B-Tree page type
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PageKind {
+    IndexInterior,
+    TableInterior,
+    IndexLeaf,
+    TableLeaf,
+}
+
+impl PageKind {
+    pub const fn is_table(self) -> bool {
+        matches!(self, Self::TableInterior | Self::TableLeaf)
+    }
+
+    pub const fn is_interior(self) -> bool {
+        matches!(self, Self::IndexInterior | Self::TableInterior)
+    }
+}
+
+impl TryFrom<u8> for PageKind {
+    type Error = Error;
+
+    fn try_from(value: u8) -> std::result::Result<Self, Self::Error> {
+        use PageKind::*;
+        match value {
+            2 => Ok(IndexInterior),
+            5 => Ok(TableInterior),
+            10 => Ok(IndexLeaf),
+            13 => Ok(TableLeaf),
+            _ => bail!("invalid b-tree page type: {}", value),
+        }
+    }
+}
+
+/// Header of a B-tree page.
+#[derive(Debug)]
+pub struct BtreeHeader {
+    /// Page type
+    pub kind: PageKind,
+    /// Offset to first freeblock in the page, or 0 if none.
+    pub first_freeblock: u16,
+    /// Number of cells on the page.
+    pub cell_count: u16,
+    /// Start of cell content area
+    pub cell_contents: u16,
+    /// Number of fragmented free bytes.
+    pub fragmented_free_bytes: u8,
+    /// Child page whose keys are greater than the keys on this page.
+    /// Only exists if it's an internal page.
+    pub rightmost_pointer: Option<u32>,
+}
+
+fn parse_btree_header(input: &[u8]) -> IResult<&[u8], BtreeHeader> {
+    let (
+        input,
+        (
+            kind,
+            first_freeblock,
+            cell_count,
+            cell_contents,
+            fragmented_free_bytes,
+            rightmost_pointer,
+        ),
+    ) = tuple((
+        map_res(u8, PageKind::try_from),
+        be_u16,
+        be_u16,
+        be_u16,
+        u8,
+        be_u32,
+    ))(input)?;
+    let rightmost_pointer = kind.is_interior().then_some(rightmost_pointer);
+    Ok((
+        input,
+        BtreeHeader {
+            kind,
+            first_freeblock,
+            cell_contents,
+            cell_count,
+            fragmented_free_bytes,
+            rightmost_pointer,
+        },
+    ))
+}
+
+pub fn cell_pointers(input: &[u8], n: usize) -> IResult<&[u8], Vec<u16>> {
+    count(be_u16, n)(input)
+}
+
+/// Compiled `SELECT` statement
+#[derive(Debug, Clone, PartialEq)]
+pub struct Select {
+    pub name: String,
+    /// `FROM name AS alias`
+    pub alias: Option<String>,
+    pub columns: SelectColumns,
+    /// Tables joined on after the first one, in order.
+    pub joins: Vec<Join>,
+    /// `WHERE` clause
+    pub filter: Option<Expr>,
+    /// `ORDER BY` terms, the one that counts most first.
+    pub order_by: Vec<OrderingTerm>,
+}
+
+/// A term of an `ORDER BY`: `expr [COLLATE name] [ASC|DESC]`. The expression
+/// can also be the position of an output column, counting from 1, or one of
+/// their aliases.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderingTerm {
+    pub expr: Expr,
+    pub descending: bool,
+}
+
+/// `[INNER] JOIN table [AS alias] ON expr`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Join {
+    pub table: String,
+    pub alias: Option<String>,
+    pub on: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SelectColumns {
+    Columns(Vec<ResultColumn>),
+    /// `SELECT *`
+    All,
+    Count,
+    /// `MIN(column)` or `MAX(column)`
+    MinMax {
+        column: String,
+        max: bool,
+    },
+}
+
+/// One output column of a `SELECT`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResultColumn {
+    /// `expr [AS alias]`
+    Expr { expr: Expr, alias: Option<String> },
+    /// `table.*`, every column of one table. `table` can be an alias.
+    AllOf(String),
+}
+
+impl ResultColumn {
+    pub fn expr(&self) -> Option<&Expr> {
+        match self {
+            ResultColumn::Expr { expr, .. } => Some(expr),
+            ResultColumn::AllOf(_) => None,
+        }
+    }
+
+    /// What an expression column is called in the output: the alias if there
+    /// is one, then the name of a plain column, then the expression itself.
+    /// `None` for `table.*`, which is more than one column.
+    pub fn name(&self) -> Option<String> {
+        let ResultColumn::Expr { expr, alias } = self else {
+            return None;
+        };
+        Some(match (alias, expr) {
+            (Some(alias), _) => alias.clone(),
+            (None, Expr::Column(name)) => match name.split_once('.') {
+                Some((_, column)) => column.to_owned(),
+                None => name.clone(),
+            },
+            (None, expr) => expr.to_string(),
+        })
+    }
+}
+
+impl From<Expr> for ResultColumn {
+    fn from(expr: Expr) -> Self {
+        ResultColumn::Expr { expr, alias: None }
+    }
+}
+
+/// Compiled `CREATE TABLE` statement
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateTable {
+    pub name: String,
+    pub columns: Vec<String>,
+    /// What each column was declared with, in the same order.
+    pub column_defs: Vec<ColumnDef>,
+    /// What each column compares text with, from its `COLLATE`.
+    pub collations: Vec<Collation>,
+    /// The `INTEGER PRIMARY KEY` column, which is stored as the rowid.
+    pub key: Option<String>,
+    /// The key is `AUTOINCREMENT`, so rowids are never used twice and the
+    /// biggest one so far is kept in `sqlite_sequence`.
+    pub autoincrement: bool,
+    /// Columns of the `PRIMARY KEY`, if there is one.
+    pub primary_key: Vec<String>,
+    pub foreign_keys: Vec<ForeignKey>,
+    /// Columns of each `UNIQUE` constraint, including ones on a single
+    /// column's definition.
+    pub unique: Vec<Vec<String>>,
+    /// How many of the `UNIQUE` constraints come before the `PRIMARY KEY`,
+    /// which SQLite numbers the automatic indexes by.
+    pub primary_key_at: usize,
+    /// The expressions of the table's own `CHECK` constraints, as written.
+    /// Ones on a column are in its [`ColumnDef`].
+    pub checks: Vec<String>,
+    /// Declared `STRICT`: every column has one of the types in
+    /// [`ColumnDef::STRICT_TYPES`] and only holds values of that type.
+    pub strict: bool,
+}
+
+/// A column of a `CREATE TABLE`, as it was declared.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnDef {
+    pub name: String,
+    /// The type name as written, like `VARCHAR(10)`. Can be empty.
+    pub declared_type: String,
+    /// How values are converted before they're compared, from the type.
+    pub affinity: Affinity,
+    pub not_null: bool,
+    /// The `DEFAULT` as written, like `'x'`, `-1` or `1 + 2` for `(1 + 2)`.
+    pub default: Option<String>,
+    /// Part of the table's `PRIMARY KEY`.
+    pub primary_key: bool,
+    /// Declared `PRIMARY KEY DESC`, which SQLite never makes the rowid.
+    pub descending: bool,
+    /// Declared `PRIMARY KEY AUTOINCREMENT`.
+    pub autoincrement: bool,
+    /// Has a `UNIQUE` constraint of its own.
+    pub unique: bool,
+    /// The expressions of its `CHECK` constraints, as written.
+    pub checks: Vec<String>,
+}
+
+impl ColumnDef {
+    /// The types a column of a `STRICT` table can have.
+    pub const STRICT_TYPES: [&'static str; 6] = ["INT", "INTEGER", "REAL", "TEXT", "BLOB", "ANY"];
+
+    /// Whether a `STRICT` table can hold `value` in this column. `REAL`
+    /// columns take integers too, as that's how SQLite stores whole numbers
+    /// in them.
+    pub fn strict_allows(&self, value: &Value) -> bool {
+        match (self.declared_type.to_ascii_uppercase().as_str(), value) {
+            (_, Value::Null) => !self.not_null,
+            ("INT" | "INTEGER", Value::Integer(_))
+            | ("REAL", Value::Integer(_) | Value::Float(_))
+            | ("TEXT", Value::String(_))
+            | ("BLOB", Value::Blob(_))
+            | ("ANY", _) => true,
+            _ => false,
+        }
+    }
+
+    /// Parse what comes after the column's name.
+    fn parse(name: String, def: &str) -> Result<Self> {
+        let tokens = tokens(def)?;
+        // the type is everything up to the first constraint
+        let constraints = [
+            "constraint",
+            "primary",
+            "not",
+            "null",
+            "unique",
+            "check",
+            "default",
+            "collate",
+            "references",
+            "generated",
+            "as",
+        ];
+        let start = tokens
+            .iter()
+            .position(|(_, word)| constraints.iter().any(|c| word.eq_ignore_ascii_case(c)))
+            .unwrap_or(tokens.len());
+        let end = tokens.get(start).map_or(def.len(), |(at, _)| *at);
+        let declared_type = def[..end].trim().to_owned();
+        let mut column = ColumnDef {
+            name,
+            affinity: Affinity::from_type_name(&declared_type),
+            declared_type,
+            not_null: false,
+            default: None,
+            primary_key: false,
+            descending: false,
+            autoincrement: false,
+            unique: false,
+            checks: vec![],
+        };
+        // one constraint keyword at a time, skipping the words that go with it
+        let word = |i: usize| {
+            tokens
+                .get(i)
+                .map_or(String::new(), |(_, t)| t.to_ascii_lowercase())
+        };
+        let mut i = start;
+        while i < tokens.len() {
+            match word(i).as_str() {
+                "constraint" => i += 1,
+                "primary" if word(i + 1) == "key" => {
+                    column.primary_key = true;
+                    column.descending = word(i + 2) == "desc";
+                    i += 1;
+                }
+                "not" if word(i + 1) == "null" => {
+                    column.not_null = true;
+                    i += 1;
+                }
+                "autoincrement" => column.autoincrement = true,
+                "unique" => column.unique = true,
+                "check" => {
+                    let expr = tokens.get(i + 1).and_then(|(_, t)| bracketed(t));
+                    let expr = expr.ok_or_else(|| anyhow!("failed to parse CHECK in {:?}", def))?;
+                    column.checks.push(expr.to_owned());
+                    i += 1;
+                }
+                "default" => {
+                    let (_, value) = tokens
+                        .get(i + 1)
+                        .ok_or_else(|| anyhow!("failed to parse DEFAULT in {:?}", def))?;
+                    column.default = Some(bracketed(value).unwrap_or(value).to_owned());
+                    i += 1;
+                }
+                // ON CONFLICT, ON DELETE and so on, and SET NULL or SET DEFAULT
+                "on" | "set" => i += 1,
+                _ => {}
+            }
+            i += 1;
+        }
+        Ok(column)
+    }
+
+    /// Only a column declared `INTEGER PRIMARY KEY`, with the type spelt just
+    /// like that and without `DESC`, is another name for the rowid.
+    fn is_rowid_alias(&self) -> bool {
+        self.primary_key && !self.descending && self.declared_type.eq_ignore_ascii_case("INTEGER")
+    }
+
+    /// The `DEFAULT`, parsed.
+    pub fn default_expr(&self) -> Result<Option<Expr>> {
+        self.default
+            .as_deref()
+            .map(|default| Parser::new(default)?.expr())
+            .transpose()
+    }
+}
+
+/// Split a definition into words, with strings, quoted names and anything in
+/// brackets kept whole, so keywords inside them aren't taken for constraints.
+/// Each comes with where it starts.
+fn tokens(def: &str) -> Result<Vec<(usize, &str)>> {
+    let mut tokens = vec![];
+    let mut at = 0;
+    while let Some(c) = def[at..].chars().next() {
+        if c.is_whitespace() {
+            at += c.len_utf8();
+            continue;
+        }
+        let rest = &def[at..];
+        let len = match c {
+            '(' => {
+                let (_, after) = split_definitions(&rest[1..])?;
+                rest.len() - after.len()
+            }
+            '\'' | '"' | '`' | '[' => {
+                let close = if c == '[' { ']' } else { c };
+                let mut len = 1;
+                loop {
+                    match rest[len..].find(close) {
+                        // a doubled quote is part of the string
+                        Some(i) if c != '[' && rest[len + i + 1..].starts_with(close) => {
+                            len += i + 2
+                        }
+                        Some(i) => break len + i + 1,
+                        None => bail!("failed to parse {:?}: unterminated {}", def, c),
+                    }
+                }
+            }
+            _ => rest
+                .find(|c: char| c.is_whitespace() || "('\"`[".contains(c))
+                .unwrap_or(rest.len()),
+        };
+        tokens.push((at, &rest[..len]));
+        at += len;
+    }
+    Ok(tokens)
+}
+
+/// What's inside a token in brackets.
+fn bracketed(token: &str) -> Option<&str> {
+    let body = token.strip_prefix('(')?.strip_suffix(')')?;
+    Some(body.trim())
+}
+
+/// The expressions of the `CHECK (...)`s in a definition.
+fn check_clauses(def: &str) -> Result<Vec<String>> {
+    let tokens = tokens(def)?;
+    tokens
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, word))| word.eq_ignore_ascii_case("check"))
+        .map(|(i, _)| {
+            tokens
+                .get(i + 1)
+                .and_then(|(_, t)| bracketed(t))
+                .map(str::to_owned)
+                .ok_or_else(|| anyhow!("failed to parse CHECK in {:?}", def))
+        })
+        .collect()
+}
+
+/// `FOREIGN KEY (columns) REFERENCES table (references)`, or `REFERENCES` on a
+/// column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForeignKey {
+    pub columns: Vec<String>,
+    pub table: String,
+    /// Columns of the other table. Empty means its primary key.
+    pub references: Vec<String>,
+    /// What happens to the rows when the row they point to changes or goes
+    /// away, like `CASCADE`. `NO ACTION` if it isn't given.
+    pub on_update: String,
+    pub on_delete: String,
+}
+
+impl CreateTable {
+    /// Get index of corresponding columns in a [`Select`]
+    pub fn select(&self, sel: &Select) -> Vec<usize> {
+        match &sel.columns {
+            SelectColumns::Columns(cols) if cols.iter().any(|col| col.expr().is_none()) => {
+                (0..self.columns.len()).collect()
+            }
+            SelectColumns::Columns(cols) => cols
+                .iter()
+                .filter_map(ResultColumn::expr)
+                .flat_map(Expr::columns)
+                .flat_map(|sc| self.column_index(sc))
+                .collect(),
+            SelectColumns::All => (0..self.columns.len()).collect(),
+            SelectColumns::Count => Vec::new(),
+            SelectColumns::MinMax { column, .. } => self.column_index(column).into_iter().collect(),
+        }
+    }
+
+    /// Position of a column. Names are case insensitive.
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.columns
+            .iter()
+            .position(|c| c.eq_ignore_ascii_case(name))
+    }
+
+    /// What `PRAGMA table_info` gives: for each column its position, name,
+    /// type, whether it's `NOT NULL`, its `DEFAULT` as written, and where it
+    /// is in the primary key counting from 1, or 0 if it isn't. Like SQLite,
+    /// types that `STRICT` tables can have are shown in capitals.
+    pub fn table_info(&self) -> Vec<Vec<Value>> {
+        self.column_defs
+            .iter()
+            .enumerate()
+            .map(|(i, def)| {
+                let pk = self
+                    .primary_key
+                    .iter()
+                    .position(|c| c.eq_ignore_ascii_case(&def.name))
+                    .map_or(0, |i| i + 1);
+                let upper = def.declared_type.to_ascii_uppercase();
+                let declared_type = match ColumnDef::STRICT_TYPES.contains(&upper.as_str()) {
+                    true => upper,
+                    false => def.declared_type.clone(),
+                };
+                vec![
+                    Value::Integer(i as i64),
+                    def.name.as_str().into(),
+                    declared_type.into(),
+                    Value::Integer(def.not_null as i64),
+                    def.default.as_deref().map_or(Value::Null, Value::from),
+                    Value::Integer(pk as i64),
+                ]
+            })
+            .collect()
+    }
+
+    /// What `PRAGMA foreign_key_list` gives: a row for each column of each
+    /// foreign key, the last one declared first, with the key's number, where
+    /// the column is in the key, the other table, the column and the one it
+    /// points to (`NULL` for the primary key), the `ON UPDATE` and
+    /// `ON DELETE` actions, and `NONE`, as SQLite ignores `MATCH`.
+    pub fn foreign_key_list(&self) -> Vec<Vec<Value>> {
+        let mut rows = vec![];
+        for (id, fk) in self.foreign_keys.iter().rev().enumerate() {
+            for (seq, column) in fk.columns.iter().enumerate() {
+                rows.push(vec![
+                    Value::Integer(id as i64),
+                    Value::Integer(seq as i64),
+                    fk.table.as_str().into(),
+                    column.as_str().into(),
+                    fk.references
+                        .get(seq)
+                        .map_or(Value::Null, |c| c.as_str().into()),
+                    fk.on_update.as_str().into(),
+                    fk.on_delete.as_str().into(),
+                    "NONE".into(),
+                ]);
+            }
+        }
+        rows
+    }
+
+    /// The columns of the indexes SQLite makes for the `PRIMARY KEY` and the
+    /// `UNIQUE` constraints, in the order their names are numbered in:
+    /// `sqlite_autoindex_TABLE_1` and so on. `true` marks the primary key's.
+    /// An `INTEGER PRIMARY KEY` is the rowid, so it doesn't need one, and
+    /// neither does a constraint on the same columns as one before it.
+    pub fn automatic_indexes(&self) -> Vec<(&[String], bool)> {
+        let mut constraints: Vec<(&[String], bool)> =
+            self.unique.iter().map(|u| (&u[..], false)).collect();
+        if !self.primary_key.is_empty() && self.key.is_none() {
+            let at = self.primary_key_at.min(constraints.len());
+            constraints.insert(at, (&self.primary_key, true));
+        }
+        let same = |a: &[String], b: &[String]| {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.eq_ignore_ascii_case(b))
+        };
+        let mut indexes: Vec<(&[String], bool)> = vec![];
+        for (columns, pk) in constraints {
+            if !indexes.iter().any(|(other, _)| same(other, columns)) {
+                indexes.push((columns, pk));
+            }
+        }
+        indexes
+    }
+
+    /// Whether `name` refers to the rowid, either through the `INTEGER PRIMARY KEY`
+    /// column or one of the built-in names when they're not taken by a real column.
+    pub fn is_rowid(&self, name: &str) -> bool {
+        if let Some(key) = &self.key {
+            if key.eq_ignore_ascii_case(name) {
+                return true;
+            }
+        }
+        ["rowid", "oid", "_rowid_"]
+            .iter()
+            .any(|r| r.eq_ignore_ascii_case(name))
+            && self.column_index(name).is_none()
+    }
+}
+
+/// Compiled `CREATE INDEX` statement
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateIndex {
+    pub name: String,
+    /// Table the index is on
+    pub table: String,
+    pub columns: Vec<String>,
+    /// `COLLATE` on each column, if it has one. Otherwise the index uses the
+    /// column's own collation from the table.
+    pub collations: Vec<Option<Collation>>,
+    /// `DESC` on each column, which stores its entries largest first.
+    pub descending: Vec<bool>,
+    /// `CREATE UNIQUE INDEX`
+    pub unique: bool,
+    /// The `WHERE` of a partial index, as written. Only the rows it's true
+    /// for are in the index.
+    pub filter: Option<String>,
+}
+
+impl CreateIndex {
+    /// The parsed `WHERE` of a partial index.
+    pub fn filter_expr(&self) -> Result<Option<Expr>> {
+        self.filter
+            .as_deref()
+            .map(|filter| Parser::new(filter)?.expr())
+            .transpose()
+    }
+}
+
+impl TryFrom<&Schema> for CreateIndex {
+    type Error = Error;
+
+    fn try_from(value: &Schema) -> std::result::Result<Self, Self::Error> {
+        value.sql.parse()
+    }
+}
+
+/// A name in a `CREATE` statement: a plain word, or quoted with `"`, `[]` or
+/// `` ` `` so it can hold spaces or be a keyword. SQLite takes a `'string'`
+/// as a name too, and writes fts5 shadow tables' names that way.
+const NAME: &str = r#"(?:"(?:[^"]|"")*"|'(?:[^']|'')*'|\[[^\]]*\]|`(?:[^`]|``)*`|\w+)"#;
+
+/// The name without its quotes.
+fn unquote(name: &str) -> String {
+    match name.chars().next() {
+        Some(q @ ('"' | '`' | '\'')) => {
+            name[1..name.len() - 1].replace(&format!("{q}{q}"), &q.to_string())
+        }
+        Some('[') => name[1..name.len() - 1].to_owned(),
+        _ => name.to_owned(),
+    }
+}
+
+/// The name at the start of `s`, if there is one, and the rest of `s`.
+fn leading_name(s: &str) -> Option<(String, &str)> {
+    let rx = RegexBuilder::new(&format!(r"^\s*({})", NAME))
+        .build()
+        .ok()?;
+    let m = rx.captures(s)?.get(1)?;
+    Some((unquote(m.as_str()), &s[m.end()..]))
+}
+
+/// The collation named by `COLLATE name` in a column definition.
+fn collate_clause(def: &str) -> Result<Option<Collation>> {
+    let rx = RegexBuilder::new(&format!(r"(?:^|\s)collate\s+({NAME})"))
+        .case_insensitive(true)
+        .build()?;
+    rx.captures(def)
+        .map(|caps| unquote(&caps[1]).parse())
+        .transpose()
+}
+
+impl FromStr for CreateIndex {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let s = &sql::strip_comments(s);
+        let rx = RegexBuilder::new(&format!(
+            r"create\s+(unique\s+)?index\s+(if\s+not\s+exists\s+)?(?P<name>{NAME})\s*on\s*(?P<table>{NAME})\s*\(\s*(?P<columns>[^\)]*)\)",
+        ))
+        .case_insensitive(true)
+        .build()?;
+        let caps = rx
+            .captures(s)
+            .ok_or_else(|| anyhow!("failed to parse CREATE INDEX"))?;
+        let desc = RegexBuilder::new(r"(?:^|\s)desc\s*$")
+            .case_insensitive(true)
+            .build()?;
+        let (mut columns, mut collations, mut descending) = (vec![], vec![], vec![]);
+        for def in caps.name("columns").unwrap().as_str().split(',') {
+            let (name, rest) = leading_name(def).unwrap_or_default();
+            columns.push(name);
+            collations.push(collate_clause(rest)?);
+            descending.push(desc.is_match(rest));
+        }
+        let filter = RegexBuilder::new(r"^\s*where\s+(.*?)[\s;]*$")
+            .case_insensitive(true)
+            .dot_matches_new_line(true)
+            .build()?;
+        let rest = &s[caps.get(0).unwrap().end()..];
+        Ok(CreateIndex {
+            name: unquote(caps.name("name").unwrap().as_str()),
+            table: unquote(caps.name("table").unwrap().as_str()),
+            columns,
+            collations,
+            descending,
+            unique: caps.get(1).is_some(),
+            filter: filter.captures(rest).map(|caps| caps[1].to_owned()),
+        })
+    }
+}
+
+/// Compiled `CREATE VIEW` statement
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateView {
+    pub name: String,
+    /// Names given to the columns in brackets after the view's name. If
+    /// there aren't any, the columns are named by the `SELECT`.
+    pub columns: Vec<String>,
+    pub select: Select,
+}
+
+impl FromStr for CreateView {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let s = &sql::strip_comments(s);
+        let rx = RegexBuilder::new(&format!(
+            r"create\s+(?:temp\s+|temporary\s+)?view\s+(?:if\s+not\s+exists\s+)?(?P<name>{NAME})\s*(?:\((?P<columns>[^\)]*)\))?\s*as\s",
+        ))
+        .case_insensitive(true)
+        .build()?;
+        let caps = rx
+            .captures(s)
+            .ok_or_else(|| anyhow!("failed to parse CREATE VIEW"))?;
+        let columns = caps.name("columns").map_or(vec![], |m| {
+            m.as_str()
+                .split(',')
+                .map(|c| leading_name(c).map(|(name, _)| name).unwrap_or_default())
+                .collect()
+        });
+        let select = s[caps.get(0).unwrap().end()..].trim().trim_end_matches(';');
+        Ok(CreateView {
+            name: unquote(caps.name("name").unwrap().as_str()),
+            columns,
+            select: select.parse()?,
+        })
+    }
+}
+
+impl TryFrom<&Schema> for CreateTable {
+    type Error = Error;
+
+    fn try_from(value: &Schema) -> std::result::Result<Self, Self::Error> {
+        value.sql.parse()
+    }
+}
+
+impl Select {
+    /// How many parameters have to be bound before the query can run.
+    pub fn param_count(&self) -> usize {
+        self.exprs().map(Expr::param_count).max().unwrap_or(0)
+    }
+
+    /// The names of the variables in the query, each once.
+    pub fn variables(&self) -> Vec<String> {
+        let mut names: Vec<String> = vec![];
+        for name in self.exprs().flat_map(Expr::variables) {
+            if !names.iter().any(|n| n == name) {
+                names.push(name.to_owned());
+            }
+        }
+        names
+    }
+
+    /// All the expressions in the query.
+    fn exprs(&self) -> impl Iterator<Item = &Expr> {
+        let columns = match &self.columns {
+            SelectColumns::Columns(cols) => &cols[..],
+            _ => &[],
+        };
+        columns
+            .iter()
+            .filter_map(ResultColumn::expr)
+            .chain(&self.filter)
+            .chain(self.joins.iter().map(|j| &j.on))
+            .chain(self.order_by.iter().map(|t| &t.expr))
+    }
+
+    /// The query with each `$name` replaced by its value from `vars`.
+    pub fn bind_vars(&self, vars: &HashMap<String, Value>) -> Result<Select> {
+        self.map_exprs(|e| e.bind_vars(vars))
+    }
+
+    /// The query with `params` in place of its parameters. There has to be
+    /// exactly one value for each.
+    pub fn bind(&self, params: &[Value]) -> Result<Select> {
+        let expected = self.param_count();
+        if params.len() != expected {
+            bail!("expected {} parameters, got {}", expected, params.len());
+        }
+        self.map_exprs(|e| e.bind(params))
+    }
+
+    /// A copy of the query with `f` applied to each of its expressions.
+    fn map_exprs(&self, f: impl Fn(&Expr) -> Result<Expr>) -> Result<Select> {
+        let mut select = self.clone();
+        if let SelectColumns::Columns(cols) = &mut select.columns {
+            for col in cols {
+                if let ResultColumn::Expr { expr, .. } = col {
+                    *expr = f(expr)?;
+                }
+            }
+        }
+        if let Some(filter) = &mut select.filter {
+            *filter = f(filter)?;
+        }
+        for join in &mut select.joins {
+            join.on = f(&join.on)?;
+        }
+        for term in &mut select.order_by {
+            term.expr = f(&term.expr)?;
+        }
+        Ok(select)
+    }
+}
+
+impl Select {
+    /// Parse a `SELECT`, failing if it's bigger than `limits` allows.
+    pub fn parse_with(s: &str, limits: &Limits) -> Result<Self> {
+        let mut parser = Parser::with_limits(s, limits)?;
+        let select = parser
+            .select()
+            .map_err(|e| anyhow!("failed to parse SELECT: {}", e))?;
+        parser.finish()?;
+        Ok(select)
+    }
+}
+
+impl FromStr for Select {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Select::parse_with(s, &Limits::default())
+    }
+}
+
+/// Split the body of a `CREATE TABLE`, from after the `(`, into column
+/// definitions and constraints, stopping at the closing `)`. Also gives what
+/// comes after it: the table options.
+fn split_definitions(body: &str) -> Result<(Vec<&str>, &str)> {
+    let mut defs = vec![];
+    let mut depth = 0;
+    let mut quote = None;
+    let mut start = 0;
+    for (i, c) in body.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"' | '`') => quote = Some(c),
+            (None, '[') => quote = Some(']'),
+            (None, '(') => depth += 1,
+            (None, ',') if depth == 0 => {
+                defs.push(body[start..i].trim());
+                start = i + 1;
+            }
+            (None, ')') if depth == 0 => {
+                defs.push(body[start..i].trim());
+                return Ok((defs, &body[i + 1..]));
+            }
+            (None, ')') => depth -= 1,
+            _ => {}
+        }
+    }
+    bail!("failed to parse CREATE TABLE: missing )")
+}
+
+impl FromStr for CreateTable {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let s = &sql::strip_comments(s);
+        let rx = RegexBuilder::new(&format!(
+            r"create\s+table\s+(?:if\s+not\s+exists\s+)?(?P<name>{NAME})\s*\("
+        ))
+        .case_insensitive(true)
+        .build()?;
+        let caps = rx
+            .captures(s)
+            .ok_or_else(|| anyhow!("failed to parse CREATE TABLE"))?;
+        let name = unquote(caps.name("name").unwrap().as_str());
+        let mut table = CreateTable {
+            name,
+            columns: vec![],
+            column_defs: vec![],
+            collations: vec![],
+            key: None,
+            autoincrement: false,
+            primary_key: vec![],
+            foreign_keys: vec![],
+            unique: vec![],
+            primary_key_at: 0,
+            checks: vec![],
+            strict: false,
+        };
+        let references = RegexBuilder::new(&format!(r"references\s*({NAME})\s*(?:\(([^\)]*)\))?"))
+            .case_insensitive(true)
+            .build()?;
+        let foreign_key = RegexBuilder::new(r"^foreign\s+key\s*\(([^\)]*)\)")
+            .case_insensitive(true)
+            .build()?;
+        let primary_key = RegexBuilder::new(r"^primary\s+key\s*\(([^\)]*)\)")
+            .case_insensitive(true)
+            .build()?;
+        let unique = RegexBuilder::new(r"^unique\s*\(([^\)]*)\)")
+            .case_insensitive(true)
+            .build()?;
+        let names = |list: &str| {
+            list.split(',')
+                .map(|c| leading_name(c).map(|(name, _)| name).unwrap_or_default())
+                .collect::<Vec<_>>()
+        };
+        let action = RegexBuilder::new(
+            r"\bon\s+(delete|update)\s+(set\s+null|set\s+default|cascade|restrict|no\s+action)",
+        )
+        .case_insensitive(true)
+        .build()?;
+        let reference = |def: &str, columns: Vec<String>| {
+            references.captures(def).map(|caps| {
+                let mut fk = ForeignKey {
+                    columns,
+                    table: unquote(&caps[1]),
+                    references: caps.get(2).map_or(vec![], |m| names(m.as_str())),
+                    on_update: "NO ACTION".to_owned(),
+                    on_delete: "NO ACTION".to_owned(),
+                };
+                for caps in action.captures_iter(&def[caps.get(0).unwrap().end()..]) {
+                    let what = caps[2].split_whitespace().collect::<Vec<_>>().join(" ");
+                    match caps[1].to_ascii_lowercase().as_str() {
+                        "update" => fk.on_update = what.to_ascii_uppercase(),
+                        _ => fk.on_delete = what.to_ascii_uppercase(),
+                    }
+                }
+                fk
+            })
+        };
+
+        let (defs, options) = split_definitions(&s[caps.get(0).unwrap().end()..])?;
+        for option in options.trim().trim_end_matches(';').split(',') {
+            let option = option.split_whitespace().collect::<Vec<_>>().join(" ");
+            match option.to_ascii_lowercase().as_str() {
+                "strict" => table.strict = true,
+                "without rowid" | "" => {}
+                _ => bail!("unknown table option: {}", option),
+            }
+        }
+        for raw in defs {
+            // normalize whitespace
+            let def = raw.split_whitespace().collect::<Vec<_>>().join(" ");
+            let lower = def.to_ascii_lowercase();
+            let first = lower
+                .split(|c: char| !c.is_alphanumeric())
+                .next()
+                .unwrap_or("");
+            if ["constraint", "primary", "unique", "check", "foreign"].contains(&first) {
+                // table constraint, maybe with a name. Not normalized, so
+                // quoted names keep their spaces
+                let def = match first {
+                    "constraint" => leading_name(raw)
+                        .and_then(|(_, rest)| leading_name(rest))
+                        .map_or("", |(_, rest)| rest.trim_start()),
+                    _ => raw,
+                };
+                if let Some(caps) = primary_key.captures(def) {
+                    table.primary_key = names(&caps[1]);
+                    table.primary_key_at = table.unique.len();
+                    // an INTEGER column on its own is the rowid, even with DESC
+                    if let [pk] = &table.primary_key[..] {
+                        let column = table
+                            .column_defs
+                            .iter()
+                            .find(|def| def.name.eq_ignore_ascii_case(pk))
+                            .filter(|def| def.declared_type.eq_ignore_ascii_case("INTEGER"));
+                        if let Some(def) = column {
+                            table.key = Some(def.name.clone());
+                        }
+                    }
+                } else if let Some(caps) = foreign_key.captures(def) {
+                    table.foreign_keys.extend(reference(def, names(&caps[1])));
+                } else if let Some(caps) = unique.captures(def) {
+                    table.unique.push(names(&caps[1]));
+                } else {
+                    table.checks.extend(check_clauses(def)?);
+                }
+                continue;
+            }
+            // the name is taken from before normalizing, as it can have spaces
+            let Some((column, rest)) = leading_name(raw) else {
+                bail!("failed to parse CREATE TABLE: bad column {:?}", raw);
+            };
+            let def = rest.split_whitespace().collect::<Vec<_>>().join(" ");
+            let column_def = ColumnDef::parse(column.clone(), &def)?;
+            if column_def.primary_key {
+                table.primary_key = vec![column.clone()];
+                table.primary_key_at = table.unique.len();
+                if column_def.is_rowid_alias() {
+                    table.key = Some(column.clone());
+                }
+            }
+            if column_def.autoincrement {
+                if table.key.as_ref() != Some(&column) {
+                    bail!("AUTOINCREMENT is only allowed on an INTEGER PRIMARY KEY");
+                }
+                table.autoincrement = true;
+            }
+            if column_def.unique {
+                table.unique.push(vec![column.clone()]);
+            }
+            table
+                .foreign_keys
+                .extend(reference(&def, vec![column.clone()]));
+            table
+                .collations
+                .push(collate_clause(&def)?.unwrap_or_default());
+            table.column_defs.push(column_def);
+            table.columns.push(column);
+        }
+        for def in &mut table.column_defs {
+            def.primary_key = table
+                .primary_key
+                .iter()
+                .any(|k| k.eq_ignore_ascii_case(&def.name));
+            def.unique = table
+                .unique
+                .iter()
+                .any(|u| u.len() == 1 && u[0].eq_ignore_ascii_case(&def.name));
+            if !table.strict {
+                continue;
+            }
+            let declared = def.declared_type.to_ascii_uppercase();
+            if declared.is_empty() {
+                bail!("missing datatype for {}.{}", table.name, def.name);
+            } else if !ColumnDef::STRICT_TYPES.contains(&declared.as_str()) {
+                bail!(
+                    "unknown datatype for {}.{}: \"{}\"",
+                    table.name,
+                    def.name,
+                    def.declared_type
+                );
+            } else if declared == "ANY" {
+                // ANY keeps values just as they're given
+                def.affinity = Affinity::Blob;
+            }
+        }
+        Ok(table)
+    }
+}
+
+#[test]
+fn sql_create_table() -> Result<()> {
+    let sql = "CREATE TABLE apples
+    (
+            id integer primary key autoincrement,
+            name text,
+            color text
+    )";
+    let table: CreateTable = sql.parse()?;
+    let column = |name: &str, declared_type: &str, affinity, primary_key| ColumnDef {
+        name: name.to_owned(),
+        declared_type: declared_type.to_owned(),
+        affinity,
+        not_null: false,
+        default: None,
+        primary_key,
+        descending: false,
+        autoincrement: name == "id",
+        unique: false,
+        checks: vec![],
+    };
+    let expected = CreateTable {
+        name: "apples".to_string(),
+        columns: vec!["id".to_owned(), "name".to_owned(), "color".to_owned()],
+        column_defs: vec![
+            column("id", "integer", Affinity::Integer, true),
+            column("name", "text", Affinity::Text, false),
+            column("color", "text", Affinity::Text, false),
+        ],
+        collations: vec![Collation::Binary; 3],
+        key: Some("id".to_owned()),
+        autoincrement: true,
+        primary_key: vec!["id".to_owned()],
+        foreign_keys: vec![],
+        unique: vec![],
+        primary_key_at: 0,
+        checks: vec![],
+        strict: false,
+    };
+    assert_eq!(table, expected);
+    // how fts5 writes its shadow tables
+    let shadow: CreateTable =
+        "CREATE TABLE IF NOT EXISTS 'd_content'(id INTEGER PRIMARY KEY, c0, c1)".parse()?;
+    assert_eq!(shadow.name, "d_content");
+    assert_eq!(shadow.columns, ["id", "c0", "c1"]);
+    Ok(())
+}
+
+#[test]
+fn sql_column_defs() -> Result<()> {
+    let sql = "CREATE TABLE t (
+        a VARCHAR(10, 2) NOT NULL DEFAULT 'it''s',
+        b DEFAULT (1 + 2) PRIMARY KEY,
+        c floating point CHECK(c > 0) DEFAULT -1.5,
+        d BLOB,
+        e MONEY COLLATE nocase
+    )";
+    let table: CreateTable = sql.parse()?;
+    let defs = &table.column_defs;
+    let types: Vec<_> = defs.iter().map(|d| d.declared_type.as_str()).collect();
+    assert_eq!(
+        types,
+        ["VARCHAR(10, 2)", "", "floating point", "BLOB", "MONEY"]
+    );
+    let affinities: Vec<_> = defs.iter().map(|d| d.affinity).collect();
+    use Affinity::*;
+    assert_eq!(affinities, [Text, Blob, Integer, Blob, Numeric]);
+    let defaults: Vec<_> = defs.iter().map(|d| d.default.as_deref()).collect();
+    assert_eq!(
+        defaults,
+        [Some("'it''s'"), Some("1 + 2"), Some("-1.5"), None, None]
+    );
+    assert!(defs[0].not_null && !defs[1].not_null);
+    assert!(defs[1].primary_key && !defs[0].primary_key);
+    Ok(())
+}
+
+#[test]
+fn sql_constraints() -> Result<()> {
+    let sql = "CREATE TABLE t (
+        a INT UNIQUE CHECK (a > 0) CONSTRAINT small CHECK(a < f(1, 2)),
+        b TEXT DEFAULT 'check (x)',
+        c,
+        CONSTRAINT pair UNIQUE (b, c),
+        UNIQUE (C),
+        CHECK (b <> 'it''s (odd)'),
+        FOREIGN KEY (c) REFERENCES other (id)
+    )";
+    let table: CreateTable = sql.parse()?;
+    let defs = &table.column_defs;
+    assert!(defs[0].unique && !defs[1].unique && defs[2].unique);
+    assert_eq!(defs[0].checks, ["a > 0", "a < f(1, 2)"]);
+    assert!(defs[1].checks.is_empty());
+    assert_eq!(table.unique, [vec!["a"], vec!["b", "c"], vec!["C"]]);
+    assert_eq!(table.checks, ["b <> 'it''s (odd)'"]);
+    assert_eq!(table.foreign_keys[0].table, "other");
+    assert_eq!(
+        defs[1].default_expr()?,
+        Some(Expr::Literal("check (x)".into()))
+    );
+    Ok(())
+}
+
+#[test]
+fn sql_constraint_bodies() -> Result<()> {
+    let sql = "CREATE TABLE t (
+        x TEXT CHECK (x IS NOT NULL OR 1),
+        y TEXT DEFAULT 'unique key',
+        z DEFAULT (abs(-1)) CHECK (z <> 'primary key (z)'),
+        w REFERENCES other ON DELETE SET DEFAULT NOT NULL
+    )";
+    let table: CreateTable = sql.parse()?;
+    let defs = &table.column_defs;
+    assert!(defs.iter().all(|d| !d.primary_key && !d.unique));
+    assert!(!defs[0].not_null && defs[3].not_null);
+    assert_eq!(defs[0].checks, ["x IS NOT NULL OR 1"]);
+    assert_eq!(defs[1].default.as_deref(), Some("'unique key'"));
+    assert_eq!(defs[2].default.as_deref(), Some("abs(-1)"));
+    assert_eq!(defs[2].checks, ["z <> 'primary key (z)'"]);
+    assert_eq!(defs[3].default, None);
+    assert!(table.primary_key.is_empty() && table.unique.is_empty());
+    let table: CreateTable = "CREATE TABLE t (
+        id INTEGER PRIMARY KEY,
+        a TEXT DEFAULT 'primary key autoincrement',
+        b CHECK (b != 'unique')
+    )"
+    .parse()?;
+    assert_eq!(table.primary_key, ["id"]);
+    assert!(!table.autoincrement && table.unique.is_empty());
+    Ok(())
+}
+
+#[test]
+fn sql_table_info() -> Result<()> {
+    let table: CreateTable =
+        "CREATE TABLE t (a text NOT NULL DEFAULT 'x', b, c INT, PRIMARY KEY (c, a))".parse()?;
+    let info = table.table_info();
+    assert_eq!(
+        info[0],
+        [
+            Value::Integer(0),
+            "a".into(),
+            "TEXT".into(),
+            Value::Integer(1),
+            "'x'".into(),
+            Value::Integer(2)
+        ]
+    );
+    assert_eq!(info[1][2], "".into());
+    assert_eq!(info[1][4], Value::Null);
+    assert_eq!(info[2][5], Value::Integer(1));
+    Ok(())
+}
+
+#[test]
+fn sql_strict_tables() -> Result<()> {
+    let table: CreateTable =
+        "CREATE TABLE t (a INT, b any NOT NULL) STRICT, WITHOUT ROWID".parse()?;
+    assert!(table.strict);
+    assert_eq!(table.column_defs[1].affinity, Affinity::Blob);
+    assert!(table.column_defs[0].strict_allows(&Value::Null));
+    assert!(!table.column_defs[0].strict_allows(&Value::Float(1.5)));
+    assert!(!table.column_defs[1].strict_allows(&Value::Null));
+    let table: CreateTable = "CREATE TABLE t (a VARCHAR(10))".parse()?;
+    assert!(!table.strict);
+    assert!("CREATE TABLE t (a VARCHAR(10)) STRICT"
+        .parse::<CreateTable>()
+        .is_err());
+    assert!("CREATE TABLE t (a) STRICT".parse::<CreateTable>().is_err());
+    assert!("CREATE TABLE t (a) LOOSE".parse::<CreateTable>().is_err());
+    Ok(())
+}
+
+#[test]
+fn sql_create_table_keys() -> Result<()> {
+    let sql = "CREATE TABLE orders (
+        id int,
+        customer integer REFERENCES customers,
+        item text references items(sku),
+        total real CHECK (total > 0),
+        -- the key, with a name
+        CONSTRAINT pk PRIMARY KEY (id, item), /* and a, comma */
+        FOREIGN KEY (id, item) REFERENCES lines (order_id, sku)
+    )";
+    let table: CreateTable = sql.parse()?;
+    assert_eq!(table.columns, ["id", "customer", "item", "total"]);
+    assert_eq!(table.key, None);
+    assert_eq!(table.primary_key, ["id", "item"]);
+    let fk = |columns: &[&str], table: &str, references: &[&str]| ForeignKey {
+        columns: columns.iter().map(|s| s.to_string()).collect(),
+        table: table.to_owned(),
+        references: references.iter().map(|s| s.to_string()).collect(),
+        on_update: "NO ACTION".to_owned(),
+        on_delete: "NO ACTION".to_owned(),
+    };
+    assert_eq!(
+        table.foreign_keys,
+        [
+            fk(&["customer"], "customers", &[]),
+            fk(&["item"], "items", &["sku"]),
+            fk(&["id", "item"], "lines", &["order_id", "sku"]),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn sql_rowid_aliases() -> Result<()> {
+    let key = |sql: &str| -> Result<Option<String>> { Ok(sql.parse::<CreateTable>()?.key) };
+    let id = Some("id".to_owned());
+    assert_eq!(key("CREATE TABLE t (id INTEGER PRIMARY KEY, x)")?, id);
+    assert_eq!(
+        key("CREATE TABLE t (id INTEGER NOT NULL PRIMARY KEY, x)")?,
+        id
+    );
+    assert_eq!(
+        key("CREATE TABLE t (id INTEGER CONSTRAINT pk PRIMARY KEY, x)")?,
+        id
+    );
+    assert_eq!(key("CREATE TABLE t (x, id integer, PRIMARY KEY (id))")?, id);
+    assert_eq!(
+        key("CREATE TABLE t (id INTEGER, x, PRIMARY KEY (id DESC))")?,
+        id
+    );
+    let table: CreateTable = "CREATE TABLE t (id INTEGER, x, PRIMARY KEY (ID))".parse()?;
+    assert_eq!(table.key, id);
+    assert!(table.column_defs[0].primary_key);
+    // not the rowid
+    assert_eq!(
+        key("CREATE TABLE t (id INTEGER PRIMARY KEY DESC, x)")?,
+        None
+    );
+    assert_eq!(key("CREATE TABLE t (id INT PRIMARY KEY, x)")?, None);
+    assert_eq!(
+        key("CREATE TABLE t (id INTEGER, x, PRIMARY KEY (id, x))")?,
+        None
+    );
+    assert_eq!(
+        key("CREATE TABLE t (id BIGINT, x, PRIMARY KEY (id))")?,
+        None
+    );
+    Ok(())
+}
+
+#[test]
+fn sql_foreign_key_list() -> Result<()> {
+    let sql = "CREATE TABLE c (
+        x REFERENCES p,
+        w REFERENCES p(id) ON DELETE CASCADE ON  UPDATE set null,
+        FOREIGN KEY (y, z) REFERENCES p (a, b) ON UPDATE RESTRICT MATCH FULL,
+        CONSTRAINT k FOREIGN KEY (x) REFERENCES \"p\" DEFERRABLE INITIALLY DEFERRED
+    )";
+    let table: CreateTable = sql.parse()?;
+    assert_eq!(table.foreign_keys[1].on_update, "SET NULL");
+    assert_eq!(table.foreign_keys[1].on_delete, "CASCADE");
+    let rows = table.foreign_key_list();
+    let row = |values: [&str; 6]| -> Vec<Value> {
+        values
+            .iter()
+            .map(|v| match v.parse() {
+                Ok(n) => Value::Integer(n),
+                Err(_) if v.is_empty() => Value::Null,
+                Err(_) => Value::from(*v),
+            })
+            .chain(["NO ACTION".into(), "NONE".into()])
+            .collect()
+    };
+    assert_eq!(rows[0], row(["0", "0", "p", "x", "", "NO ACTION"]));
+    assert_eq!(rows[2], row(["1", "1", "p", "z", "b", "RESTRICT"]));
+    assert_eq!(rows.len(), 5);
+    Ok(())
+}
+
+#[test]
+fn sql_create_table_quoted() -> Result<()> {
+    let sql = r#"CREATE TABLE "order items" (
+        [id] integer primary key,
+        "unit  price" real,
+        `from` text REFERENCES "the ""items""",
+        PRIMARY KEY ("unit  price")
+    )"#;
+    let table: CreateTable = sql.parse()?;
+    assert_eq!(table.name, "order items");
+    assert_eq!(table.columns, ["id", "unit  price", "from"]);
+    assert_eq!(table.key.as_deref(), Some("id"));
+    assert_eq!(table.primary_key, ["unit  price"]);
+    assert_eq!(table.foreign_keys[0].table, r#"the "items""#);
+    let index: CreateIndex =
+        r#"CREATE INDEX [by price] ON "order items" ("unit  price")"#.parse()?;
+    assert_eq!(index.name, "by price");
+    assert_eq!(index.table, "order items");
+    assert_eq!(index.columns, ["unit  price"]);
+    Ok(())
+}
+
+#[test]
+fn sql_select() -> Result<()> {
+    let sql = "SELECT name FROM apples";
+    let sel: Select = sql.parse()?;
+    let expected = Select {
+        name: "apples".to_owned(),
+        alias: None,
+        columns: SelectColumns::Columns(vec![Expr::Column("name".to_owned()).into()]),
+        joins: vec![],
+        filter: None,
+        order_by: vec![],
+    };
+    assert_eq!(sel, expected);
+    Ok(())
+}
+
+#[test]
+fn sql_multi_select() -> Result<()> {
+    let sql = "SELECT name, description FROM apples";
+    let sel: Select = sql.parse()?;
+    let expected = Select {
+        name: "apples".to_owned(),
+        alias: None,
+        columns: SelectColumns::Columns(vec![
+            Expr::Column("name".to_owned()).into(),
+            Expr::Column("description".to_owned()).into(),
+        ]),
+        joins: vec![],
+        filter: None,
+        order_by: vec![],
+    };
+    assert_eq!(sel, expected);
+    Ok(())
+}
+
+#[test]
+fn sql_select_count() -> Result<()> {
+    let sql = "SELECT COUNT(*) FROM apples";
+    let sel: Select = sql.parse()?;
+    let expected = Select {
+        name: "apples".to_owned(),
+        alias: None,
+        columns: SelectColumns::Count,
+        joins: vec![],
+        filter: None,
+        order_by: vec![],
+    };
+    assert_eq!(sel, expected);
+    Ok(())
+}
+
+#[test]
+fn sql_select_min_max() -> Result<()> {
+    let sel: Select = "SELECT max(a.color) FROM apples a".parse()?;
+    let expected = SelectColumns::MinMax {
+        column: "a.color".to_owned(),
+        max: true,
+    };
+    assert_eq!(sel.columns, expected);
+    let sel: Select = "SELECT MIN(name) FROM apples WHERE id > 1".parse()?;
+    let expected = SelectColumns::MinMax {
+        column: "name".to_owned(),
+        max: false,
+    };
+    assert_eq!(sel.columns, expected);
+    // anything more than that is an ordinary column
+    let sel: Select = "SELECT min(name), id FROM apples".parse()?;
+    assert!(matches!(sel.columns, SelectColumns::Columns(_)));
+    Ok(())
+}
+
+#[test]
+fn sql_select_where() -> Result<()> {
+    use self::sql::BinaryOp;
+    let sql = "SELECT id, name FROM companies WHERE country = 'eritrea'";
+    let sel: Select = sql.parse()?;
+    let expected = Select {
+        name: "companies".to_owned(),
+        alias: None,
+        columns: SelectColumns::Columns(vec![
+            Expr::Column("id".to_owned()).into(),
+            Expr::Column("name".to_owned()).into(),
+        ]),
+        joins: vec![],
+        filter: Some(Expr::binary(
+            BinaryOp::Eq,
+            Expr::Column("country".to_owned()),
+            Expr::Literal(Value::String("eritrea".to_owned())),
+        )),
+        order_by: vec![],
+    };
+    assert_eq!(sel, expected);
+    Ok(())
+}
+
+#[test]
+fn sql_create_index() -> Result<()> {
+    let sql = "CREATE INDEX idx_companies_country\n\ton companies (country)";
+    let index: CreateIndex = sql.parse()?;
+    let expected = CreateIndex {
+        name: "idx_companies_country".to_owned(),
+        table: "companies".to_owned(),
+        columns: vec!["country".to_owned()],
+        collations: vec![None],
+        descending: vec![false],
+        unique: false,
+        filter: None,
+    };
+    assert_eq!(index, expected);
+    Ok(())
+}
+
+#[test]
+fn sql_partial_unique_index() -> Result<()> {
+    let sql = "CREATE UNIQUE INDEX i ON t (a DESC, b)\n  WHERE a > 0 AND\n b IS NOT NULL;";
+    let index: CreateIndex = sql.parse()?;
+    assert!(index.unique);
+    assert_eq!(index.columns, ["a", "b"]);
+    assert_eq!(index.descending, [true, false]);
+    assert_eq!(index.filter.as_deref(), Some("a > 0 AND\n b IS NOT NULL"));
+    assert_eq!(index.filter_expr()?.map(|f| f.conjuncts().len()), Some(2));
+    Ok(())
+}
+
+#[test]
+fn sql_automatic_indexes() -> Result<()> {
+    let sql = "CREATE TABLE t (a, b UNIQUE, c, d, PRIMARY KEY(c, d), UNIQUE(a,b), UNIQUE (B))";
+    let table: CreateTable = sql.parse()?;
+    let names = |columns: &[&str]| columns.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+    let (b, cd, ab) = (names(&["b"]), names(&["c", "d"]), names(&["a", "b"]));
+    assert_eq!(
+        table.automatic_indexes(),
+        [(&b[..], false), (&cd[..], true), (&ab[..], false)]
+    );
+    // the rowid doesn't need one
+    let table: CreateTable = "CREATE TABLE t (id INTEGER PRIMARY KEY, n UNIQUE)".parse()?;
+    assert_eq!(table.automatic_indexes(), [(&names(&["n"])[..], false)]);
+    Ok(())
+}
+
+#[test]
+fn sql_collations() -> Result<()> {
+    let sql = "CREATE TABLE t (a text COLLATE NOCASE, b text collate \"rtrim\" not null, c)";
+    let table: CreateTable = sql.parse()?;
+    use Collation::*;
+    assert_eq!(table.collations, [NoCase, Rtrim, Binary]);
+    let index: CreateIndex = "CREATE INDEX i ON t (a COLLATE binary DESC, b ASC)".parse()?;
+    assert_eq!(index.collations, [Some(Binary), None]);
+    assert_eq!(index.descending, [true, false]);
+    let table: CreateTable = "CREATE TABLE t (a COLLATE klingon)".parse()?;
+    assert_eq!(table.collations, [Custom("klingon".into())]);
+    Ok(())
+}
+
+#[cfg(all(feature = "mmap", unix, target_pointer_width = "64"))]
+#[test]
+fn mmap_pages_match_reads() -> Result<()> {
+    let file = OpenOptions::new().open("sample.db")?;
+    // SAFETY: sample.db is only ever read
+    let mapped = unsafe { OpenOptions::new().mmap(true) }.open("sample.db")?;
+    for page_id in 1..=file.page_count()? {
+        let page_id = NonZeroU64::new(page_id).unwrap();
+        let page = mapped.get_page(page_id)?;
+        assert!(matches!(page.data, PageData::Mapped { .. }));
+        assert_eq!(&page.data[..], &file.get_page(page_id)?.data[..]);
+    }
+    Ok(())
+}
+
+#[test]
+fn stats_count_page_reads() -> Result<()> {
+    let file = OpenOptions::new().open("sample.db")?;
+    file.reset_stats();
+    let page_id = NonZeroU64::new(2).unwrap();
+    file.get_page(page_id)?;
+    file.get_page(page_id)?;
+    let stats = file.stats();
+    assert_eq!(stats.pages_read, 1);
+    assert_eq!(stats.cache_misses, 1);
+    assert_eq!(stats.cache_hits, 1);
+    assert_eq!(stats.bytes_read, file.page_size() as u64);
+    file.reset_stats();
+    assert_eq!(file.stats(), Stats::default());
+    Ok(())
+}
+
+#[test]
+fn autoincrement_sequences() -> Result<()> {
+    let rows = |ids: &[i64]| ids.iter().map(|&id| vec![id.into(), "x".into()]).collect();
+    let file = fixtures::Fixture::new()
+        .table(
+            "CREATE TABLE t (id INTEGER PRIMARY KEY AUTOINCREMENT, x)",
+            rows(&[1, 2, 3]),
+        )
+        .table(
+            "CREATE TABLE sqlite_sequence(name,seq)",
+            vec![vec!["t".into(), 10.into()]],
+        )
+        .table("CREATE TABLE u (id INTEGER PRIMARY KEY, x)", rows(&[5, 7]))
+        .table("CREATE TABLE v (x)", vec![])
+        .bulk_table(
+            "CREATE TABLE big (id INTEGER PRIMARY KEY, x)",
+            (1..=2000).map(|id| vec![id.into(), "x".into()]),
+        )?
+        .open()?;
+    assert_eq!(file.sequences()?, [("t".to_owned(), 10)]);
+    assert_eq!(file.next_rowid("t")?, 11);
+    assert_eq!(file.next_rowid("u")?, 8);
+    assert_eq!(file.next_rowid("v")?, 1);
+    assert_eq!(file.next_rowid("big")?, 2001);
+    assert!(file.next_rowid("nope").is_err());
+    assert!("CREATE TABLE w (id TEXT PRIMARY KEY AUTOINCREMENT)"
+        .parse::<CreateTable>()
+        .is_err());
+    Ok(())
+}
+
+#[test]
+fn sql_create_view() -> Result<()> {
+    let view: CreateView =
+        "CREATE VIEW IF NOT EXISTS \"red apples\" (n, c) AS\n SELECT name, color FROM apples WHERE color = 'Red';"
+            .parse()?;
+    assert_eq!(view.name, "red apples");
+    assert_eq!(view.columns, ["n", "c"]);
+    assert_eq!(
+        view.select,
+        "SELECT name, color FROM apples WHERE color = 'Red'".parse()?
+    );
+    let view: CreateView = "create temp view v as select * from t".parse()?;
+    assert!(view.columns.is_empty());
+    assert!("CREATE VIEW v".parse::<CreateView>().is_err());
+    Ok(())
+}
+
+#[test]
+fn journals() -> Result<()> {
+    let path = std::env::temp_dir().join(format!("journal-{}.db", std::process::id()));
+    let journal = path.with_extension("db-journal");
+    std::fs::copy("sample.db", &path)?;
+    let mut opened = vec![];
+    // committed in journal_mode=PERSIST: the header is zeroed
+    std::fs::write(&journal, [0; 512])?;
+    opened.push(OpenOptions::new().open(&path).map(|_| ()));
+    std::fs::write(&journal, [])?;
+    opened.push(OpenOptions::new().open(&path).map(|_| ()));
+    let mut hot = vec![0xd9, 0xd5, 0x05, 0xf9, 0x20, 0xa1, 0x63, 0xd7];
+    hot.resize(512, 0);
+    std::fs::write(&journal, hot)?;
+    opened.push(OpenOptions::new().open(&path).map(|_| ()));
+    opened.push(OpenOptions::new().immutable(true).open(&path).map(|_| ()));
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_file(&journal);
+    let errors: Vec<Option<String>> = opened
+        .into_iter()
+        .map(|r| r.err().map(|e| e.to_string()))
+        .collect();
+    assert_eq!(
+        errors,
+        [None, None, Some(OpenError::HotJournal.to_string()), None]
+    );
+    Ok(())
+}
+
+#[test]
+fn in_memory() -> Result<()> {
+    let db = OpenOptions::new().open(":memory:")?;
+    assert_eq!(db.page_count()?, 1);
+    assert!(db.get_schema()?.is_empty());
+    assert!(integrity::integrity_check(&db)?.is_empty());
+    let bytes = std::fs::read("sample.db")?;
+    let db = SqliteFile::from_bytes(bytes)?;
+    assert_eq!(
+        db.prepare("SELECT COUNT(*) FROM apples")?.rows()?,
+        [vec![Value::Integer(4)]]
+    );
+    assert!(SqliteFile::from_bytes(vec![0; 50]).is_err());
+    let db = SqliteFile::from_reader(std::fs::File::open("sample.db")?)?;
+    assert_eq!(db.get_schema()?.len(), 3);
+    Ok(())
+}
@@ -0,0 +1,193 @@
+//! What each page of the file is used for, worked out by following every
+//! B-tree from the schema, their overflow chains and the freelist. `.map`
+//! draws it one character per page, which makes things like a table spread
+//! thinly across the file or a pile of free pages stand out.
+
+use std::collections::HashSet;
+use std::num::NonZeroU64;
+
+use anyhow::Result;
+
+use super::btree::walk;
+use super::{PageKind, SqliteFile};
+
+/// What a page is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum PageUse {
+    TableInterior,
+    TableLeaf,
+    IndexInterior,
+    IndexLeaf,
+    Overflow,
+    Freelist,
+    Ptrmap,
+    /// Not reachable from anything, or in a B-tree too broken to follow.
+    Unknown,
+}
+
+impl PageUse {
+    pub const ALL: [PageUse; 8] = [
+        PageUse::TableInterior,
+        PageUse::TableLeaf,
+        PageUse::IndexInterior,
+        PageUse::IndexLeaf,
+        PageUse::Overflow,
+        PageUse::Freelist,
+        PageUse::Ptrmap,
+        PageUse::Unknown,
+    ];
+
+    /// The character `.map` draws it as.
+    pub fn symbol(self) -> char {
+        match self {
+            PageUse::TableInterior => 'T',
+            PageUse::TableLeaf => 't',
+            PageUse::IndexInterior => 'I',
+            PageUse::IndexLeaf => 'i',
+            PageUse::Overflow => 'o',
+            PageUse::Freelist => 'f',
+            PageUse::Ptrmap => 'p',
+            PageUse::Unknown => '?',
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PageUse::TableInterior => "table interior",
+            PageUse::TableLeaf => "table leaf",
+            PageUse::IndexInterior => "index interior",
+            PageUse::IndexLeaf => "index leaf",
+            PageUse::Overflow => "overflow",
+            PageUse::Freelist => "freelist",
+            PageUse::Ptrmap => "pointer map",
+            PageUse::Unknown => "unknown",
+        }
+    }
+}
+
+impl From<PageKind> for PageUse {
+    fn from(kind: PageKind) -> Self {
+        match kind {
+            PageKind::TableInterior => PageUse::TableInterior,
+            PageKind::TableLeaf => PageUse::TableLeaf,
+            PageKind::IndexInterior => PageUse::IndexInterior,
+            PageKind::IndexLeaf => PageUse::IndexLeaf,
+        }
+    }
+}
+
+/// What each page is, starting with page 1. Broken trees and chains are
+/// followed as far as they go, leaving the rest `Unknown`, since finding
+/// those is the point.
+pub fn page_map(file: &SqliteFile) -> Result<Vec<PageUse>> {
+    let page_count = file.page_count()?;
+    let mut map = vec![PageUse::Unknown; page_count as usize];
+    let mut set = |page: u64, what: PageUse| -> bool {
+        match map.get_mut((page as usize).wrapping_sub(1)) {
+            Some(slot) if *slot == PageUse::Unknown => {
+                *slot = what;
+                true
+            }
+            // off the end, or already seen, which would loop forever
+            _ => false,
+        }
+    };
+
+    for page in 2..=page_count {
+        if file.is_ptrmap_page(page)? {
+            set(page, PageUse::Ptrmap);
+        }
+    }
+
+    let mut roots = vec![NonZeroU64::MIN];
+    roots.extend(
+        file.get_schema()?
+            .iter()
+            .filter_map(|s| NonZeroU64::new(s.rootpage)),
+    );
+    let per_page = file.usable_size() as u64 - 4;
+    let mut seen = HashSet::new();
+    for root in roots {
+        if !seen.insert(root) {
+            continue;
+        }
+        let mut chains = vec![];
+        // a tree that can't be followed to the end still has what was found
+        let _ = walk(file, root, |page, _| {
+            set(page.page_id, page.header.kind.into());
+            for cell in page.cells() {
+                if let Some(payload) = cell.get_payload() {
+                    if let Some(first) = payload.overflow {
+                        chains.push((first, payload.overflow_size().div_ceil(per_page)));
+                    }
+                }
+            }
+            Ok(())
+        });
+        for (first, mut pages) in chains {
+            let mut next = first as u64;
+            while next != 0 && pages > 0 && set(next, PageUse::Overflow) {
+                let Ok(data) = file.read_raw(NonZeroU64::new(next).unwrap()) else {
+                    break;
+                };
+                next = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as u64;
+                pages -= 1;
+            }
+        }
+    }
+
+    // trunk pages hold the next trunk, a count, then that many leaf pages
+    let mut trunk = file.header()?.first_freelist_trunk() as u64;
+    while trunk != 0 && set(trunk, PageUse::Freelist) {
+        let Ok(data) = file.read_raw(NonZeroU64::new(trunk).unwrap()) else {
+            break;
+        };
+        let u32_at = |i: usize| {
+            data.get(i..i + 4)
+                .map_or(0, |b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as u64)
+        };
+        for i in 0..u32_at(4) as usize {
+            set(u32_at(8 + i * 4), PageUse::Freelist);
+        }
+        trunk = u32_at(0);
+    }
+    Ok(map)
+}
+
+/// How many pages there are of each kind, leaving out kinds with none.
+pub fn histogram(map: &[PageUse]) -> Vec<(PageUse, usize)> {
+    PageUse::ALL
+        .iter()
+        .map(|&what| (what, map.iter().filter(|&&p| p == what).count()))
+        .filter(|&(_, n)| n > 0)
+        .collect()
+}
+
+#[test]
+fn map_pages() -> Result<()> {
+    let rows = (1..=300)
+        .map(|i| vec![i.into(), format!("{:040}", i).into()])
+        .collect();
+    let file = super::fixtures::Fixture::new()
+        .page_size(512)
+        .table("CREATE TABLE t (id INTEGER PRIMARY KEY, x)", rows)
+        .index("CREATE INDEX t_x ON t (x)")
+        .open()?;
+    let map = page_map(&file)?;
+    assert_eq!(map.len() as u64, file.page_count()?);
+    // page 1 holds the schema
+    assert_eq!(map[0], PageUse::TableLeaf);
+    let counts = histogram(&map);
+    let kinds: Vec<_> = counts.iter().map(|&(what, _)| what).collect();
+    assert_eq!(
+        kinds,
+        [
+            PageUse::TableInterior,
+            PageUse::TableLeaf,
+            PageUse::IndexInterior,
+            PageUse::IndexLeaf
+        ]
+    );
+    assert_eq!(counts.iter().map(|&(_, n)| n).sum::<usize>(), map.len());
+    Ok(())
+}
@@ -0,0 +1,153 @@
+//! Prepared statements: a query parsed once, with values bound to its `?`
+//! parameters and `:name` variables instead of spliced into the SQL. A single
+//! table query is planned once too, so running it again with new values only
+//! has to read the rows.
+//!
+//! ```no_run
+//! # use sqlite_starter_rust::OpenOptions;
+//! let db = OpenOptions::new().open("sample.db")?;
+//! let rows = db
+//!     .prepare("SELECT name FROM apples WHERE id = ? OR color = :color")?
+//!     .bind(1, 2)?
+//!     .bind_name(":color", "Red")?
+//!     .rows()?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+
+use super::query;
+use super::record::Value;
+use super::{Select, SqliteFile};
+
+/// A parsed `SELECT` and the values bound to it so far. Like SQLite, anything
+/// that hasn't been bound is `NULL`.
+#[derive(Clone)]
+pub struct Prepared<'f> {
+    file: &'f SqliteFile,
+    select: Select,
+    /// `None` for a join
+    compiled: Option<query::CompiledQuery>,
+    params: Vec<Value>,
+    vars: HashMap<String, Value>,
+}
+
+impl<'f> Prepared<'f> {
+    pub fn new(file: &'f SqliteFile, select: Select) -> Result<Self> {
+        // fail now for a missing table, not when it's run
+        let compiled = query::CompiledQuery::new(file, &select)?;
+        if compiled.is_none() {
+            query::column_names(file, &select)?;
+        }
+        let params = vec![Value::Null; select.param_count()];
+        let vars = select
+            .variables()
+            .into_iter()
+            .map(|name| (name, Value::Null))
+            .collect();
+        Ok(Prepared {
+            file,
+            select,
+            compiled,
+            params,
+            vars,
+        })
+    }
+
+    /// The largest `?N` in the query: `?` parameters are numbered from 1.
+    pub fn param_count(&self) -> usize {
+        self.params.len()
+    }
+
+    /// Bind a value to parameter `?index`, counting from 1.
+    pub fn bind(&mut self, index: usize, value: impl Into<Value>) -> Result<&mut Self> {
+        match index.checked_sub(1).and_then(|i| self.params.get_mut(i)) {
+            Some(param) => *param = value.into(),
+            None => bail!(
+                "parameter index {} out of range: the query has {}",
+                index,
+                self.params.len()
+            ),
+        }
+        Ok(self)
+    }
+
+    /// Bind a value to a variable, like `:name`. The `:`, `@` or `$` can be
+    /// left out.
+    pub fn bind_name(&mut self, name: &str, value: impl Into<Value>) -> Result<&mut Self> {
+        let name = name.trim_start_matches([':', '@', '$']);
+        match self.vars.get_mut(name) {
+            Some(var) => *var = value.into(),
+            None => bail!("no variable named {} in the query", name),
+        }
+        Ok(self)
+    }
+
+    /// Set everything back to `NULL`.
+    pub fn clear_bindings(&mut self) -> &mut Self {
+        self.params.fill(Value::Null);
+        self.vars.values_mut().for_each(|v| *v = Value::Null);
+        self
+    }
+
+    pub fn column_names(&self) -> Result<Vec<String>> {
+        query::column_names(self.file, &self.select)
+    }
+
+    /// Run the query with the values bound so far.
+    pub fn rows(&self) -> Result<Vec<Vec<Value>>> {
+        let select = self.select.bind_vars(&self.vars)?.bind(&self.params)?;
+        match &self.compiled {
+            Some(compiled) => compiled.execute(self.file, &select),
+            None => query::execute(self.file, &select),
+        }
+    }
+}
+
+#[test]
+fn bind_and_run() -> Result<()> {
+    let file = super::OpenOptions::new().open("sample.db")?;
+    let mut stmt = file.prepare("SELECT id FROM oranges WHERE id = ? OR name = :name")?;
+    assert_eq!(stmt.param_count(), 1);
+    assert_eq!(stmt.bind(1, 2)?.rows()?, [[Value::Integer(2)]]);
+    stmt.bind_name(":name", "Mandarin")?;
+    assert_eq!(stmt.rows()?.len(), 2);
+    // unbound is NULL, which matches nothing
+    assert!(stmt.clear_bindings().rows()?.is_empty());
+    assert!(stmt.bind(2, 1).is_err());
+    assert!(stmt.bind_name("nope", 1).is_err());
+    assert!(file.prepare("SELECT id FROM nope").is_err());
+    Ok(())
+}
+
+#[test]
+fn run_again_without_replanning() -> Result<()> {
+    let file = super::OpenOptions::new().open("sample.db")?;
+    let mut stmt = file.prepare("SELECT name, ? FROM oranges WHERE id = ?2")?;
+    for (id, name) in [(1, "Mandarin"), (3, "Tangerine")] {
+        let rows = stmt.bind(1, "x")?.bind(2, id)?.rows()?;
+        assert_eq!(rows, [[Value::from(name), Value::from("x")]]);
+    }
+    // the schema was only read when it was prepared
+    let pages = |run: &dyn Fn() -> Result<Vec<Vec<Value>>>| -> Result<u64> {
+        let touched = || file.stats().pages_read + file.stats().cache_hits;
+        let before = touched();
+        run()?;
+        Ok(touched() - before)
+    };
+    let select = "SELECT name, 'x' FROM oranges WHERE id = 2".parse()?;
+    stmt.bind(2, 2)?;
+    let fresh = || {
+        // as if the file had changed since the last query
+        file.tables().clear();
+        query::execute(&file, &select)
+    };
+    assert!(pages(&|| stmt.rows())? < pages(&fresh)?);
+    // joins still work, they're just planned each time
+    let mut join =
+        file.prepare("SELECT a.id FROM apples a JOIN oranges o ON a.id = o.id WHERE o.id = ?")?;
+    assert_eq!(join.bind(1, 2)?.rows()?, [[Value::Integer(2)]]);
+    Ok(())
+}
@@ -0,0 +1,130 @@
+//! Pointer map pages. Auto-vacuum databases keep a note of what every page is
+//! used for and which page points to it, so vacuuming can move a page to fill
+//! a gap and fix up its parent.
+//! See <https://www.sqlite.org/fileformat.html#pointer_map_or_ptrmap_pages>.
+
+use std::fmt::Display;
+use std::num::NonZeroU64;
+
+use anyhow::Result;
+
+use super::{SqliteError, SqliteFile};
+
+/// What a page is used for, according to the pointer map.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PtrmapKind {
+    /// Root page of a B-tree. Has no parent.
+    RootPage = 1,
+    /// On the freelist. Has no parent.
+    FreePage = 2,
+    /// First overflow page of a cell. The parent is the B-tree page the cell
+    /// is on.
+    Overflow1 = 3,
+    /// Later overflow page. The parent is the overflow page before it.
+    Overflow2 = 4,
+    /// Non-root B-tree page. The parent is its parent in the B-tree.
+    Btree = 5,
+}
+
+/// One entry of a pointer map page.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PtrmapEntry {
+    pub kind: PtrmapKind,
+    pub parent: u32,
+}
+
+impl PtrmapEntry {
+    pub fn new(kind: PtrmapKind, parent: u32) -> Self {
+        Self { kind, parent }
+    }
+
+    /// Parse the 5 bytes of an entry: the kind, then the parent page.
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        let kind = match bytes[0] {
+            1 => PtrmapKind::RootPage,
+            2 => PtrmapKind::FreePage,
+            3 => PtrmapKind::Overflow1,
+            4 => PtrmapKind::Overflow2,
+            5 => PtrmapKind::Btree,
+            _ => return None,
+        };
+        let parent = u32::from_be_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]);
+        Some(Self { kind, parent })
+    }
+
+    /// What the page is, for error messages.
+    pub fn describe(&self) -> String {
+        match self.kind {
+            PtrmapKind::RootPage => "a b-tree root page".to_owned(),
+            PtrmapKind::FreePage => "on the freelist".to_owned(),
+            PtrmapKind::Overflow1 => format!("an overflow page for a cell on page {}", self.parent),
+            PtrmapKind::Overflow2 => format!("an overflow page after page {}", self.parent),
+            PtrmapKind::Btree => format!("a b-tree page under page {}", self.parent),
+        }
+    }
+}
+
+/// `(kind,parent)`, the way SQLite's integrity check shows them.
+impl Display for PtrmapEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "({},{})", self.kind as u8, self.parent)
+    }
+}
+
+impl SqliteFile {
+    /// Whether the file has pointer map pages. Only auto-vacuum and
+    /// incremental vacuum databases do.
+    pub fn has_ptrmap(&self) -> Result<bool> {
+        Ok(self.header()?.largest_root_page() != 0)
+    }
+
+    /// The pointer map page with the entry for `page`. The first one is page
+    /// 2, and each is followed by the pages it has entries for.
+    fn ptrmap_page_for(&self, page: u64) -> u64 {
+        let per_map = self.usable_size() as u64 / 5 + 1;
+        2 + (page.max(2) - 2) / per_map * per_map
+    }
+
+    /// Whether `page` is a pointer map page rather than part of a B-tree.
+    pub fn is_ptrmap_page(&self, page: u64) -> Result<bool> {
+        Ok(page >= 2 && self.has_ptrmap()? && self.ptrmap_page_for(page) == page)
+    }
+
+    /// What the pointer map says `page` is used for. Page 1 and the pointer
+    /// map pages themselves don't have entries.
+    pub fn ptrmap_entry(&self, page: u64) -> Result<PtrmapEntry> {
+        let map = self.ptrmap_page_for(page);
+        if page < 3 || map == page {
+            return Err(SqliteError::Ptrmap {
+                page,
+                reason: "has no pointer map entry".to_owned(),
+            }
+            .into());
+        }
+        let data = self.read_raw(NonZeroU64::new(map).unwrap())?;
+        let offset = 5 * (page - map - 1) as usize;
+        PtrmapEntry::parse(&data[offset..offset + 5]).ok_or_else(|| {
+            SqliteError::Ptrmap {
+                page,
+                reason: format!(
+                    "bad entry type {} on pointer map page {}",
+                    data[offset], map
+                ),
+            }
+            .into()
+        })
+    }
+}
+
+#[test]
+fn ptrmap_pages() -> Result<()> {
+    let file = super::OpenOptions::new().open("sample.db")?;
+    // not an auto-vacuum database
+    assert!(!file.is_ptrmap_page(2)?);
+    // 4096 / 5 entries per map page
+    assert_eq!(file.ptrmap_page_for(3), 2);
+    assert_eq!(file.ptrmap_page_for(821), 2);
+    assert_eq!(file.ptrmap_page_for(822), 822);
+    assert_eq!(file.ptrmap_page_for(823), 822);
+    Ok(())
+}
@@ -0,0 +1,3305 @@
+//! Running `SELECT` statements.
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+use std::num::NonZeroU64;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Result};
+
+use super::auth::{Action, Authorization};
+use super::autoindex::AutoIndex;
+use super::btree::{
+    count_rows, index_edge, index_lookup, index_range, parallel_scan, Bound, TableScan,
+};
+use super::cast::Affinity;
+use super::collation::Collation;
+use super::fts::CreateFts5;
+use super::functions::Functions;
+use super::limits::Limits;
+use super::record::{Row, RowRef, Value, ValueRef};
+use super::rowset::RowidSet;
+use super::sql::{BinaryOp, Expr};
+use super::stats;
+use super::temp::TempTable;
+use super::{
+    ColumnDef, CreateIndex, CreateTable, CreateView, ResultColumn, Schema, SchemaType, Select,
+    SelectColumns, SqliteFile,
+};
+
+/// How the rows of a table get found.
+#[derive(Debug, PartialEq)]
+pub enum Plan {
+    /// Read the whole table.
+    FullScan,
+    /// Look rows up by rowid.
+    RowidLookup(RowidSet),
+    /// Read the rows with rowids between the bounds, including both ends.
+    RowidRange { min: Option<i64>, max: Option<i64> },
+    /// Collect the rowids from an index, then fetch those rows.
+    IndexLookup {
+        /// Name of the index.
+        index: String,
+        /// The indexed column that's being compared.
+        column: String,
+        root: NonZeroU64,
+        /// One lookup for each of these, more than one for `IN`.
+        keys: Vec<Value>,
+        /// What the index compares the column with.
+        collation: Collation,
+        /// Whether the index has the column's biggest values first.
+        descending: bool,
+    },
+    /// Collect the rowids from the entries of an index whose leading columns
+    /// equal a prefix and whose next column is between the bounds, if there
+    /// are any, then fetch those rows.
+    IndexRange {
+        /// Name of the index.
+        index: String,
+        /// The indexed columns that are compared: the ones that have to be
+        /// equal, then the one with the bounds, if there are any.
+        columns: Vec<String>,
+        root: NonZeroU64,
+        /// Values for the equal columns. One scan for each, more than one
+        /// for `IN`.
+        prefixes: Vec<Vec<Value>>,
+        min: Option<Bound>,
+        max: Option<Bound>,
+        /// What the index compares each of `columns` with.
+        collations: Vec<Collation>,
+        /// Which of `columns` the index has biggest values first.
+        descending: Vec<bool>,
+    },
+    /// One lookup per term of an `OR`, with the rowids merged so each row is
+    /// only fetched once.
+    MultiIndexOr(Vec<Plan>),
+    /// Fetch the row of the first entry of an index whose column isn't
+    /// `NULL`, or the last one, for `MIN` or `MAX` of the column.
+    IndexEdge {
+        /// Name of the index.
+        index: String,
+        root: NonZeroU64,
+        last: bool,
+        /// Whether the index has the column's biggest values first.
+        descending: bool,
+    },
+}
+
+impl Plan {
+    /// Lower is better.
+    fn cost(&self) -> u8 {
+        match self {
+            Plan::RowidLookup(_) | Plan::IndexEdge { .. } => 0,
+            // the more columns it narrows down, the fewer entries it reads
+            Plan::IndexRange { columns, .. } if columns.len() > 1 => 1,
+            Plan::IndexLookup { .. } => 2,
+            Plan::RowidRange { .. } => 3,
+            Plan::IndexRange { .. } => 4,
+            Plan::MultiIndexOr(_) => 5,
+            Plan::FullScan => 6,
+        }
+    }
+
+    /// The rowids the plan picks out, or `None` if it reads a stretch of the
+    /// table rather than particular rows.
+    pub fn rowids(&self, file: &SqliteFile) -> Result<Option<RowidSet>> {
+        Ok(match self {
+            Plan::FullScan | Plan::RowidRange { .. } => None,
+            Plan::RowidLookup(rowids) => Some(rowids.clone()),
+            Plan::IndexLookup {
+                root,
+                keys,
+                collation,
+                descending,
+                ..
+            } => {
+                let mut all = RowidSet::new();
+                let collations = std::slice::from_ref(collation);
+                let descending = std::slice::from_ref(descending);
+                for key in keys {
+                    let key = std::slice::from_ref(key);
+                    all.extend(index_lookup(file, *root, key, collations, descending)?.iter());
+                }
+                Some(all)
+            }
+            Plan::IndexRange {
+                root,
+                prefixes,
+                min,
+                max,
+                collations,
+                descending,
+                ..
+            } => {
+                let mut all = RowidSet::new();
+                for prefix in prefixes {
+                    let (min, max) = (min.as_ref(), max.as_ref());
+                    let rowids =
+                        index_range(file, *root, prefix, min, max, collations, descending)?;
+                    all.extend(rowids.iter());
+                }
+                Some(all)
+            }
+            Plan::IndexEdge {
+                root,
+                last,
+                descending,
+                ..
+            } => Some(
+                index_edge(file, *root, *last, *descending)?
+                    .into_iter()
+                    .collect(),
+            ),
+            Plan::MultiIndexOr(plans) => {
+                let mut all = RowidSet::new();
+                for plan in plans {
+                    match plan.rowids(file)? {
+                        Some(rowids) => all.extend(rowids.iter()),
+                        None => return Ok(None),
+                    }
+                }
+                Some(all)
+            }
+        })
+    }
+
+    /// A scan of the rows the plan picks out, or `None` for a full scan,
+    /// which is quicker done with [`parallel_scan`].
+    pub fn scan<'f>(
+        &self,
+        file: &'f SqliteFile,
+        root: NonZeroU64,
+    ) -> Result<Option<TableScan<'f>>> {
+        if let Plan::RowidRange { min, max } = self {
+            let scan = TableScan::new(file, root);
+            return Ok(Some(
+                scan.range(min.unwrap_or(i64::MIN), max.unwrap_or(i64::MAX)),
+            ));
+        }
+        Ok(self
+            .rowids(file)?
+            .map(|rowids| TableScan::with_rowids(file, root, rowids)))
+    }
+
+    fn access(&self) -> Access {
+        match self {
+            Plan::FullScan => Access::Scan,
+            Plan::RowidLookup(_) => Access::Rowid,
+            Plan::RowidRange { .. } => Access::RowidRange,
+            Plan::IndexLookup { index, .. }
+            | Plan::IndexRange { index, .. }
+            | Plan::IndexEdge { index, .. } => Access::Index(index.clone()),
+            Plan::MultiIndexOr(plans) => Access::Or(plans.iter().map(Plan::access).collect()),
+        }
+    }
+
+    fn node(&self, table: &str) -> PlanNode {
+        match self {
+            Plan::FullScan => PlanNode::leaf(format!("SCAN {}", table)),
+            Plan::RowidLookup(_) => PlanNode::leaf(format!(
+                "SEARCH {} USING INTEGER PRIMARY KEY (rowid=?)",
+                table
+            )),
+            Plan::IndexLookup { index, column, .. } => PlanNode::leaf(format!(
+                "SEARCH {} USING INDEX {} ({}=?)",
+                table, index, column
+            )),
+            Plan::IndexRange {
+                index,
+                columns,
+                prefixes,
+                min,
+                max,
+                ..
+            } => {
+                let n = prefixes.first().map_or(0, Vec::len);
+                let mut terms: Vec<String> =
+                    columns[..n].iter().map(|c| format!("{}=?", c)).collect();
+                if min.is_some() {
+                    terms.push(format!("{}>?", columns[n]));
+                }
+                if max.is_some() {
+                    terms.push(format!("{}<?", columns[n]));
+                }
+                PlanNode::leaf(format!(
+                    "SEARCH {} USING INDEX {} ({})",
+                    table,
+                    index,
+                    terms.join(" AND ")
+                ))
+            }
+            Plan::RowidRange { min, max } => {
+                let bounds = match (min, max) {
+                    (Some(_), Some(_)) => "rowid>? AND rowid<?",
+                    (Some(_), None) => "rowid>?",
+                    _ => "rowid<?",
+                };
+                PlanNode::leaf(format!(
+                    "SEARCH {} USING INTEGER PRIMARY KEY ({})",
+                    table, bounds
+                ))
+            }
+            Plan::IndexEdge { index, .. } => {
+                PlanNode::leaf(format!("SEARCH {} USING INDEX {}", table, index))
+            }
+            Plan::MultiIndexOr(plans) => PlanNode {
+                label: "MULTI-INDEX OR".to_owned(),
+                children: plans
+                    .iter()
+                    .enumerate()
+                    .map(|(i, plan)| PlanNode {
+                        label: format!("INDEX {}", i + 1),
+                        children: vec![plan.node(table)],
+                    })
+                    .collect(),
+            },
+        }
+    }
+}
+
+/// A line of `EXPLAIN QUERY PLAN` output.
+struct PlanNode {
+    label: String,
+    children: Vec<PlanNode>,
+}
+
+impl PlanNode {
+    fn leaf(label: String) -> Self {
+        PlanNode {
+            label,
+            children: vec![],
+        }
+    }
+
+    /// Draw the tree the same way the sqlite3 shell does.
+    fn render(nodes: &[PlanNode], prefix: &str, lines: &mut Vec<String>) {
+        for (i, node) in nodes.iter().enumerate() {
+            let last = i + 1 == nodes.len();
+            let branch = if last { "`--" } else { "|--" };
+            lines.push(format!("{}{}{}", prefix, branch, node.label));
+            let indent = if last { "   " } else { "|  " };
+            Self::render(&node.children, &format!("{}{}", prefix, indent), lines);
+        }
+    }
+}
+
+/// Where an output column's value comes from. Worked out when a query is
+/// planned, so `SELECT *` doesn't look each column up by name in every row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Rowid,
+    /// The value at this position in the record.
+    Column(usize),
+    /// Anything else, which is evaluated for each row.
+    Expr,
+}
+
+/// Which of a filter's terms a plan comes from. Binding values to a query's
+/// parameters doesn't change its terms, so this can be kept and used again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Choice {
+    FullScan,
+    Term(usize),
+    RowidRange,
+    /// A range of entries of the table's index at this position.
+    Index(usize),
+}
+
+/// Fail if view `name` reads from itself, directly or through other views,
+/// which would never finish. `stack` is the views on the way to it.
+fn check_view_cycle(schema: &[Schema], name: &str, stack: &mut Vec<String>) -> Result<()> {
+    let Some(entry) = Schema::find(schema, SchemaType::View, name) else {
+        return Ok(());
+    };
+    if stack.iter().any(|v| v.eq_ignore_ascii_case(name)) {
+        bail!("view {} is circularly defined", stack[0]);
+    }
+    let view: CreateView = entry.sql.parse()?;
+    stack.push(view.name);
+    let select = &view.select;
+    for table in std::iter::once(&select.name).chain(select.joins.iter().map(|j| &j.table)) {
+        check_view_cycle(schema, table, stack)?;
+    }
+    stack.pop();
+    Ok(())
+}
+
+/// Default number of parsed tables kept per file.
+pub const DEFAULT_TABLE_CACHE_SIZE: usize = 500;
+
+/// Tables queries have used, parsed from the schema, by lowercase name. Each
+/// one's `CREATE` statements are only parsed the first time it's used, and
+/// past `capacity` the least recently used are thrown away, so a schema with
+/// tens of thousands of tables doesn't all end up in memory.
+pub(crate) struct TableCache {
+    entries: HashMap<String, (Table, u64)>,
+    /// Names by when they were last used, oldest first.
+    order: BTreeMap<u64, String>,
+    tick: u64,
+    capacity: usize,
+}
+
+impl Default for TableCache {
+    fn default() -> Self {
+        Self {
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+            capacity: DEFAULT_TABLE_CACHE_SIZE,
+        }
+    }
+}
+
+impl TableCache {
+    /// Look up a table, marking it as recently used.
+    fn get(&mut self, key: &str) -> Option<Table> {
+        self.tick += 1;
+        let (table, last_used) = self.entries.get_mut(key)?;
+        let key = self.order.remove(last_used)?;
+        self.order.insert(self.tick, key);
+        *last_used = self.tick;
+        Some(table.clone())
+    }
+
+    fn insert(&mut self, key: String, table: Table) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+        if let Some((_, old)) = self.entries.insert(key, (table, self.tick)) {
+            self.order.remove(&old);
+        }
+        self.enforce_limit();
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries = HashMap::new();
+        self.order = BTreeMap::new();
+    }
+
+    pub(crate) fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.enforce_limit();
+    }
+
+    fn enforce_limit(&mut self) {
+        while self.entries.len() > self.capacity {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+    }
+}
+
+/// A table a query reads from, with its indexes.
+#[derive(Clone)]
+pub(crate) struct Table {
+    def: CreateTable,
+    /// `AS alias` in the query
+    alias: Option<String>,
+    /// Not used for temp tables.
+    root: NonZeroU64,
+    indexes: Vec<(CreateIndex, NonZeroU64)>,
+    /// What each column reads as in rows written before it was added.
+    defaults: Vec<Value>,
+    /// Columns the authorizer said to read as `NULL`.
+    ignored: Vec<String>,
+    /// The rows of a temp table, which are in memory instead of the file.
+    temp: Option<Arc<Vec<Row>>>,
+    /// The attached file the table is in, if it's not in the main one.
+    attached: Option<Arc<SqliteFile>>,
+}
+
+impl Table {
+    fn open(file: &SqliteFile, name: &str, alias: Option<&str>) -> Result<Self> {
+        // `schema.table` is in an attached file, or the main one
+        if let Some((schema, table)) = name.split_once('.') {
+            if schema.eq_ignore_ascii_case("main") {
+                return Self::open(file, table, alias);
+            }
+            if let Some(attached) = file.attached(schema) {
+                let table = Self::open(&attached, table, alias)?;
+                return Ok(Table {
+                    attached: table.attached.clone().or(Some(attached)),
+                    ..table
+                });
+            }
+        }
+        if let Some(temp) = file.temp_table(name) {
+            return Ok(Table {
+                def: temp.def.clone(),
+                alias: alias.map(str::to_owned),
+                root: NonZeroU64::MIN,
+                indexes: vec![],
+                defaults: vec![],
+                ignored: vec![],
+                temp: Some(Arc::clone(&temp.rows)),
+                attached: None,
+            });
+        }
+        let key = name.to_ascii_lowercase();
+        if let Some(table) = file.tables().get(&key) {
+            return Ok(Table {
+                alias: alias.map(str::to_owned),
+                ..table
+            });
+        }
+        if let Some(view) = Self::view(file, name)? {
+            // not cached, as the rows are only right for now
+            return Ok(Table {
+                alias: alias.map(str::to_owned),
+                ..view
+            });
+        }
+        if let Some(fts) = Self::fts(file, name)? {
+            // not cached either, like a view
+            return Ok(Table {
+                alias: alias.map(str::to_owned),
+                ..fts
+            });
+        }
+        let mut table = Self::read(file, name)?;
+        file.tables().insert(key, table.clone());
+        table.alias = alias.map(str::to_owned);
+        Ok(table)
+    }
+
+    /// Run a view's `SELECT` and keep the rows in memory, like a temp table.
+    /// `None` if there's no view called `name`.
+    fn view(file: &SqliteFile, name: &str) -> Result<Option<Self>> {
+        let schema = file.get_schema()?;
+        let Some(entry) = Schema::find(&schema, SchemaType::View, name) else {
+            return Ok(None);
+        };
+        check_view_cycle(&schema, name, &mut vec![])?;
+        let view: CreateView = entry.sql.parse()?;
+        let mut columns = column_names(file, &view.select)?;
+        if !view.columns.is_empty() {
+            if view.columns.len() != columns.len() {
+                bail!(
+                    "expected {} columns for '{}' but got {}",
+                    view.columns.len(),
+                    view.name,
+                    columns.len()
+                );
+            }
+            columns = view.columns;
+        }
+        let rows = execute(file, &view.select)?;
+        let temp = TempTable::new(&view.name, &columns, rows)?;
+        Ok(Some(Table {
+            def: temp.def,
+            alias: None,
+            root: NonZeroU64::MIN,
+            indexes: vec![],
+            defaults: vec![],
+            ignored: vec![],
+            temp: Some(temp.rows),
+            attached: None,
+        }))
+    }
+
+    /// Read the documents of an fts5 table into memory, from the table
+    /// they're kept in. `None` if `name` isn't a virtual table.
+    fn fts(file: &SqliteFile, name: &str) -> Result<Option<Self>> {
+        let schema = file.get_schema()?;
+        let Some(entry) = Schema::find(&schema, SchemaType::Table, name) else {
+            return Ok(None);
+        };
+        if entry.rootpage != 0 {
+            return Ok(None);
+        }
+        let fts: CreateFts5 = entry.sql.parse()?;
+        let mut rows: Vec<Row> = execute(file, &fts.documents()?)?
+            .into_iter()
+            .map(|mut values| {
+                let rowid = i64::from(values.remove(0));
+                Row { rowid, values }
+            })
+            .collect();
+        // an external content table's rowids might not be in order
+        rows.sort_by_key(|row| row.rowid);
+        let temp = TempTable::new(&fts.name, &fts.columns, vec![])?;
+        Ok(Some(Table {
+            def: temp.def,
+            alias: None,
+            root: NonZeroU64::MIN,
+            indexes: vec![],
+            defaults: vec![],
+            ignored: vec![],
+            temp: Some(Arc::new(rows)),
+            attached: None,
+        }))
+    }
+
+    /// Parse a table's definition and indexes from the schema.
+    fn read(file: &SqliteFile, name: &str) -> Result<Self> {
+        let schema = file.get_schema()?;
+        let entry = schema
+            .iter()
+            .find(|s| s.stype == SchemaType::Table && s.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| anyhow!("no such table: {}", name))?;
+        let root = NonZeroU64::new(entry.rootpage)
+            .ok_or_else(|| anyhow!("table {} has no root page", name))?;
+        let def: CreateTable = entry.try_into()?;
+        let indexes = schema
+            .iter()
+            .filter(|s| s.stype == SchemaType::Index && s.table_name.eq_ignore_ascii_case(name))
+            // automatic indexes don't have any SQL to parse
+            .filter_map(|s| Some((CreateIndex::try_from(s).ok()?, NonZeroU64::new(s.rootpage)?)))
+            // a partial index can't be used without knowing which rows it has
+            .filter(|(index, _)| index.filter_expr().is_ok())
+            .collect();
+        let defaults = def
+            .column_defs
+            .iter()
+            .map(|c| default_value(file, c))
+            .collect();
+        Ok(Table {
+            def,
+            alias: None,
+            root,
+            indexes,
+            defaults,
+            ignored: vec![],
+            temp: None,
+            attached: None,
+        })
+    }
+
+    /// The file the table's rows are in: `main`, unless it's attached.
+    fn file<'f>(&'f self, main: &'f SqliteFile) -> &'f SqliteFile {
+        self.attached.as_deref().unwrap_or(main)
+    }
+
+    /// All the table's rows, in rowid order.
+    fn full_scan<'f>(&'f self, file: &'f SqliteFile) -> Box<dyn Iterator<Item = Result<Row>> + 'f> {
+        match &self.temp {
+            Some(rows) => Box::new(rows.iter().cloned().map(Ok)),
+            None => Box::new(TableScan::new(self.file(file), self.root)),
+        }
+    }
+
+    /// What the query calls the table.
+    fn label(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.def.name)
+    }
+
+    /// `name` without its `table.` qualifier, or `None` if it's qualified
+    /// with some other table.
+    fn local<'n>(&self, name: &'n str) -> Option<&'n str> {
+        match name.split_once('.') {
+            Some((table, column)) => table.eq_ignore_ascii_case(self.label()).then_some(column),
+            None => Some(name),
+        }
+    }
+
+    fn has_column(&self, name: &str) -> bool {
+        self.local(name)
+            .is_some_and(|c| self.def.is_rowid(c) || self.def.column_index(c).is_some())
+    }
+
+    /// Value of a column in a row.
+    fn column<'a>(&'a self, row: &RowRef<'a>, name: &str) -> Result<ValueRef<'a>> {
+        let no_such_column = || anyhow!("no such column: {}", name);
+        let column = self.local(name).ok_or_else(no_such_column)?;
+        if self.is_ignored(name) {
+            return Ok(ValueRef::Null);
+        }
+        if self.def.is_rowid(column) {
+            return Ok(ValueRef::Integer(row.rowid));
+        }
+        let i = self.def.column_index(column).ok_or_else(no_such_column)?;
+        Ok(self.stored(row, i))
+    }
+
+    /// Value of the column at position `i`, which isn't the rowid.
+    fn stored<'a>(&'a self, row: &RowRef<'a>, i: usize) -> ValueRef<'a> {
+        // columns added with ALTER TABLE are missing from older rows
+        match row.values.get(i) {
+            Some(value) => *value,
+            None => self
+                .defaults
+                .get(i)
+                .map_or(ValueRef::Null, Value::as_value_ref),
+        }
+    }
+
+    /// Where the value of an output column comes from.
+    fn source(&self, expr: &Expr) -> Source {
+        let Expr::Column(name) = expr else {
+            return Source::Expr;
+        };
+        match self.local(name) {
+            Some(column) if self.def.is_rowid(column) => Source::Rowid,
+            Some(column) => self
+                .def
+                .column_index(column)
+                .map_or(Source::Expr, Source::Column),
+            None => Source::Expr,
+        }
+    }
+
+    /// The collation a column was declared with, or `None` if it isn't one
+    /// of this table's.
+    fn collation(&self, name: &str) -> Option<Collation> {
+        let column = self.local(name)?;
+        if self.def.is_rowid(column) {
+            return Some(Collation::Binary);
+        }
+        let i = self.def.column_index(column)?;
+        self.def.collations.get(i).cloned()
+    }
+
+    /// The affinity of one of this table's columns.
+    fn affinity(&self, name: &str) -> Option<Affinity> {
+        let column = self.local(name)?;
+        if self.def.is_rowid(column) {
+            return Some(Affinity::Integer);
+        }
+        let i = self.def.column_index(column)?;
+        self.def.column_defs.get(i).map(|def| def.affinity)
+    }
+
+    /// Whether the authorizer said to read the column as `NULL`.
+    fn is_ignored(&self, name: &str) -> bool {
+        self.local(name)
+            .is_some_and(|c| self.ignored.iter().any(|i| i.eq_ignore_ascii_case(c)))
+    }
+
+    /// `expr` with the collations and affinities of this table's columns
+    /// spelled out, and the ignored columns taken out.
+    fn with_schema(&self, expr: &Expr) -> Result<Expr> {
+        expr.without_columns(&|name| self.is_ignored(name))?
+            .with_collations(&|name| self.collation(name))?
+            .with_affinities(&|name| self.affinity(name))
+    }
+
+    /// What an index compares its first column with.
+    fn index_collation(&self, index: &CreateIndex) -> Collation {
+        self.index_column_collation(index, 0)
+    }
+
+    /// What an index compares its `i`th column with: its own `COLLATE`, or
+    /// the column's.
+    fn index_column_collation(&self, index: &CreateIndex, i: usize) -> Collation {
+        match index.collations.get(i) {
+            Some(Some(collation)) => collation.clone(),
+            _ => index
+                .columns
+                .get(i)
+                .and_then(|c| self.collation(c))
+                .unwrap_or_default(),
+        }
+    }
+
+    /// An index whose first column is `column` and that has every row
+    /// matching all of `terms` in it.
+    fn index_on(&self, column: &str, terms: &[&Expr]) -> Option<&(CreateIndex, NonZeroU64)> {
+        self.indexes.iter().find(|(index, _)| {
+            let first = index.columns.first();
+            first.is_some_and(|c| c.eq_ignore_ascii_case(column)) && self.covers(index, terms)
+        })
+    }
+
+    /// Whether `index` has every row matching all of `terms` in it: it isn't
+    /// partial, or `terms` imply each term of its `WHERE`.
+    fn covers(&self, index: &CreateIndex, terms: &[&Expr]) -> bool {
+        let filter = match index.filter_expr() {
+            Ok(Some(filter)) => filter,
+            Ok(None) => return true,
+            Err(_) => return false,
+        };
+        let Ok(filter) = self.with_schema(&filter) else {
+            return false;
+        };
+        filter
+            .conjuncts()
+            .iter()
+            .all(|want| terms.iter().any(|term| self.implies(term, want)))
+    }
+
+    /// Whether every row `term` is true for makes `want` true as well. Only
+    /// simple cases are worked out: the same term, `IS NOT NULL` on a column
+    /// that's compared, and bounds inside other bounds.
+    fn implies(&self, term: &Expr, want: &Expr) -> bool {
+        if term == want {
+            return true;
+        }
+        let same = |a: &str, b: &str| match (self.local(a), self.local(b)) {
+            (Some(a), Some(b)) => a.eq_ignore_ascii_case(b),
+            _ => false,
+        };
+        let lookup = lookup_term(term);
+        let range = range_term(term);
+        if let Expr::IsNull {
+            expr,
+            negated: true,
+        } = want
+        {
+            let Expr::Column(column) = uncollated(expr) else {
+                return false;
+            };
+            // comparisons are never true for NULL
+            let compared = lookup.map(|(c, _, _)| c).or(range.map(|(c, ..)| c));
+            return compared.is_some_and(|c| same(c, column));
+        }
+        // other collations compare values in ways that aren't known here
+        let Some((column, op, bound, Collation::Binary)) = range_term(want) else {
+            return false;
+        };
+        if *bound == Value::Null {
+            return false;
+        }
+        let lower = |op| matches!(op, BinaryOp::Gt | BinaryOp::Ge);
+        let inclusive = |op| matches!(op, BinaryOp::Ge | BinaryOp::Le);
+        // whether `value` is on the side of `bound` that `want` allows, where
+        // `reached` is whether rows can have `value` itself
+        let inside = |value: &Value, reached: bool| match value.compare(bound) {
+            Ordering::Equal => !reached || inclusive(op),
+            Ordering::Greater => lower(op),
+            Ordering::Less => !lower(op),
+        };
+        if let Some((c, Collation::Binary, values)) = lookup {
+            return same(c, column) && values.iter().all(|v| inside(v, true));
+        }
+        match range {
+            Some((c, term_op, value, Collation::Binary)) if same(c, column) => {
+                lower(term_op) == lower(op)
+                    && *value != Value::Null
+                    && inside(value, inclusive(term_op))
+            }
+            _ => false,
+        }
+    }
+
+    /// For `MIN` or `MAX` of a column and nothing else, with no `WHERE`, the
+    /// first or last entry of an index on the column, since that's the row
+    /// with the smallest or biggest value.
+    fn plan_min_max(&self, select: &Select) -> Option<Plan> {
+        let SelectColumns::MinMax { column, max } = &select.columns else {
+            return None;
+        };
+        if select.filter.is_some() || self.temp.is_some() {
+            return None;
+        }
+        let column = self.local(column)?;
+        let (index, root) = self.index_on(column, &[])?;
+        // the index has to be in the order the column compares in
+        if Some(self.index_collation(index)) != self.collation(column) {
+            return None;
+        }
+        let descending = index.descending.first() == Some(&true);
+        Some(Plan::IndexEdge {
+            index: index.name.clone(),
+            root: *root,
+            last: *max != descending,
+            descending,
+        })
+    }
+
+    /// The plan for `select` from this table alone, whose `WHERE` is
+    /// `filter`.
+    fn plan_select(&self, select: &Select, filter: Option<&Expr>) -> Result<Plan> {
+        match self.plan_min_max(select) {
+            Some(plan) => Ok(plan),
+            None => self.plan(filter),
+        }
+    }
+
+    /// Pick the cheapest way to find the rows matching `filter`.
+    fn plan(&self, filter: Option<&Expr>) -> Result<Plan> {
+        Ok(match filter {
+            Some(filter) => self.plan_terms(&self.with_schema(filter)?.conjuncts()),
+            None => Plan::FullScan,
+        })
+    }
+
+    /// Pick the cheapest way to find the rows matching all of `terms`.
+    fn plan_terms(&self, terms: &[&Expr]) -> Plan {
+        self.plan_choice(terms, self.choose(terms))
+    }
+
+    /// Which of `terms` gives the cheapest plan.
+    fn choose(&self, terms: &[&Expr]) -> Choice {
+        // temp tables don't have indexes, and their rows are right there
+        if self.temp.is_some() {
+            return Choice::FullScan;
+        }
+        terms
+            .iter()
+            .enumerate()
+            .map(|(i, term)| (Choice::Term(i), self.plan_term(term, terms)))
+            .chain(
+                self.rowid_range(terms)
+                    .map(|plan| (Choice::RowidRange, plan)),
+            )
+            .chain(
+                (0..self.indexes.len())
+                    .filter_map(|i| Some((Choice::Index(i), self.index_range(terms, i)?))),
+            )
+            .min_by_key(|(_, plan)| plan.cost())
+            .map_or(Choice::FullScan, |(choice, _)| choice)
+    }
+
+    /// The plan for `terms` that [`Table::choose`] picked.
+    fn plan_choice(&self, terms: &[&Expr], choice: Choice) -> Plan {
+        match choice {
+            Choice::FullScan => Plan::FullScan,
+            Choice::Term(i) => terms
+                .get(i)
+                .map_or(Plan::FullScan, |t| self.plan_term(t, terms)),
+            Choice::RowidRange => self.rowid_range(terms).unwrap_or(Plan::FullScan),
+            Choice::Index(i) => self.index_range(terms, i).unwrap_or(Plan::FullScan),
+        }
+    }
+
+    /// A range of entries of the `i`th index for `terms`: the index's leading
+    /// columns that `terms` say are equal to something, then bounds on the
+    /// column after them. `None` if that doesn't narrow down more than an
+    /// [`Plan::IndexLookup`] on the first column would.
+    fn index_range(&self, terms: &[&Expr], i: usize) -> Option<Plan> {
+        let (index, root) = self
+            .indexes
+            .get(i)
+            .filter(|(index, _)| self.covers(index, terms))?;
+        let mut columns = vec![];
+        let mut collations = vec![];
+        let mut prefixes: Vec<Vec<Value>> = vec![vec![]];
+        for (n, column) in index.columns.iter().enumerate() {
+            let collation = self.index_column_collation(index, n);
+            // the index is in the wrong order for any other collation
+            let values = terms.iter().find_map(|term| {
+                let (c, term_collation, values) = lookup_term(term)?;
+                let same = self.local(c)?.eq_ignore_ascii_case(column);
+                (same && term_collation == collation).then_some(values)
+            });
+            let Some(values) = values else {
+                break;
+            };
+            prefixes = prefixes
+                .iter()
+                .flat_map(|prefix| {
+                    values.iter().map(move |&value| {
+                        let mut prefix = prefix.clone();
+                        prefix.push(value.clone());
+                        prefix
+                    })
+                })
+                .collect();
+            columns.push(column.clone());
+            collations.push(collation);
+        }
+        let (mut min, mut max): (Option<Bound>, Option<Bound>) = (None, None);
+        if let Some(column) = index.columns.get(columns.len()) {
+            let collation = self.index_column_collation(index, columns.len());
+            // whether bound `a` already leaves out as much as `b` would. For
+            // collations besides BINARY it's a guess, which only costs reads,
+            // as rows from the index are still checked against the filter
+            let tighter = |a: &Bound, b: &Value, ord: Ordering| match a.value.compare(b) {
+                Ordering::Equal => !a.inclusive,
+                o => o == ord,
+            };
+            for term in terms {
+                let Some((c, op, value, term_collation)) = range_term(term) else {
+                    continue;
+                };
+                let same = self
+                    .local(c)
+                    .is_some_and(|c| c.eq_ignore_ascii_case(column));
+                if !same || term_collation != collation || *value == Value::Null {
+                    continue;
+                }
+                let bound = Bound {
+                    value: value.clone(),
+                    inclusive: matches!(op, BinaryOp::Ge | BinaryOp::Le),
+                };
+                match op {
+                    BinaryOp::Gt | BinaryOp::Ge => {
+                        if !min
+                            .as_ref()
+                            .is_some_and(|m| tighter(m, value, Ordering::Greater))
+                        {
+                            min = Some(bound);
+                        }
+                    }
+                    _ => {
+                        if !max
+                            .as_ref()
+                            .is_some_and(|m| tighter(m, value, Ordering::Less))
+                        {
+                            max = Some(bound);
+                        }
+                    }
+                }
+            }
+            if min.is_some() || max.is_some() {
+                columns.push(column.clone());
+                collations.push(collation);
+            }
+        }
+        let ranged = min.is_some() || max.is_some();
+        if columns.len() < 2 && !ranged {
+            return None;
+        }
+        let descending = (0..columns.len())
+            .map(|n| index.descending.get(n) == Some(&true))
+            .collect();
+        Some(Plan::IndexRange {
+            index: index.name.clone(),
+            columns,
+            root: *root,
+            prefixes,
+            min,
+            max,
+            collations,
+            descending,
+        })
+    }
+
+    /// Bounds on the rowid from all the `<`, `<=`, `>` and `>=` terms put
+    /// together, if there are any.
+    fn rowid_range(&self, terms: &[&Expr]) -> Option<Plan> {
+        let (mut min, mut max) = (None, None);
+        for term in terms {
+            let Some((column, op, value, _)) = range_term(term) else {
+                continue;
+            };
+            if !self.local(column).is_some_and(|c| self.def.is_rowid(c)) {
+                continue;
+            }
+            // work out the inclusive integer bound, in i128 so that going
+            // past the ends of i64 is fine
+            let bound = match (op, value) {
+                (BinaryOp::Ge, Value::Integer(n)) => *n as i128,
+                (BinaryOp::Gt, Value::Integer(n)) => *n as i128 + 1,
+                (BinaryOp::Le, Value::Integer(n)) => *n as i128,
+                (BinaryOp::Lt, Value::Integer(n)) => *n as i128 - 1,
+                (BinaryOp::Ge, Value::Float(f)) if !f.is_nan() => f.ceil() as i128,
+                (BinaryOp::Gt, Value::Float(f)) if !f.is_nan() => f.floor() as i128 + 1,
+                (BinaryOp::Le, Value::Float(f)) if !f.is_nan() => f.floor() as i128,
+                (BinaryOp::Lt, Value::Float(f)) if !f.is_nan() => f.ceil() as i128 - 1,
+                _ => continue,
+            };
+            match op {
+                BinaryOp::Ge | BinaryOp::Gt => min = min.max(Some(bound)),
+                _ => max = Some(max.map_or(bound, |m: i128| m.min(bound))),
+            }
+        }
+        if min.is_none() && max.is_none() {
+            return None;
+        }
+        let lo = min.unwrap_or(i64::MIN as i128).max(i64::MIN as i128);
+        let hi = max.unwrap_or(i64::MAX as i128).min(i64::MAX as i128);
+        if lo > hi {
+            return Some(Plan::RowidLookup(RowidSet::new()));
+        }
+        Some(Plan::RowidRange {
+            min: min.map(|_| lo as i64),
+            max: max.map(|_| hi as i64),
+        })
+    }
+
+    /// How to find the rows matching `term`, one of the `terms` of an `AND`.
+    fn plan_term(&self, term: &Expr, terms: &[&Expr]) -> Plan {
+        if let Some((column, collation, values)) = lookup_term(term) {
+            let Some(column) = self.local(column) else {
+                return Plan::FullScan;
+            };
+            if self.def.is_rowid(column) {
+                let rowids = values
+                    .into_iter()
+                    // rowids are integers, so nothing else can match
+                    .filter_map(|value| match value {
+                        Value::Integer(n) => Some(*n),
+                        _ => None,
+                    })
+                    .collect();
+                return Plan::RowidLookup(rowids);
+            }
+            // the index is in the wrong order for any other collation
+            match self.index_on(column, terms) {
+                Some((index, root)) if self.index_collation(index) == collation => {
+                    return Plan::IndexLookup {
+                        index: index.name.clone(),
+                        column: index.columns[0].clone(),
+                        root: *root,
+                        keys: values.into_iter().cloned().collect(),
+                        collation,
+                        descending: index.descending.first() == Some(&true),
+                    };
+                }
+                _ => {}
+            }
+            return Plan::FullScan;
+        }
+        let alternatives = term.disjuncts();
+        if alternatives.len() > 1 {
+            // only worth it if every alternative can use an index
+            let plans: Vec<Plan> = alternatives
+                .into_iter()
+                .map(|alt| self.plan_terms(&alt.conjuncts()))
+                .collect();
+            if plans
+                .iter()
+                .all(|p| !matches!(p, Plan::FullScan | Plan::RowidRange { .. }))
+            {
+                return Plan::MultiIndexOr(plans);
+            }
+        }
+        Plan::FullScan
+    }
+}
+
+/// The value of a column's `DEFAULT`, or `NULL` if it doesn't have one. Only
+/// constants can be added to existing rows, so anything else is `NULL` too.
+fn default_value(file: &SqliteFile, def: &ColumnDef) -> Value {
+    let Ok(Some(expr)) = def.default_expr() else {
+        return Value::Null;
+    };
+    let no_columns = |name: &str| -> Result<ValueRef> { bail!("no such column: {}", name) };
+    eval(&expr, &no_columns, file.functions()).map_or(Value::Null, |v| v.into_value())
+}
+
+/// `expr` without any `COLLATE`s on it.
+fn uncollated(expr: &Expr) -> &Expr {
+    match expr {
+        Expr::Collate { expr, .. } => uncollated(expr),
+        e => e,
+    }
+}
+
+/// `column = literal`, either way round, or `column IN (literals...)`: terms
+/// that can be found with a lookup for each value, and the collation they
+/// compare with. `NULL`s are left out since they never match.
+fn lookup_term(expr: &Expr) -> Option<(&str, Collation, Vec<&Value>)> {
+    match expr {
+        Expr::Binary {
+            op: BinaryOp::Eq,
+            left,
+            right,
+        } => {
+            let collation = left.collation().or(right.collation()).unwrap_or_default();
+            match (uncollated(left), uncollated(right)) {
+                (Expr::Column(c), Expr::Literal(v)) | (Expr::Literal(v), Expr::Column(c))
+                    if *v != Value::Null =>
+                {
+                    Some((c, collation, vec![v]))
+                }
+                _ => None,
+            }
+        }
+        Expr::In {
+            expr,
+            list,
+            negated: false,
+        } => {
+            let Expr::Column(c) = uncollated(expr) else {
+                return None;
+            };
+            let values = list
+                .iter()
+                .map(|item| match item {
+                    Expr::Literal(v) => Some(v),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()?;
+            Some((
+                c,
+                expr.collation().unwrap_or_default(),
+                values.into_iter().filter(|v| **v != Value::Null).collect(),
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// `column < literal` and the like, turned round so the column is on the
+/// left, and the collation it compares with. The rowid is an integer, so for
+/// it the collation doesn't matter.
+fn range_term(expr: &Expr) -> Option<(&str, BinaryOp, &Value, Collation)> {
+    let Expr::Binary { op, left, right } = expr else {
+        return None;
+    };
+    let collation = left.collation().or(right.collation()).unwrap_or_default();
+    match (uncollated(left), uncollated(right)) {
+        (Expr::Column(c), Expr::Literal(v)) => Some((c.as_str(), *op, v, collation)),
+        (Expr::Literal(v), Expr::Column(c)) => Some((c.as_str(), op.flip()?, v, collation)),
+        _ => None,
+    }
+    .filter(|(_, op, _, _)| {
+        matches!(
+            op,
+            BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge
+        )
+    })
+}
+
+/// What an expression comes out as. Columns and literals are borrowed from the
+/// row or the query, and only values that had to be worked out, like `a || b`,
+/// are owned.
+#[derive(Debug, Clone, PartialEq)]
+enum Evaluated<'a> {
+    Borrowed(ValueRef<'a>),
+    Owned(Value),
+}
+
+impl<'a> Evaluated<'a> {
+    fn get(&self) -> ValueRef<'_> {
+        match self {
+            Evaluated::Borrowed(v) => *v,
+            Evaluated::Owned(v) => v.as_value_ref(),
+        }
+    }
+
+    fn is_true(&self) -> bool {
+        self.get().is_true()
+    }
+
+    fn truth(&self) -> Option<bool> {
+        self.get().truth()
+    }
+
+    fn into_value(self) -> Value {
+        match self {
+            Evaluated::Borrowed(v) => v.to_value(),
+            Evaluated::Owned(v) => v,
+        }
+    }
+}
+
+impl<'a> From<ValueRef<'a>> for Evaluated<'a> {
+    fn from(v: ValueRef<'a>) -> Self {
+        Evaluated::Borrowed(v)
+    }
+}
+
+/// Whether an expression, like the `WHERE` of a partial index, is true, with
+/// the values of columns from `column`.
+#[cfg(any(test, feature = "fixtures"))]
+pub(crate) fn is_true<'a, F>(expr: &'a Expr, column: &F, functions: &Functions) -> Result<bool>
+where
+    F: Fn(&str) -> Result<ValueRef<'a>>,
+{
+    Ok(eval(expr, column, functions)?.is_true())
+}
+
+/// Evaluate an expression, getting the values of columns from `column` and
+/// calling SQL functions from `functions`.
+/// Comparisons with `NULL` are `NULL`, which stands for unknown: `AND` and
+/// `OR` only give `NULL` when the answer depends on it, and a `WHERE` clause
+/// that comes out `NULL` leaves the row out.
+fn eval<'a, F>(expr: &'a Expr, column: &F, functions: &Functions) -> Result<Evaluated<'a>>
+where
+    F: Fn(&str) -> Result<ValueRef<'a>>,
+{
+    let bool = |b: bool| Evaluated::Borrowed(ValueRef::Integer(b as i64));
+    let null = Evaluated::Borrowed(ValueRef::Null);
+    Ok(match expr {
+        Expr::Column(name) => column(name)?.into(),
+        Expr::Literal(v) => v.as_value_ref().into(),
+        Expr::Binary {
+            op: BinaryOp::And,
+            left,
+            right,
+        } => {
+            let left = eval(left, column, functions)?.truth();
+            if left == Some(false) {
+                return Ok(bool(false));
+            }
+            match (left, eval(right, column, functions)?.truth()) {
+                (_, Some(false)) => bool(false),
+                (Some(true), Some(true)) => bool(true),
+                _ => null,
+            }
+        }
+        Expr::Binary {
+            op: BinaryOp::Or,
+            left,
+            right,
+        } => {
+            let left = eval(left, column, functions)?.truth();
+            if left == Some(true) {
+                return Ok(bool(true));
+            }
+            match (left, eval(right, column, functions)?.truth()) {
+                (_, Some(true)) => bool(true),
+                (Some(false), Some(false)) => bool(false),
+                _ => null,
+            }
+        }
+        Expr::Binary { op, left, right } => {
+            let collation = left.collation().or(right.collation()).unwrap_or_default();
+            let left = eval(left, column, functions)?;
+            let right = eval(right, column, functions)?;
+            if left.get() == ValueRef::Null || right.get() == ValueRef::Null {
+                return Ok(null);
+            }
+            let ord = || collation.compare(&left.get(), &right.get(), functions);
+            match op {
+                BinaryOp::Eq => bool(ord().is_eq()),
+                BinaryOp::Ne => bool(ord().is_ne()),
+                BinaryOp::Lt => bool(ord().is_lt()),
+                BinaryOp::Le => bool(ord().is_le()),
+                BinaryOp::Gt => bool(ord().is_gt()),
+                BinaryOp::Ge => bool(ord().is_ge()),
+                BinaryOp::Add => Evaluated::Owned(left.into_value() + right.into_value()),
+                BinaryOp::Sub => Evaluated::Owned(left.into_value() - right.into_value()),
+                BinaryOp::Mul => Evaluated::Owned(left.into_value() * right.into_value()),
+                BinaryOp::Div => Evaluated::Owned(left.into_value() / right.into_value()),
+                BinaryOp::Rem => Evaluated::Owned(left.into_value() % right.into_value()),
+                BinaryOp::Concat => Evaluated::Owned(Value::String(
+                    left.get().to_text().into_owned() + &right.get().to_text(),
+                )),
+                BinaryOp::And | BinaryOp::Or => unreachable!(),
+            }
+        }
+        Expr::In {
+            expr,
+            list,
+            negated,
+        } => {
+            let collation = expr.collation().unwrap_or_default();
+            let value = eval(expr, column, functions)?;
+            let mut found = list.is_empty().then_some(Some(false));
+            for item in list {
+                let item = eval(item, column, functions)?;
+                if value.get() == ValueRef::Null || item.get() == ValueRef::Null {
+                    // unknown, unless something else matches
+                    found.get_or_insert(None);
+                } else if collation
+                    .compare(&value.get(), &item.get(), functions)
+                    .is_eq()
+                {
+                    found = Some(Some(true));
+                    break;
+                }
+            }
+            match found.unwrap_or(Some(false)) {
+                Some(found) => bool(found != *negated),
+                None => null,
+            }
+        }
+        Expr::Not(expr) => match eval(expr, column, functions)?.truth() {
+            Some(truth) => bool(!truth),
+            None => null,
+        },
+        Expr::Neg(expr) => Evaluated::Owned(-eval(expr, column, functions)?.into_value()),
+        Expr::IsNull { expr, negated } => {
+            let is_null = eval(expr, column, functions)?.get() == ValueRef::Null;
+            bool(is_null != *negated)
+        }
+        Expr::Param(n) => bail!("no value bound to parameter ?{}", n),
+        Expr::Variable(name) => bail!("no value bound to variable ${}", name),
+        Expr::Cast { expr, to } => {
+            Evaluated::Owned(eval(expr, column, functions)?.into_value().cast(*to))
+        }
+        Expr::Collate { expr, collation } => {
+            collation.check(functions)?;
+            eval(expr, column, functions)?
+        }
+        Expr::Affinity { expr, affinity } => Evaluated::Owned(
+            eval(expr, column, functions)?
+                .into_value()
+                .apply_affinity(*affinity),
+        ),
+        Expr::Function { name, args } => {
+            let args = args
+                .iter()
+                .map(|arg| eval(arg, column, functions))
+                .collect::<Result<Vec<_>>>()?;
+            let args: Vec<ValueRef> = args.iter().map(Evaluated::get).collect();
+            Evaluated::Owned(functions.call(name, &args)?)
+        }
+    })
+}
+
+/// Run a `SELECT` and return the rows it produces.
+pub fn execute(file: &SqliteFile, select: &Select) -> Result<Vec<Vec<Value>>> {
+    trace_span!("select", table = %select.name);
+    if !select.order_by.is_empty() {
+        return sorted(file, select);
+    }
+    let rows = if select.joins.is_empty() {
+        let query = Rows::new(file, select)?;
+        if let Some(count) = query.count_all(select)? {
+            return Ok(counted(file, count));
+        }
+        let plan = query.table.plan_select(select, query.filter.as_ref())?;
+        scan_rows(file, &query, plan)?
+    } else {
+        Join::open(file, select)?.execute()?
+    };
+    finish(file, select, rows)
+}
+
+/// Run a query with an `ORDER BY`. Its terms are worked out as extra output
+/// columns, then the rows are sorted on those with the terms' collations and
+/// they're dropped again.
+fn sorted(file: &SqliteFile, select: &Select) -> Result<Vec<Vec<Value>>> {
+    let mut unsorted = Select {
+        order_by: vec![],
+        ..select.clone()
+    };
+    if !matches!(
+        select.columns,
+        SelectColumns::Columns(_) | SelectColumns::All
+    ) {
+        // there's only one row
+        return execute(file, &unsorted);
+    }
+    let mut tables = vec![Table::open(file, &select.name, select.alias.as_deref())?];
+    for join in &select.joins {
+        tables.push(Table::open(file, &join.table, join.alias.as_deref())?);
+    }
+    let mut columns = output_columns(select, &tables, file.limits())?;
+    let width = columns.len();
+    let mut collations = vec![];
+    for term in &select.order_by {
+        let expr = order_term(&term.expr, select, &columns)?;
+        // like in comparisons, a COLLATE wins over the column's own
+        let collation = match (expr.collation(), uncollated(&expr)) {
+            (Some(collation), _) => collation,
+            (None, Expr::Column(name)) => tables[resolve(&tables, name)?]
+                .collation(name)
+                .unwrap_or_default(),
+            (None, _) => Collation::Binary,
+        };
+        columns.push(expr);
+        collations.push(collation);
+    }
+    unsorted.columns = SelectColumns::Columns(columns.into_iter().map(Into::into).collect());
+    let mut rows = execute(file, &unsorted)?;
+    rows.sort_by(|a, b| {
+        let keys = a[width..].iter().zip(&b[width..]);
+        for ((a, b), (term, collation)) in keys.zip(select.order_by.iter().zip(&collations)) {
+            let ord = collation.compare(&a.as_value_ref(), &b.as_value_ref(), file.functions());
+            let ord = if term.descending { ord.reverse() } else { ord };
+            if ord != Ordering::Equal {
+                return ord;
+            }
+        }
+        Ordering::Equal
+    });
+    for row in &mut rows {
+        row.truncate(width);
+    }
+    Ok(rows)
+}
+
+/// What an `ORDER BY` term sorts on: the output column at a position or with
+/// an alias, or else the expression itself.
+fn order_term(expr: &Expr, select: &Select, columns: &[Expr]) -> Result<Expr> {
+    Ok(match expr {
+        Expr::Literal(Value::Integer(n)) => match usize::try_from(*n) {
+            Ok(n @ 1..) if n <= columns.len() => columns[n - 1].clone(),
+            _ => bail!(
+                "ORDER BY term out of range - should be between 1 and {}",
+                columns.len()
+            ),
+        },
+        Expr::Column(name) => {
+            let aliased = match &select.columns {
+                SelectColumns::Columns(cols) => cols.iter().find_map(|col| match col {
+                    ResultColumn::Expr {
+                        expr,
+                        alias: Some(alias),
+                    } if alias.eq_ignore_ascii_case(name) => Some(expr),
+                    _ => None,
+                }),
+                _ => None,
+            };
+            aliased.unwrap_or(expr).clone()
+        }
+        Expr::Collate { expr, collation } => Expr::Collate {
+            expr: Box::new(order_term(expr, select, columns)?),
+            collation: collation.clone(),
+        },
+        expr => expr.clone(),
+    })
+}
+
+/// Count the rows or pick the smallest or biggest value if that's what the
+/// query asked for, and add them to the stats.
+fn finish(file: &SqliteFile, select: &Select, rows: Vec<Vec<Value>>) -> Result<Vec<Vec<Value>>> {
+    let rows = match &select.columns {
+        SelectColumns::Count => return Ok(counted(file, rows.len() as u64)),
+        SelectColumns::MinMax { column, max } => {
+            let collation = column_collation(file, select, column)?;
+            let better = |a: &Value, b: &Value| {
+                let ord = collation.compare(&a.as_value_ref(), &b.as_value_ref(), file.functions());
+                ord == if *max {
+                    Ordering::Greater
+                } else {
+                    Ordering::Less
+                }
+            };
+            // NULLs are left out, and it's NULL if that's all there is
+            let best = rows
+                .into_iter()
+                .filter_map(|row| row.into_iter().next())
+                .filter(|value| *value != Value::Null)
+                .reduce(|best, value| if better(&value, &best) { value } else { best });
+            vec![vec![best.unwrap_or(Value::Null)]]
+        }
+        _ => rows,
+    };
+    stats::add(&file.counters().rows_emitted, rows.len() as u64);
+    Ok(rows)
+}
+
+/// The row `COUNT(*)` gives, added to the stats.
+fn counted(file: &SqliteFile, count: u64) -> Vec<Vec<Value>> {
+    stats::add(&file.counters().rows_emitted, 1);
+    vec![vec![Value::Integer(count as i64)]]
+}
+
+/// The collation of one of the columns of a query's tables.
+fn column_collation(file: &SqliteFile, select: &Select, column: &str) -> Result<Collation> {
+    let mut tables = vec![Table::open(file, &select.name, select.alias.as_deref())?];
+    for join in &select.joins {
+        tables.push(Table::open(file, &join.table, join.alias.as_deref())?);
+    }
+    let table = &tables[resolve(&tables, column)?];
+    Ok(table.collation(column).unwrap_or_default())
+}
+
+/// A single table query with its table opened and its plan picked, to run
+/// over and over with different values bound to its parameters without
+/// reading the schema or planning again.
+#[derive(Clone)]
+pub struct CompiledQuery {
+    table: Table,
+    needed: Vec<usize>,
+    sources: Vec<Source>,
+    choice: Choice,
+}
+
+impl CompiledQuery {
+    /// Compile `select` with its parameters still in it. Joins give `None`,
+    /// since they're planned with the values in them each time.
+    pub fn new(file: &SqliteFile, select: &Select) -> Result<Option<Self>> {
+        if !select.joins.is_empty() {
+            return Ok(None);
+        }
+        // any value that isn't NULL lets the same terms use an index or the
+        // rowid as the real ones will
+        let vars = select
+            .variables()
+            .into_iter()
+            .map(|name| (name, Value::Integer(0)))
+            .collect();
+        let placeholder = select
+            .bind_vars(&vars)?
+            .bind(&vec![Value::Integer(0); select.param_count()])?;
+        let query = Rows::new(file, &placeholder)?;
+        let choice = match &query.filter {
+            Some(filter) => query.table.choose(&filter.conjuncts()),
+            None => Choice::FullScan,
+        };
+        Ok(Some(CompiledQuery {
+            table: query.table.into_owned(),
+            needed: query.needed,
+            sources: query.sources,
+            choice,
+        }))
+    }
+
+    /// Where each output column comes from.
+    pub fn sources(&self) -> &[Source] {
+        &self.sources
+    }
+
+    /// Run the query, where `select` is the one it was compiled from with
+    /// values bound to its parameters.
+    pub fn execute(&self, file: &SqliteFile, select: &Select) -> Result<Vec<Vec<Value>>> {
+        if !select.order_by.is_empty() {
+            return execute(file, select);
+        }
+        let table = &self.table;
+        let columns = output_columns(select, std::slice::from_ref(table), file.limits())?;
+        let query = Rows {
+            file,
+            table: Cow::Borrowed(table),
+            columns: columns
+                .iter()
+                .map(|c| table.with_schema(c))
+                .collect::<Result<_>>()?,
+            filter: select
+                .filter
+                .as_ref()
+                .map(|f| table.with_schema(f))
+                .transpose()?,
+            needed: self.needed.clone(),
+            sources: self.sources.clone(),
+            after: None,
+        };
+        if let Some(count) = query.count_all(select)? {
+            return Ok(counted(file, count));
+        }
+        let plan = match &query.filter {
+            Some(filter) => table.plan_choice(&filter.conjuncts(), self.choice),
+            None => table.plan_min_max(select).unwrap_or(Plan::FullScan),
+        };
+        let rows = scan_rows(file, &query, plan)?;
+        finish(file, select, rows)
+    }
+}
+
+/// Rows from [`execute_limited`], and whether there were more.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LimitedRows {
+    pub rows: Vec<Vec<Value>>,
+    /// There were more than the limit, and the rest were left out.
+    pub truncated: bool,
+}
+
+/// Run a `SELECT` like [`execute`], but keep at most `max_rows` rows. A single
+/// table query stops reading as soon as it has found one too many.
+pub fn execute_limited(file: &SqliteFile, select: &Select, max_rows: usize) -> Result<LimitedRows> {
+    let plain = matches!(
+        select.columns,
+        SelectColumns::Columns(_) | SelectColumns::All
+    ) && select.order_by.is_empty();
+    let mut rows = if select.joins.is_empty() && plain {
+        Rows::new(file, select)?
+            .page(max_rows.saturating_add(1))?
+            .rows
+    } else {
+        execute(file, select)?
+    };
+    let truncated = rows.len() > max_rows;
+    rows.truncate(max_rows);
+    Ok(LimitedRows { rows, truncated })
+}
+
+/// Read the rows a single table query wants, using `plan`.
+fn scan_rows(file: &SqliteFile, query: &Rows, plan: Plan) -> Result<Vec<Vec<Value>>> {
+    trace!(?plan, "plan");
+    if let Some(rows) = &query.table.temp {
+        return rows
+            .iter()
+            .filter_map(|row| {
+                let output = file
+                    .check_interrupt()
+                    .and_then(|_| query.output(row.as_row_ref()));
+                output.transpose()
+            })
+            .collect();
+    }
+    let file = query.table.file(file);
+    match plan.scan(file, query.table.root)? {
+        Some(scan) => {
+            let mut rows = vec![];
+            scan.columns(query.needed.clone()).for_each_ref(|row| {
+                rows.extend(query.output(row)?);
+                Ok(())
+            })?;
+            Ok(rows)
+        }
+        None => parallel_scan(file, query.table.root, Some(&query.needed), |row| {
+            query.output(row)
+        }),
+    }
+}
+
+/// Where a page of results ended, for carrying on from in the next page.
+///
+/// Rows come out in rowid order, so this is the last rowid, and the next page
+/// starts looking right after it instead of skipping rows like `OFFSET`. It
+/// turns into a string and back, to hand out to clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageToken {
+    last_rowid: i64,
+}
+
+impl Display for PageToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.last_rowid)
+    }
+}
+
+impl FromStr for PageToken {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let last_rowid = s.parse().map_err(|_| anyhow!("bad page token: {:?}", s))?;
+        Ok(PageToken { last_rowid })
+    }
+}
+
+/// A page of results.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResultPage {
+    pub rows: Vec<Vec<Value>>,
+    /// Where the next page starts, or `None` if this was the last one.
+    pub next: Option<PageToken>,
+}
+
+/// The results of a single table `SELECT`, to be read a page at a time.
+pub struct Rows<'q> {
+    file: &'q SqliteFile,
+    table: Cow<'q, Table>,
+    /// Output columns
+    columns: Vec<Expr>,
+    /// The `WHERE` clause, with the columns' collations spelled out.
+    filter: Option<Expr>,
+    /// Columns to decode, since the query only uses these.
+    needed: Vec<usize>,
+    /// Where each of `columns` comes from.
+    sources: Vec<Source>,
+    after: Option<PageToken>,
+}
+
+impl<'q> Rows<'q> {
+    pub fn new(file: &'q SqliteFile, select: &'q Select) -> Result<Self> {
+        if !select.joins.is_empty() {
+            bail!("can't page through a join");
+        }
+        let mut table = Table::open(file, &select.name, select.alias.as_deref())?;
+        authorize(file, select, std::slice::from_mut(&mut table))?;
+        let columns = output_columns(select, std::slice::from_ref(&table), file.limits())?
+            .iter()
+            .map(|c| table.with_schema(c))
+            .collect::<Result<Vec<_>>>()?;
+        let filter = select
+            .filter
+            .as_ref()
+            .map(|f| table.with_schema(f))
+            .transpose()?;
+        // check the names up front, so a typo fails even if no rows match
+        let dummy = RowRef {
+            rowid: 0,
+            values: vec![],
+        };
+        for expr in columns.iter().chain(&filter) {
+            for collation in expr.collations() {
+                collation.check(file.functions())?;
+            }
+            eval(
+                expr,
+                &|name: &str| table.column(&dummy, name),
+                file.functions(),
+            )?;
+        }
+
+        let mut needed: Vec<usize> = columns
+            .iter()
+            .chain(&filter)
+            .flat_map(Expr::columns)
+            .filter_map(|name| table.local(name))
+            .filter(|name| !table.def.is_rowid(name))
+            .filter_map(|name| table.def.column_index(name))
+            .collect();
+        needed.sort_unstable();
+        needed.dedup();
+        let sources = columns.iter().map(|c| table.source(c)).collect();
+
+        Ok(Rows {
+            file,
+            table: Cow::Owned(table),
+            columns,
+            filter,
+            needed,
+            sources,
+            after: None,
+        })
+    }
+
+    /// For `COUNT(*)` with no `WHERE`, the number of rows in the table, from
+    /// the headers of its leaf pages rather than the rows themselves.
+    fn count_all(&self, select: &Select) -> Result<Option<u64>> {
+        if select.columns != SelectColumns::Count || self.filter.is_some() {
+            return Ok(None);
+        }
+        if let Some(rows) = &self.table.temp {
+            return Ok(Some(rows.len() as u64));
+        }
+        let file = self.table.file(self.file);
+        count_rows(file, self.table.root).map(Some)
+    }
+
+    /// Carry on from where an earlier page ended.
+    pub fn resume(mut self, token: PageToken) -> Self {
+        self.after = Some(token);
+        self
+    }
+
+    /// Filter a row and pick out the columns, or an empty row when counting.
+    fn output(&self, row: RowRef) -> Result<Option<Vec<Value>>> {
+        let column = |name: &str| self.table.column(&row, name);
+        if let Some(filter) = &self.filter {
+            if !eval(filter, &column, self.file.functions())?.is_true() {
+                return Ok(None);
+            }
+        }
+        let values = self
+            .columns
+            .iter()
+            .zip(&self.sources)
+            .map(|(expr, source)| {
+                Ok(match *source {
+                    Source::Rowid => Value::Integer(row.rowid),
+                    Source::Column(i) => self.table.stored(&row, i).to_value(),
+                    Source::Expr => eval(expr, &column, self.file.functions())?.into_value(),
+                })
+            })
+            .collect::<Result<_>>()?;
+        trace!(rowid = row.rowid, "row");
+        Ok(Some(values))
+    }
+
+    /// The next `size` rows. Reading stops as soon as the page is full.
+    pub fn page(&mut self, size: usize) -> Result<ResultPage> {
+        let plan = self.table.plan(self.filter.as_ref())?;
+        let after = self.after.map(|token| token.last_rowid);
+        let mut scan: Box<dyn Iterator<Item = Result<Row>>> = match &self.table.temp {
+            Some(rows) => Box::new(
+                rows.iter()
+                    .filter(move |row| after.is_none_or(|last| row.rowid > last))
+                    .cloned()
+                    .map(Ok),
+            ),
+            None => {
+                let file = self.table.file(self.file);
+                let scan = plan
+                    .scan(file, self.table.root)?
+                    .unwrap_or_else(|| TableScan::new(file, self.table.root));
+                let scan = scan.columns(self.needed.clone());
+                Box::new(match after {
+                    Some(last) => scan.after(last),
+                    None => scan,
+                })
+            }
+        };
+        let mut rows = vec![];
+        let mut last = None;
+        while rows.len() < size {
+            let Some(row) = scan.next().transpose()? else {
+                break;
+            };
+            rows.extend(self.output(row.as_row_ref())?);
+            last = Some(row.rowid);
+        }
+        // a full page might have more after it
+        let next = match last {
+            Some(last_rowid) if rows.len() == size => Some(PageToken { last_rowid }),
+            _ => None,
+        };
+        self.after = next;
+        stats::add(&self.file.counters().rows_emitted, rows.len() as u64);
+        Ok(ResultPage { rows, next })
+    }
+}
+
+/// How to find the rows of a joined table that go with each row of the tables
+/// before it.
+#[derive(Debug)]
+enum JoinPlan<'s> {
+    /// Go through every row, checking the `ON` clause.
+    Scan,
+    /// `rowid = key`
+    Rowid { key: &'s Expr },
+    /// `column = key`, with an index on the column.
+    Index {
+        index: &'s str,
+        column: &'s str,
+        root: NonZeroU64,
+        key: &'s Expr,
+        collation: Collation,
+        descending: bool,
+    },
+    /// `column = key` without an index, so one is built for the query.
+    Auto { column: &'s str, key: &'s Expr },
+}
+
+impl JoinPlan<'_> {
+    /// Lower is better.
+    fn cost(&self) -> u8 {
+        match self {
+            JoinPlan::Rowid { .. } => 0,
+            JoinPlan::Index { .. } => 1,
+            JoinPlan::Auto { .. } => 2,
+            JoinPlan::Scan => 3,
+        }
+    }
+
+    fn access(&self) -> Access {
+        match self {
+            JoinPlan::Scan => Access::Scan,
+            JoinPlan::Rowid { .. } => Access::Rowid,
+            JoinPlan::Index { index, .. } => Access::Index(index.to_string()),
+            JoinPlan::Auto { column, .. } => Access::AutoIndex(column.to_string()),
+        }
+    }
+
+    fn node(&self, table: &str) -> PlanNode {
+        PlanNode::leaf(match self {
+            JoinPlan::Scan => format!("SCAN {}", table),
+            JoinPlan::Rowid { .. } => {
+                format!("SEARCH {} USING INTEGER PRIMARY KEY (rowid=?)", table)
+            }
+            JoinPlan::Index { index, column, .. } => {
+                format!("SEARCH {} USING INDEX {} ({}=?)", table, index, column)
+            }
+            JoinPlan::Auto { column, .. } => format!(
+                "SEARCH {} USING AUTOMATIC COVERING INDEX ({}=?)",
+                table, column
+            ),
+        })
+    }
+}
+
+/// The rows of a joined table that [`JoinPlan`] needs in memory.
+enum JoinRows {
+    /// Looked up in the file each time.
+    Lookup,
+    All(Vec<Row>),
+    Auto(AutoIndex),
+}
+
+/// A `SELECT` with `JOIN`s, run as nested loops: for each row of the first
+/// table, find the matching rows of the second, and so on.
+struct Join<'s> {
+    file: &'s SqliteFile,
+    select: &'s Select,
+    /// The `FROM` table, then the joined ones in order.
+    tables: Vec<Table>,
+    /// The `WHERE` clause and the `ON` clauses, with the columns' collations
+    /// spelled out.
+    filter: Option<Expr>,
+    ons: Vec<Expr>,
+}
+
+impl<'s> Join<'s> {
+    fn open(file: &'s SqliteFile, select: &'s Select) -> Result<Self> {
+        let mut tables = vec![Table::open(file, &select.name, select.alias.as_deref())?];
+        for join in &select.joins {
+            tables.push(Table::open(file, &join.table, join.alias.as_deref())?);
+        }
+        authorize(file, select, &mut tables)?;
+        let mut query = Join {
+            file,
+            select,
+            tables,
+            filter: None,
+            ons: vec![],
+        };
+        // check the names up front, so a typo fails even if no rows match
+        let on = select.joins.iter().map(|j| &j.on);
+        for expr in select.filter.iter().chain(on) {
+            for name in expr.columns() {
+                query.resolve(name)?;
+            }
+        }
+        for expr in query.columns()? {
+            for name in expr.columns() {
+                query.resolve(name)?;
+            }
+        }
+        query.filter = select
+            .filter
+            .as_ref()
+            .map(|f| query.with_schema(f))
+            .transpose()?;
+        query.ons = select
+            .joins
+            .iter()
+            .map(|j| query.with_schema(&j.on))
+            .collect::<Result<_>>()?;
+        for expr in query.filter.iter().chain(&query.ons) {
+            for collation in expr.collations() {
+                collation.check(file.functions())?;
+            }
+        }
+        Ok(query)
+    }
+
+    /// `expr` with the collations and affinities of the tables' columns spelled
+    /// out.
+    fn with_schema(&self, expr: &Expr) -> Result<Expr> {
+        let table = |name: &str| Some(&self.tables[self.resolve(name).ok()?]);
+        expr.without_columns(&|name| table(name).is_some_and(|t| t.is_ignored(name)))?
+            .with_collations(&|name| table(name)?.collation(name))?
+            .with_affinities(&|name| table(name)?.affinity(name))
+    }
+
+    /// Which table a column belongs to.
+    fn resolve(&self, name: &str) -> Result<usize> {
+        resolve(&self.tables, name)
+    }
+
+    fn columns(&self) -> Result<Vec<Expr>> {
+        output_columns(self.select, &self.tables, self.file.limits())?
+            .iter()
+            .map(|c| self.with_schema(c))
+            .collect()
+    }
+
+    /// How to find the rows of the first table: with the terms of the `WHERE`
+    /// clause that only use its columns.
+    fn first_plan(&self) -> Plan {
+        let terms: Vec<&Expr> = self
+            .filter
+            .iter()
+            .flat_map(|f| f.conjuncts())
+            .filter(|term| {
+                term.columns()
+                    .iter()
+                    .all(|c| self.resolve(c).is_ok_and(|i| i == 0))
+            })
+            .collect();
+        self.tables[0].plan_terms(&terms)
+    }
+
+    /// How to find the rows of table `i`, from an equality in its `ON` clause
+    /// with something the tables before it know.
+    fn plan(&self, i: usize) -> JoinPlan<'_> {
+        let on = &self.ons[i - 1];
+        let mut plans = vec![JoinPlan::Scan];
+        for term in on.conjuncts() {
+            let Expr::Binary {
+                op: BinaryOp::Eq,
+                left,
+                right,
+            } = term
+            else {
+                continue;
+            };
+            let collation = left.collation().or(right.collation()).unwrap_or_default();
+            for (inner, key) in [(left, right), (right, left)] {
+                let Expr::Column(name) = uncollated(inner) else {
+                    continue;
+                };
+                let earlier = key
+                    .columns()
+                    .iter()
+                    .all(|c| self.resolve(c).is_ok_and(|t| t < i));
+                if self.resolve(name).ok() != Some(i) || !earlier {
+                    continue;
+                }
+                let table = &self.tables[i];
+                let column = table.local(name).unwrap_or(name);
+                // partial indexes need the WHERE clause, which isn't split
+                // up by table
+                let index = table
+                    .index_on(column, &[])
+                    .filter(|(index, _)| table.index_collation(index) == collation);
+                plans.push(if table.def.is_rowid(column) && table.temp.is_none() {
+                    JoinPlan::Rowid { key }
+                } else if let Some((index, root)) = index {
+                    JoinPlan::Index {
+                        index: &index.name,
+                        column: &index.columns[0],
+                        root: *root,
+                        key,
+                        collation: collation.clone(),
+                        descending: index.descending.first() == Some(&true),
+                    }
+                } else if collation == Collation::Binary {
+                    JoinPlan::Auto { column, key }
+                } else {
+                    // an automatic index only finds exact matches
+                    JoinPlan::Scan
+                });
+            }
+        }
+        plans.into_iter().min_by_key(JoinPlan::cost).unwrap()
+    }
+
+    fn execute(&self) -> Result<Vec<Vec<Value>>> {
+        let plans: Vec<JoinPlan> = (1..self.tables.len()).map(|i| self.plan(i)).collect();
+        let mut rows = vec![];
+        for (plan, table) in plans.iter().zip(&self.tables[1..]) {
+            trace!(?plan, table = table.label(), "join");
+            rows.push(match plan {
+                JoinPlan::Scan => JoinRows::All(table.full_scan(self.file).collect::<Result<_>>()?),
+                JoinPlan::Auto { column, .. } => {
+                    JoinRows::Auto(AutoIndex::build(table.full_scan(self.file), |row| {
+                        Ok(table.column(&row.as_row_ref(), column)?.to_value())
+                    })?)
+                }
+                JoinPlan::Rowid { .. } | JoinPlan::Index { .. } => JoinRows::Lookup,
+            });
+        }
+
+        let first = &self.tables[0];
+        let first_rows: Vec<Row> =
+            match self.first_plan().scan(first.file(self.file), first.root)? {
+                Some(scan) => scan.collect::<Result<_>>()?,
+                None => first.full_scan(self.file).collect::<Result<_>>()?,
+            };
+        let columns = self.columns()?;
+        let mut out = vec![];
+        for row in &first_rows {
+            self.visit(&[row], &plans, &rows, &columns, &mut out)?;
+        }
+        Ok(out)
+    }
+
+    /// Carry on from a row of each of the first `current.len()` tables.
+    fn visit(
+        &self,
+        current: &[&Row],
+        plans: &[JoinPlan],
+        rows: &[JoinRows],
+        columns: &[Expr],
+        out: &mut Vec<Vec<Value>>,
+    ) -> Result<()> {
+        self.file.check_interrupt()?;
+        let refs: Vec<RowRef> = current.iter().map(|r| r.as_row_ref()).collect();
+        let column = |name: &str| {
+            let t = self.resolve(name)?;
+            let row = refs
+                .get(t)
+                .ok_or_else(|| anyhow!("no such column: {}", name))?;
+            self.tables[t].column(row, name)
+        };
+        let n = current.len();
+        if n > 1 && !eval(&self.ons[n - 2], &column, self.file.functions())?.is_true() {
+            return Ok(());
+        }
+        if n == self.tables.len() {
+            if let Some(filter) = &self.filter {
+                if !eval(filter, &column, self.file.functions())?.is_true() {
+                    return Ok(());
+                }
+            }
+            let values = columns
+                .iter()
+                .map(|expr| Ok(eval(expr, &column, self.file.functions())?.into_value()))
+                .collect::<Result<_>>()?;
+            out.push(values);
+            return Ok(());
+        }
+
+        let table = &self.tables[n];
+        let candidates: Cow<[Row]> = match (&plans[n - 1], &rows[n - 1]) {
+            (_, JoinRows::All(all)) => Cow::Borrowed(all),
+            (JoinPlan::Auto { key: k, .. }, JoinRows::Auto(index)) => {
+                Cow::Borrowed(index.get(&eval(k, &column, self.file.functions())?.into_value()))
+            }
+            (JoinPlan::Rowid { key: k }, _) => {
+                let rowids = match eval(k, &column, self.file.functions())?.into_value() {
+                    Value::Integer(n) => [n].into_iter().collect(),
+                    Value::Float(x) if x.fract() == 0.0 => [x as i64].into_iter().collect(),
+                    _ => RowidSet::new(),
+                };
+                let found = TableScan::with_rowids(table.file(self.file), table.root, rowids);
+                Cow::Owned(found.collect::<Result<_>>()?)
+            }
+            (
+                JoinPlan::Index {
+                    root,
+                    key: k,
+                    collation,
+                    descending,
+                    ..
+                },
+                _,
+            ) => {
+                let k = eval(k, &column, self.file.functions())?.into_value();
+                let rowids = if k == Value::Null {
+                    RowidSet::new()
+                } else {
+                    let file = table.file(self.file);
+                    let collations = std::slice::from_ref(collation);
+                    let descending = std::slice::from_ref(descending);
+                    index_lookup(file, *root, &[k], collations, descending)?
+                };
+                let found = TableScan::with_rowids(table.file(self.file), table.root, rowids);
+                Cow::Owned(found.collect::<Result<_>>()?)
+            }
+            (plan, _) => unreachable!("no rows for {:?}", plan),
+        };
+        for row in candidates.iter() {
+            let mut next: Vec<&Row> = current.to_vec();
+            next.push(row);
+            self.visit(&next, plans, rows, columns, out)?;
+        }
+        Ok(())
+    }
+}
+
+/// The output columns of a query over `tables`, with `*` and `table.*`
+/// spelled out as qualified column names. There can't be more than `limits`
+/// allows.
+fn output_columns(select: &Select, tables: &[Table], limits: &Limits) -> Result<Vec<Expr>> {
+    let all_of = |t: &Table| -> Vec<Expr> {
+        let label = t.label();
+        t.def
+            .columns
+            .iter()
+            .map(|c| Expr::Column(format!("{}.{}", label, c)))
+            .collect()
+    };
+    let columns = match &select.columns {
+        SelectColumns::Columns(cols) => {
+            let mut exprs = vec![];
+            for col in cols {
+                match col {
+                    ResultColumn::Expr { expr, .. } => exprs.push(expr.clone()),
+                    ResultColumn::AllOf(name) => exprs.extend(all_of(find_table(tables, name)?)),
+                }
+            }
+            exprs
+        }
+        SelectColumns::All => tables.iter().flat_map(all_of).collect(),
+        SelectColumns::Count => vec![],
+        SelectColumns::MinMax { column, .. } => vec![Expr::Column(column.clone())],
+    };
+    if columns.len() > limits.columns {
+        bail!("too many columns in result set");
+    }
+    Ok(columns)
+}
+
+/// Which of `tables` a column belongs to.
+fn resolve(tables: &[Table], name: &str) -> Result<usize> {
+    let mut found = (0..tables.len()).filter(|&i| tables[i].has_column(name));
+    match (found.next(), found.next()) {
+        (Some(i), None) => Ok(i),
+        (Some(_), Some(_)) => bail!("ambiguous column name: {}", name),
+        (None, _) => bail!("no such column: {}", name),
+    }
+}
+
+/// Ask the file's authorizer about everything a query does, before it's
+/// planned, and mark the columns it says to ignore on their tables.
+fn authorize(file: &SqliteFile, select: &Select, tables: &mut [Table]) -> Result<()> {
+    if !file.has_authorizer() {
+        return Ok(());
+    }
+    file.authorize(&Action::Select)?;
+    let mut exprs = output_columns(select, tables, file.limits())?;
+    exprs.extend(select.filter.iter().cloned());
+    exprs.extend(select.joins.iter().map(|j| j.on.clone()));
+    let mut read = vec![false; tables.len()];
+    for expr in &exprs {
+        for name in expr.functions() {
+            file.authorize(&Action::Function(name))?;
+        }
+        for name in expr.columns() {
+            // names that don't resolve fail later with a better message
+            let Ok(i) = resolve(tables, name) else {
+                continue;
+            };
+            read[i] = true;
+            let table = &mut tables[i];
+            let column = table.local(name).unwrap_or(name);
+            let action = Action::Read {
+                table: &table.def.name,
+                column,
+            };
+            if file.authorize(&action)? == Authorization::Ignore {
+                table.ignored.push(column.to_owned());
+            }
+        }
+    }
+    for (table, read) in tables.iter().zip(read) {
+        if !read {
+            file.authorize(&Action::Read {
+                table: &table.def.name,
+                column: "",
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// The table called `name`, or with `name` as its alias.
+fn find_table<'t>(tables: &'t [Table], name: &str) -> Result<&'t Table> {
+    tables
+        .iter()
+        .find(|t| t.label().eq_ignore_ascii_case(name))
+        .ok_or_else(|| anyhow!("no such table: {}", name))
+}
+
+/// The names of the columns a `SELECT` returns, for headers. `*` and
+/// `table.*` give the names of the tables' columns.
+pub fn column_names(file: &SqliteFile, select: &Select) -> Result<Vec<String>> {
+    let mut tables = vec![Table::open(file, &select.name, select.alias.as_deref())?];
+    for join in &select.joins {
+        tables.push(Table::open(file, &join.table, join.alias.as_deref())?);
+    }
+    Ok(match &select.columns {
+        SelectColumns::Columns(cols) => {
+            let mut names = vec![];
+            for col in cols {
+                match col {
+                    ResultColumn::AllOf(name) => {
+                        names.extend(find_table(&tables, name)?.def.columns.iter().cloned())
+                    }
+                    col => names.extend(col.name()),
+                }
+            }
+            names
+        }
+        SelectColumns::Count => vec!["COUNT(*)".to_owned()],
+        SelectColumns::MinMax { column, max } => {
+            vec![format!("{}({})", if *max { "MAX" } else { "MIN" }, column)]
+        }
+        SelectColumns::All => tables.into_iter().flat_map(|t| t.def.columns).collect(),
+    })
+}
+
+/// `EXPLAIN QUERY PLAN` for a `SELECT`, one line per step.
+pub fn explain(file: &SqliteFile, select: &Select) -> Result<Vec<String>> {
+    let mut lines = vec!["QUERY PLAN".to_owned()];
+    if select.joins.is_empty() {
+        let mut table = Table::open(file, &select.name, select.alias.as_deref())?;
+        authorize(file, select, std::slice::from_mut(&mut table))?;
+        let plan = table.plan_select(select, select.filter.as_ref())?;
+        PlanNode::render(&[plan.node(table.label())], "", &mut lines);
+        return Ok(lines);
+    }
+    let join = Join::open(file, select)?;
+    let mut nodes = vec![join.first_plan().node(join.tables[0].label())];
+    for i in 1..join.tables.len() {
+        nodes.push(join.plan(i).node(join.tables[i].label()));
+    }
+    PlanNode::render(&nodes, "", &mut lines);
+    Ok(lines)
+}
+
+/// How the rows of one table of a query get found, without the values being
+/// looked up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Access {
+    Scan,
+    Rowid,
+    RowidRange,
+    /// Lookups in the index with this name.
+    Index(String),
+    /// An index built for the query, on this column.
+    AutoIndex(String),
+    /// One of these per term of an `OR`.
+    Or(Vec<Access>),
+}
+
+impl Display for Access {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Access::Scan => write!(f, "scan"),
+            Access::Rowid => write!(f, "rowid"),
+            Access::RowidRange => write!(f, "rowid range"),
+            Access::Index(index) => write!(f, "index {}", index),
+            Access::AutoIndex(column) => write!(f, "auto index {}", column),
+            Access::Or(terms) => {
+                let terms: Vec<String> = terms.iter().map(Access::to_string).collect();
+                write!(f, "or({})", terms.join(", "))
+            }
+        }
+    }
+}
+
+impl FromStr for Access {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if let Some(inner) = s.strip_prefix("or(").and_then(|s| s.strip_suffix(')')) {
+            // split on the commas that aren't inside a nested or(...)
+            let mut terms = vec![];
+            let (mut depth, mut start) = (0, 0);
+            for (i, c) in inner.char_indices() {
+                match c {
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    ',' if depth == 0 => {
+                        terms.push(inner[start..i].parse()?);
+                        start = i + 1;
+                    }
+                    _ => {}
+                }
+            }
+            terms.push(inner[start..].parse()?);
+            return Ok(Access::Or(terms));
+        }
+        Ok(match s {
+            "scan" => Access::Scan,
+            "rowid" => Access::Rowid,
+            "rowid range" => Access::RowidRange,
+            _ => {
+                if let Some(column) = s.strip_prefix("auto index ") {
+                    Access::AutoIndex(column.to_owned())
+                } else if let Some(index) = s.strip_prefix("index ") {
+                    Access::Index(index.to_owned())
+                } else {
+                    bail!("bad table access: {:?}", s)
+                }
+            }
+        })
+    }
+}
+
+/// The strategy the planner picked for a query: the order the tables are
+/// read in and how each one is read. It leaves out the values being looked
+/// up, so two snapshots are equal when the query runs the same way. Save one
+/// for an important query and compare it later to notice when a new version
+/// plans it differently. Turns into a string and back, one `table: access`
+/// line per table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlanSnapshot {
+    /// `(table, access)` for each table, in join order.
+    pub steps: Vec<(String, Access)>,
+}
+
+impl Display for PlanSnapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (table, access) in &self.steps {
+            writeln!(f, "{}: {}", table, access)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for PlanSnapshot {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let steps = s
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let (table, access) = line
+                    .split_once(':')
+                    .ok_or_else(|| anyhow!("bad plan snapshot line: {:?}", line))?;
+                Ok((table.trim().to_owned(), access.parse()?))
+            })
+            .collect::<Result<_>>()?;
+        Ok(PlanSnapshot { steps })
+    }
+}
+
+/// Take a [`PlanSnapshot`] of the plan for a `SELECT`, without running it.
+/// It's the plan the query runs with, `MIN` and `MAX` shortcuts included.
+pub fn plan_snapshot(file: &SqliteFile, select: &Select) -> Result<PlanSnapshot> {
+    if select.joins.is_empty() {
+        let table = Table::open(file, &select.name, select.alias.as_deref())?;
+        let access = table.plan_select(select, select.filter.as_ref())?.access();
+        return Ok(PlanSnapshot {
+            steps: vec![(table.label().to_owned(), access)],
+        });
+    }
+    let join = Join::open(file, select)?;
+    let mut steps = vec![(
+        join.tables[0].label().to_owned(),
+        join.first_plan().access(),
+    )];
+    for i in 1..join.tables.len() {
+        steps.push((join.tables[i].label().to_owned(), join.plan(i).access()));
+    }
+    Ok(PlanSnapshot { steps })
+}
+
+#[test]
+fn pages_match_whole_query() -> Result<()> {
+    let file = super::OpenOptions::new().open("sample.db")?;
+    let select: Select = "SELECT id, name FROM oranges WHERE id != 3".parse()?;
+    let all = execute(&file, &select)?;
+    let mut paged = vec![];
+    let mut rows = Rows::new(&file, &select)?;
+    let first = rows.page(2)?;
+    let token: PageToken = first.next.unwrap().to_string().parse()?;
+    paged.extend(first.rows);
+    // carry on from the token, like a new request would
+    let mut rows = Rows::new(&file, &select)?.resume(token);
+    loop {
+        let page = rows.page(2)?;
+        paged.extend(page.rows);
+        if page.next.is_none() {
+            break;
+        }
+    }
+    assert_eq!(paged, all);
+    Ok(())
+}
+
+#[test]
+fn limited_rows() -> Result<()> {
+    let file = super::OpenOptions::new().open("sample.db")?;
+    let select: Select = "SELECT id FROM oranges".parse()?;
+    let all = execute(&file, &select)?;
+    let limited = execute_limited(&file, &select, 2)?;
+    assert_eq!(limited.rows, all[..2]);
+    assert!(limited.truncated);
+    let limited = execute_limited(&file, &select, all.len())?;
+    assert_eq!(limited.rows, all);
+    assert!(!limited.truncated);
+    Ok(())
+}
+
+#[test]
+fn between_uses_rowid_range() -> Result<()> {
+    let file = super::OpenOptions::new().open("sample.db")?;
+    let select: Select = "SELECT name FROM oranges WHERE id BETWEEN 2 AND 4.5".parse()?;
+    let table = Table::open(&file, "oranges", None)?;
+    assert_eq!(
+        table.plan(select.filter.as_ref())?,
+        Plan::RowidRange {
+            min: Some(2),
+            max: Some(4)
+        }
+    );
+    let names: Vec<String> = execute(&file, &select)?
+        .iter()
+        .map(|row| row[0].to_string())
+        .collect();
+    assert_eq!(names, ["Tangelo", "Tangerine", "Clementine"]);
+    Ok(())
+}
+
+#[test]
+fn plan_snapshot_round_trips() -> Result<()> {
+    let file = super::OpenOptions::new().open("sample.db")?;
+    let select: Select = "SELECT name FROM oranges WHERE id BETWEEN 2 AND 4".parse()?;
+    let snapshot = plan_snapshot(&file, &select)?;
+    assert_eq!(snapshot.to_string(), "oranges: rowid range\n");
+    let or = "t: or(index a, rowid, or(auto index b, scan))\n";
+    assert_eq!(or.parse::<PlanSnapshot>()?.to_string(), or);
+    Ok(())
+}
+
+#[test]
+fn null_is_unknown() -> Result<()> {
+    fn check(sql: &str, expected: Value) -> Result<()> {
+        let expr = super::sql::Parser::new(sql)?.expr()?;
+        let column = |name: &str| {
+            Ok(match name {
+                "one" => ValueRef::Integer(1),
+                _ => ValueRef::Null,
+            })
+        };
+        assert_eq!(
+            eval(&expr, &column, &Functions::default())?.into_value(),
+            expected,
+            "{}",
+            sql
+        );
+        Ok(())
+    }
+    check("x = 1", Value::Null)?;
+    check("x = 1 AND one = 2", Value::Integer(0))?;
+    check("x = 1 AND one = 1", Value::Null)?;
+    check("x = 1 OR one = 1", Value::Integer(1))?;
+    check("x = 1 OR one = 2", Value::Null)?;
+    check("x IS NULL AND one IS NOT NULL", Value::Integer(1))?;
+    check("one ISNULL OR x NOT NULL", Value::Integer(0))?;
+    Ok(())
+}
+
+#[test]
+fn expressions_in_select() -> Result<()> {
+    let file = super::OpenOptions::new().open("sample.db")?;
+    let select: Select = "SELECT id * 10 + 1, name || '!' FROM apples WHERE -id = -2".parse()?;
+    assert_eq!(
+        execute(&file, &select)?,
+        vec![vec![Value::Integer(21), Value::String("Fuji!".to_owned())]]
+    );
+    Ok(())
+}
+
+#[test]
+fn collations() -> Result<()> {
+    let rows = (1..=300)
+        .map(|i| {
+            let name = if i % 2 == 0 { "Fig" } else { "fig" };
+            vec![
+                Value::Integer(i),
+                format!("{} {}", name, i % 50).into(),
+                format!("c{}  ", i).into(),
+            ]
+        })
+        .collect();
+    let file = super::fixtures::Fixture::new()
+        .page_size(512)
+        .table(
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT COLLATE NOCASE, code TEXT)",
+            rows,
+        )
+        .index("CREATE INDEX t_name ON t (name)")
+        .open()?;
+    let table = Table::open(&file, "t", None)?;
+    let count = |filter: &str| -> Result<(usize, Access)> {
+        let select: Select = format!("SELECT id FROM t WHERE {}", filter).parse()?;
+        let access = table.plan(select.filter.as_ref())?.access();
+        Ok((execute(&file, &select)?.len(), access))
+    };
+    let index = Access::Index("t_name".to_owned());
+    // the column is NOCASE, and so is the index
+    assert_eq!(count("name = 'FIG 7'")?, (6, index.clone()));
+    assert_eq!(count("'FIG 7' = name")?, (6, index.clone()));
+    assert_eq!(count("name IN ('FIG 7', 'fig 8')")?, (12, index.clone()));
+    assert_eq!(count("name > 'FIG 7'")?.0, 12);
+    // BINARY can't use the index
+    assert_eq!(count("name = 'Fig 8' COLLATE BINARY")?, (6, Access::Scan));
+    assert_eq!(count("name COLLATE BINARY = 'fig 8'")?.0, 0);
+    assert_eq!(count("code = 'c12'")?.0, 0);
+    assert_eq!(count("code = 'c12' COLLATE RTRIM")?.0, 1);
+    Ok(())
+}
+
+#[test]
+fn order_by() -> Result<()> {
+    let rows = [(1, "b", 2), (2, "A", 1), (3, "a", 3), (4, "B", 1)]
+        .into_iter()
+        .map(|(id, name, n)| vec![Value::Integer(id), name.into(), Value::Integer(n)])
+        .collect();
+    let file = super::fixtures::Fixture::new()
+        .table(
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT COLLATE NOCASE, n INT)",
+            rows,
+        )
+        .open()?;
+    let ids = |order: &str| -> Result<Vec<Value>> {
+        let select: Select =
+            format!("SELECT id, name AS label FROM t ORDER BY {}", order).parse()?;
+        let rows = execute(&file, &select)?;
+        assert!(rows.iter().all(|row| row.len() == 2));
+        Ok(rows.into_iter().map(|row| row[0].clone()).collect())
+    };
+    let int = |ids: &[i64]| ids.iter().map(|&id| Value::Integer(id)).collect::<Vec<_>>();
+    // the column is NOCASE, so rows that only differ in case keep their order
+    assert_eq!(ids("name")?, int(&[2, 3, 1, 4]));
+    assert_eq!(ids("name COLLATE BINARY")?, int(&[2, 4, 3, 1]));
+    assert_eq!(ids("label DESC, id")?, int(&[1, 4, 2, 3]));
+    assert_eq!(ids("n, 2 COLLATE BINARY DESC")?, int(&[4, 2, 1, 3]));
+    assert_eq!(ids("n * -1 ASC, id DESC")?, int(&[3, 1, 4, 2]));
+    assert!(ids("3").is_err());
+    Ok(())
+}
+
+#[test]
+fn affinities() -> Result<()> {
+    let text = |s: &str| Value::String(s.to_owned());
+    let file = super::fixtures::Fixture::new()
+        .table(
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, code TEXT, n INTEGER, b)",
+            vec![
+                vec![Value::Integer(1), text("3"), Value::Integer(3), text("3")],
+                vec![
+                    Value::Integer(2),
+                    text("10"),
+                    Value::Integer(10),
+                    Value::Integer(10),
+                ],
+                vec![Value::Integer(3), text("x"), Value::Null, Value::Null],
+            ],
+        )
+        .index("CREATE INDEX t_code ON t (code)")
+        .open()?;
+    let table = Table::open(&file, "t", None)?;
+    let count = |filter: &str| -> Result<(usize, Access)> {
+        let select: Select = format!("SELECT id FROM t WHERE {}", filter).parse()?;
+        let access = table.plan(select.filter.as_ref())?.access();
+        Ok((execute(&file, &select)?.len(), access))
+    };
+    // the literal is converted to the column's affinity, even for lookups
+    assert_eq!(count("code = 3")?, (1, Access::Index("t_code".to_owned())));
+    assert_eq!(count("id = '2'")?, (1, Access::Rowid));
+    assert_eq!(count("n = '3.0'")?.0, 1);
+    assert_eq!(count("code < 9")?.0, 2);
+    assert_eq!(count("n < '9'")?.0, 1);
+    assert_eq!(count("code IN (3, 10)")?.0, 2);
+    assert_eq!(count("code = 3 + 0")?.0, 1);
+    assert_eq!(count("code = n")?.0, 2);
+    // no affinity, so nothing's converted
+    assert_eq!(count("b = 3")?.0, 0);
+    assert_eq!(count("b = 10")?.0, 1);
+    Ok(())
+}
+
+#[test]
+fn columns_added_later() -> Result<()> {
+    use super::record::Provenance;
+    // the first row was written before `added` was
+    let file = super::fixtures::Fixture::new()
+        .table(
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, added TEXT DEFAULT 'new')",
+            vec![
+                vec![Value::Integer(1)],
+                vec![Value::Integer(2), Value::Null],
+            ],
+        )
+        .open()?;
+    let select = "SELECT added FROM t".parse()?;
+    assert_eq!(
+        execute(&file, &select)?,
+        [[Value::from("new")], [Value::Null]]
+    );
+    let select = "SELECT id FROM t WHERE added = 'new'".parse()?;
+    assert_eq!(execute(&file, &select)?, [[Value::Integer(1)]]);
+
+    let table = Table::open(&file, "t", None)?;
+    let rows = TableScan::new(&file, table.root).collect::<Result<Vec<_>>>()?;
+    let provenance: Vec<_> = rows.iter().map(|row| row.provenance(1)).collect();
+    assert_eq!(provenance, [Provenance::Default, Provenance::Stored]);
+    Ok(())
+}
+
+#[test]
+fn custom_collations() -> Result<()> {
+    // words compare by their length
+    let by_length = |a: &str, b: &str| a.len().cmp(&b.len());
+    let rows = (1..=200)
+        .map(|i| vec![Value::Integer(i), "x".repeat(i as usize % 20 + 1).into()])
+        .collect();
+    let mut file = super::fixtures::Fixture::new()
+        .page_size(512)
+        .collation("length", by_length)
+        .table(
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, word TEXT COLLATE length)",
+            rows,
+        )
+        .index("CREATE INDEX t_word ON t (word)")
+        .open()?;
+    let count = |file: &SqliteFile, filter: &str| -> Result<(usize, Access)> {
+        let select: Select = format!("SELECT id FROM t WHERE {}", filter).parse()?;
+        let access = Table::open(file, "t", None)?
+            .plan(select.filter.as_ref())?
+            .access();
+        Ok((execute(file, &select)?.len(), access))
+    };
+    let index = Access::Index("t_word".to_owned());
+    assert_eq!(count(&file, "word = 'abc'")?, (10, index));
+    assert_eq!(count(&file, "word < 'ab'")?.0, 10);
+    assert_eq!(count(&file, "word = 'abc' COLLATE binary")?.0, 0);
+    // one that was never added fails, even with no rows to compare
+    assert!(count(&file, "word = 'x' COLLATE klingon").is_err());
+    assert!(count(&file, "0 AND word = 'x' COLLATE klingon").is_err());
+    file.create_collation("klingon", |a, b| b.cmp(a));
+    assert_eq!(count(&file, "word > 'xx' COLLATE klingon")?.0, 10);
+    Ok(())
+}
+
+#[test]
+fn authorizer() -> Result<()> {
+    use std::sync::{Arc, Mutex};
+    let mut file = super::fixtures::Fixture::new()
+        .table(
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT, password TEXT)",
+            vec![
+                vec![Value::Integer(1), "alice".into(), "hunter2".into()],
+                vec![Value::Integer(2), "bob".into(), "letmein".into()],
+            ],
+        )
+        .table("CREATE TABLE secrets (s TEXT)", vec![vec!["x".into()]])
+        .open()?;
+    let seen = Arc::new(Mutex::new(vec![]));
+    let log = Arc::clone(&seen);
+    file.set_authorizer(move |action| {
+        log.lock().unwrap().push(action.to_string());
+        match action {
+            Action::Read {
+                table: "secrets", ..
+            }
+            | Action::Function("hex") => Authorization::Deny,
+            Action::Read {
+                column: "password", ..
+            } => Authorization::Ignore,
+            _ => Authorization::Allow,
+        }
+    });
+    let run = |sql: &str| execute(&file, &sql.parse()?);
+    assert_eq!(
+        run("SELECT name, password FROM users")?,
+        [
+            vec![Value::from("alice"), Value::Null],
+            vec![Value::from("bob"), Value::Null]
+        ]
+    );
+    assert_eq!(
+        *seen.lock().unwrap(),
+        ["SELECT", "read users.name", "read users.password"]
+    );
+    // an ignored column can't be used to find things out either
+    assert!(run("SELECT id FROM users WHERE password = 'hunter2'")?.is_empty());
+    let error = run("SELECT COUNT(*) FROM secrets").unwrap_err();
+    assert_eq!(error.to_string(), "access to secrets. is prohibited");
+    assert!(run("SELECT u.id FROM users u JOIN secrets ON 1").is_err());
+    assert!(run("SELECT hex(name) FROM users").is_err());
+    assert!(file.prepare("SELECT * FROM secrets").is_err());
+    Ok(())
+}
+
+#[test]
+fn temp_tables() -> Result<()> {
+    let rows = (1..=50)
+        .map(|i| vec![Value::Integer(i), Value::Integer(i % 5)])
+        .collect();
+    let file = super::fixtures::Fixture::new()
+        .page_size(512)
+        .table("CREATE TABLE t (id INTEGER PRIMARY KEY, n INT)", rows)
+        .index("CREATE INDEX t_n ON t (n)")
+        .open()?;
+    let run = |sql: &str| execute(&file, &sql.parse()?);
+    let select = "SELECT id, n * 10 AS tens, id FROM t WHERE n = 3".parse()?;
+    assert_eq!(file.materialize("threes", &select)?, 10);
+    assert!(file.materialize("THREES", &select).is_err());
+    assert!(file.materialize("t", &select).is_err());
+    let temp = file.temp_table("threes").unwrap();
+    assert_eq!(temp.def.columns, ["id", "tens", "id:1"]);
+
+    assert_eq!(
+        run("SELECT id FROM threes WHERE rowid = 2 OR id > 45")?,
+        [vec![Value::Integer(8)], vec![Value::Integer(48)]]
+    );
+    assert_eq!(
+        run("SELECT COUNT(*) FROM threes")?,
+        [vec![Value::Integer(10)]]
+    );
+    // joined either way round, with the file's table looked up by rowid
+    let joined = "SELECT COUNT(*) FROM threes JOIN t ON t.id = threes.id + 1 WHERE t.n = 4";
+    assert_eq!(run(joined)?, [vec![Value::Integer(10)]]);
+    let joined = "SELECT x.tens FROM t JOIN threes x ON x.rowid = t.id WHERE t.id = 3";
+    assert_eq!(run(joined)?, [vec![Value::Integer(30)]]);
+
+    let select: Select = "SELECT id FROM threes".parse()?;
+    let mut rows = Rows::new(&file, &select)?;
+    let first = rows.page(4)?;
+    assert_eq!(first.rows.last(), Some(&vec![Value::Integer(18)]));
+    let mut rows = Rows::new(&file, &select)?.resume(first.next.unwrap());
+    assert_eq!(rows.page(100)?.rows.len(), 6);
+    Ok(())
+}
+
+/// Run `SELECT id FROM t WHERE filter` and check it finds the rows that
+/// `expected` picks out of `rows`, in order. Returns how it read `t`.
+#[cfg(test)]
+fn check_filter(
+    file: &SqliteFile,
+    rows: &[Vec<Value>],
+    filter: &str,
+    expected: &dyn Fn(&[Value]) -> bool,
+) -> Result<Access> {
+    let select: Select = format!("SELECT id FROM t WHERE {}", filter).parse()?;
+    let ids: Vec<Value> = execute(file, &select)?.concat();
+    let want: Vec<Value> = rows
+        .iter()
+        .filter(|row| expected(row))
+        .map(|row| row[0].clone())
+        .collect();
+    assert_eq!(ids, want, "{}", filter);
+    Ok(plan_snapshot(file, &select)?.steps.remove(0).1)
+}
+
+/// The value as an integer, if it is one.
+#[cfg(test)]
+fn int(value: &Value) -> Option<i64> {
+    match value {
+        Value::Integer(n) => Some(*n),
+        _ => None,
+    }
+}
+
+#[test]
+fn composite_index_ranges() -> Result<()> {
+    let rows: Vec<Vec<Value>> = (1..=400i64)
+        .map(|i| {
+            let a = ["x", "y", "z"][i as usize % 3];
+            let c = if i % 2 == 0 { "Q" } else { "q" };
+            let b = if i % 7 == 0 {
+                Value::Null
+            } else {
+                (i % 20).into()
+            };
+            vec![i.into(), a.into(), b, format!("{}{}", c, i % 5).into()]
+        })
+        .collect();
+    let file = super::fixtures::Fixture::new()
+        .page_size(512)
+        .table(
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, a TEXT, b INTEGER, c TEXT COLLATE NOCASE)",
+            rows.clone(),
+        )
+        .index("CREATE INDEX t_a ON t (a)")
+        .index("CREATE INDEX t_ab ON t (a, b)")
+        .index("CREATE INDEX t_ac ON t (a, c)")
+        .open()?;
+    let check = |filter: &str, expected: &dyn Fn(&[Value]) -> bool| {
+        check_filter(&file, &rows, filter, expected)
+    };
+    let ab = Access::Index("t_ab".to_owned());
+    assert_eq!(
+        check("a = 'y' AND b = 4", &|r| r[1] == "y".into()
+            && int(&r[2]) == Some(4))?,
+        ab
+    );
+    assert_eq!(
+        check("b > 3 AND a = 'x' AND b <= 9", &|r| {
+            r[1] == "x".into() && int(&r[2]).is_some_and(|b| b > 3 && b <= 9)
+        })?,
+        ab
+    );
+    // NULLs aren't less than anything
+    assert_eq!(
+        check("a IN ('x', 'z') AND b < 2", &|r| {
+            r[1] != "y".into() && int(&r[2]).is_some_and(|b| b < 2)
+        })?,
+        ab
+    );
+    assert_eq!(
+        check("a = 'z' AND b > 5 AND b > 15", &|r| {
+            r[1] == "z".into() && int(&r[2]).is_some_and(|b| b > 15)
+        })?,
+        ab
+    );
+    // c compares with its NOCASE collation, like the index
+    assert_eq!(
+        check("a = 'x' AND c = 'Q3'", &|r| {
+            r[1] == "x".into() && r[3].to_string().eq_ignore_ascii_case("q3")
+        })?,
+        Access::Index("t_ac".to_owned())
+    );
+    assert_eq!(
+        check("a = 'x' AND c >= 'q3'", &|r| {
+            r[1] == "x".into() && r[3].to_string().to_lowercase().as_str() >= "q3"
+        })?,
+        Access::Index("t_ac".to_owned())
+    );
+    // a range on the first column
+    assert_eq!(
+        check("a > 'x'", &|r| r[1] != "x".into())?,
+        Access::Index("t_a".to_owned())
+    );
+    let explain = explain(
+        &file,
+        &"SELECT id FROM t WHERE a = 'x' AND b > 1 AND b < 3".parse()?,
+    )?;
+    assert_eq!(
+        explain,
+        [
+            "QUERY PLAN",
+            "`--SEARCH t USING INDEX t_ab (a=? AND b>? AND b<?)"
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn descending_indexes() -> Result<()> {
+    let rows: Vec<Vec<Value>> = (1..=300i64)
+        .map(|i| {
+            let b = if i % 11 == 0 {
+                Value::Null
+            } else {
+                (i % 25).into()
+            };
+            vec![i.into(), (i % 4).into(), b]
+        })
+        .collect();
+    let file = super::fixtures::Fixture::new()
+        .page_size(512)
+        .table(
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, a INTEGER, b INTEGER)",
+            rows.clone(),
+        )
+        .index("CREATE INDEX t_b ON t (b DESC)")
+        .index("CREATE INDEX t_ab ON t (a, b desc)")
+        .open()?;
+    let check = |filter: &str, expected: &dyn Fn(&[Value]) -> bool| {
+        check_filter(&file, &rows, filter, expected)
+    };
+    check("b = 7", &|r| int(&r[2]) == Some(7))?;
+    check("b IN (0, 24)", &|r| matches!(int(&r[2]), Some(0 | 24)))?;
+    check("b > 20", &|r| int(&r[2]).is_some_and(|b| b > 20))?;
+    check("b <= 3", &|r| int(&r[2]).is_some_and(|b| b <= 3))?;
+    check("a = 2 AND b = 6", &|r| {
+        int(&r[1]) == Some(2) && int(&r[2]) == Some(6)
+    })?;
+    check("a = 1 AND b >= 5 AND b < 10", &|r| {
+        int(&r[1]) == Some(1) && int(&r[2]).is_some_and(|b| (5..10).contains(&b))
+    })?;
+    check("a IN (0, 3) AND b < 4", &|r| {
+        matches!(int(&r[1]), Some(0 | 3)) && int(&r[2]).is_some_and(|b| b < 4)
+    })?;
+    Ok(())
+}
+
+#[test]
+fn partial_indexes() -> Result<()> {
+    let rows: Vec<Vec<Value>> = (1..=200i64)
+        .map(|i| {
+            let s = if i % 3 == 0 {
+                Value::Null
+            } else {
+                format!("s{}", i % 4).into()
+            };
+            vec![i.into(), (i % 10).into(), s]
+        })
+        .collect();
+    let file = super::fixtures::Fixture::new()
+        .page_size(512)
+        .table(
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, n INT, s TEXT)",
+            rows.clone(),
+        )
+        .index("CREATE INDEX t_big ON t (n) WHERE n > 5")
+        .index("CREATE INDEX t_named ON t (n) WHERE s IS NOT NULL AND n != 0")
+        .open()?;
+    let check = |filter: &str, expected: &dyn Fn(i64, &Value) -> bool| {
+        check_filter(&file, &rows, filter, &|r| {
+            int(&r[1]).is_some_and(|n| expected(n, &r[2]))
+        })
+    };
+    let big = Access::Index("t_big".to_owned());
+    let named = Access::Index("t_named".to_owned());
+    assert_eq!(check("n = 7", &|n, _| n == 7)?, big);
+    assert_eq!(check("n IN (6, 9)", &|n, _| n == 6 || n == 9)?, big);
+    assert_eq!(check("n >= 8", &|n, _| n >= 8)?, big);
+    assert_eq!(check("n = 3", &|n, _| n == 3)?, Access::Scan);
+    // n = 5 is in the index's WHERE as n > 5 leaves it out
+    assert_eq!(check("n >= 5", &|n, _| n >= 5)?, Access::Scan);
+    assert_eq!(check("5 < n AND n < 7", &|n, _| n == 6)?, big);
+    assert_eq!(
+        check("n = 3 AND s = 's1' AND n != 0", &|n, s| n == 3
+            && *s == "s1".into())?,
+        named
+    );
+    assert_eq!(
+        check("n = 3 AND s = 's1'", &|n, s| n == 3 && *s == "s1".into())?,
+        Access::Scan
+    );
+    Ok(())
+}
+
+#[test]
+fn min_max_from_indexes() -> Result<()> {
+    // plenty of NULLs at one end, so skipping them takes a few pages
+    let rows: Vec<Vec<Value>> = (1..=1000i64)
+        .map(|i| {
+            let n = if i % 3 == 0 {
+                Value::Null
+            } else {
+                ((i * 37) % 501 - 250).into()
+            };
+            let s = ["pear", "Apple", "fig", "apricot", "Zucchini"][i as usize % 5];
+            vec![i.into(), n.clone(), n, s.into(), Value::Null]
+        })
+        .collect();
+    let file = super::fixtures::Fixture::new()
+        .page_size(512)
+        .table(
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, a INT, d INT, s TEXT COLLATE NOCASE, z)",
+            rows,
+        )
+        .index("CREATE INDEX t_a ON t (a)")
+        .index("CREATE INDEX t_d ON t (d DESC)")
+        .index("CREATE INDEX t_s ON t (s COLLATE BINARY)")
+        .index("CREATE INDEX t_z ON t (z)")
+        .open()?;
+    let query = |sql: &str| -> Result<(Value, Access, u64)> {
+        let select: Select = sql.parse()?;
+        file.reset_stats();
+        let rows = execute(&file, &select)?;
+        let cells = file.stats().cells_parsed;
+        let access = plan_snapshot(&file, &select)?.steps.remove(0).1;
+        Ok((rows[0][0].clone(), access, cells))
+    };
+    let index = |name: &str| Access::Index(name.to_owned());
+    let (_, _, scanned) = query("SELECT MAX(a) FROM t WHERE z IS NULL")?;
+    for (sql, value, access) in [
+        ("SELECT MIN(a) FROM t", -249, index("t_a")),
+        ("SELECT MAX(a) FROM t", 250, index("t_a")),
+        ("SELECT min(d) FROM t", -249, index("t_d")),
+        ("SELECT max(d) FROM t", 250, index("t_d")),
+    ] {
+        let (found, used, cells) = query(sql)?;
+        assert_eq!((found, used), (value.into(), access), "{}", sql);
+        assert!(cells * 4 < scanned, "{} parsed {} cells", sql, cells);
+    }
+    // with a WHERE, the rows that match are gone through instead
+    let (found, used, _) = query("SELECT MAX(a) FROM t WHERE id < 10")?;
+    assert_eq!((found, used), (Value::Integer(46), Access::RowidRange));
+    // the index sorts by BINARY, the column by NOCASE
+    let (found, used, _) = query("SELECT MIN(s) FROM t")?;
+    assert_eq!((found, used), ("Apple".into(), Access::Scan));
+    let (found, _, _) = query("SELECT MAX(s) FROM t")?;
+    assert_eq!(found, "Zucchini".into());
+    // all NULLs
+    let (found, used, _) = query("SELECT MAX(z) FROM t")?;
+    assert_eq!((found, used), (Value::Null, index("t_z")));
+    assert_eq!(query("SELECT MIN(z) FROM t")?.0, Value::Null);
+    assert_eq!(
+        column_names(&file, &"SELECT MAX(a) FROM t".parse()?)?,
+        ["MAX(a)"]
+    );
+    Ok(())
+}
+
+#[test]
+fn count_from_leaf_headers() -> Result<()> {
+    let rows: Vec<Vec<Value>> = (1..=2000i64)
+        .map(|i| vec![(i * 2).into(), format!("row {}", i).into()])
+        .collect();
+    let file = super::fixtures::Fixture::new()
+        .page_size(512)
+        .table("CREATE TABLE t (id INTEGER PRIMARY KEY, s TEXT)", rows)
+        .open()?;
+    let count = |sql: &str| -> Result<(Value, u64)> {
+        let select: Select = sql.parse()?;
+        // open the table first, so only the count itself is measured
+        execute(&file, &select)?;
+        file.reset_stats();
+        let rows = execute(&file, &select)?;
+        Ok((rows[0][0].clone(), file.stats().cells_parsed))
+    };
+    assert_eq!(count("SELECT COUNT(*) FROM t")?, (Value::Integer(2000), 0));
+    let (filtered, cells) = count("SELECT COUNT(*) FROM t WHERE s > 'row 5'")?;
+    assert_eq!(filtered, Value::Integer(554));
+    assert!(cells >= 2000);
+    let select: Select = "SELECT COUNT(*) FROM t".parse()?;
+    let compiled = CompiledQuery::new(&file, &select)?.unwrap();
+    assert_eq!(compiled.execute(&file, &select)?, [[Value::Integer(2000)]]);
+    assert_eq!(file.row_count("T")?, 2000);
+    assert!(file.row_count("nope").is_err());
+    Ok(())
+}
+
+#[test]
+fn output_sources() -> Result<()> {
+    let rows = (1..=3)
+        .map(|i| vec![Value::Integer(i), Value::Integer(i * 10)])
+        .collect();
+    let file = super::fixtures::Fixture::new()
+        .table("CREATE TABLE t (id INTEGER PRIMARY KEY, n INT)", rows)
+        .open()?;
+    let select = "SELECT t.*, n + 1, t.ID FROM t WHERE id = ?".parse()?;
+    let query = CompiledQuery::new(&file, &select)?.unwrap();
+    use Source::*;
+    assert_eq!(query.sources(), [Rowid, Column(1), Expr, Rowid]);
+    let bound = select.bind(&[Value::Integer(2)])?;
+    let row = [2, 20, 21, 2].map(Value::Integer);
+    assert_eq!(query.execute(&file, &bound)?, [row]);
+    // the schema is only read the first time
+    let select = "SELECT n FROM t".parse()?;
+    let reads = || -> Result<u64> {
+        let before = file.stats().cache_hits;
+        execute(&file, &select)?;
+        Ok(file.stats().cache_hits - before)
+    };
+    file.tables().clear();
+    let first = reads()?;
+    assert!(reads()? < first);
+    Ok(())
+}
+
+#[test]
+fn table_cache_eviction() -> Result<()> {
+    let mut fixture = super::fixtures::Fixture::new();
+    for name in ["a", "b", "c", "d"] {
+        fixture.table(&format!("CREATE TABLE {} (x)", name), vec![]);
+    }
+    let file = fixture.open()?;
+    file.set_table_cache_size(2);
+    let query = |name: &str| -> Result<()> {
+        execute(&file, &format!("SELECT x FROM {}", name).parse()?)?;
+        Ok(())
+    };
+    let cached = || {
+        let tables = file.tables();
+        let mut names: Vec<_> = tables.entries.keys().cloned().collect();
+        names.sort();
+        let mut ordered: Vec<_> = tables.order.values().cloned().collect();
+        ordered.sort();
+        assert_eq!(names, ordered);
+        names
+    };
+    for name in ["a", "b", "c"] {
+        query(name)?;
+    }
+    assert_eq!(cached(), ["b", "c"]);
+    // using b makes c the oldest
+    query("b")?;
+    query("d")?;
+    assert_eq!(cached(), ["b", "d"]);
+    file.set_table_cache_size(1);
+    assert_eq!(cached(), ["d"]);
+    file.set_table_cache_size(0);
+    query("a")?;
+    assert!(cached().is_empty());
+    Ok(())
+}
+
+#[test]
+fn views() -> Result<()> {
+    let rows = vec![
+        vec![1.into(), "Fuji".into(), "Red".into()],
+        vec![2.into(), "Gala".into(), "Red".into()],
+        vec![3.into(), "Granny Smith".into(), "Green".into()],
+    ];
+    let file = super::fixtures::Fixture::new()
+        .table(
+            "CREATE TABLE apples (id INTEGER PRIMARY KEY, name, color)",
+            rows,
+        )
+        .view("CREATE VIEW red AS SELECT id, name FROM apples WHERE color = 'Red'")
+        .view("CREATE VIEW named (n) AS SELECT name FROM red")
+        .view("CREATE VIEW wrong (a, b) AS SELECT name FROM apples")
+        .view("CREATE VIEW loop1 AS SELECT x FROM loop2")
+        .view("CREATE VIEW loop2 AS SELECT x FROM loop1")
+        .open()?;
+    let query = |sql: &str| execute(&file, &sql.parse()?);
+    assert_eq!(
+        query("SELECT name FROM red WHERE id > 1")?,
+        [vec![Value::from("Gala")]]
+    );
+    assert_eq!(
+        query("SELECT COUNT(*) FROM red")?,
+        [vec![Value::Integer(2)]]
+    );
+    assert_eq!(column_names(&file, &"SELECT * FROM named".parse()?)?, ["n"]);
+    // a view joins like any other table
+    let joined =
+        query("SELECT apples.color, r.name FROM apples JOIN red AS r ON r.id = apples.id")?;
+    assert_eq!(joined.len(), 2);
+    assert!(query("SELECT a FROM wrong").is_err());
+    let err = query("SELECT x FROM loop1").unwrap_err();
+    assert_eq!(err.to_string(), "view loop1 is circularly defined");
+    Ok(())
+}
+
+#[test]
+fn fts5_tables() -> Result<()> {
+    let file = super::fixtures::Fixture::new()
+        .virtual_table("CREATE VIRTUAL TABLE docs USING fts5(title, body)")
+        .table(
+            "CREATE TABLE docs_content (id INTEGER PRIMARY KEY, c0, c1)",
+            vec![
+                vec![3.into(), "Apples".into(), "Crisp and red".into()],
+                vec![7.into(), "Pears".into(), "Soft and green".into()],
+            ],
+        )
+        .virtual_table(
+            "CREATE VIRTUAL TABLE idx USING fts5(name, content='notes', content_rowid='id')",
+        )
+        .table(
+            "CREATE TABLE notes (id INTEGER PRIMARY KEY, name, extra)",
+            vec![vec![5.into(), "five".into(), "x".into()]],
+        )
+        .virtual_table("CREATE VIRTUAL TABLE gone USING fts5(a, content='')")
+        .table(
+            "CREATE TABLE gone_docsize (id INTEGER PRIMARY KEY, sz BLOB)",
+            vec![vec![9.into(), Value::Blob(vec![1])]],
+        )
+        .open()?;
+    let query = |sql: &str| execute(&file, &sql.parse()?);
+    assert_eq!(
+        query("SELECT rowid, title FROM docs WHERE match_all(body, 'green')")?,
+        [vec![Value::Integer(7), "Pears".into()]]
+    );
+    assert_eq!(
+        column_names(&file, &"SELECT * FROM docs".parse()?)?,
+        ["title", "body"]
+    );
+    assert_eq!(
+        query("SELECT rowid, name FROM idx")?,
+        [vec![Value::Integer(5), "five".into()]]
+    );
+    assert_eq!(
+        query("SELECT rowid, a FROM gone")?,
+        [vec![Value::Integer(9), Value::Null]]
+    );
+    Ok(())
+}
+
+#[test]
+fn attached_files() -> Result<()> {
+    use super::fixtures::Fixture;
+    let file = Fixture::new()
+        .table(
+            "CREATE TABLE apples (id INTEGER PRIMARY KEY, name)",
+            vec![vec![1.into(), "Fuji".into()], vec![2.into(), "Gala".into()]],
+        )
+        .open()?;
+    let path = std::env::temp_dir().join(format!("attached-{}.db", std::process::id()));
+    Fixture::new()
+        .table(
+            "CREATE TABLE prices (id INTEGER PRIMARY KEY, apple_id, price)",
+            vec![
+                vec![1.into(), 2.into(), 30.into()],
+                vec![2.into(), 1.into(), 25.into()],
+            ],
+        )
+        .index("CREATE INDEX prices_apple ON prices (apple_id)")
+        .write(&path)?;
+    let attached = file.attach(&path, "shop");
+    let _ = std::fs::remove_file(&path);
+    attached?;
+    let query = |sql: &str| execute(&file, &sql.parse()?);
+    assert_eq!(
+        query("SELECT price FROM shop.prices WHERE apple_id = 1")?,
+        [vec![Value::Integer(25)]]
+    );
+    assert_eq!(
+        query("SELECT apples.name, p.price FROM main.apples JOIN shop.prices AS p ON p.apple_id = apples.id")?,
+        [
+            vec![Value::from("Fuji"), Value::Integer(25)],
+            vec![Value::from("Gala"), Value::Integer(30)],
+        ]
+    );
+    assert!(query("SELECT price FROM prices").is_err());
+    assert!(file.attach(&path, "SHOP").is_err());
+    file.detach("shop")?;
+    assert!(query("SELECT price FROM shop.prices").is_err());
+    assert!(file.detach("shop").is_err());
+    Ok(())
+}
+
+#[test]
+fn interrupted_queries() -> Result<()> {
+    use super::interrupt::Interrupted;
+    let rows: Vec<Vec<Value>> = (1..=500i64)
+        .map(|i| vec![i.into(), (i % 7).into()])
+        .collect();
+    let file = super::fixtures::Fixture::new()
+        .page_size(512)
+        .table("CREATE TABLE t (id INTEGER PRIMARY KEY, n)", rows)
+        .index("CREATE INDEX t_n ON t (n)")
+        .open()?;
+    let query = |sql: &str| execute(&file, &sql.parse()?);
+    let sqls = [
+        "SELECT id FROM t WHERE id > 10",
+        "SELECT id FROM t WHERE n = 3",
+        "SELECT a.id FROM t AS a JOIN t AS b ON a.id = b.n",
+    ];
+    let interrupt = file.interrupt_handle();
+    interrupt.interrupt();
+    for sql in sqls {
+        let e = query(sql).unwrap_err();
+        assert!(e.is::<Interrupted>(), "{}: {:?}", sql, e);
+    }
+    interrupt.reset();
+    assert_eq!(query(sqls[0])?.len(), 490);
+    assert_eq!(query(sqls[1])?.len(), 72);
+    assert_eq!(query(sqls[2])?.len(), 429);
+    Ok(())
+}
+
+#[test]
+fn progress_handler() -> Result<()> {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    let rows: Vec<Vec<Value>> = (1..=500i64).map(|i| vec![i.into()]).collect();
+    let mut file = super::fixtures::Fixture::new()
+        .page_size(512)
+        .table("CREATE TABLE t (id INTEGER PRIMARY KEY)", rows)
+        .open()?;
+    let calls = Arc::new(AtomicU64::new(0));
+    let seen = calls.clone();
+    file.set_progress_handler(3, move || seen.fetch_add(1, Ordering::Relaxed) >= 2);
+    let select: Select = "SELECT a.id FROM t AS a JOIN t AS b ON a.id = b.id".parse()?;
+    let e = execute(&file, &select).unwrap_err();
+    assert!(e.is::<super::interrupt::Interrupted>());
+    assert_eq!(calls.load(Ordering::Relaxed), 3);
+    file.set_progress_handler(2, || false);
+    assert_eq!(execute(&file, &select)?.len(), 500);
+    file.set_progress_handler(0, || true);
+    assert_eq!(execute(&file, &select)?.len(), 500);
+    Ok(())
+}
@@ -1,165 +1,581 @@
-use std::fmt::Display;
-
-use crate::varint::varint;
-use nom::{
-    bytes::complete::take,
-    combinator::into,
-    multi::many1,
-    number::complete::{be_f64, be_i16, be_i24, be_i32, be_i64, i8},
-    IResult,
-};
-
-/// Record from an SQLite database.
-#[derive(Debug, Clone)]
-pub enum Value {
-    /// `NULL` value
-    Null,
-    /// Integer value
-    Integer(i64),
-    /// Floating point value
-    Float(f64),
-    /// `BLOB` value (binary data)
-    Blob(Vec<u8>),
-    /// `TEXT` value (unicode text)
-    String(String),
-}
-
-impl Display for Value {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Value::Null => write!(f, "NULL"),
-            Value::Integer(n) => write!(f, "{}", *n),
-            Value::Float(n) => write!(f, "{}", *n),
-            Value::Blob(b) => write!(f, "{:?}", b),
-            Value::String(s) => write!(f, "{}", s),
-        }
-    }
-}
-
-macro_rules! impl_from_value {
-    ($($t:ty),* $(,)?) => {
-        $(
-            impl From<Value> for $t {
-                fn from(v: Value) -> $t {
-                    match v {
-                        Value::Null => Default::default(),
-                        Value::Integer(n) => n as $t,
-                        Value::Float(n) => n as $t,
-                        Value::Blob(_) => Default::default(),
-                        Value::String(s) => s.parse::<$t>().unwrap_or_default()
-                    }
-                }
-            }
-        )*
-    }
-}
-
-impl_from_value!(u8, i8, u16, i16, u32, i32, u64, i64, f32, f64, usize);
-
-#[derive(Clone, Copy)]
-enum RecordCode {
-    Null,
-    I8,
-    I16,
-    I24,
-    I32,
-    I48,
-    I64,
-    F64,
-    Zero,
-    One,
-    Blob(usize),
-    String(usize),
-}
-
-impl From<u64> for RecordCode {
-    fn from(value: u64) -> Self {
-        use RecordCode::*;
-        match value {
-            0 => Null,
-            1 => I8,
-            2 => I16,
-            3 => I24,
-            4 => I32,
-            5 => I48,
-            6 => I64,
-            7 => F64,
-            8 => Zero,
-            9 => One,
-            n if n >= 12 && n % 2 == 0 => Blob((n as usize - 12) / 2),
-            n if n >= 13 && n % 2 == 1 => String((n as usize - 13) / 2),
-            _ => unreachable!("serial type 10 and 11 are reserved."),
-        }
-    }
-}
-
-impl<'a> RecordCode {
-    fn parse(self, input: &'a [u8]) -> IResult<&'a [u8], Value> {
-        match self {
-            RecordCode::Null => Ok((input, Value::Null)),
-            RecordCode::I8 => {
-                let (input, n) = i8(input)?;
-                Ok((input, Value::Integer(n.into())))
-            }
-
-            RecordCode::I16 => {
-                let (input, n) = be_i16(input)?;
-                Ok((input, Value::Integer(n.into())))
-            }
-            RecordCode::I24 => {
-                let (input, n) = be_i24(input)?;
-                Ok((input, Value::Integer(n.into())))
-            }
-            RecordCode::I32 => {
-                let (input, n) = be_i32(input)?;
-                Ok((input, Value::Integer(n.into())))
-            }
-            RecordCode::I48 => {
-                let (input, n) = take(6 as usize)(input)?;
-                let mut x = 0u64;
-                for b in n {
-                    x = (x << 8) | (*b as u64);
-                }
-                if n[0] >= 0x80 {
-                    x |= 0xff_ff_00_00_00_00_00_00;
-                }
-                Ok((input, Value::Integer(x as i64)))
-            }
-            RecordCode::I64 => {
-                let (input, n) = be_i64(input)?;
-                Ok((input, Value::Integer(n.into())))
-            }
-            RecordCode::F64 => {
-                let (input, n) = be_f64(input)?;
-                Ok((input, Value::Float(n)))
-            }
-            RecordCode::Zero => Ok((input, Value::Integer(0))),
-            RecordCode::One => Ok((input, Value::Integer(1))),
-            RecordCode::Blob(n) => {
-                let (input, b) = take(n)(input)?;
-                Ok((input, Value::Blob(b.to_vec())))
-            }
-            RecordCode::String(n) => {
-                let (input, s) = take(n)(input)?;
-                Ok((input, Value::String(String::from_utf8_lossy(s).to_string())))
-            }
-        }
-    }
-}
-
-/// Parse a [`Cell`][crate::cells::Cell] payload into a series of [`Value`]s.
-pub fn parse_payload<'a>(input: &'a [u8]) -> IResult<&'a [u8], Vec<Value>> {
-    let (_, header_size) = varint(input)?;
-    let header = &input[..header_size as usize];
-    let (header, _) = varint(header)?;
-    let (_, codes): (_, Vec<RecordCode>) = many1(into(varint))(header)?;
-    let mut body = &input[header_size as usize..];
-    let mut records = vec![];
-    for code in codes {
-        let (input, rec) = code.parse(body)?;
-        body = input;
-        records.push(rec);
-    }
-
-    Ok((body, records))
-}
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::fmt::Display;
+
+use crate::varint::{put_varint, varint, varint_len};
+use nom::{
+    bytes::complete::take,
+    combinator::{all_consuming, map_res},
+    multi::many1,
+    number::complete::{be_f64, be_i16, be_i24, be_i32, be_i64, i8},
+    IResult,
+};
+
+/// Record from an SQLite database.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// `NULL` value
+    Null,
+    /// Integer value
+    Integer(i64),
+    /// Floating point value
+    Float(f64),
+    /// `BLOB` value (binary data)
+    Blob(Vec<u8>),
+    /// `TEXT` value (unicode text)
+    String(String),
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Null => write!(f, "NULL"),
+            Value::Integer(n) => write!(f, "{}", *n),
+            Value::Float(n) => write!(f, "{}", *n),
+            Value::Blob(b) => write!(f, "{:?}", b),
+            Value::String(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl Value {
+    /// Compare two values the way SQLite sorts them: `NULL`s first, then
+    /// numbers, then text, then blobs. Text is compared byte by byte.
+    pub fn compare(&self, other: &Value) -> Ordering {
+        self.as_value_ref().compare(&other.as_value_ref())
+    }
+
+    /// Whether the value counts as true in a `WHERE` clause.
+    pub fn is_true(&self) -> bool {
+        self.as_value_ref().is_true()
+    }
+
+    /// Borrow the value as a [`ValueRef`].
+    pub fn as_value_ref(&self) -> ValueRef<'_> {
+        match self {
+            Value::Null => ValueRef::Null,
+            Value::Integer(n) => ValueRef::Integer(*n),
+            Value::Float(n) => ValueRef::Float(*n),
+            Value::Blob(b) => ValueRef::Blob(b),
+            Value::String(s) => ValueRef::String(s.as_bytes()),
+        }
+    }
+}
+
+/// A [`Value`] that borrows its text or blob from the record it was read from,
+/// so scanning and filtering rows doesn't copy anything. Only rows that make it
+/// to the output get turned into [`Value`]s.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueRef<'a> {
+    Null,
+    Integer(i64),
+    Float(f64),
+    Blob(&'a [u8]),
+    /// `TEXT`, as stored. It isn't checked to be valid UTF-8 until it's
+    /// turned into a [`Value`].
+    String(&'a [u8]),
+}
+
+impl<'a> ValueRef<'a> {
+    /// Copy into an owned [`Value`].
+    pub fn to_value(self) -> Value {
+        match self {
+            ValueRef::Null => Value::Null,
+            ValueRef::Integer(n) => Value::Integer(n),
+            ValueRef::Float(n) => Value::Float(n),
+            ValueRef::Blob(b) => Value::Blob(b.to_vec()),
+            ValueRef::String(s) => Value::String(String::from_utf8_lossy(s).into_owned()),
+        }
+    }
+
+    /// Same as [`Value::compare`].
+    pub fn compare(&self, other: &ValueRef) -> Ordering {
+        use ValueRef::*;
+        fn rank(v: &ValueRef) -> u8 {
+            match v {
+                Null => 0,
+                Integer(_) | Float(_) => 1,
+                String(_) => 2,
+                Blob(_) => 3,
+            }
+        }
+        match (self, other) {
+            (Integer(a), Integer(b)) => a.cmp(b),
+            (Integer(a), Float(b)) => compare_int_float(*a, *b),
+            (Float(a), Integer(b)) => compare_int_float(*b, *a).reverse(),
+            (Float(a), Float(b)) => a.total_cmp(b),
+            (String(a), String(b)) => a.cmp(b),
+            (Blob(a), Blob(b)) => a.cmp(b),
+            (a, b) => rank(a).cmp(&rank(b)),
+        }
+    }
+
+    /// The value as text, the way SQLite converts it for `||` and string
+    /// functions. `NULL` is empty.
+    pub fn to_text(self) -> Cow<'a, str> {
+        match self {
+            ValueRef::Null => Cow::Borrowed(""),
+            ValueRef::String(s) | ValueRef::Blob(s) => String::from_utf8_lossy(s),
+            v => Cow::Owned(v.to_value().to_string()),
+        }
+    }
+
+    /// True, false, or `None` for `NULL`, which is unknown in SQL's three
+    /// valued logic.
+    pub fn truth(&self) -> Option<bool> {
+        (*self != ValueRef::Null).then(|| self.is_true())
+    }
+
+    /// Same as [`Value::is_true`].
+    pub fn is_true(&self) -> bool {
+        match self {
+            ValueRef::Null | ValueRef::Blob(_) => false,
+            ValueRef::Integer(n) => *n != 0,
+            ValueRef::Float(n) => *n != 0.0,
+            ValueRef::String(s) => std::str::from_utf8(s)
+                .ok()
+                .and_then(|s| s.trim().parse::<f64>().ok())
+                .is_some_and(|n| n != 0.0),
+        }
+    }
+}
+
+/// Compare exactly, without rounding the integer to a double, which would make
+/// big integers equal to their neighbours.
+fn compare_int_float(i: i64, f: f64) -> Ordering {
+    // 2^63 doesn't fit in an i64, so anything from there up is bigger
+    if f >= 9223372036854775808.0 {
+        return Ordering::Less;
+    }
+    if f < -9223372036854775808.0 {
+        return Ordering::Greater;
+    }
+    if f.is_nan() {
+        return Ordering::Greater;
+    }
+    let t = f.trunc();
+    match i.cmp(&(t as i64)) {
+        Ordering::Equal => t.total_cmp(&f),
+        ord => ord,
+    }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Self {
+        Value::Integer(n)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(n: i32) -> Self {
+        Value::Integer(n.into())
+    }
+}
+
+impl From<f64> for Value {
+    fn from(x: f64) -> Self {
+        Value::Float(x)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.to_owned())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(s)
+    }
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(b: Vec<u8>) -> Self {
+        Value::Blob(b)
+    }
+}
+
+/// `None` is `NULL`.
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(v: Option<T>) -> Self {
+        v.map_or(Value::Null, Into::into)
+    }
+}
+
+impl From<ValueRef<'_>> for Value {
+    fn from(value: ValueRef<'_>) -> Self {
+        value.to_value()
+    }
+}
+
+/// A row of a table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    pub rowid: i64,
+    pub values: Vec<Value>,
+}
+
+impl Row {
+    /// Borrow as a [`RowRef`].
+    pub fn as_row_ref(&self) -> RowRef<'_> {
+        RowRef {
+            rowid: self.rowid,
+            values: self.values.iter().map(Value::as_value_ref).collect(),
+        }
+    }
+
+    /// Whether column `col` of the table was in the row's record. Rows
+    /// written before a column was added with `ALTER TABLE` stop short, and
+    /// the column reads as its default instead.
+    pub fn provenance(&self, col: usize) -> Provenance {
+        if col < self.values.len() {
+            Provenance::Stored
+        } else {
+            Provenance::Default
+        }
+    }
+}
+
+/// Where a column's value in a [`Row`] comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Provenance {
+    /// In the record, even if it's `NULL`.
+    Stored,
+    /// Missing from the record, so it's the column's `DEFAULT`, or `NULL`.
+    Default,
+}
+
+/// A row of a table, borrowing from the page it's on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowRef<'a> {
+    pub rowid: i64,
+    pub values: Vec<ValueRef<'a>>,
+}
+
+impl RowRef<'_> {
+    /// Copy into an owned [`Row`].
+    pub fn to_row(&self) -> Row {
+        Row {
+            rowid: self.rowid,
+            values: self.values.iter().map(|v| v.to_value()).collect(),
+        }
+    }
+}
+
+macro_rules! impl_from_value {
+    ($($t:ty),* $(,)?) => {
+        $(
+            impl From<Value> for $t {
+                fn from(v: Value) -> $t {
+                    match v {
+                        Value::Null => Default::default(),
+                        Value::Integer(n) => n as $t,
+                        Value::Float(n) => n as $t,
+                        Value::Blob(_) => Default::default(),
+                        Value::String(s) => s.parse::<$t>().unwrap_or_default()
+                    }
+                }
+            }
+        )*
+    }
+}
+
+impl_from_value!(u8, i8, u16, i16, u32, i32, u64, i64, f32, f64, usize);
+
+#[derive(Clone, Copy)]
+enum RecordCode {
+    Null,
+    I8,
+    I16,
+    I24,
+    I32,
+    I48,
+    I64,
+    F64,
+    Zero,
+    One,
+    Blob(usize),
+    String(usize),
+}
+
+/// Fails with the serial type if it's one of the reserved ones, 10 and 11.
+impl TryFrom<u64> for RecordCode {
+    type Error = u64;
+
+    fn try_from(value: u64) -> Result<Self, Self::Error> {
+        use RecordCode::*;
+        Ok(match value {
+            0 => Null,
+            1 => I8,
+            2 => I16,
+            3 => I24,
+            4 => I32,
+            5 => I48,
+            6 => I64,
+            7 => F64,
+            8 => Zero,
+            9 => One,
+            n if n >= 12 && n % 2 == 0 => Blob((n as usize - 12) / 2),
+            n if n >= 13 && n % 2 == 1 => String((n as usize - 13) / 2),
+            n => return Err(n),
+        })
+    }
+}
+
+impl<'a> RecordCode {
+    /// Bytes the value takes up in the record body.
+    fn size(self) -> usize {
+        match self {
+            RecordCode::Null | RecordCode::Zero | RecordCode::One => 0,
+            RecordCode::I8 => 1,
+            RecordCode::I16 => 2,
+            RecordCode::I24 => 3,
+            RecordCode::I32 => 4,
+            RecordCode::I48 => 6,
+            RecordCode::I64 | RecordCode::F64 => 8,
+            RecordCode::Blob(n) | RecordCode::String(n) => n,
+        }
+    }
+
+    fn parse(self, input: &'a [u8]) -> IResult<&'a [u8], ValueRef<'a>> {
+        match self {
+            RecordCode::Null => Ok((input, ValueRef::Null)),
+            RecordCode::I8 => {
+                let (input, n) = i8(input)?;
+                Ok((input, ValueRef::Integer(n.into())))
+            }
+
+            RecordCode::I16 => {
+                let (input, n) = be_i16(input)?;
+                Ok((input, ValueRef::Integer(n.into())))
+            }
+            RecordCode::I24 => {
+                let (input, n) = be_i24(input)?;
+                Ok((input, ValueRef::Integer(n.into())))
+            }
+            RecordCode::I32 => {
+                let (input, n) = be_i32(input)?;
+                Ok((input, ValueRef::Integer(n.into())))
+            }
+            RecordCode::I48 => {
+                let (input, n) = take(6_usize)(input)?;
+                let mut x = 0u64;
+                for b in n {
+                    x = (x << 8) | (*b as u64);
+                }
+                if n[0] >= 0x80 {
+                    x |= 0xff_ff_00_00_00_00_00_00;
+                }
+                Ok((input, ValueRef::Integer(x as i64)))
+            }
+            RecordCode::I64 => {
+                let (input, n) = be_i64(input)?;
+                Ok((input, ValueRef::Integer(n)))
+            }
+            RecordCode::F64 => {
+                let (input, n) = be_f64(input)?;
+                Ok((input, ValueRef::Float(n)))
+            }
+            RecordCode::Zero => Ok((input, ValueRef::Integer(0))),
+            RecordCode::One => Ok((input, ValueRef::Integer(1))),
+            RecordCode::Blob(n) => {
+                let (input, b) = take(n)(input)?;
+                Ok((input, ValueRef::Blob(b)))
+            }
+            RecordCode::String(n) => {
+                let (input, s) = take(n)(input)?;
+                Ok((input, ValueRef::String(s)))
+            }
+        }
+    }
+}
+
+/// The serial types in a record header, and the size of the header.
+fn record_header(input: &[u8]) -> IResult<&[u8], (usize, Vec<RecordCode>)> {
+    let (_, header_size) = varint(input)?;
+    let header_size = header_size as usize;
+    if header_size > input.len() {
+        return Err(nom::Err::Incomplete(nom::Needed::new(
+            header_size - input.len(),
+        )));
+    }
+    let header = &input[..header_size];
+    let (header, _) = varint(header)?;
+    // a reserved serial type stops many1 early, so it has to be the end
+    let (_, codes) = all_consuming(many1(map_res(varint, RecordCode::try_from)))(header)?;
+    Ok((&input[header_size..], (header_size, codes)))
+}
+
+/// How many bytes of a record are needed to decode the given columns, or
+/// `None` if the header itself isn't all in `input`.
+pub fn record_prefix_len(input: &[u8], columns: &[usize]) -> Option<usize> {
+    let (_, (header_size, codes)) = record_header(input).ok()?;
+    let last = columns
+        .iter()
+        .max()
+        .map_or(0, |&c| (c + 1).min(codes.len()));
+    Some(header_size + codes[..last].iter().map(|c| c.size()).sum::<usize>())
+}
+
+/// Parse only some columns of a record. The other columns come back as
+/// `NULL` without being decoded, so the result still has one value per column
+/// and can be indexed the same way.
+///
+/// Only the part of the record up to the last wanted column has to be there.
+pub fn parse_columns<'a>(
+    input: &'a [u8],
+    columns: &[usize],
+) -> IResult<&'a [u8], Vec<ValueRef<'a>>> {
+    let (mut body, (_, codes)) = record_header(input)?;
+    let last = columns.iter().max().map_or(0, |&c| c + 1);
+    let mut records = vec![ValueRef::Null; codes.len()];
+    for (i, code) in codes.into_iter().enumerate().take(last) {
+        if columns.contains(&i) {
+            let (rest, rec) = code.parse(body)?;
+            body = rest;
+            records[i] = rec;
+        } else {
+            let (rest, _) = take(code.size())(body)?;
+            body = rest;
+        }
+    }
+    Ok((body, records))
+}
+
+/// Parse a [`Cell`][crate::cells::Cell] payload into a series of [`Value`]s.
+pub fn parse_payload(input: &[u8]) -> IResult<&[u8], Vec<Value>> {
+    let (body, records) = parse_payload_ref(input)?;
+    Ok((body, records.into_iter().map(ValueRef::to_value).collect()))
+}
+
+/// Like [`parse_payload`], but borrowing text and blobs from `input`.
+pub fn parse_payload_ref(input: &[u8]) -> IResult<&[u8], Vec<ValueRef<'_>>> {
+    let (mut body, (_, codes)) = record_header(input)?;
+    let mut records = vec![];
+    for code in codes {
+        let (input, rec) = code.parse(body)?;
+        body = input;
+        records.push(rec);
+    }
+
+    Ok((body, records))
+}
+
+/// Encode values as a record, the way [`parse_payload`] reads them back.
+/// Integers take the fewest bytes they fit in.
+pub fn encode_record(values: &[Value]) -> Vec<u8> {
+    let mut types = vec![];
+    let mut body = vec![];
+    for value in values {
+        let serial_type = match value {
+            Value::Null => 0,
+            Value::Integer(0) => 8,
+            Value::Integer(1) => 9,
+            &Value::Integer(n) => {
+                let (serial_type, size) = match n {
+                    -0x80..=0x7f => (1, 1),
+                    -0x8000..=0x7fff => (2, 2),
+                    -0x80_0000..=0x7f_ffff => (3, 3),
+                    -0x8000_0000..=0x7fff_ffff => (4, 4),
+                    -0x8000_0000_0000..=0x7fff_ffff_ffff => (5, 6),
+                    _ => (6, 8),
+                };
+                body.extend_from_slice(&n.to_be_bytes()[8 - size..]);
+                serial_type
+            }
+            Value::Float(x) => {
+                body.extend_from_slice(&x.to_bits().to_be_bytes());
+                7
+            }
+            Value::String(s) => {
+                body.extend_from_slice(s.as_bytes());
+                s.len() as u64 * 2 + 13
+            }
+            Value::Blob(b) => {
+                body.extend_from_slice(b);
+                b.len() as u64 * 2 + 12
+            }
+        };
+        put_varint(&mut types, serial_type);
+    }
+    // the header size counts itself, which can make it a byte longer
+    let mut header_size = types.len() + 1;
+    while varint_len(header_size as u64) + types.len() != header_size {
+        header_size = varint_len(header_size as u64) + types.len();
+    }
+    let mut record = Vec::with_capacity(header_size + body.len());
+    put_varint(&mut record, header_size as u64);
+    record.extend(types);
+    record.extend(body);
+    record
+}
+
+#[test]
+fn encode_and_parse_records() {
+    let values = vec![
+        Value::Null,
+        Value::Integer(0),
+        Value::Integer(1),
+        Value::Integer(-200),
+        Value::Integer(1 << 40),
+        Value::Integer(i64::MIN),
+        Value::Float(1.5),
+        Value::String("x".repeat(100)),
+        Value::Blob(vec![1, 2, 3]),
+    ];
+    let record = encode_record(&values);
+    assert_eq!(parse_payload(&record).unwrap(), (&[][..], values));
+}
+
+#[test]
+fn compare_big_integers_with_reals() {
+    let max = Value::Integer(i64::MAX);
+    assert_eq!(
+        max.compare(&Value::Float(9223372036854775807.0)),
+        Ordering::Less
+    );
+    assert_eq!(
+        Value::Integer(3).compare(&Value::Float(3.5)),
+        Ordering::Less
+    );
+    assert_eq!(
+        Value::Float(-3.5).compare(&Value::Integer(-3)),
+        Ordering::Less
+    );
+    assert_eq!(
+        Value::Integer(2).compare(&Value::Float(2.0)),
+        Ordering::Equal
+    );
+}
+
+#[test]
+fn parse_some_columns() {
+    // header: size 4, int8, text of 3, int16; body: 7, "abc", 258
+    let record = [4, 1, 19, 2, 7, b'a', b'b', b'c', 1, 2];
+    let (_, all) = parse_payload(&record).unwrap();
+    assert_eq!(
+        all,
+        vec![
+            Value::Integer(7),
+            Value::String("abc".into()),
+            Value::Integer(258)
+        ]
+    );
+    let (_, some) = parse_columns(&record, &[1, 2]).unwrap();
+    assert_eq!(
+        some,
+        vec![
+            ValueRef::Null,
+            ValueRef::String(b"abc"),
+            ValueRef::Integer(258)
+        ]
+    );
+    // serial types 10 and 11 are reserved
+    assert!(parse_payload(&[3, 1, 10, 7]).is_err());
+    assert!(parse_payload(&[3, 11, 1, 7]).is_err());
+    // the first column can be read without the rest of the body
+    assert_eq!(record_prefix_len(&record, &[0]), Some(5));
+    let (_, first) = parse_columns(&record[..5], &[0]).unwrap();
+    assert_eq!(first[0], ValueRef::Integer(7));
+}
@@ -0,0 +1,185 @@
+//! Compact sorted sets of rowids.
+
+use std::collections::BTreeMap;
+
+/// Chunks with more entries than this are stored as a bitmap.
+const ARRAY_MAX: usize = 4096;
+
+/// The low 16 bits of the rowids in one chunk.
+#[derive(Clone, Debug, PartialEq)]
+enum Container {
+    /// Sorted list, for sparse chunks.
+    Array(Vec<u16>),
+    /// One bit per possible value, for dense chunks.
+    Bitmap(Box<[u64; 1024]>),
+}
+
+impl Container {
+    fn insert(&mut self, low: u16) -> bool {
+        match self {
+            Container::Array(v) => match v.binary_search(&low) {
+                Ok(_) => false,
+                Err(i) => {
+                    v.insert(i, low);
+                    if v.len() > ARRAY_MAX {
+                        let mut bits = Box::new([0u64; 1024]);
+                        for &x in v.iter() {
+                            bits[x as usize / 64] |= 1 << (x % 64);
+                        }
+                        *self = Container::Bitmap(bits);
+                    }
+                    true
+                }
+            },
+            Container::Bitmap(bits) => {
+                let (word, bit) = (low as usize / 64, 1u64 << (low % 64));
+                let new = bits[word] & bit == 0;
+                bits[word] |= bit;
+                new
+            }
+        }
+    }
+
+    fn contains(&self, low: u16) -> bool {
+        match self {
+            Container::Array(v) => v.binary_search(&low).is_ok(),
+            Container::Bitmap(bits) => bits[low as usize / 64] & (1 << (low % 64)) != 0,
+        }
+    }
+
+    /// Smallest value `>= low`.
+    fn first_at_or_after(&self, low: u16) -> Option<u16> {
+        match self {
+            Container::Array(v) => {
+                let i = v.partition_point(|&x| x < low);
+                v.get(i).copied()
+            }
+            Container::Bitmap(_) => (low..=u16::MAX).find(|&x| self.contains(x)),
+        }
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = u16> + '_> {
+        match self {
+            Container::Array(v) => Box::new(v.iter().copied()),
+            Container::Bitmap(bits) => Box::new(bits.iter().enumerate().flat_map(|(i, &word)| {
+                (0..64)
+                    .filter(move |b| word & (1 << b) != 0)
+                    .map(move |b| (i * 64 + b) as u16)
+            })),
+        }
+    }
+}
+
+/// A sorted set of rowids, split into chunks of 65536 possible values that are
+/// stored either as a sorted list or as a bitmap depending on how full they are,
+/// like a Roaring bitmap.
+///
+/// Index lookups collect matching rowids into one of these, so the table rows
+/// can be fetched in rowid order, which is mostly the order they're stored in.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RowidSet {
+    chunks: BTreeMap<i64, Container>,
+    len: usize,
+}
+
+fn split(rowid: i64) -> (i64, u16) {
+    (rowid >> 16, (rowid & 0xffff) as u16)
+}
+
+fn join(high: i64, low: u16) -> i64 {
+    (high << 16) | low as i64
+}
+
+impl RowidSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a rowid. Returns `false` if it was already there.
+    pub fn insert(&mut self, rowid: i64) -> bool {
+        let (high, low) = split(rowid);
+        let added = self
+            .chunks
+            .entry(high)
+            .or_insert_with(|| Container::Array(vec![]))
+            .insert(low);
+        if added {
+            self.len += 1;
+        }
+        added
+    }
+
+    pub fn contains(&self, rowid: i64) -> bool {
+        let (high, low) = split(rowid);
+        self.chunks.get(&high).is_some_and(|c| c.contains(low))
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Smallest rowid in the set that's `>= rowid`.
+    pub fn first_at_or_after(&self, rowid: i64) -> Option<i64> {
+        let (high, low) = split(rowid);
+        for (&h, container) in self.chunks.range(high..) {
+            let from = if h == high { low } else { 0 };
+            if let Some(l) = container.first_at_or_after(from) {
+                return Some(join(h, l));
+            }
+        }
+        None
+    }
+
+    /// Whether any rowid in the set is in `lo..=hi`.
+    pub fn any_in(&self, lo: i64, hi: i64) -> bool {
+        self.first_at_or_after(lo).is_some_and(|r| r <= hi)
+    }
+
+    /// All rowids in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = i64> + '_ {
+        self.chunks
+            .iter()
+            .flat_map(|(&high, c)| c.iter().map(move |low| join(high, low)))
+    }
+}
+
+impl FromIterator<i64> for RowidSet {
+    fn from_iter<T: IntoIterator<Item = i64>>(iter: T) -> Self {
+        let mut set = RowidSet::new();
+        set.extend(iter);
+        set
+    }
+}
+
+impl Extend<i64> for RowidSet {
+    fn extend<T: IntoIterator<Item = i64>>(&mut self, iter: T) {
+        for rowid in iter {
+            self.insert(rowid);
+        }
+    }
+}
+
+#[test]
+fn rowid_set_sorted_and_dense() {
+    let mut set: RowidSet = [70000, 5, -3, 5, 65536].into_iter().collect();
+    assert_eq!(set.len(), 4);
+    assert_eq!(set.iter().collect::<Vec<_>>(), vec![-3, 5, 65536, 70000]);
+    assert_eq!(set.first_at_or_after(6), Some(65536));
+    assert!(set.any_in(-10, 0));
+    assert!(!set.any_in(6, 65535));
+
+    // enough to turn the first chunk into a bitmap
+    for rowid in 0..10000 {
+        set.insert(rowid * 2);
+    }
+    assert_eq!(set.len(), 10000 + 4);
+    assert!(set.contains(19998));
+    assert!(!set.contains(19999));
+    assert_eq!(set.first_at_or_after(7), Some(8));
+    let all: Vec<i64> = set.iter().collect();
+    assert!(all.windows(2).all(|w| w[0] < w[1]));
+}
@@ -0,0 +1,97 @@
+//! Simple full-text search over ordinary text columns, for databases that
+//! don't have an FTS index. Every row gets read, so it's only as fast as a
+//! full scan, but it finds words the way FTS would: split into tokens and
+//! folded with [`Unicode61`].
+
+use anyhow::Result;
+
+use super::query;
+use super::record::Value;
+use super::sql::Expr;
+use super::unicode61::Unicode61;
+use super::{Select, SelectColumns, SqliteFile};
+
+/// A row that matched a search.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hit {
+    pub rowid: i64,
+    /// Higher is a better match.
+    pub score: f64,
+    pub text: String,
+}
+
+fn tokenize(text: &str) -> Vec<String> {
+    Unicode61::new().tokenize(text).map(|t| t.text).collect()
+}
+
+/// Whether every term of `query` is in `text`. A term matches any token it's
+/// part of, so `data` finds `database`.
+pub fn match_all(text: &str, terms: &str) -> bool {
+    let tokens = tokenize(text);
+    tokenize(terms)
+        .iter()
+        .all(|term| tokens.iter().any(|t| t.contains(term.as_str())))
+}
+
+/// How well `text` matches the terms. Whole words count more than parts of
+/// words, and matches in short text count more than in long text.
+pub fn score(text: &str, terms: &str) -> f64 {
+    let tokens = tokenize(text);
+    if tokens.is_empty() {
+        return 0.0;
+    }
+    let mut score = 0.0;
+    for term in tokenize(terms) {
+        for token in &tokens {
+            if *token == term {
+                score += 1.0;
+            } else if token.contains(&term) {
+                score += 0.5;
+            }
+        }
+    }
+    score / (tokens.len() as f64).sqrt()
+}
+
+/// Search one column of a table for rows containing all of `terms`, best
+/// matches first.
+pub fn search(file: &SqliteFile, table: &str, column: &str, terms: &str) -> Result<Vec<Hit>> {
+    let select = Select {
+        name: table.to_owned(),
+        alias: None,
+        columns: SelectColumns::Columns(vec![
+            Expr::Column("rowid".to_owned()).into(),
+            Expr::Column(column.to_owned()).into(),
+        ]),
+        joins: vec![],
+        filter: Some(Expr::Function {
+            name: "match_all".to_owned(),
+            args: vec![
+                Expr::Column(column.to_owned()),
+                Expr::Literal(Value::String(terms.to_owned())),
+            ],
+        }),
+        order_by: vec![],
+    };
+    let mut hits: Vec<Hit> = query::execute(file, &select)?
+        .into_iter()
+        .map(|row| {
+            let text = row[1].to_string();
+            Hit {
+                rowid: i64::from(row[0].clone()),
+                score: score(&text, terms),
+                text,
+            }
+        })
+        .collect();
+    hits.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.rowid.cmp(&b.rowid)));
+    Ok(hits)
+}
+
+#[test]
+fn search_ranks_whole_words_first() {
+    assert!(match_all("The Café database", "cafe DATA"));
+    assert!(!match_all("The Café database", "cafe tables"));
+    assert!(score("a database", "database") > score("a database", "data"));
+    assert!(score("database", "database") > score("a big old database", "database"));
+}
@@ -0,0 +1,1969 @@
+//! Tokenizer and parser for the bits of SQL we understand.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Result};
+
+use super::cast::Affinity;
+use super::collation::Collation;
+use super::limits::Limits;
+use super::record::Value;
+use super::{Join, OrderingTerm, ResultColumn, Select, SelectColumns};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    /// Identifier or keyword. Keywords are matched case-insensitively.
+    Ident(String),
+    /// `"name"`, `[name]` or `` `name` ``: an identifier that can be a keyword
+    /// or have spaces in it.
+    Quoted(String),
+    /// `'string'` literal.
+    String(String),
+    Integer(i64),
+    /// `9223372036854775808`, which is only an integer with a minus sign in
+    /// front. Anywhere else it's too big for one, and a real.
+    MinIntegerDigits,
+    Float(f64),
+    /// Operators and punctuation.
+    Symbol(&'static str),
+    /// `?` or `?NNN`, a parameter to bind a value to.
+    Param(Option<usize>),
+    /// `$name`, `:name` or `@name`, a variable to bind a value to by name.
+    Variable(String),
+}
+
+impl Token {
+    fn is_keyword(&self, kw: &str) -> bool {
+        matches!(self, Token::Ident(s) if s.eq_ignore_ascii_case(kw))
+    }
+}
+
+// longest first, so `<=` wins over `<`
+const SYMBOLS: &[&str] = &[
+    "<=", ">=", "==", "!=", "<>", "||", "(", ")", ",", "*", "=", "<", ">", ";", ".", "+", "-", "/",
+    "%",
+];
+
+/// Keywords that can follow a table name, so they aren't taken for an alias.
+const KEYWORDS: &[&str] = &["WHERE", "JOIN", "INNER", "ON", "ORDER", "GROUP", "LIMIT"];
+
+/// SQL with its `-- line` and `/* block */` comments turned into spaces.
+/// Quoted strings and names are left alone, even if they have `--` in them.
+/// Like SQLite, a block comment that isn't closed runs to the end.
+pub fn strip_comments(sql: &str) -> String {
+    let mut out = String::with_capacity(sql.len());
+    let mut quote = None;
+    let mut rest = sql;
+    while let Some(c) = rest.chars().next() {
+        match quote {
+            Some(q) => {
+                if c == q {
+                    quote = None;
+                }
+            }
+            None if rest.starts_with("--") => {
+                rest = rest.find('\n').map_or("", |i| &rest[i..]);
+                out.push(' ');
+                continue;
+            }
+            None if rest.starts_with("/*") => {
+                rest = rest[2..].find("*/").map_or("", |i| &rest[i + 4..]);
+                out.push(' ');
+                continue;
+            }
+            None => match c {
+                '\'' | '"' | '`' => quote = Some(c),
+                '[' => quote = Some(']'),
+                _ => {}
+            },
+        }
+        out.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    out
+}
+
+/// Split SQL text into statements at each `;` that isn't quoted or in a
+/// comment. Comments are taken out and empty statements are left out.
+pub fn split_statements(sql: &str) -> Vec<String> {
+    let sql = strip_comments(sql);
+    let mut statements = vec![];
+    let mut quote = None;
+    let mut start = 0;
+    for (i, c) in sql.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"' | '`') => quote = Some(c),
+            (None, '[') => quote = Some(']'),
+            (None, ';') => {
+                statements.push(sql[start..i].trim().to_owned());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    statements.push(sql[start..].trim().to_owned());
+    statements.retain(|s| !s.is_empty());
+    statements
+}
+
+/// Parse each statement in SQL text on its own, so a syntax error in one
+/// doesn't stop the rest from being parsed. Each `;` that isn't quoted or in
+/// a comment is a fresh start. Gives the text of each statement with what
+/// came of parsing it.
+pub fn parse_statements(sql: &str, limits: &Limits) -> Vec<(String, Result<Statement>)> {
+    split_statements(sql)
+        .into_iter()
+        .map(|text| {
+            let stmt = Statement::parse_with(&text, limits);
+            (text, stmt)
+        })
+        .collect()
+}
+
+/// Split SQL text into tokens. Comments are skipped.
+pub fn tokenize(sql: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = sql.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if sql[start..].starts_with("--") {
+            while chars.next_if(|&(_, c)| c != '\n').is_some() {}
+        } else if sql[start..].starts_with("/*") {
+            let end = sql[start + 2..]
+                .find("*/")
+                .map_or(sql.len(), |i| start + i + 4);
+            while chars.next_if(|&(i, _)| i < end).is_some() {}
+        } else if c.is_alphabetic() || c == '_' {
+            let mut end = start;
+            while let Some(&(i, c)) = chars.peek() {
+                if !(c.is_alphanumeric() || c == '_' || c == '$') {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            tokens.push(Token::Ident(sql[start..end].to_owned()));
+        } else if c.is_ascii_digit()
+            || (c == '.' && sql[start + 1..].starts_with(|c: char| c.is_ascii_digit()))
+        {
+            let (token, len) = number(&sql[start..])?;
+            while chars.next_if(|&(i, _)| i < start + len).is_some() {}
+            tokens.push(token);
+        } else if c == '?' {
+            chars.next();
+            let mut digits = String::new();
+            while let Some(&(_, c)) = chars.peek().filter(|(_, c)| c.is_ascii_digit()) {
+                digits.push(c);
+                chars.next();
+            }
+            let number = match digits.as_str() {
+                "" => None,
+                n => match n.parse() {
+                    Ok(n) if n > 0 => Some(n),
+                    _ => bail!("bad parameter number ?{}", n),
+                },
+            };
+            tokens.push(Token::Param(number));
+        } else if matches!(c, '$' | ':' | '@') {
+            chars.next();
+            let mut name = String::new();
+            while let Some(&(_, c)) = chars
+                .peek()
+                .filter(|(_, c)| c.is_alphanumeric() || *c == '_')
+            {
+                name.push(c);
+                chars.next();
+            }
+            if name.is_empty() {
+                bail!("expected a variable name after '{}'", c);
+            }
+            tokens.push(Token::Variable(name));
+        } else if let Some(close) = match c {
+            '"' | '`' => Some(c),
+            '[' => Some(']'),
+            _ => None,
+        } {
+            chars.next();
+            let mut s = String::new();
+            loop {
+                match chars.next() {
+                    // a doubled quote is part of the name, except in [name]
+                    Some((_, c)) if c == close => match chars.peek() {
+                        Some(&(_, c)) if c == close && close != ']' => {
+                            s.push(c);
+                            chars.next();
+                        }
+                        _ => break,
+                    },
+                    Some((_, c)) => s.push(c),
+                    None => bail!("unterminated identifier {}{}", c, s),
+                }
+            }
+            tokens.push(Token::Quoted(s));
+        } else if c == '\'' {
+            chars.next();
+            let mut s = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '\'')) => {
+                        // '' is an escaped quote
+                        if let Some((_, '\'')) = chars.peek() {
+                            s.push('\'');
+                            chars.next();
+                        } else {
+                            break;
+                        }
+                    }
+                    Some((_, c)) => s.push(c),
+                    None => bail!("unterminated string literal"),
+                }
+            }
+            tokens.push(Token::String(s));
+        } else {
+            let rest = &sql[start..];
+            let sym = SYMBOLS
+                .iter()
+                .find(|s| rest.starts_with(*s))
+                .ok_or_else(|| anyhow!("unexpected character {:?} in SQL", c))?;
+            for _ in 0..sym.len() {
+                chars.next();
+            }
+            tokens.push(Token::Symbol(sym));
+        }
+    }
+    Ok(tokens)
+}
+
+/// The number `sql` starts with, and how long it is. That's digits with a
+/// point and an exponent if they're there, or `0x` and up to 64 bits of hex.
+fn number(sql: &str) -> Result<(Token, usize)> {
+    let b = sql.as_bytes();
+    let hex = b.len() > 2 && sql[..2].eq_ignore_ascii_case("0x") && b[2].is_ascii_hexdigit();
+    let (token, end) = if hex {
+        let end = 2 + b[2..].iter().take_while(|c| c.is_ascii_hexdigit()).count();
+        // the bits of an i64, so 0xffffffffffffffff is -1
+        let n = u64::from_str_radix(&sql[2..end], 16)
+            .map_err(|_| anyhow!("hex literal too big: {}", &sql[..end]))?;
+        (Token::Integer(n as i64), end)
+    } else {
+        decimal(sql)?
+    };
+    // `1e` or `12abc` isn't a number followed by a name
+    if let Some(c) = sql[end..]
+        .chars()
+        .next()
+        .filter(|&c| c.is_alphanumeric() || c == '_' || c == '$')
+    {
+        bail!("unrecognized token: {}{}", &sql[..end], c);
+    }
+    Ok((token, end))
+}
+
+/// A number like `12`, `1.5` or `1e-3` at the start of `sql`, and its length.
+fn decimal(sql: &str) -> Result<(Token, usize)> {
+    let b = sql.as_bytes();
+    let digits = |from: usize| from + b[from..].iter().take_while(|c| c.is_ascii_digit()).count();
+    let mut end = digits(0);
+    let mut float = false;
+    if b.get(end) == Some(&b'.') {
+        float = true;
+        end = digits(end + 1);
+    }
+    if matches!(b.get(end), Some(b'e' | b'E')) {
+        let sign = usize::from(matches!(b.get(end + 1), Some(b'+' | b'-')));
+        let exp_end = digits(end + 1 + sign);
+        if exp_end > end + 1 + sign {
+            float = true;
+            end = exp_end;
+        }
+    }
+    let text = &sql[..end];
+    if float {
+        return Ok((Token::Float(text.parse()?), end));
+    }
+    let token = match text.parse() {
+        Ok(n) => Token::Integer(n),
+        Err(_) if text.trim_start_matches('0') == "9223372036854775808" => Token::MinIntegerDigits,
+        // too big for an integer
+        Err(_) => Token::Float(text.parse()?),
+    };
+    Ok((token, end))
+}
+
+/// Binary operators, from lowest to highest precedence.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    /// `||`
+    Concat,
+}
+
+impl BinaryOp {
+    /// How tightly the operator binds. Higher goes first.
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::Eq
+            | BinaryOp::Ne
+            | BinaryOp::Lt
+            | BinaryOp::Le
+            | BinaryOp::Gt
+            | BinaryOp::Ge => 3,
+            BinaryOp::Add | BinaryOp::Sub => 4,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => 5,
+            BinaryOp::Concat => 6,
+        }
+    }
+
+    /// `=`, `<` and the others that compare their two sides.
+    pub fn is_comparison(self) -> bool {
+        matches!(
+            self,
+            BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge
+        )
+    }
+
+    /// The same operator with the two sides swapped, so `a < b` is `b > a`.
+    /// `None` if swapping the sides changes the answer, like for `-`.
+    pub fn flip(self) -> Option<Self> {
+        Some(match self {
+            BinaryOp::Lt => BinaryOp::Gt,
+            BinaryOp::Le => BinaryOp::Ge,
+            BinaryOp::Gt => BinaryOp::Lt,
+            BinaryOp::Ge => BinaryOp::Le,
+            BinaryOp::Sub | BinaryOp::Div | BinaryOp::Rem | BinaryOp::Concat => return None,
+            op => op,
+        })
+    }
+}
+
+/// An expression, like a `WHERE` clause.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Column(String),
+    Literal(Value),
+    Binary {
+        op: BinaryOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    /// `expr [NOT] IN (list...)`
+    In {
+        expr: Box<Expr>,
+        list: Vec<Expr>,
+        negated: bool,
+    },
+    /// `NOT expr`
+    Not(Box<Expr>),
+    /// `-expr`
+    Neg(Box<Expr>),
+    /// `expr IS [NOT] NULL`
+    IsNull {
+        expr: Box<Expr>,
+        negated: bool,
+    },
+    /// `?` or `?NNN`, numbered from 1. Has to be bound to a value before the
+    /// statement runs.
+    Param(usize),
+    /// `$name`, bound to a value by name.
+    Variable(String),
+    /// `CAST(expr AS type)`
+    Cast {
+        expr: Box<Expr>,
+        to: Affinity,
+    },
+    /// `expr COLLATE name`: the value is the same, but comparisons with it
+    /// use the collation.
+    Collate {
+        expr: Box<Expr>,
+        collation: Collation,
+    },
+    /// `name(args...)`
+    Function {
+        name: String,
+        args: Vec<Expr>,
+    },
+    /// Not written in SQL, but added by [`Expr::with_affinities`]: `expr`
+    /// converted like a column with the affinity would store it, so it can
+    /// be compared with one.
+    Affinity {
+        expr: Box<Expr>,
+        affinity: Affinity,
+    },
+}
+
+impl Expr {
+    pub fn binary(op: BinaryOp, left: Expr, right: Expr) -> Self {
+        Expr::Binary {
+            op,
+            left: Box::new(left),
+            right: Box::new(right),
+        }
+    }
+
+    /// Split a chain of `AND`s into its terms.
+    pub fn conjuncts(&self) -> Vec<&Expr> {
+        match self {
+            Expr::Binary {
+                op: BinaryOp::And,
+                left,
+                right,
+            } => {
+                let mut terms = left.conjuncts();
+                terms.extend(right.conjuncts());
+                terms
+            }
+            e => vec![e],
+        }
+    }
+
+    /// Names of all the columns the expression refers to.
+    pub fn columns(&self) -> Vec<&str> {
+        match self {
+            Expr::Column(name) => vec![name],
+            Expr::Literal(_) | Expr::Param(_) | Expr::Variable(_) => vec![],
+            Expr::Binary { left, right, .. } => {
+                let mut names = left.columns();
+                names.extend(right.columns());
+                names
+            }
+            Expr::In { expr, list, .. } => {
+                let mut names = expr.columns();
+                names.extend(list.iter().flat_map(Expr::columns));
+                names
+            }
+            Expr::Not(expr)
+            | Expr::Neg(expr)
+            | Expr::IsNull { expr, .. }
+            | Expr::Cast { expr, .. }
+            | Expr::Collate { expr, .. }
+            | Expr::Affinity { expr, .. } => expr.columns(),
+            Expr::Function { args, .. } => args.iter().flat_map(Expr::columns).collect(),
+        }
+    }
+
+    /// How many levels the expression tree has: 1 for a column or a value.
+    /// Worked out without recursing, so it's safe on trees too deep for
+    /// anything else.
+    pub fn depth(&self) -> usize {
+        let mut deepest = 0;
+        let mut stack = vec![(self, 1)];
+        while let Some((expr, depth)) = stack.pop() {
+            deepest = deepest.max(depth);
+            let children: Vec<&Expr> = match expr {
+                Expr::Column(_) | Expr::Literal(_) | Expr::Param(_) | Expr::Variable(_) => vec![],
+                Expr::Binary { left, right, .. } => vec![left, right],
+                Expr::In { expr, list, .. } => std::iter::once(&**expr).chain(list).collect(),
+                Expr::Not(expr)
+                | Expr::Neg(expr)
+                | Expr::IsNull { expr, .. }
+                | Expr::Cast { expr, .. }
+                | Expr::Collate { expr, .. }
+                | Expr::Affinity { expr, .. } => vec![expr],
+                Expr::Function { args, .. } => args.iter().collect(),
+            };
+            stack.extend(children.into_iter().map(|e| (e, depth + 1)));
+        }
+        deepest
+    }
+
+    /// Every collation given with `COLLATE` in the expression.
+    pub fn collations(&self) -> Vec<&Collation> {
+        match self {
+            Expr::Column(_) | Expr::Literal(_) | Expr::Param(_) | Expr::Variable(_) => vec![],
+            Expr::Binary { left, right, .. } => {
+                let mut collations = left.collations();
+                collations.extend(right.collations());
+                collations
+            }
+            Expr::In { expr, list, .. } => {
+                let mut collations = expr.collations();
+                collations.extend(list.iter().flat_map(Expr::collations));
+                collations
+            }
+            Expr::Collate { expr, collation } => {
+                let mut collations = expr.collations();
+                collations.push(collation);
+                collations
+            }
+            Expr::Not(expr)
+            | Expr::Neg(expr)
+            | Expr::IsNull { expr, .. }
+            | Expr::Cast { expr, .. }
+            | Expr::Affinity { expr, .. } => expr.collations(),
+            Expr::Function { args, .. } => args.iter().flat_map(Expr::collations).collect(),
+        }
+    }
+
+    /// Names of the functions the expression calls, maybe more than once.
+    pub fn functions(&self) -> Vec<&str> {
+        match self {
+            Expr::Column(_) | Expr::Literal(_) | Expr::Param(_) | Expr::Variable(_) => vec![],
+            Expr::Binary { left, right, .. } => {
+                let mut names = left.functions();
+                names.extend(right.functions());
+                names
+            }
+            Expr::In { expr, list, .. } => {
+                let mut names = expr.functions();
+                names.extend(list.iter().flat_map(Expr::functions));
+                names
+            }
+            Expr::Function { name, args } => {
+                let mut names = vec![name.as_str()];
+                names.extend(args.iter().flat_map(Expr::functions));
+                names
+            }
+            Expr::Not(expr)
+            | Expr::Neg(expr)
+            | Expr::IsNull { expr, .. }
+            | Expr::Cast { expr, .. }
+            | Expr::Collate { expr, .. }
+            | Expr::Affinity { expr, .. } => expr.functions(),
+        }
+    }
+
+    /// The highest parameter number used, or 0 if there aren't any.
+    pub fn param_count(&self) -> usize {
+        match self {
+            Expr::Column(_) | Expr::Literal(_) | Expr::Variable(_) => 0,
+            Expr::Param(n) => *n,
+            Expr::Binary { left, right, .. } => left.param_count().max(right.param_count()),
+            Expr::In { expr, list, .. } => list
+                .iter()
+                .map(Expr::param_count)
+                .fold(expr.param_count(), usize::max),
+            Expr::Not(expr)
+            | Expr::Neg(expr)
+            | Expr::IsNull { expr, .. }
+            | Expr::Cast { expr, .. }
+            | Expr::Collate { expr, .. }
+            | Expr::Affinity { expr, .. } => expr.param_count(),
+            Expr::Function { args, .. } => args.iter().map(Expr::param_count).max().unwrap_or(0),
+        }
+    }
+
+    /// The names of the variables in the expression, in order, maybe more
+    /// than once.
+    pub fn variables(&self) -> Vec<&str> {
+        match self {
+            Expr::Column(_) | Expr::Literal(_) | Expr::Param(_) => vec![],
+            Expr::Variable(name) => vec![name],
+            Expr::Binary { left, right, .. } => {
+                let mut names = left.variables();
+                names.extend(right.variables());
+                names
+            }
+            Expr::In { expr, list, .. } => {
+                let mut names = expr.variables();
+                names.extend(list.iter().flat_map(Expr::variables));
+                names
+            }
+            Expr::Not(expr)
+            | Expr::Neg(expr)
+            | Expr::IsNull { expr, .. }
+            | Expr::Cast { expr, .. }
+            | Expr::Collate { expr, .. }
+            | Expr::Affinity { expr, .. } => expr.variables(),
+            Expr::Function { args, .. } => args.iter().flat_map(Expr::variables).collect(),
+        }
+    }
+
+    /// Replace parameter `?N` with `params[N - 1]`.
+    pub fn bind(&self, params: &[Value]) -> Result<Expr> {
+        self.replace(&|e| match e {
+            Expr::Param(n) => match params.get(n - 1) {
+                Some(value) => Ok(Some(Expr::Literal(value.clone()))),
+                None => bail!("no value for parameter ?{}", n),
+            },
+            _ => Ok(None),
+        })
+    }
+
+    /// Replace each `$name` with its value from `vars`.
+    pub fn bind_vars(&self, vars: &HashMap<String, Value>) -> Result<Expr> {
+        self.replace(&|e| match e {
+            Expr::Variable(name) => match vars.get(name) {
+                Some(value) => Ok(Some(Expr::Literal(value.clone()))),
+                None => bail!("no value for variable ${}", name),
+            },
+            _ => Ok(None),
+        })
+    }
+
+    /// Replace the columns `hidden` says to with `NULL`.
+    pub fn without_columns(&self, hidden: &dyn Fn(&str) -> bool) -> Result<Expr> {
+        self.replace(&|e| match e {
+            Expr::Column(name) if hidden(name) => Ok(Some(Expr::Literal(Value::Null))),
+            _ => Ok(None),
+        })
+    }
+
+    /// A copy of the expression with the parts `f` gives a replacement for
+    /// swapped out.
+    fn replace(&self, f: &dyn Fn(&Expr) -> Result<Option<Expr>>) -> Result<Expr> {
+        if let Some(e) = f(self)? {
+            return Ok(e);
+        }
+        let replace = |e: &Expr| e.replace(f).map(Box::new);
+        let replace_all = |list: &[Expr]| list.iter().map(|e| e.replace(f)).collect::<Result<_>>();
+        Ok(match self {
+            Expr::Column(_) | Expr::Literal(_) | Expr::Param(_) | Expr::Variable(_) => self.clone(),
+            Expr::Binary { op, left, right } => Expr::Binary {
+                op: *op,
+                left: replace(left)?,
+                right: replace(right)?,
+            },
+            Expr::In {
+                expr,
+                list,
+                negated,
+            } => Expr::In {
+                expr: replace(expr)?,
+                list: replace_all(list)?,
+                negated: *negated,
+            },
+            Expr::Not(expr) => Expr::Not(replace(expr)?),
+            Expr::Neg(expr) => Expr::Neg(replace(expr)?),
+            Expr::IsNull { expr, negated } => Expr::IsNull {
+                expr: replace(expr)?,
+                negated: *negated,
+            },
+            Expr::Cast { expr, to } => Expr::Cast {
+                expr: replace(expr)?,
+                to: *to,
+            },
+            Expr::Collate { expr, collation } => Expr::Collate {
+                expr: replace(expr)?,
+                collation: collation.clone(),
+            },
+            Expr::Function { name, args } => Expr::Function {
+                name: name.clone(),
+                args: replace_all(args)?,
+            },
+            Expr::Affinity { expr, affinity } => Expr::Affinity {
+                expr: replace(expr)?,
+                affinity: *affinity,
+            },
+        })
+    }
+
+    /// The collation given with `COLLATE`, if the expression has one.
+    pub fn collation(&self) -> Option<Collation> {
+        match self {
+            Expr::Collate { collation, .. } => Some(collation.clone()),
+            _ => None,
+        }
+    }
+
+    /// The expression with `COLLATE` added to comparisons that get a
+    /// collation from a column, so it can be evaluated without knowing what
+    /// the columns were declared with. `declared` gives that for a column
+    /// name, or `None` if it isn't a column.
+    pub fn with_collations(&self, declared: &dyn Fn(&str) -> Option<Collation>) -> Result<Expr> {
+        let column = |e: &Expr| match e {
+            Expr::Column(name) => declared(name),
+            _ => None,
+        };
+        let collate = |e: &Expr, collation| -> Result<Box<Expr>> {
+            Ok(Box::new(Expr::Collate {
+                expr: Box::new(e.with_collations(declared)?),
+                collation,
+            }))
+        };
+        self.replace(&|e| {
+            Ok(match e {
+                // the left column wins, even if it's BINARY
+                Expr::Binary { op, left, right }
+                    if op.is_comparison() && left.collation().or(right.collation()).is_none() =>
+                {
+                    match column(left).or_else(|| column(right)) {
+                        Some(c) if c != Collation::Binary => Some(Expr::Binary {
+                            op: *op,
+                            left: collate(left, c)?,
+                            right: Box::new(right.with_collations(declared)?),
+                        }),
+                        _ => None,
+                    }
+                }
+                Expr::In {
+                    expr,
+                    list,
+                    negated,
+                } if expr.collation().is_none() => match column(expr) {
+                    Some(c) if c != Collation::Binary => Some(Expr::In {
+                        expr: collate(expr, c)?,
+                        list: list
+                            .iter()
+                            .map(|e| e.with_collations(declared))
+                            .collect::<Result<_>>()?,
+                        negated: *negated,
+                    }),
+                    _ => None,
+                },
+                _ => None,
+            })
+        })
+    }
+
+    /// The affinity the expression's values have when it's compared, if any:
+    /// a column's from `declared`, or the type of a `CAST`. `BLOB` counts as
+    /// none.
+    pub fn affinity(&self, declared: &dyn Fn(&str) -> Option<Affinity>) -> Option<Affinity> {
+        let affinity = match self {
+            Expr::Column(name) => declared(name),
+            Expr::Cast { to, .. } => Some(*to),
+            Expr::Collate { expr, .. } => expr.affinity(declared),
+            Expr::Affinity { affinity, .. } => Some(*affinity),
+            _ => None,
+        };
+        affinity.filter(|&a| a != Affinity::Blob)
+    }
+
+    /// The expression with SQLite's conversions before comparisons spelled
+    /// out: if one side is a number column and the other isn't, the other
+    /// side gets numeric affinity, and if one side is a text column and the
+    /// other has no affinity, it gets text affinity. Literals are converted
+    /// now, so `id = '3'` can still look up a rowid. `declared` gives a
+    /// column's affinity, like for [`Expr::affinity`].
+    pub fn with_affinities(&self, declared: &dyn Fn(&str) -> Option<Affinity>) -> Result<Expr> {
+        // what to convert each side to, if anything
+        let sides =
+            |left: &Expr, right: &Expr| match (left.affinity(declared), right.affinity(declared)) {
+                (Some(l), r) if l.is_numeric() && !r.is_some_and(Affinity::is_numeric) => {
+                    (None, Some(Affinity::Numeric))
+                }
+                (l, Some(r)) if r.is_numeric() && !l.is_some_and(Affinity::is_numeric) => {
+                    (Some(Affinity::Numeric), None)
+                }
+                (Some(Affinity::Text), None) => (None, Some(Affinity::Text)),
+                (None, Some(Affinity::Text)) => (Some(Affinity::Text), None),
+                _ => (None, None),
+            };
+        let convert = |e: &Expr, affinity: Option<Affinity>| -> Result<Expr> {
+            let e = e.with_affinities(declared)?;
+            Ok(match affinity {
+                Some(affinity) => e.converted(affinity),
+                None => e,
+            })
+        };
+        self.replace(&|e| {
+            Ok(match e {
+                Expr::Binary { op, left, right } if op.is_comparison() => {
+                    let (l, r) = sides(left, right);
+                    Some(Expr::Binary {
+                        op: *op,
+                        left: Box::new(convert(left, l)?),
+                        right: Box::new(convert(right, r)?),
+                    })
+                }
+                // the list is converted to the left side's affinity
+                Expr::In {
+                    expr,
+                    list,
+                    negated,
+                } => {
+                    let affinity = expr.affinity(declared).map(|a| match a {
+                        Affinity::Text => Affinity::Text,
+                        _ => Affinity::Numeric,
+                    });
+                    let list = list
+                        .iter()
+                        .map(|item| {
+                            let converted = affinity.filter(|&a| match item.affinity(declared) {
+                                Some(i) => a == Affinity::Numeric && !i.is_numeric(),
+                                None => true,
+                            });
+                            convert(item, converted)
+                        })
+                        .collect::<Result<_>>()?;
+                    Some(Expr::In {
+                        expr: Box::new(expr.with_affinities(declared)?),
+                        list,
+                        negated: *negated,
+                    })
+                }
+                _ => None,
+            })
+        })
+    }
+
+    /// The expression with [`Expr::Affinity`] around it, inside any
+    /// `COLLATE`. A literal is converted straight away.
+    fn converted(self, affinity: Affinity) -> Expr {
+        match self {
+            Expr::Literal(value) => Expr::Literal(value.apply_affinity(affinity)),
+            Expr::Collate { expr, collation } => Expr::Collate {
+                expr: Box::new(expr.converted(affinity)),
+                collation,
+            },
+            e => Expr::Affinity {
+                expr: Box::new(e),
+                affinity,
+            },
+        }
+    }
+
+    /// Split a chain of `OR`s into its terms.
+    pub fn disjuncts(&self) -> Vec<&Expr> {
+        match self {
+            Expr::Binary {
+                op: BinaryOp::Or,
+                left,
+                right,
+            } => {
+                let mut terms = left.disjuncts();
+                terms.extend(right.disjuncts());
+                terms
+            }
+            e => vec![e],
+        }
+    }
+}
+
+/// A statement we know how to run.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Statement {
+    Select(Select),
+    /// `EXPLAIN QUERY PLAN SELECT ...`
+    ExplainQueryPlan(Select),
+    /// `PRAGMA name`, `PRAGMA name(arg)` or `PRAGMA name = arg`
+    Pragma {
+        name: String,
+        arg: Option<Value>,
+    },
+    /// `ATTACH [DATABASE] 'path' AS name`
+    Attach {
+        path: String,
+        name: String,
+    },
+    /// `DETACH [DATABASE] name`
+    Detach(String),
+}
+
+impl Statement {
+    /// How many parameters have to be bound before the statement can run.
+    pub fn param_count(&self) -> usize {
+        match self {
+            Statement::Select(select) | Statement::ExplainQueryPlan(select) => select.param_count(),
+            Statement::Pragma { .. } | Statement::Attach { .. } | Statement::Detach(_) => 0,
+        }
+    }
+
+    /// The statement with its parameters replaced by `params`, which has to
+    /// have a value for each one.
+    pub fn bind(&self, params: &[Value]) -> Result<Statement> {
+        match self {
+            Statement::Select(select) => Ok(Statement::Select(select.bind(params)?)),
+            Statement::ExplainQueryPlan(select) => {
+                Ok(Statement::ExplainQueryPlan(select.bind(params)?))
+            }
+            _ if params.is_empty() => Ok(self.clone()),
+            _ => bail!("expected 0 parameters, got {}", params.len()),
+        }
+    }
+
+    /// The statement with each `$name` replaced by its value from `vars`.
+    pub fn bind_vars(&self, vars: &HashMap<String, Value>) -> Result<Statement> {
+        Ok(match self {
+            Statement::Select(select) => Statement::Select(select.bind_vars(vars)?),
+            Statement::ExplainQueryPlan(select) => {
+                Statement::ExplainQueryPlan(select.bind_vars(vars)?)
+            }
+            Statement::Pragma { .. } | Statement::Attach { .. } | Statement::Detach(_) => {
+                self.clone()
+            }
+        })
+    }
+}
+
+impl Statement {
+    /// Parse a statement, failing if it's bigger than `limits` allows.
+    pub fn parse_with(sql: &str, limits: &Limits) -> Result<Self> {
+        let mut parser = Parser::with_limits(sql, limits)?;
+        let stmt = parser.statement()?;
+        parser.finish()?;
+        Ok(stmt)
+    }
+}
+
+impl FromStr for Statement {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Statement::parse_with(s, &Limits::default())
+    }
+}
+
+impl Display for BinaryOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            BinaryOp::Or => "OR",
+            BinaryOp::And => "AND",
+            BinaryOp::Eq => "=",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+            BinaryOp::Concat => "||",
+        })
+    }
+}
+
+/// The expression as SQL, with brackets only where they're needed. Used for
+/// the names of output columns without an alias.
+impl Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expr::Column(name) => write!(f, "{}", name),
+            Expr::Literal(Value::String(s)) => write!(f, "'{}'", s.replace('\'', "''")),
+            Expr::Literal(v) => write!(f, "{}", v),
+            Expr::Binary { op, left, right } => {
+                // the right side needs brackets at the same precedence, for `a - (b - c)`
+                let side = |e: &Expr, right: bool| match e {
+                    Expr::Binary { op: inner, .. }
+                        if inner.precedence() < op.precedence()
+                            || (right && inner.precedence() == op.precedence()) =>
+                    {
+                        format!("({})", e)
+                    }
+                    e => e.to_string(),
+                };
+                write!(f, "{} {} {}", side(left, false), op, side(right, true))
+            }
+            Expr::In {
+                expr,
+                list,
+                negated,
+            } => {
+                let list: Vec<String> = list.iter().map(Expr::to_string).collect();
+                let not = if *negated { "NOT " } else { "" };
+                write!(f, "{} {}IN ({})", expr, not, list.join(", "))
+            }
+            Expr::Not(expr) => match expr.as_ref() {
+                Expr::Binary {
+                    op: BinaryOp::And | BinaryOp::Or,
+                    ..
+                } => write!(f, "NOT ({})", expr),
+                _ => write!(f, "NOT {}", expr),
+            },
+            Expr::Neg(expr) => match expr.as_ref() {
+                Expr::Binary { .. } => write!(f, "-({})", expr),
+                _ => write!(f, "-{}", expr),
+            },
+            Expr::IsNull { expr, negated } => {
+                let not = if *negated { "NOT " } else { "" };
+                write!(f, "{} IS {}NULL", expr, not)
+            }
+            Expr::Param(n) => write!(f, "?{}", n),
+            Expr::Variable(name) => write!(f, "${}", name),
+            Expr::Cast { expr, to } => {
+                let to = format!("{:?}", to).to_uppercase();
+                write!(f, "CAST({} AS {})", expr, to)
+            }
+            Expr::Collate { expr, collation } => match expr.as_ref() {
+                Expr::Binary { .. } => write!(f, "({}) COLLATE {}", expr, collation),
+                _ => write!(f, "{} COLLATE {}", expr, collation),
+            },
+            Expr::Function { name, args } => {
+                let args: Vec<String> = args.iter().map(Expr::to_string).collect();
+                write!(f, "{}({})", name, args.join(", "))
+            }
+            Expr::Affinity { expr, .. } => write!(f, "{}", expr),
+        }
+    }
+}
+
+/// Recursive descent parser over a list of tokens.
+pub struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// Highest parameter number so far, so a bare `?` gets the next one.
+    params: usize,
+    limits: Limits,
+    /// How deep in the expression tree we are, counting each operator of a
+    /// chain like `a + b + c` as a level, as that's how deep its tree is.
+    depth: usize,
+}
+
+impl Parser {
+    pub fn new(sql: &str) -> Result<Self> {
+        Self::with_limits(sql, &Limits::default())
+    }
+
+    /// A parser that fails on SQL bigger than `limits` allows.
+    pub fn with_limits(sql: &str, limits: &Limits) -> Result<Self> {
+        if sql.len() > limits.sql_length {
+            bail!(
+                "statement too long: the limit is {} bytes",
+                limits.sql_length
+            );
+        }
+        Ok(Self {
+            tokens: tokenize(sql)?,
+            pos: 0,
+            params: 0,
+            limits: *limits,
+            depth: 0,
+        })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let t = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        t
+    }
+
+    fn peek_keyword(&self, kw: &str) -> bool {
+        self.peek().is_some_and(|t| t.is_keyword(kw))
+    }
+
+    fn eat_keyword(&mut self, kw: &str) -> bool {
+        let found = self.peek_keyword(kw);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    /// Eat a run of keywords, but only if they're all there.
+    fn eat_keywords(&mut self, kws: &[&str]) -> bool {
+        let found = kws.iter().enumerate().all(|(i, kw)| {
+            self.tokens
+                .get(self.pos + i)
+                .is_some_and(|t| t.is_keyword(kw))
+        });
+        if found {
+            self.pos += kws.len();
+        }
+        found
+    }
+
+    fn expect_keyword(&mut self, kw: &str) -> Result<()> {
+        if !self.eat_keyword(kw) {
+            bail!("expected {}, found {:?}", kw, self.peek());
+        }
+        Ok(())
+    }
+
+    fn eat_symbol(&mut self, sym: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Symbol(s)) if *s == sym);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect_symbol(&mut self, sym: &str) -> Result<()> {
+        if !self.eat_symbol(sym) {
+            bail!("expected {:?}, found {:?}", sym, self.peek());
+        }
+        Ok(())
+    }
+
+    fn ident(&mut self) -> Result<String> {
+        match self.next() {
+            Some(Token::Ident(s) | Token::Quoted(s)) => Ok(s),
+            t => bail!("expected a name, found {:?}", t),
+        }
+    }
+
+    /// `MIN(column)` or `MAX(column)` on its own before `FROM`, or `None`
+    /// without using up any tokens if it's something else.
+    fn min_max(&mut self) -> Option<SelectColumns> {
+        let start = self.pos;
+        let max = if self.eat_keyword("MAX") {
+            true
+        } else if self.eat_keyword("MIN") {
+            false
+        } else {
+            return None;
+        };
+        // `column` and `table.column` are read like a table's name
+        if self.eat_symbol("(") {
+            if let Ok(column) = self.table_name() {
+                if self.eat_symbol(")") && self.peek_keyword("FROM") {
+                    return Some(SelectColumns::MinMax { column, max });
+                }
+            }
+        }
+        self.pos = start;
+        None
+    }
+
+    /// A table's name, which can be `schema.table` for one in an attached
+    /// file.
+    fn table_name(&mut self) -> Result<String> {
+        let name = self.ident()?;
+        if !self.eat_symbol(".") {
+            return Ok(name);
+        }
+        Ok(format!("{}.{}", name, self.ident()?))
+    }
+
+    /// Fail unless all the tokens were used, ignoring a trailing `;`.
+    pub fn finish(&mut self) -> Result<()> {
+        self.eat_symbol(";");
+        match self.peek() {
+            None => Ok(()),
+            Some(t) => bail!("unexpected {:?} at end of statement", t),
+        }
+    }
+
+    pub fn statement(&mut self) -> Result<Statement> {
+        if self.eat_keyword("EXPLAIN") {
+            self.expect_keyword("QUERY")?;
+            self.expect_keyword("PLAN")?;
+            return Ok(Statement::ExplainQueryPlan(self.select()?));
+        }
+        if self.eat_keyword("ATTACH") {
+            self.eat_keyword("DATABASE");
+            let path = match self.next() {
+                Some(Token::String(path)) => path,
+                t => bail!("expected a file name, found {:?}", t),
+            };
+            self.expect_keyword("AS")?;
+            let name = self.ident()?;
+            return Ok(Statement::Attach { path, name });
+        }
+        if self.eat_keyword("DETACH") {
+            self.eat_keyword("DATABASE");
+            return Ok(Statement::Detach(self.ident()?));
+        }
+        if self.eat_keyword("PRAGMA") {
+            let name = self.ident()?.to_ascii_lowercase();
+            let arg = if self.eat_symbol("=") {
+                Some(self.pragma_arg()?)
+            } else if self.eat_symbol("(") {
+                let arg = self.pragma_arg()?;
+                self.expect_symbol(")")?;
+                Some(arg)
+            } else {
+                None
+            };
+            return Ok(Statement::Pragma { name, arg });
+        }
+        Ok(Statement::Select(self.select()?))
+    }
+
+    /// A name or a literal. Names are taken as strings, like SQLite does.
+    fn pragma_arg(&mut self) -> Result<Value> {
+        match self.next() {
+            Some(Token::Ident(s) | Token::Quoted(s) | Token::String(s)) => Ok(Value::String(s)),
+            Some(Token::Integer(n)) => Ok(Value::Integer(n)),
+            Some(Token::MinIntegerDigits) => Ok(Value::Float(-(i64::MIN as f64))),
+            Some(Token::Float(n)) => Ok(Value::Float(n)),
+            t => bail!("expected a pragma value, found {:?}", t),
+        }
+    }
+
+    pub fn select(&mut self) -> Result<Select> {
+        self.expect_keyword("SELECT")?;
+        let columns = if self.eat_symbol("*") {
+            SelectColumns::All
+        } else if self.peek_keyword("COUNT")
+            && self.tokens.get(self.pos + 1) == Some(&Token::Symbol("("))
+        {
+            self.pos += 2;
+            self.expect_symbol("*")?;
+            self.expect_symbol(")")?;
+            SelectColumns::Count
+        } else if let Some(columns) = self.min_max() {
+            columns
+        } else {
+            let mut cols = vec![self.result_column()?];
+            while self.eat_symbol(",") {
+                cols.push(self.result_column()?);
+                if cols.len() > self.limits.columns {
+                    bail!("too many columns in result set");
+                }
+            }
+            SelectColumns::Columns(cols)
+        };
+        self.expect_keyword("FROM")?;
+        let name = self.table_name()?;
+        let alias = self.alias()?;
+        let mut joins = vec![];
+        loop {
+            if self.eat_keyword("INNER") {
+                self.expect_keyword("JOIN")?;
+            } else if !self.eat_keyword("JOIN") {
+                break;
+            }
+            let table = self.table_name()?;
+            let alias = self.alias()?;
+            self.expect_keyword("ON")?;
+            let on = self.expr()?;
+            joins.push(Join { table, alias, on });
+        }
+        let filter = if self.eat_keyword("WHERE") {
+            Some(self.expr()?)
+        } else {
+            None
+        };
+        let mut order_by = vec![];
+        if self.eat_keyword("ORDER") {
+            self.expect_keyword("BY")?;
+            loop {
+                let expr = self.expr()?;
+                let descending = self.eat_keyword("DESC");
+                if !descending {
+                    self.eat_keyword("ASC");
+                }
+                order_by.push(OrderingTerm { expr, descending });
+                if !self.eat_symbol(",") {
+                    break;
+                }
+            }
+        }
+        Ok(Select {
+            name,
+            alias,
+            columns,
+            joins,
+            filter,
+            order_by,
+        })
+    }
+
+    /// An output column of a `SELECT`, with an optional alias. The `AS` can
+    /// be left out, like for tables.
+    fn result_column(&mut self) -> Result<ResultColumn> {
+        if let (
+            Some(Token::Ident(table) | Token::Quoted(table)),
+            Some(Token::Symbol(".")),
+            Some(Token::Symbol("*")),
+        ) = (
+            self.tokens.get(self.pos),
+            self.tokens.get(self.pos + 1),
+            self.tokens.get(self.pos + 2),
+        ) {
+            let table = table.clone();
+            self.pos += 3;
+            return Ok(ResultColumn::AllOf(table));
+        }
+        let expr = self.expr()?;
+        let alias = if self.eat_keyword("AS") {
+            match self.next() {
+                Some(Token::Ident(s) | Token::Quoted(s) | Token::String(s)) => Some(s),
+                t => bail!("expected an alias, found {:?}", t),
+            }
+        } else if self.peek_keyword("FROM") {
+            None
+        } else {
+            self.alias()?
+        };
+        Ok(ResultColumn::Expr { expr, alias })
+    }
+
+    /// `AS alias`, or just `alias`, after a table name.
+    fn alias(&mut self) -> Result<Option<String>> {
+        if self.eat_keyword("AS") {
+            return Ok(Some(self.ident()?));
+        }
+        match self.peek() {
+            Some(t @ Token::Ident(_)) if !KEYWORDS.iter().any(|kw| t.is_keyword(kw)) => {
+                Ok(Some(self.ident()?))
+            }
+            Some(Token::Quoted(_)) => Ok(Some(self.ident()?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// An expression, from `OR` down to literals, columns and function
+    /// calls. Fails if it nests deeper than the limits allow.
+    pub fn expr(&mut self) -> Result<Expr> {
+        let base = self.depth;
+        self.deeper()?;
+        let expr = self.or_expr()?;
+        self.depth = base;
+        // the counting above stops runaway input early, this is exact
+        if base == 0 && expr.depth() > self.limits.expr_depth {
+            self.too_deep()?;
+        }
+        Ok(expr)
+    }
+
+    /// Go down a level of the expression tree, failing if that's too deep.
+    /// Each function that calls this puts [`Parser::depth`] back before it
+    /// returns.
+    fn deeper(&mut self) -> Result<()> {
+        self.depth += 1;
+        if self.depth > self.limits.expr_depth {
+            self.too_deep()?;
+        }
+        Ok(())
+    }
+
+    fn too_deep(&self) -> Result<()> {
+        bail!(
+            "Expression tree is too large (maximum depth {})",
+            self.limits.expr_depth
+        )
+    }
+
+    fn or_expr(&mut self) -> Result<Expr> {
+        let base = self.depth;
+        let mut left = self.and_expr()?;
+        while self.eat_keyword("OR") {
+            self.deeper()?;
+            let right = self.and_expr()?;
+            left = Expr::binary(BinaryOp::Or, left, right);
+        }
+        self.depth = base;
+        Ok(left)
+    }
+
+    fn and_expr(&mut self) -> Result<Expr> {
+        let base = self.depth;
+        let mut left = self.not_expr()?;
+        while self.eat_keyword("AND") {
+            self.deeper()?;
+            let right = self.not_expr()?;
+            left = Expr::binary(BinaryOp::And, left, right);
+        }
+        self.depth = base;
+        Ok(left)
+    }
+
+    /// `NOT` binds less tightly than comparisons, so `NOT a = 1` is
+    /// `NOT (a = 1)`.
+    fn not_expr(&mut self) -> Result<Expr> {
+        if self.eat_keyword("NOT") {
+            let base = self.depth;
+            self.deeper()?;
+            let expr = self.not_expr()?;
+            self.depth = base;
+            return Ok(Expr::Not(Box::new(expr)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr> {
+        let left = self.additive()?;
+        if let Some(negated) = self.null_test() {
+            return Ok(Expr::IsNull {
+                expr: Box::new(left),
+                negated,
+            });
+        }
+        let negated = self.peek_keyword("NOT")
+            && self
+                .tokens
+                .get(self.pos + 1)
+                .is_some_and(|t| t.is_keyword("IN") || t.is_keyword("BETWEEN"));
+        if negated {
+            self.pos += 1;
+        }
+        if self.eat_keyword("BETWEEN") {
+            // the AND belongs to the BETWEEN, so the bounds can't have their
+            // own ANDs or ORs
+            let low = self.additive()?;
+            self.expect_keyword("AND")?;
+            let high = self.additive()?;
+            return Ok(if negated {
+                Expr::binary(
+                    BinaryOp::Or,
+                    Expr::binary(BinaryOp::Lt, left.clone(), low),
+                    Expr::binary(BinaryOp::Gt, left, high),
+                )
+            } else {
+                Expr::binary(
+                    BinaryOp::And,
+                    Expr::binary(BinaryOp::Ge, left.clone(), low),
+                    Expr::binary(BinaryOp::Le, left, high),
+                )
+            });
+        }
+        if self.eat_keyword("IN") {
+            self.expect_symbol("(")?;
+            let mut list = vec![];
+            if !self.eat_symbol(")") {
+                list.push(self.expr()?);
+                while self.eat_symbol(",") {
+                    list.push(self.expr()?);
+                }
+                self.expect_symbol(")")?;
+            }
+            return Ok(Expr::In {
+                expr: Box::new(left),
+                list,
+                negated,
+            });
+        }
+        let op = match self.peek() {
+            Some(Token::Symbol("=" | "==")) => BinaryOp::Eq,
+            Some(Token::Symbol("!=" | "<>")) => BinaryOp::Ne,
+            Some(Token::Symbol("<")) => BinaryOp::Lt,
+            Some(Token::Symbol("<=")) => BinaryOp::Le,
+            Some(Token::Symbol(">")) => BinaryOp::Gt,
+            Some(Token::Symbol(">=")) => BinaryOp::Ge,
+            _ => return Ok(left),
+        };
+        self.pos += 1;
+        let right = self.additive()?;
+        Ok(Expr::binary(op, left, right))
+    }
+
+    fn additive(&mut self) -> Result<Expr> {
+        let base = self.depth;
+        let mut left = self.multiplicative()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Symbol("+")) => BinaryOp::Add,
+                Some(Token::Symbol("-")) => BinaryOp::Sub,
+                _ => break,
+            };
+            self.pos += 1;
+            self.deeper()?;
+            left = Expr::binary(op, left, self.multiplicative()?);
+        }
+        self.depth = base;
+        Ok(left)
+    }
+
+    fn multiplicative(&mut self) -> Result<Expr> {
+        let base = self.depth;
+        let mut left = self.concat()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Symbol("*")) => BinaryOp::Mul,
+                Some(Token::Symbol("/")) => BinaryOp::Div,
+                Some(Token::Symbol("%")) => BinaryOp::Rem,
+                _ => break,
+            };
+            self.pos += 1;
+            self.deeper()?;
+            left = Expr::binary(op, left, self.concat()?);
+        }
+        self.depth = base;
+        Ok(left)
+    }
+
+    /// `||` binds tighter than any other binary operator.
+    fn concat(&mut self) -> Result<Expr> {
+        let base = self.depth;
+        let mut left = self.unary()?;
+        while self.eat_symbol("||") {
+            self.deeper()?;
+            left = Expr::binary(BinaryOp::Concat, left, self.unary()?);
+        }
+        self.depth = base;
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        let number_next = matches!(
+            self.tokens.get(self.pos + 1),
+            Some(Token::Integer(_) | Token::MinIntegerDigits | Token::Float(_))
+        );
+        // a minus sign on a number is part of the literal, so `id = -1` can
+        // still use an index
+        let base = self.depth;
+        if !number_next && self.eat_symbol("-") {
+            self.deeper()?;
+            let expr = self.unary()?;
+            self.depth = base;
+            return Ok(Expr::Neg(Box::new(expr)));
+        }
+        if self.eat_symbol("+") {
+            self.deeper()?;
+            let expr = self.unary()?;
+            self.depth = base;
+            return Ok(expr);
+        }
+        let mut expr = self.primary()?;
+        // COLLATE binds tighter than anything else
+        while self.eat_keyword("COLLATE") {
+            self.deeper()?;
+            expr = Expr::Collate {
+                expr: Box::new(expr),
+                collation: self.ident()?.parse()?,
+            };
+        }
+        self.depth = base;
+        Ok(expr)
+    }
+
+    /// `IS NULL`, `IS NOT NULL` and the shorthands `ISNULL`, `NOTNULL` and
+    /// `NOT NULL`. Returns whether it was negated.
+    fn null_test(&mut self) -> Option<bool> {
+        if self.eat_keywords(&["ISNULL"]) || self.eat_keywords(&["IS", "NULL"]) {
+            Some(false)
+        } else if self.eat_keywords(&["NOTNULL"])
+            || self.eat_keywords(&["NOT", "NULL"])
+            || self.eat_keywords(&["IS", "NOT", "NULL"])
+        {
+            Some(true)
+        } else {
+            None
+        }
+    }
+
+    /// A type name like `INTEGER` or `VARCHAR(10)`. Sizes are left out, as
+    /// nothing uses them.
+    fn type_name(&mut self) -> Result<String> {
+        let mut words = vec![self.ident()?];
+        while let Some(Token::Ident(word)) = self.peek() {
+            words.push(word.clone());
+            self.pos += 1;
+        }
+        if self.eat_symbol("(") {
+            while !self.eat_symbol(")") {
+                match self.next() {
+                    Some(Token::Integer(_) | Token::Symbol(",") | Token::Symbol("-")) => {}
+                    t => bail!("expected a type size, found {:?}", t),
+                }
+            }
+        }
+        Ok(words.join(" "))
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        match self.next() {
+            Some(Token::Symbol("(")) => {
+                let expr = self.expr()?;
+                self.expect_symbol(")")?;
+                Ok(expr)
+            }
+            Some(Token::Ident(s)) if s.eq_ignore_ascii_case("NULL") => {
+                Ok(Expr::Literal(Value::Null))
+            }
+            Some(Token::Ident(s)) if s.eq_ignore_ascii_case("CAST") && self.eat_symbol("(") => {
+                let expr = self.expr()?;
+                self.expect_keyword("AS")?;
+                let to = Affinity::from_type_name(&self.type_name()?);
+                self.expect_symbol(")")?;
+                Ok(Expr::Cast {
+                    expr: Box::new(expr),
+                    to,
+                })
+            }
+            Some(Token::Ident(name)) if self.eat_symbol("(") => {
+                let mut args = vec![];
+                if !self.eat_symbol(")") {
+                    args.push(self.expr()?);
+                    while self.eat_symbol(",") {
+                        args.push(self.expr()?);
+                    }
+                    self.expect_symbol(")")?;
+                }
+                Ok(Expr::Function { name, args })
+            }
+            Some(Token::Ident(s) | Token::Quoted(s)) if self.eat_symbol(".") => {
+                Ok(Expr::Column(format!("{}.{}", s, self.ident()?)))
+            }
+            Some(Token::Ident(s) | Token::Quoted(s)) => Ok(Expr::Column(s)),
+            Some(Token::String(s)) => Ok(Expr::Literal(Value::String(s))),
+            Some(Token::Integer(n)) => Ok(Expr::Literal(Value::Integer(n))),
+            Some(Token::MinIntegerDigits) => Ok(Expr::Literal(Value::Float(-(i64::MIN as f64)))),
+            Some(Token::Float(n)) => Ok(Expr::Literal(Value::Float(n))),
+            Some(Token::Param(n)) => {
+                let n = n.unwrap_or(self.params + 1);
+                self.params = self.params.max(n);
+                Ok(Expr::Param(n))
+            }
+            Some(Token::Variable(name)) => Ok(Expr::Variable(name)),
+            Some(Token::Symbol("-")) => match self.next() {
+                Some(Token::Integer(n)) => match n.checked_neg() {
+                    Some(n) => Ok(Expr::Literal(Value::Integer(n))),
+                    // only 0x8000000000000000 comes out that negative
+                    None => bail!("hex literal too big: -0x{:x}", n),
+                },
+                Some(Token::MinIntegerDigits) => Ok(Expr::Literal(Value::Integer(i64::MIN))),
+                Some(Token::Float(n)) => Ok(Expr::Literal(Value::Float(-n))),
+                t => bail!("expected a number after '-', found {:?}", t),
+            },
+            t => bail!("expected an expression, found {:?}", t),
+        }
+    }
+}
+
+/// Parse a list of literal values, like the arguments of `.run`. Commas
+/// between them are optional.
+pub fn parse_values(s: &str) -> Result<Vec<Value>> {
+    let mut parser = Parser::new(s)?;
+    let mut values = vec![];
+    while parser.peek().is_some() {
+        match parser.unary()? {
+            Expr::Literal(value) => values.push(value),
+            e => bail!("expected a value, found {:?}", e),
+        }
+        parser.eat_symbol(",");
+    }
+    Ok(values)
+}
+
+#[test]
+fn tokenize_select() -> Result<()> {
+    let tokens = tokenize("SELECT name FROM t WHERE color = 'it''s' AND n >= 1.5")?;
+    assert_eq!(
+        tokens,
+        vec![
+            Token::Ident("SELECT".into()),
+            Token::Ident("name".into()),
+            Token::Ident("FROM".into()),
+            Token::Ident("t".into()),
+            Token::Ident("WHERE".into()),
+            Token::Ident("color".into()),
+            Token::Symbol("="),
+            Token::String("it's".into()),
+            Token::Ident("AND".into()),
+            Token::Ident("n".into()),
+            Token::Symbol(">="),
+            Token::Float(1.5),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn tokenize_numbers() -> Result<()> {
+    assert_eq!(
+        tokenize("1e5 1.5E-2 .5 1. 0x10 0XfF 0xffffffffffffffff")?,
+        [
+            Token::Float(1e5),
+            Token::Float(0.015),
+            Token::Float(0.5),
+            Token::Float(1.0),
+            Token::Integer(16),
+            Token::Integer(255),
+            Token::Integer(-1),
+        ]
+    );
+    for bad in [
+        "1e",
+        "1e+",
+        "12abc",
+        "0x",
+        "0x1g",
+        "1.5x",
+        "0x10000000000000000",
+    ] {
+        assert!(tokenize(bad).is_err(), "{}", bad);
+    }
+    let values = parse_values("-9223372036854775808 9223372036854775808 0x8000000000000000")?;
+    assert_eq!(
+        values,
+        [
+            Value::Integer(i64::MIN),
+            Value::Float(9223372036854775808.0),
+            Value::Integer(i64::MIN),
+        ]
+    );
+    assert!(parse_values("-0x8000000000000000").is_err());
+    let stmt: Statement = "SELECT id FROM t WHERE id < 1e1".parse()?;
+    let Statement::Select(select) = stmt else {
+        panic!("expected SELECT, got {:?}", stmt);
+    };
+    let expected = Expr::binary(
+        BinaryOp::Lt,
+        Expr::Column("id".to_owned()),
+        Expr::Literal(Value::Float(10.0)),
+    );
+    assert_eq!(select.filter, Some(expected));
+    Ok(())
+}
+
+#[test]
+fn parse_or_below_and() -> Result<()> {
+    let stmt: Statement =
+        "EXPLAIN QUERY PLAN SELECT id FROM t WHERE a = 1 OR b = 2 AND c = 3".parse()?;
+    let Statement::ExplainQueryPlan(select) = stmt else {
+        panic!("expected EXPLAIN QUERY PLAN, got {:?}", stmt);
+    };
+    let eq = |c: &str, n| {
+        Expr::binary(
+            BinaryOp::Eq,
+            Expr::Column(c.to_owned()),
+            Expr::Literal(Value::Integer(n)),
+        )
+    };
+    let expected = Expr::binary(
+        BinaryOp::Or,
+        eq("a", 1),
+        Expr::binary(BinaryOp::And, eq("b", 2), eq("c", 3)),
+    );
+    assert_eq!(select.filter, Some(expected));
+    Ok(())
+}
+
+#[test]
+fn parse_join() -> Result<()> {
+    let select: Select = "SELECT a.x, y FROM t1 AS a JOIN t2 b ON a.id = b.t1_id".parse()?;
+    assert_eq!(select.alias.as_deref(), Some("a"));
+    assert_eq!(
+        select.columns,
+        SelectColumns::Columns(vec![
+            Expr::Column("a.x".to_owned()).into(),
+            Expr::Column("y".to_owned()).into()
+        ])
+    );
+    let on = Expr::binary(
+        BinaryOp::Eq,
+        Expr::Column("a.id".to_owned()),
+        Expr::Column("b.t1_id".to_owned()),
+    );
+    assert_eq!(
+        select.joins,
+        vec![Join {
+            table: "t2".to_owned(),
+            alias: Some("b".to_owned()),
+            on,
+        }]
+    );
+    Ok(())
+}
+
+#[test]
+fn parse_attach() -> Result<()> {
+    assert_eq!(
+        "ATTACH DATABASE 'other.db' AS aux".parse::<Statement>()?,
+        Statement::Attach {
+            path: "other.db".to_owned(),
+            name: "aux".to_owned(),
+        }
+    );
+    assert_eq!(
+        "detach aux;".parse::<Statement>()?,
+        Statement::Detach("aux".to_owned())
+    );
+    let select: Select = "SELECT x FROM aux.t JOIN main.u ON u.id = t.id".parse()?;
+    assert_eq!(select.name, "aux.t");
+    assert_eq!(select.joins[0].table, "main.u");
+    assert!("ATTACH aux AS other".parse::<Statement>().is_err());
+    Ok(())
+}
+
+#[test]
+fn parse_not_in() -> Result<()> {
+    let select: Select = "SELECT id FROM t WHERE id NOT IN (1, 'a') AND x IN ()".parse()?;
+    let expected = Expr::binary(
+        BinaryOp::And,
+        Expr::In {
+            expr: Box::new(Expr::Column("id".to_owned())),
+            list: vec![
+                Expr::Literal(Value::Integer(1)),
+                Expr::Literal(Value::String("a".to_owned())),
+            ],
+            negated: true,
+        },
+        Expr::In {
+            expr: Box::new(Expr::Column("x".to_owned())),
+            list: vec![],
+            negated: false,
+        },
+    );
+    assert_eq!(select.filter, Some(expected));
+    Ok(())
+}
+
+#[test]
+fn bind_params() -> Result<()> {
+    let stmt: Statement = "SELECT name FROM t WHERE id = ? OR id IN (?3, ?)".parse()?;
+    let Statement::Select(select) = &stmt else {
+        unreachable!()
+    };
+    assert_eq!(select.param_count(), 4);
+    let values = parse_values("1, 'two' -3 NULL")?;
+    let Statement::Select(bound) = stmt.bind(&values)? else {
+        unreachable!()
+    };
+    assert_eq!(
+        bound,
+        "SELECT name FROM t WHERE id = 1 OR id IN (-3, NULL)".parse()?
+    );
+    assert!(stmt.bind(&values[..3]).is_err());
+    Ok(())
+}
+
+#[test]
+fn bind_variables() -> Result<()> {
+    let select: Select = "SELECT name FROM t WHERE id = $id AND color = $color".parse()?;
+    let mut vars = HashMap::new();
+    vars.insert("id".to_owned(), Value::Integer(2));
+    assert!(select.bind_vars(&vars).is_err());
+    vars.insert("color".to_owned(), Value::String("red".into()));
+    assert_eq!(
+        select.bind_vars(&vars)?,
+        "SELECT name FROM t WHERE id = 2 AND color = 'red'".parse()?
+    );
+    Ok(())
+}
+
+#[test]
+fn column_aliases() -> Result<()> {
+    let select: Select = "SELECT price * 2 AS doubled, a.name n, (x + 1) * -y FROM t a".parse()?;
+    let SelectColumns::Columns(cols) = select.columns else {
+        unreachable!()
+    };
+    let names: Vec<String> = cols.iter().flat_map(ResultColumn::name).collect();
+    assert_eq!(names, ["doubled", "n", "(x + 1) * -y"]);
+    let select: Select = "SELECT a.*, b.x FROM t a JOIN u b ON a.id = b.id".parse()?;
+    assert_eq!(
+        select.columns,
+        SelectColumns::Columns(vec![
+            ResultColumn::AllOf("a".into()),
+            Expr::Column("b.x".into()).into()
+        ])
+    );
+    assert_eq!(select.alias.as_deref(), Some("a"));
+    Ok(())
+}
+
+#[test]
+fn parse_cast() -> Result<()> {
+    let expr = Parser::new("CAST(a + 1 AS varchar(10))")?.expr()?;
+    assert_eq!(
+        expr,
+        Expr::Cast {
+            expr: Box::new(Expr::binary(
+                BinaryOp::Add,
+                Expr::Column("a".into()),
+                Expr::Literal(Value::Integer(1))
+            )),
+            to: Affinity::Text,
+        }
+    );
+    Ok(())
+}
+
+#[test]
+fn parse_not_and_parens() -> Result<()> {
+    let select: Select = "SELECT id FROM t WHERE NOT (a = 1 OR b = 2) AND NOT c = 3".parse()?;
+    let eq = |col: &str, n| {
+        Expr::binary(
+            BinaryOp::Eq,
+            Expr::Column(col.to_owned()),
+            Expr::Literal(Value::Integer(n)),
+        )
+    };
+    let expected = Expr::binary(
+        BinaryOp::And,
+        Expr::Not(Box::new(Expr::binary(BinaryOp::Or, eq("a", 1), eq("b", 2)))),
+        Expr::Not(Box::new(eq("c", 3))),
+    );
+    assert_eq!(select.filter, Some(expected));
+    Ok(())
+}
+
+#[test]
+fn quoted_identifiers() -> Result<()> {
+    let select: Select =
+        r#"SELECT "order", [unit price], `a``b` FROM "my table" WHERE "select" = 'x'"#.parse()?;
+    assert_eq!(select.name, "my table");
+    assert_eq!(
+        select.columns,
+        SelectColumns::Columns(vec![
+            Expr::Column("order".into()).into(),
+            Expr::Column("unit price".into()).into(),
+            Expr::Column("a`b".into()).into(),
+        ])
+    );
+    assert_eq!(
+        select.filter,
+        Some(Expr::binary(
+            BinaryOp::Eq,
+            Expr::Column("select".into()),
+            Expr::Literal(Value::String("x".into()))
+        ))
+    );
+    assert!(tokenize(r#"SELECT "oops"#).is_err());
+    Ok(())
+}
+
+#[test]
+fn comments() -> Result<()> {
+    let select: Select =
+        "SELECT a, -- the first one\n b /* and -- another */ FROM t /* unclosed".parse()?;
+    assert_eq!(
+        select.columns,
+        SelectColumns::Columns(vec![
+            Expr::Column("a".into()).into(),
+            Expr::Column("b".into()).into(),
+        ])
+    );
+    let select: Select = "SELECT 1 - -2 FROM t".parse()?;
+    assert_eq!(select.name, "t");
+    assert_eq!(
+        strip_comments("a -- x\n'--' /* y */ \"/*\""),
+        "a  \n'--'   \"/*\""
+    );
+    Ok(())
+}
+
+#[test]
+fn parse_collate() -> Result<()> {
+    let mut parser = Parser::new("name COLLATE nocase = 'x' AND -n COLLATE rtrim IN (1)")?;
+    let expr = parser.expr()?;
+    assert_eq!(
+        expr.to_string(),
+        "name COLLATE NOCASE = 'x' AND -n COLLATE RTRIM IN (1)"
+    );
+    // any name parses, it's only checked when the query runs
+    let expr = Parser::new("a COLLATE Klingon")?.expr()?;
+    assert_eq!(expr.collation(), Some(Collation::Custom("klingon".into())));
+
+    // a column's collation is only added where nothing else says otherwise
+    let declared = |name: &str| match name {
+        "a" => Some(Collation::NoCase),
+        "b" => Some(Collation::Binary),
+        _ => None,
+    };
+    let expr = Parser::new("a = 'x' OR 'x' < a OR b = a OR a = b COLLATE rtrim OR a IN ('x')")?
+        .expr()?
+        .with_collations(&declared)?;
+    assert_eq!(
+        expr.to_string(),
+        "a COLLATE NOCASE = 'x' OR 'x' COLLATE NOCASE < a OR b = a \
+         OR a = b COLLATE RTRIM OR a COLLATE NOCASE IN ('x')"
+    );
+    Ok(())
+}
+
+#[test]
+fn recover_after_syntax_errors() {
+    let parsed = parse_statements(
+        "SELEC 1; SELECT a FROM t WHERE #; SELECT ';' FROM t",
+        &Limits::default(),
+    );
+    let ok: Vec<bool> = parsed.iter().map(|(_, stmt)| stmt.is_ok()).collect();
+    assert_eq!(ok, [false, false, true]);
+    assert_eq!(parsed[2].0, "SELECT ';' FROM t");
+}
+
+#[test]
+fn split_into_statements() {
+    assert_eq!(
+        split_statements("SELECT ';' FROM t; -- a; b\n SELECT [x;y] FROM u;;"),
+        ["SELECT ';' FROM t", "SELECT [x;y] FROM u"]
+    );
+}
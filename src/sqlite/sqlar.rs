@@ -0,0 +1,626 @@
+//! SQLite archives: files kept in an `sqlar` table, the way `sqlite3 -A`
+//! makes them.
+//!
+//! ```sql
+//! CREATE TABLE sqlar(
+//!   name TEXT PRIMARY KEY,  -- path, relative to where it was archived
+//!   mode INT,               -- permissions and file type, as from stat(2)
+//!   mtime INT,              -- last modified, in seconds since 1970
+//!   sz INT,                 -- size before compression, or -1 for a symlink
+//!   data BLOB               -- zlib compressed, unless that didn't make it smaller
+//! );
+//! ```
+//!
+//! There's no zlib crate to lean on, so the compressed blobs are inflated
+//! here.
+
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, bail, Result};
+
+use super::query;
+use super::record::Value;
+use super::{Select, SqliteFile};
+
+/// The file type bits of `mode`.
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFLNK: u32 = 0o120000;
+
+/// A file, directory or symlink in an archive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub name: String,
+    pub mode: u32,
+    pub mtime: i64,
+    /// Bytes in the file once it's inflated.
+    pub size: i64,
+    /// Bytes in the blob as stored.
+    pub stored: usize,
+}
+
+impl Entry {
+    pub fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.mode & S_IFMT == S_IFLNK
+    }
+
+    /// Whether `.ar extract NAME` takes this entry: it's NAME, or in
+    /// directory NAME.
+    fn selected(&self, names: &[&str]) -> bool {
+        names.is_empty()
+            || names.iter().any(|name| {
+                let name = name.trim_end_matches('/');
+                self.name == name
+                    || self
+                        .name
+                        .strip_prefix(name)
+                        .is_some_and(|rest| rest.starts_with('/'))
+            })
+    }
+
+    /// The file's contents, inflated from `data`, or a symlink's target.
+    fn contents(&self, data: Value) -> Result<Vec<u8>> {
+        let data = match data {
+            Value::Null => vec![],
+            Value::Blob(b) => b,
+            Value::String(s) => s.into_bytes(),
+            v => bail!("{}: data is {}, not a blob", self.name, v),
+        };
+        // stored as it is when compressing didn't help
+        if self.is_symlink() || data.len() as i64 == self.size {
+            return Ok(data);
+        }
+        let inflated = inflate(&data).map_err(|e| anyhow!("{}: {}", self.name, e))?;
+        if inflated.len() as i64 != self.size {
+            bail!(
+                "{}: inflated to {} bytes but sz says {}",
+                self.name,
+                inflated.len(),
+                self.size
+            );
+        }
+        Ok(inflated)
+    }
+}
+
+/// Read the rows of the `sqlar` table, in name order, with each row's data.
+fn rows(file: &SqliteFile) -> Result<Vec<(Entry, Value)>> {
+    let select: Select = "SELECT name, mode, mtime, sz, data FROM sqlar".parse()?;
+    let mut rows: Vec<(Entry, Value)> = query::execute(file, &select)
+        .map_err(|e| anyhow!("not an archive: {}", e))?
+        .into_iter()
+        .map(|mut row| {
+            let data = row.pop().unwrap_or(Value::Null);
+            let stored = match &data {
+                Value::Blob(b) => b.len(),
+                Value::String(s) => s.len(),
+                _ => 0,
+            };
+            let entry = Entry {
+                name: row[0].to_string(),
+                mode: i64::from(row[1].clone()) as u32,
+                mtime: i64::from(row[2].clone()),
+                size: i64::from(row[3].clone()),
+                stored,
+            };
+            (entry, data)
+        })
+        .collect();
+    rows.sort_by(|a, b| a.0.name.cmp(&b.0.name));
+    Ok(rows)
+}
+
+/// The files in an archive, in name order.
+pub fn list(file: &SqliteFile) -> Result<Vec<Entry>> {
+    Ok(rows(file)?.into_iter().map(|(entry, _)| entry).collect())
+}
+
+/// Write the files of an archive under `dir`, or only the ones named and
+/// what's in the directories named. Directories are made as they're needed.
+/// Nothing is written through a symlink, so a link in the archive can't send
+/// later entries outside `dir`. Returns what was written.
+pub fn extract(file: &SqliteFile, dir: &Path, names: &[&str]) -> Result<Vec<Entry>> {
+    let rows: Vec<(Entry, Value)> = rows(file)?
+        .into_iter()
+        .filter(|(entry, _)| entry.selected(names))
+        .collect();
+    if let Some(name) = names
+        .iter()
+        .find(|name| !rows.iter().any(|(entry, _)| entry.selected(&[name])))
+    {
+        bail!("{} isn't in the archive", name);
+    }
+    let mut written = vec![];
+    // symlinks after the files, so none of them are written through one, and
+    // directories last, so writing their files doesn't change their times
+    let (dirs, others): (Vec<_>, Vec<_>) = rows.into_iter().partition(|(e, _)| e.is_dir());
+    let (links, files): (Vec<_>, Vec<_>) = others.into_iter().partition(|(e, _)| e.is_symlink());
+    for (entry, data) in files.into_iter().chain(links).chain(dirs.into_iter().rev()) {
+        let relative = relative_path(&entry.name)?;
+        // a symlink that's already there is replaced, not followed
+        if entry.is_symlink() {
+            no_symlinks(dir, relative.parent().unwrap_or(Path::new("")))?;
+        } else {
+            no_symlinks(dir, &relative)?;
+        }
+        let path = dir.join(relative);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        if entry.is_dir() {
+            std::fs::create_dir_all(&path)?;
+        } else if entry.is_symlink() {
+            symlink(&entry.contents(data)?, &path)?;
+            written.push(entry);
+            continue;
+        } else {
+            std::fs::write(&path, entry.contents(data)?)?;
+        }
+        set_mode(&path, entry.mode)?;
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(entry.mtime.max(0) as u64);
+        std::fs::File::open(&path)?.set_modified(mtime)?;
+        written.push(entry);
+    }
+    written.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(written)
+}
+
+/// `name` as a path, as long as it stays inside the directory it's
+/// extracted to.
+fn relative_path(name: &str) -> Result<PathBuf> {
+    let path = PathBuf::from(name);
+    if path.components().all(|c| matches!(c, Component::Normal(_))) {
+        Ok(path)
+    } else {
+        bail!("{}: refusing to extract outside the directory", name)
+    }
+}
+
+/// Fail if `relative`, or a directory on the way to it from `dir`, is a
+/// symlink, since writing there could end up anywhere.
+fn no_symlinks(dir: &Path, relative: &Path) -> Result<()> {
+    let mut path = dir.to_owned();
+    for component in relative.components() {
+        path.push(component);
+        match path.symlink_metadata() {
+            Ok(meta) if meta.file_type().is_symlink() => {
+                bail!("{}: refusing to write through a symlink", path.display())
+            }
+            Ok(_) => {}
+            // nothing here yet, so nothing further down either
+            Err(_) => break,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode & 0o7777))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn symlink(target: &[u8], path: &Path) -> Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    if path.symlink_metadata().is_ok() {
+        std::fs::remove_file(path)?;
+    }
+    std::os::unix::fs::symlink(std::ffi::OsStr::from_bytes(target), path)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn symlink(_target: &[u8], path: &Path) -> Result<()> {
+    bail!("{}: symlinks can only be made on unix", path.display())
+}
+
+/// Inflate a zlib stream (RFC 1950) and check its Adler-32.
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>> {
+    let [cmf, flg, ..] = *data else {
+        bail!("zlib stream is too short");
+    };
+    if cmf & 0x0f != 8 || (u16::from(cmf) << 8 | u16::from(flg)) % 31 != 0 {
+        bail!("not a zlib stream");
+    }
+    if flg & 0x20 != 0 {
+        bail!("zlib stream needs a preset dictionary");
+    }
+    let mut bits = Bits::new(&data[2..]);
+    let out = inflate_raw(&mut bits)?;
+    let end = 2 + bits.byte_pos();
+    let Some(check) = data.get(end..end + 4) else {
+        bail!("zlib stream has no checksum");
+    };
+    if u32::from_be_bytes(check.try_into().unwrap()) != adler32(&out) {
+        bail!("zlib checksum doesn't match");
+    }
+    Ok(out)
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    b << 16 | a
+}
+
+/// Reads a deflate stream a bit at a time, least significant first.
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl<'a> Bits<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Bits {
+            data,
+            pos: 0,
+            bit: 0,
+        }
+    }
+
+    fn bits(&mut self, n: u32) -> Result<u32> {
+        let mut value = 0;
+        for i in 0..n {
+            let Some(&byte) = self.data.get(self.pos) else {
+                bail!("compressed data ends early");
+            };
+            value |= u32::from(byte >> self.bit & 1) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.pos += 1;
+            }
+        }
+        Ok(value)
+    }
+
+    /// Skip to the next whole byte.
+    fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+
+    /// Where the next whole byte starts.
+    fn byte_pos(&self) -> usize {
+        self.pos + usize::from(self.bit != 0)
+    }
+}
+
+/// A canonical Huffman code, as the count of codes of each length and the
+/// symbols in code order.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut symbols: Vec<u16> = (0..lengths.len() as u16)
+            .filter(|&s| lengths[s as usize] != 0)
+            .collect();
+        symbols.sort_by_key(|&s| lengths[s as usize]);
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16> {
+        // codes of each length follow on from the last code of the length
+        // before, so the code read so far is a symbol once it's below the
+        // end of its length's range
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in &self.counts[1..] {
+            code |= bits.bits(1)? as i32;
+            let count = i32::from(count);
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        bail!("bad Huffman code in compressed data")
+    }
+}
+
+/// Lengths for length codes 257 to 285, and the extra bits after each.
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+/// Distances for distance codes 0 to 29, and the extra bits after each.
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// The order code length code lengths come in, in a dynamic block.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Inflate raw deflate blocks (RFC 1951) until the last one.
+fn inflate_raw(bits: &mut Bits) -> Result<Vec<u8>> {
+    let mut out = vec![];
+    loop {
+        let last = bits.bits(1)? == 1;
+        match bits.bits(2)? {
+            0 => {
+                bits.align();
+                let start = bits.pos;
+                let Some(header) = bits.data.get(start..start + 4) else {
+                    bail!("compressed data ends early");
+                };
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                let nlen = u16::from_le_bytes([header[2], header[3]]);
+                if len != !nlen {
+                    bail!("stored block length doesn't match its complement");
+                }
+                let Some(stored) = bits.data.get(start + 4..start + 4 + len as usize) else {
+                    bail!("compressed data ends early");
+                };
+                out.extend_from_slice(stored);
+                bits.pos = start + 4 + len as usize;
+            }
+            1 => {
+                let mut lengths = [0u8; 288];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..].fill(8);
+                let lit = Huffman::new(&lengths);
+                let dist = Huffman::new(&[5; 30]);
+                inflate_block(bits, &lit, &dist, &mut out)?;
+            }
+            2 => {
+                let (lit, dist) = dynamic_codes(bits)?;
+                inflate_block(bits, &lit, &dist, &mut out)?;
+            }
+            _ => bail!("bad block type in compressed data"),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+/// Read the literal/length and distance codes at the start of a dynamic
+/// block.
+fn dynamic_codes(bits: &mut Bits) -> Result<(Huffman, Huffman)> {
+    let nlen = bits.bits(5)? as usize + 257;
+    let ndist = bits.bits(5)? as usize + 1;
+    let ncode = bits.bits(4)? as usize + 4;
+    let mut code_lengths = [0u8; 19];
+    for &i in &CODE_LENGTH_ORDER[..ncode] {
+        code_lengths[i] = bits.bits(3)? as u8;
+    }
+    let code = Huffman::new(&code_lengths);
+    let mut lengths = Vec::with_capacity(nlen + ndist);
+    while lengths.len() < nlen + ndist {
+        let (len, repeat) = match code.decode(bits)? {
+            sym @ 0..=15 => (sym as u8, 1),
+            16 => match lengths.last() {
+                Some(&last) => (last, 3 + bits.bits(2)?),
+                None => bail!("repeated code length with nothing before it"),
+            },
+            17 => (0, 3 + bits.bits(3)?),
+            _ => (0, 11 + bits.bits(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(len, repeat as usize));
+    }
+    if lengths.len() > nlen + ndist {
+        bail!("too many code lengths in compressed data");
+    }
+    Ok((
+        Huffman::new(&lengths[..nlen]),
+        Huffman::new(&lengths[nlen..]),
+    ))
+}
+
+/// Decode literals and back references up to the end of block code.
+fn inflate_block(bits: &mut Bits, lit: &Huffman, dist: &Huffman, out: &mut Vec<u8>) -> Result<()> {
+    loop {
+        let sym = lit.decode(bits)? as usize;
+        match sym {
+            0..=255 => out.push(sym as u8),
+            256 => return Ok(()),
+            257..=285 => {
+                let i = sym - 257;
+                let len = LENGTH_BASE[i] as usize + bits.bits(LENGTH_EXTRA[i].into())? as usize;
+                let d = dist.decode(bits)? as usize;
+                if d >= DIST_BASE.len() {
+                    bail!("bad distance code in compressed data");
+                }
+                let distance = DIST_BASE[d] as usize + bits.bits(DIST_EXTRA[d].into())? as usize;
+                if distance > out.len() {
+                    bail!("back reference before the start of compressed data");
+                }
+                // byte by byte, as the copy can overlap what it's copying
+                let start = out.len() - distance;
+                for i in 0..len {
+                    out.push(out[start + i]);
+                }
+            }
+            _ => bail!("bad length code in compressed data"),
+        }
+    }
+}
+
+#[test]
+fn inflate_zlib() -> Result<()> {
+    let hex = |s: &str| -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    };
+    // zlib.compress() of each, which picks a fixed and a dynamic block
+    let fixed = hex("78dacb48cdc9c957c84027b90070be08bb");
+    assert_eq!(inflate(&fixed)?, b"hello hello hello hello\n");
+    let dynamic = hex(concat!(
+        "78dad58dd915403014055bb91a70ec4b173e341004b13d428254efb5e17b66ced4",
+        "a3c461543ba3d1746fe8e9c164d6fd0459a971315e847bd1d1e0a312ecad2f1a96",
+        "6e758de895958c9cdcb0a8c390e676383d0461142769961725eadf0f3e7be762fb",
+    ));
+    let text = "The quick brown fox jumps over the lazy dog. \
+                Pack my box with five dozen liquor jugs! 0123456789 "
+        .repeat(3);
+    assert_eq!(inflate(&dynamic)?, text.as_bytes());
+    // a stored block
+    let stored = hex("7801010500faff686921212104770135");
+    assert_eq!(inflate(&stored)?, b"hi!!!");
+
+    let mut corrupt = fixed.clone();
+    *corrupt.last_mut().unwrap() ^= 1;
+    assert!(inflate(&corrupt).is_err());
+    assert!(inflate(&fixed[..8]).is_err());
+    Ok(())
+}
+
+#[test]
+fn extract_archive() -> Result<()> {
+    let hex = |s: &str| -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    };
+    let row = |name: &str, mode: i64, sz: i64, data: Value| {
+        vec![
+            name.into(),
+            mode.into(),
+            1_000_000_000.into(),
+            sz.into(),
+            data,
+        ]
+    };
+    let file = super::fixtures::Fixture::new()
+        .table(
+            "CREATE TABLE sqlar(name TEXT PRIMARY KEY, mode INT, mtime INT, sz INT, data BLOB)",
+            vec![
+                row("docs", 0o040755, 0, Value::Null),
+                row(
+                    "docs/plain.txt",
+                    0o100644,
+                    5,
+                    Value::Blob(b"plain".to_vec()),
+                ),
+                row(
+                    "docs/hello.txt",
+                    0o100600,
+                    24,
+                    Value::Blob(hex("78dacb48cdc9c957c84027b90070be08bb")),
+                ),
+                row("top.txt", 0o100644, 3, Value::Blob(b"top".to_vec())),
+            ],
+        )
+        .open()?;
+    let names: Vec<String> = list(&file)?.into_iter().map(|e| e.name).collect();
+    assert_eq!(
+        names,
+        ["docs", "docs/hello.txt", "docs/plain.txt", "top.txt"]
+    );
+
+    let dir = std::env::temp_dir().join(format!("sqlar-test-{}", std::process::id()));
+    let written = extract(&file, &dir, &["docs/"])?;
+    assert_eq!(written.len(), 3);
+    let hello = dir.join("docs/hello.txt");
+    assert_eq!(std::fs::read(&hello)?, b"hello hello hello hello\n");
+    assert_eq!(std::fs::read(dir.join("docs/plain.txt"))?, b"plain");
+    assert!(!dir.join("top.txt").exists());
+    let modified = std::fs::metadata(&hello)?.modified()?;
+    assert_eq!(
+        modified,
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000)
+    );
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(
+            std::fs::metadata(&hello)?.permissions().mode() & 0o777,
+            0o600
+        );
+    }
+    assert!(extract(&file, &dir, &["nope"]).is_err());
+    std::fs::remove_dir_all(&dir)?;
+
+    assert!(relative_path("../etc/passwd").is_err());
+    assert!(relative_path("/etc/passwd").is_err());
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn extract_nothing_through_symlinks() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("sqlar-links-{}", std::process::id()));
+    let outside = dir.join("outside");
+    let into = dir.join("into");
+    std::fs::create_dir_all(&outside)?;
+    let link = |name: &str, target: &Path| {
+        let target = target.to_str().unwrap().as_bytes().to_vec();
+        vec![
+            name.into(),
+            0o120777.into(),
+            0.into(),
+            (-1).into(),
+            Value::Blob(target),
+        ]
+    };
+    let file = super::fixtures::Fixture::new()
+        .table(
+            "CREATE TABLE sqlar(name TEXT PRIMARY KEY, mode INT, mtime INT, sz INT, data BLOB)",
+            vec![
+                link("a", &outside),
+                vec![
+                    "a/passwd".into(),
+                    0o100644.into(),
+                    0.into(),
+                    2.into(),
+                    Value::Blob(b"hi".to_vec()),
+                ],
+            ],
+        )
+        .open()?;
+    assert!(extract(&file, &into, &[]).is_err());
+    assert!(!outside.join("passwd").exists());
+
+    // a link that was there before is no way out either
+    std::fs::remove_dir_all(&into)?;
+    std::fs::create_dir_all(&into)?;
+    std::os::unix::fs::symlink(&outside, into.join("a"))?;
+    assert!(extract(&file, &into, &["a/passwd"]).is_err());
+    assert!(!outside.join("passwd").exists());
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
@@ -0,0 +1,106 @@
+//! Counters for the work queries do, to see why one is slow.
+
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// What a [`SqliteFile`][super::SqliteFile] has done since it was opened or
+/// since [`reset_stats`][super::SqliteFile::reset_stats].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Pages read from the file, including overflow pages. Pages used straight
+    /// out of a memory map count too.
+    pub pages_read: u64,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    /// B-tree cells looked at by scans and lookups.
+    pub cells_parsed: u64,
+    /// Rows returned by queries.
+    pub rows_emitted: u64,
+    /// Bytes read from the file with read calls. Memory mapped pages don't
+    /// count.
+    pub bytes_read: u64,
+}
+
+/// Prints like the sqlite3 shell's `.stats`.
+impl Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Pages read:                          {}",
+            self.pages_read
+        )?;
+        writeln!(
+            f,
+            "Page cache hits:                     {}",
+            self.cache_hits
+        )?;
+        writeln!(
+            f,
+            "Page cache misses:                   {}",
+            self.cache_misses
+        )?;
+        writeln!(
+            f,
+            "Cells parsed:                        {}",
+            self.cells_parsed
+        )?;
+        writeln!(
+            f,
+            "Rows emitted:                        {}",
+            self.rows_emitted
+        )?;
+        write!(
+            f,
+            "Bytes read:                          {}",
+            self.bytes_read
+        )
+    }
+}
+
+/// The live counters. Scans run on several threads, so they're atomic.
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    pub(crate) pages_read: AtomicU64,
+    pub(crate) cache_hits: AtomicU64,
+    pub(crate) cache_misses: AtomicU64,
+    pub(crate) cells_parsed: AtomicU64,
+    pub(crate) rows_emitted: AtomicU64,
+    pub(crate) bytes_read: AtomicU64,
+}
+
+impl Counters {
+    fn all(&self) -> [&AtomicU64; 6] {
+        [
+            &self.pages_read,
+            &self.cache_hits,
+            &self.cache_misses,
+            &self.cells_parsed,
+            &self.rows_emitted,
+            &self.bytes_read,
+        ]
+    }
+
+    pub(crate) fn snapshot(&self) -> Stats {
+        let [pages_read, cache_hits, cache_misses, cells_parsed, rows_emitted, bytes_read] =
+            self.all().map(|c| c.load(Ordering::Relaxed));
+        Stats {
+            pages_read,
+            cache_hits,
+            cache_misses,
+            cells_parsed,
+            rows_emitted,
+            bytes_read,
+        }
+    }
+
+    pub(crate) fn reset(&self) {
+        for counter in self.all() {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Add `n` to a counter.
+pub(crate) fn add(counter: &AtomicU64, n: u64) {
+    counter.fetch_add(n, Ordering::Relaxed);
+}
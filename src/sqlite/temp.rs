@@ -0,0 +1,153 @@
+//! Temp tables: results of a query kept in memory for as long as the file is
+//! open, so they can be queried and joined like any other table without
+//! running the query again. Nothing is written to the file.
+
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+
+use super::json::Json;
+use super::record::{Row, Value};
+use super::CreateTable;
+
+/// A table whose rows are all in memory.
+#[derive(Debug)]
+pub struct TempTable {
+    pub def: CreateTable,
+    /// In rowid order, counting from 1.
+    pub rows: Arc<Vec<Row>>,
+}
+
+impl TempTable {
+    /// A table called `name` holding `rows`, with a column for each of
+    /// `columns`. Names that come up more than once get `:1`, `:2` and so on
+    /// added, like `CREATE TABLE ... AS SELECT` does. The columns have no type.
+    pub fn new(name: &str, columns: &[String], rows: Vec<Vec<Value>>) -> Result<Self> {
+        let mut names: Vec<String> = vec![];
+        for column in columns {
+            let mut unique = column.clone();
+            let mut n = 0;
+            while names.iter().any(|c| c.eq_ignore_ascii_case(&unique)) {
+                n += 1;
+                unique = format!("{}:{}", column, n);
+            }
+            names.push(unique);
+        }
+        let quote = |name: &str| format!("\"{}\"", name.replace('"', "\"\""));
+        let sql = format!(
+            "CREATE TABLE {} ({})",
+            quote(name),
+            names
+                .iter()
+                .map(|c| quote(c))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        let rows = rows
+            .into_iter()
+            .zip(1..)
+            .map(|(values, rowid)| Row { rowid, values })
+            .collect();
+        Ok(TempTable {
+            def: sql.parse()?,
+            rows: Arc::new(rows),
+        })
+    }
+
+    /// A table called `name` from an array of JSON objects, with a column for
+    /// each key in the order they first come up.
+    pub fn from_json(name: &str, json: &Json) -> Result<Self> {
+        let mut columns = vec![];
+        let rows = json_rows(&mut columns, json, true)?;
+        if columns.is_empty() {
+            bail!("no columns to import into {}", name);
+        }
+        TempTable::new(name, &columns, rows)
+    }
+
+    /// This table with the objects in a JSON array added on the end. Each key
+    /// goes in the column of the same name, and columns an object doesn't
+    /// have are `NULL`.
+    pub fn append_json(&self, json: &Json) -> Result<Self> {
+        let mut columns = self.def.columns.clone();
+        let mut rows = self.rows.to_vec();
+        let start = rows.last().map_or(0, |row| row.rowid);
+        let added = json_rows(&mut columns, json, false)?;
+        rows.extend(
+            added
+                .into_iter()
+                .zip(start + 1..)
+                .map(|(values, rowid)| Row { rowid, values }),
+        );
+        Ok(TempTable {
+            def: self.def.clone(),
+            rows: Arc::new(rows),
+        })
+    }
+}
+
+/// The values for each object in `json`, in the order of `columns`. Keys
+/// that aren't columns yet are added if `add_columns`, and are an error if
+/// not.
+fn json_rows(columns: &mut Vec<String>, json: &Json, add_columns: bool) -> Result<Vec<Vec<Value>>> {
+    let Json::Array(items) = json else {
+        bail!("expected a JSON array of objects");
+    };
+    let mut rows = vec![];
+    for item in items {
+        let Json::Object(fields) = item else {
+            bail!("expected a JSON array of objects, found {}", item);
+        };
+        let mut values = vec![Value::Null; columns.len()];
+        for (key, value) in fields {
+            let i = match columns.iter().position(|c| c.eq_ignore_ascii_case(key)) {
+                Some(i) => i,
+                None if add_columns => {
+                    columns.push(key.clone());
+                    values.push(Value::Null);
+                    columns.len() - 1
+                }
+                None => bail!("table has no column named {}", key),
+            };
+            values[i] = value.to_value();
+        }
+        rows.push(values);
+    }
+    for values in &mut rows {
+        values.resize(columns.len(), Value::Null);
+    }
+    Ok(rows)
+}
+
+#[test]
+fn duplicate_column_names() -> Result<()> {
+    let columns = ["id", "name", "ID"].map(String::from);
+    let table = TempTable::new("my \"t\"", &columns, vec![vec![Value::Integer(7)]])?;
+    assert_eq!(table.def.name, "my \"t\"");
+    assert_eq!(table.def.columns, ["id", "name", "ID:1"]);
+    assert_eq!(table.rows[0].rowid, 1);
+    Ok(())
+}
+
+#[test]
+fn import_json() -> Result<()> {
+    let json: Json = r#"[{"id": 1, "tags": ["a"]}, {"name": "x", "ID": 2}]"#.parse()?;
+    let table = TempTable::from_json("t", &json)?;
+    assert_eq!(table.def.columns, ["id", "tags", "name"]);
+    let values = |t: &TempTable| t.rows.iter().map(|r| r.values.clone()).collect::<Vec<_>>();
+    assert_eq!(
+        values(&table),
+        [
+            vec![1.into(), "[\"a\"]".into(), Value::Null],
+            vec![2.into(), Value::Null, "x".into()],
+        ]
+    );
+    let more = table.append_json(&r#"[{"NAME": "y", "tags": true}]"#.parse()?)?;
+    assert_eq!(more.rows[2].rowid, 3);
+    assert_eq!(more.rows[2].values, [Value::Null, 1.into(), "y".into()]);
+    assert!(table
+        .append_json(&r#"[{"colour": "red"}]"#.parse()?)
+        .is_err());
+    assert!(TempTable::from_json("t", &"[{}]".parse()?).is_err());
+    Ok(())
+}
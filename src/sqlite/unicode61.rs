@@ -0,0 +1,174 @@
+//! The rules of SQLite's `unicode61` full-text tokenizer, for reading FTS
+//! tables and searching text the same way SQLite would.
+//!
+//! Letters and digits make up tokens and everything else separates them.
+//! Tokens are folded to lower case and, by default, lose their diacritics, so
+//! `Café` and `cafe` are the same token.
+
+use anyhow::{bail, Result};
+
+/// A token and where it was in the text, as byte offsets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub text: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// A `unicode61` tokenizer with its options.
+#[derive(Debug, Clone)]
+pub struct Unicode61 {
+    remove_diacritics: bool,
+    /// Characters that are part of tokens even though they aren't letters or
+    /// digits.
+    token_chars: Vec<char>,
+    /// Characters that separate tokens even though they're letters or digits.
+    separators: Vec<char>,
+}
+
+impl Default for Unicode61 {
+    fn default() -> Self {
+        Unicode61 {
+            remove_diacritics: true,
+            token_chars: vec![],
+            separators: vec![],
+        }
+    }
+}
+
+impl Unicode61 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read the options given after `tokenize = 'unicode61 ...'` in a
+    /// `CREATE VIRTUAL TABLE`, e.g. `["remove_diacritics", "0"]`.
+    pub fn from_args(args: &[&str]) -> Result<Self> {
+        let mut tokenizer = Self::default();
+        if !args.len().is_multiple_of(2) {
+            bail!("unicode61 options come in pairs");
+        }
+        for pair in args.chunks(2) {
+            match (pair[0], pair[1]) {
+                ("remove_diacritics", "0") => tokenizer.remove_diacritics(false),
+                // 2 also fixes diacritics that 1 misses, which we never miss
+                ("remove_diacritics", "1" | "2") => tokenizer.remove_diacritics(true),
+                ("tokenchars", chars) => tokenizer.token_chars(chars),
+                ("separators", chars) => tokenizer.separators(chars),
+                (option, value) => bail!("bad unicode61 option: {} {}", option, value),
+            };
+        }
+        Ok(tokenizer)
+    }
+
+    pub fn remove_diacritics(&mut self, remove: bool) -> &mut Self {
+        self.remove_diacritics = remove;
+        self
+    }
+
+    /// Make these characters part of tokens.
+    pub fn token_chars(&mut self, chars: &str) -> &mut Self {
+        self.token_chars.extend(chars.chars());
+        self.separators.retain(|c| !chars.contains(*c));
+        self
+    }
+
+    /// Split tokens on these characters.
+    pub fn separators(&mut self, chars: &str) -> &mut Self {
+        self.separators.extend(chars.chars());
+        self.token_chars.retain(|c| !chars.contains(*c));
+        self
+    }
+
+    /// Whether `c` is part of a token rather than a separator.
+    pub fn is_token_char(&self, c: char) -> bool {
+        if self.separators.contains(&c) {
+            return false;
+        }
+        c.is_alphanumeric() || is_combining(c) || is_private_use(c) || self.token_chars.contains(&c)
+    }
+
+    /// Split `text` into tokens, folded the way SQLite folds them.
+    pub fn tokenize<'a>(&'a self, text: &'a str) -> impl Iterator<Item = Token> + 'a {
+        let mut chars = text.char_indices().peekable();
+        std::iter::from_fn(move || {
+            while chars.next_if(|&(_, c)| !self.is_token_char(c)).is_some() {}
+            let &(start, _) = chars.peek()?;
+            let mut end = start;
+            let mut folded = String::new();
+            while let Some((i, c)) = chars.next_if(|&(_, c)| self.is_token_char(c)) {
+                end = i + c.len_utf8();
+                for c in c.to_lowercase() {
+                    self.fold_into(c, &mut folded);
+                }
+            }
+            Some(Token {
+                text: folded,
+                start,
+                end,
+            })
+        })
+    }
+
+    fn fold_into(&self, c: char, out: &mut String) {
+        if !self.remove_diacritics {
+            out.push(c);
+        } else if !is_combining(c) {
+            out.push(remove_diacritic(c));
+        }
+    }
+}
+
+fn is_combining(c: char) -> bool {
+    matches!(c, '\u{300}'..='\u{36f}')
+}
+
+/// SQLite counts private use characters as letters.
+fn is_private_use(c: char) -> bool {
+    matches!(c, '\u{e000}'..='\u{f8ff}')
+}
+
+/// Base letters of U+00C0 to U+017F, for the letters that are a base letter
+/// plus accents. Letters like `ø` and `ł` aren't, so they stay as they are.
+const LATIN_BASE: &str = concat!(
+    "AAAAAAÆCEEEEIIIIÐNOOOOO×ØUUUUYÞß",
+    "aaaaaaæceeeeiiiiðnooooo÷øuuuuyþy",
+    "AaAaAaCcCcCcCcDdĐđEeEeEeEeEeGgGg",
+    "GgGgHhĦħIiIiIiIiIıĲĳJjKkĸLlLlLlĿ",
+    "ŀŁłNnNnNnŉŊŋOoOoOoŒœRrRrRrSsSsSs",
+    "SsTtTtŦŧUuUuUuUuUuUuWwYyYZzZzZzſ",
+);
+
+fn remove_diacritic(c: char) -> char {
+    match c {
+        '\u{c0}'..='\u{17f}' => LATIN_BASE.chars().nth(c as usize - 0xc0).unwrap_or(c),
+        _ => c,
+    }
+}
+
+#[test]
+fn unicode61_tokens() -> Result<()> {
+    let words = |t: &Unicode61, s| t.tokenize(s).map(|t| t.text).collect::<Vec<_>>();
+    let t = Unicode61::new();
+    assert_eq!(
+        words(&t, "Crème Brûlée, naïve  façade-42"),
+        ["creme", "brulee", "naive", "facade", "42"]
+    );
+    assert_eq!(words(&t, "ÉCOLE Ørsted"), ["ecole", "ørsted"]);
+    // a combining accent is part of the token, then dropped
+    assert_eq!(words(&t, "cafe\u{301}"), ["cafe"]);
+    let tokens: Vec<Token> = t.tokenize(" héllo!").collect();
+    assert_eq!((tokens[0].start, tokens[0].end), (1, 7));
+
+    let t = Unicode61::from_args(&[
+        "remove_diacritics",
+        "0",
+        "tokenchars",
+        "-",
+        "separators",
+        "x",
+    ])?;
+    assert_eq!(words(&t, "Crème-brûlée box"), ["crème-brûlée", "bo"]);
+    assert!(Unicode61::from_args(&["remove_diacritics"]).is_err());
+    Ok(())
+}
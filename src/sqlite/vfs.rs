@@ -0,0 +1,243 @@
+//! Where a database's bytes come from. The pager only ever asks a [`VfsFile`]
+//! to read bytes at an offset, so a backend that keeps them somewhere other
+//! than a local file, like memory or a server, only has to implement that.
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::RwLock;
+
+use anyhow::Result;
+
+use super::OpenError;
+
+/// Opens database files by name, for [`OpenOptions::vfs`][super::OpenOptions::vfs].
+pub trait Vfs: std::fmt::Debug + Send + Sync {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>>;
+
+    /// Whether there could be a rollback journal or WAL next to `path` that
+    /// opening should check for. Only files on disk have them.
+    fn has_journal(&self) -> bool {
+        false
+    }
+}
+
+/// An open database file.
+pub trait VfsFile: Send + Sync {
+    /// Fill `buf` from `offset`, or fail with `UnexpectedEof` if the file
+    /// isn't that long.
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()>;
+
+    /// Write all of `data` at `offset`, growing the file if needed.
+    fn write_all_at(&self, data: &[u8], offset: u64) -> io::Result<()> {
+        let _ = (data, offset);
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// How many bytes long the file is.
+    fn len(&self) -> io::Result<u64>;
+
+    fn is_empty(&self) -> io::Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Take a shared lock, so nobody writes while we read. Without `wait`
+    /// this fails with [`OpenError::WouldBlock`] instead of waiting for a
+    /// writer to finish. Nothing to lock by default.
+    fn lock_shared(&self, wait: bool) -> Result<()> {
+        let _ = wait;
+        Ok(())
+    }
+
+    /// The local file, if this is one, for memory mapping it.
+    fn as_file(&self) -> Option<&File> {
+        None
+    }
+}
+
+/// Files on disk, the default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OsVfs;
+
+impl Vfs for OsVfs {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        Ok(Box::new(File::open(path)?))
+    }
+
+    fn has_journal(&self) -> bool {
+        true
+    }
+}
+
+impl VfsFile for File {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        read_exact_at(self, buf, offset)
+    }
+
+    fn write_all_at(&self, data: &[u8], offset: u64) -> io::Result<()> {
+        write_all_at(self, data, offset)
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    /// Locks the same bytes SQLite does, so we wait for sqlite3 and it
+    /// waits for us.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    fn lock_shared(&self, wait: bool) -> Result<()> {
+        match super::lock::lock_shared(self, wait) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Err(OpenError::WouldBlock.into()),
+            result => Ok(result?),
+        }
+    }
+
+    fn as_file(&self) -> Option<&File> {
+        Some(self)
+    }
+}
+
+/// A file kept in memory, for `:memory:` and
+/// [`SqliteFile::from_bytes`][super::SqliteFile::from_bytes].
+#[derive(Debug, Default)]
+pub struct MemoryFile {
+    data: RwLock<Vec<u8>>,
+}
+
+impl MemoryFile {
+    pub fn new(data: Vec<u8>) -> Self {
+        MemoryFile {
+            data: RwLock::new(data),
+        }
+    }
+}
+
+impl VfsFile for MemoryFile {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        let data = self.data.read().unwrap();
+        let bytes = usize::try_from(offset)
+            .ok()
+            .and_then(|start| data.get(start..)?.get(..buf.len()));
+        let bytes = bytes.ok_or(io::ErrorKind::UnexpectedEof)?;
+        buf.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn write_all_at(&self, bytes: &[u8], offset: u64) -> io::Result<()> {
+        let start = usize::try_from(offset).map_err(|_| io::ErrorKind::OutOfMemory)?;
+        let mut data = self.data.write().unwrap();
+        if data.len() < start + bytes.len() {
+            data.resize(start + bytes.len(), 0);
+        }
+        data[start..start + bytes.len()].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.data.read().unwrap().len() as u64)
+    }
+}
+
+/// Fill `buf` from `offset` in the file. Uses positioned reads where the
+/// platform has them, so the file's cursor is never touched.
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    FileExt::read_exact_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn read_exact_at(mut file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    use std::io::{Read, Seek, SeekFrom};
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buf)
+}
+
+#[cfg(unix)]
+fn write_all_at(file: &File, data: &[u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    FileExt::write_all_at(file, data, offset)
+}
+
+#[cfg(windows)]
+fn write_all_at(file: &File, mut data: &[u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !data.is_empty() {
+        match file.seek_write(data, offset) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => {
+                data = &data[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn write_all_at(mut file: &File, data: &[u8], offset: u64) -> io::Result<()> {
+    use std::io::{Seek, SeekFrom, Write};
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(data)
+}
+
+#[test]
+fn custom_vfs() -> Result<()> {
+    use super::record::Value;
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    /// Databases bundled into the program, by name.
+    #[derive(Debug)]
+    struct Bundled(HashMap<PathBuf, Vec<u8>>);
+
+    impl Vfs for Bundled {
+        fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+            let data = self.0.get(path).ok_or(io::ErrorKind::NotFound)?;
+            Ok(Box::new(MemoryFile::new(data.clone())))
+        }
+    }
+
+    let bundled = Bundled(HashMap::from([(
+        PathBuf::from("apples.db"),
+        std::fs::read("sample.db")?,
+    )]));
+    let db = super::OpenOptions::new()
+        .vfs(std::sync::Arc::new(bundled))
+        .open("apples.db")?;
+    assert_eq!(
+        db.prepare("SELECT COUNT(*) FROM apples")?.rows()?,
+        [vec![Value::Integer(4)]]
+    );
+    let missing = super::OpenOptions::new()
+        .vfs(std::sync::Arc::new(Bundled(HashMap::new())))
+        .open("apples.db");
+    assert!(missing.is_err());
+
+    let file = MemoryFile::new(vec![1, 2]);
+    file.write_all_at(&[3, 4], 1)?;
+    let mut buf = [0; 3];
+    file.read_exact_at(&mut buf, 0)?;
+    assert_eq!(buf, [1, 3, 4]);
+    assert!(file.read_exact_at(&mut buf, 1).is_err());
+    Ok(())
+}
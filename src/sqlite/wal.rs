@@ -0,0 +1,591 @@
+//! Change data capture from a WAL file written by another program. Each
+//! committed transaction in the WAL is applied to a copy of the database in
+//! memory, and the rows on the table leaf pages it touched are compared with
+//! the copy from before, which turns page writes back into inserts, updates
+//! and deletes. See <https://www.sqlite.org/fileformat.html#the_write_ahead_log>.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::num::NonZeroU64;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Result};
+
+use super::btree::walk;
+use super::cells::Cell;
+use super::record::Value;
+use super::{CreateTable, PageKind, SchemaType, SqliteFile};
+
+const HEADER_SIZE: usize = 32;
+const FRAME_HEADER_SIZE: usize = 24;
+
+/// What a writer has committed to a WAL, read a transaction at a time.
+#[derive(Debug, Clone)]
+pub struct Wal {
+    header: [u8; HEADER_SIZE],
+    page_size: usize,
+    /// Checksums are of big-endian words, rather than little-endian ones.
+    big_endian: bool,
+    /// The running checksum as of the last commit read.
+    checksum: (u32, u32),
+    /// Where the frame after the last commit read starts.
+    offset: u64,
+}
+
+/// One committed transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Commit {
+    /// The pages it wrote, by page number. A page written twice has the later
+    /// version.
+    pub pages: BTreeMap<u32, Vec<u8>>,
+    /// How many pages the database has afterwards.
+    pub page_count: u32,
+}
+
+impl Wal {
+    /// Check the 32-byte header at the start of a WAL file.
+    pub fn new(header: &[u8]) -> Result<Self> {
+        let header: [u8; HEADER_SIZE] = header
+            .get(..HEADER_SIZE)
+            .and_then(|h| h.try_into().ok())
+            .ok_or_else(|| anyhow!("WAL header is too short"))?;
+        let big_endian = match u32_at(&header, 0) {
+            0x377f0682 => false,
+            0x377f0683 => true,
+            magic => bail!("not a WAL file: magic number {:#x}", magic),
+        };
+        let page_size = match u32_at(&header, 8) {
+            1 => 65536,
+            n if (512..=32768).contains(&n) && n.is_power_of_two() => n as usize,
+            n => bail!("bad page size {} in WAL header", n),
+        };
+        let checksum = checksum(&header[..24], big_endian, (0, 0));
+        if checksum != (u32_at(&header, 24), u32_at(&header, 28)) {
+            bail!("WAL header checksum doesn't match");
+        }
+        Ok(Wal {
+            header,
+            page_size,
+            big_endian,
+            checksum,
+            offset: HEADER_SIZE as u64,
+        })
+    }
+
+    pub fn header(&self) -> &[u8] {
+        &self.header
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// Where reading picks up from, just past the last commit read.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// The transactions committed in `data`, which is the file from
+    /// [`offset`][Self::offset] on. Reading stops at the first frame that
+    /// isn't valid, which is the end of the log, and a transaction still
+    /// being written is left for next time.
+    pub fn commits(&mut self, data: &[u8]) -> Vec<Commit> {
+        let frame_size = FRAME_HEADER_SIZE + self.page_size;
+        let mut commits = vec![];
+        let mut pages = BTreeMap::new();
+        let mut checksum = self.checksum;
+        let start = self.offset;
+        for (i, frame) in data.chunks_exact(frame_size).enumerate() {
+            // frames left over from before the log was restarted have the
+            // old salt
+            if frame[8..16] != self.header[16..24] {
+                break;
+            }
+            checksum = self::checksum(&frame[..8], self.big_endian, checksum);
+            checksum = self::checksum(&frame[FRAME_HEADER_SIZE..], self.big_endian, checksum);
+            if checksum != (u32_at(frame, 16), u32_at(frame, 20)) {
+                break;
+            }
+            pages.insert(u32_at(frame, 0), frame[FRAME_HEADER_SIZE..].to_vec());
+            let page_count = u32_at(frame, 4);
+            if page_count != 0 {
+                commits.push(Commit {
+                    pages: std::mem::take(&mut pages),
+                    page_count,
+                });
+                self.checksum = checksum;
+                self.offset = start + ((i + 1) * frame_size) as u64;
+            }
+        }
+        commits
+    }
+}
+
+/// Whether the WAL next to the database at `path` has commits that aren't in
+/// the database file yet. A WAL that's there but empty, or whose commits the
+/// `-shm` index says have all been copied back, is normal for a database in
+/// WAL mode that someone has open.
+pub fn uncheckpointed(path: &Path) -> Result<bool> {
+    let sidecar = |suffix: &str| {
+        let mut p = path.as_os_str().to_owned();
+        p.push(suffix);
+        PathBuf::from(p)
+    };
+    let data = match std::fs::read(sidecar("-wal")) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+    // without a good header there are no frames either
+    let Ok(mut wal) = Wal::new(&data) else {
+        return Ok(false);
+    };
+    if wal.commits(&data[HEADER_SIZE..]).is_empty() {
+        return Ok(false);
+    }
+    let frames = (wal.offset() - HEADER_SIZE as u64) / (FRAME_HEADER_SIZE + wal.page_size) as u64;
+    // the checkpoint info after the two copies of the index header says how
+    // many frames have been copied back, in the writer's byte order
+    let backfilled = std::fs::read(sidecar("-shm"))
+        .ok()
+        .and_then(|shm| Some(u32::from_ne_bytes(shm.get(96..100)?.try_into().ok()?)));
+    Ok(backfilled.is_none_or(|n| (n as u64) < frames))
+}
+
+fn u32_at(data: &[u8], i: usize) -> u32 {
+    u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]])
+}
+
+/// SQLite's WAL checksum, carried on from `sum`.
+fn checksum(data: &[u8], big_endian: bool, mut sum: (u32, u32)) -> (u32, u32) {
+    let word = |b: &[u8]| {
+        let b = [b[0], b[1], b[2], b[3]];
+        if big_endian {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        }
+    };
+    for pair in data.chunks_exact(8) {
+        sum.0 = sum.0.wrapping_add(word(&pair[..4])).wrapping_add(sum.1);
+        sum.1 = sum.1.wrapping_add(word(&pair[4..])).wrapping_add(sum.0);
+    }
+    sum
+}
+
+/// What happened to a row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Insert,
+    Update,
+    Delete,
+}
+
+impl ChangeKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            ChangeKind::Insert => "insert",
+            ChangeKind::Update => "update",
+            ChangeKind::Delete => "delete",
+        }
+    }
+}
+
+/// A row that a transaction inserted, updated or deleted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub table: String,
+    pub kind: ChangeKind,
+    pub rowid: i64,
+    pub columns: Vec<String>,
+    /// The row before, empty for an insert.
+    pub old: Vec<Value>,
+    /// The row after, empty for a delete.
+    pub new: Vec<Value>,
+}
+
+/// The database as of some commit, with which table each page belongs to.
+struct Snapshot {
+    file: SqliteFile,
+    tables: HashMap<String, Table>,
+    /// Table leaf and interior pages of each rowid table, by page number.
+    /// `WITHOUT ROWID` tables are index B-trees and aren't included.
+    owners: HashMap<u64, String>,
+}
+
+struct Table {
+    columns: Vec<String>,
+    /// Which column is the `INTEGER PRIMARY KEY`, stored as `NULL` in the
+    /// record since it's the rowid.
+    key: Option<usize>,
+}
+
+impl Snapshot {
+    fn new(data: Vec<u8>) -> Result<Self> {
+        let file = SqliteFile::from_bytes(data)?;
+        let mut tables = HashMap::new();
+        let mut owners = HashMap::new();
+        for schema in file.get_schema()? {
+            let Some(root) = NonZeroU64::new(schema.rootpage) else {
+                continue;
+            };
+            if schema.stype != SchemaType::Table {
+                continue;
+            }
+            let def: CreateTable = schema.sql.parse()?;
+            let key = def.key.as_deref().and_then(|key| def.column_index(key));
+            walk(&file, root, |page, _| {
+                owners.insert(page.page_id, schema.name.clone());
+                Ok(())
+            })?;
+            tables.insert(
+                schema.name.clone(),
+                Table {
+                    columns: def.columns,
+                    key,
+                },
+            );
+        }
+        Ok(Snapshot {
+            file,
+            tables,
+            owners,
+        })
+    }
+
+    fn data(&self) -> Result<Vec<u8>> {
+        let page_size = self.file.page_size() as usize;
+        let mut data = vec![0; self.file.file_page_count()? as usize * page_size];
+        for (i, page) in data.chunks_exact_mut(page_size).enumerate() {
+            page.copy_from_slice(&self.file.read_raw(NonZeroU64::new(i as u64 + 1).unwrap())?);
+        }
+        Ok(data)
+    }
+
+    /// Add the rows on `page` to `rows`, if it's a table leaf page.
+    fn read_rows(
+        &self,
+        page: u64,
+        rows: &mut BTreeMap<String, BTreeMap<i64, Vec<Value>>>,
+    ) -> Result<()> {
+        let Some(name) = self.owners.get(&page) else {
+            return Ok(());
+        };
+        let page = self.file.get_page(NonZeroU64::new(page).unwrap())?;
+        if page.header.kind != PageKind::TableLeaf {
+            return Ok(());
+        }
+        let table = &self.tables[name];
+        let rows = rows.entry(name.clone()).or_default();
+        for cell in page.cells() {
+            if let Cell::TableLeaf { rowid, payload } = cell {
+                let mut values = self.file.read_record(&payload)?;
+                values.resize(table.columns.len(), Value::Null);
+                if let Some(key) = table.key {
+                    values[key] = Value::Integer(rowid as i64);
+                }
+                rows.insert(rowid as i64, values);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The rows that differ between two versions of the database, looking only
+/// at `pages` and any page that moved to another table or out of one, like
+/// when a table is dropped or emptied.
+fn diff(
+    old: &Snapshot,
+    new: &Snapshot,
+    pages: impl IntoIterator<Item = u64>,
+) -> Result<Vec<Change>> {
+    let mut pages: Vec<u64> = pages.into_iter().collect();
+    for (page, table) in &old.owners {
+        if new.owners.get(page) != Some(table) {
+            pages.push(*page);
+        }
+    }
+    for (page, table) in &new.owners {
+        if old.owners.get(page) != Some(table) {
+            pages.push(*page);
+        }
+    }
+    pages.sort_unstable();
+    pages.dedup();
+    let (mut before, mut after) = (BTreeMap::new(), BTreeMap::new());
+    for &page in &pages {
+        old.read_rows(page, &mut before)?;
+        new.read_rows(page, &mut after)?;
+    }
+
+    let mut changes = vec![];
+    let names: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+    let empty = BTreeMap::new();
+    for name in names {
+        let rows_before = before.get(name).unwrap_or(&empty);
+        let rows_after = after.get(name).unwrap_or(&empty);
+        let columns = match new.tables.get(name).or_else(|| old.tables.get(name)) {
+            Some(table) => table.columns.clone(),
+            None => vec![],
+        };
+        let mut rowids: Vec<i64> = rows_before
+            .keys()
+            .chain(rows_after.keys())
+            .copied()
+            .collect();
+        rowids.sort_unstable();
+        rowids.dedup();
+        for rowid in rowids {
+            let (kind, old_row, new_row) = match (rows_before.get(&rowid), rows_after.get(&rowid)) {
+                (Some(a), Some(b)) if a == b => continue,
+                (Some(a), Some(b)) => (ChangeKind::Update, a.clone(), b.clone()),
+                (Some(a), None) => (ChangeKind::Delete, a.clone(), vec![]),
+                (None, Some(b)) => (ChangeKind::Insert, vec![], b.clone()),
+                (None, None) => unreachable!(),
+            };
+            changes.push(Change {
+                table: name.clone(),
+                kind,
+                rowid,
+                columns: columns.clone(),
+                old: old_row,
+                new: new_row,
+            });
+        }
+    }
+    Ok(changes)
+}
+
+/// Follows a database's WAL file as another program writes to it, turning
+/// each commit into the rows it changed.
+pub struct WalTail {
+    path: PathBuf,
+    wal_path: PathBuf,
+    snapshot: Snapshot,
+    wal: Option<Wal>,
+}
+
+impl WalTail {
+    /// Start from what's in the database file, so the first
+    /// [`poll`][Self::poll] has everything in the WAL so far.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_owned();
+        let mut wal_path = path.clone().into_os_string();
+        wal_path.push("-wal");
+        let snapshot = Snapshot::new(std::fs::read(&path)?)?;
+        Ok(WalTail {
+            path,
+            wal_path: wal_path.into(),
+            snapshot,
+            wal: None,
+        })
+    }
+
+    /// The rows changed by transactions committed since the last poll.
+    ///
+    /// When the writer checkpoints and starts the WAL over, whatever it
+    /// committed in between is found by comparing the database file with
+    /// the copy in memory instead.
+    pub fn poll(&mut self) -> Result<Vec<Change>> {
+        let mut header = [0; HEADER_SIZE];
+        let mut file = match File::open(&self.wal_path) {
+            Ok(mut file) => file.read_exact(&mut header).ok().map(|_| file),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        let mut changes = vec![];
+        if file.is_none() || self.wal.as_ref().is_some_and(|wal| wal.header() != header) {
+            self.wal = None;
+        }
+        if self.wal.is_none() {
+            changes.extend(self.reload()?);
+            if file.is_some() {
+                self.wal = Some(Wal::new(&header)?);
+            }
+        }
+        let (Some(wal), Some(file)) = (&mut self.wal, &mut file) else {
+            return Ok(changes);
+        };
+        let mut data = vec![];
+        file.seek(SeekFrom::Start(wal.offset()))?;
+        file.read_to_end(&mut data)?;
+        for commit in wal.commits(&data) {
+            let mut bytes = self.snapshot.data()?;
+            bytes.resize(commit.page_count as usize * wal.page_size(), 0);
+            for (&page, content) in &commit.pages {
+                let start = (page as usize - 1) * wal.page_size();
+                if let Some(slot) = bytes.get_mut(start..start + wal.page_size()) {
+                    slot.copy_from_slice(content);
+                }
+            }
+            let next = Snapshot::new(bytes)?;
+            changes.extend(diff(
+                &self.snapshot,
+                &next,
+                commit.pages.keys().map(|&page| page as u64),
+            )?);
+            self.snapshot = next;
+        }
+        Ok(changes)
+    }
+
+    /// Catch up with the database file, after the WAL was checkpointed into
+    /// it.
+    fn reload(&mut self) -> Result<Vec<Change>> {
+        let bytes = std::fs::read(&self.path)?;
+        let old = self.snapshot.data()?;
+        if bytes == old {
+            return Ok(vec![]);
+        }
+        let page_size = self.snapshot.file.page_size() as usize;
+        let pages = bytes.len().max(old.len()) / page_size;
+        let changed = (0..pages).filter(|&i| {
+            let range = i * page_size..(i + 1) * page_size;
+            bytes.get(range.clone()) != old.get(range)
+        });
+        let changed: Vec<u64> = changed.map(|i| i as u64 + 1).collect();
+        let next = Snapshot::new(bytes)?;
+        let changes = diff(&self.snapshot, &next, changed)?;
+        self.snapshot = next;
+        Ok(changes)
+    }
+}
+
+/// A WAL with the header SQLite writes, and a commit for each of `images`
+/// that writes every page of it.
+#[cfg(test)]
+fn build_wal(page_size: usize, images: &[&[u8]]) -> Vec<u8> {
+    let mut wal = vec![];
+    for n in [0x377f0682, 3007000, page_size as u32, 0, 0x1234, 0x5678] {
+        wal.extend_from_slice(&u32::to_be_bytes(n));
+    }
+    let mut sum = checksum(&wal, false, (0, 0));
+    wal.extend_from_slice(&sum.0.to_be_bytes());
+    wal.extend_from_slice(&sum.1.to_be_bytes());
+    for image in images {
+        let page_count = image.len() / page_size;
+        for (i, data) in image.chunks(page_size).enumerate() {
+            let mut frame = vec![];
+            frame.extend_from_slice(&(i as u32 + 1).to_be_bytes());
+            let commit = if i + 1 == page_count { page_count } else { 0 };
+            frame.extend_from_slice(&(commit as u32).to_be_bytes());
+            frame.extend_from_slice(&wal[16..24]);
+            sum = checksum(&frame[..8], false, sum);
+            sum = checksum(data, false, sum);
+            frame.extend_from_slice(&sum.0.to_be_bytes());
+            frame.extend_from_slice(&sum.1.to_be_bytes());
+            frame.extend_from_slice(data);
+            wal.extend(frame);
+        }
+    }
+    wal
+}
+
+#[test]
+fn tail_wal() -> Result<()> {
+    use super::fixtures::Fixture;
+    let db = |rows: &[(i64, &str)]| {
+        let rows = rows
+            .iter()
+            .map(|&(id, name)| vec![id.into(), name.into()])
+            .collect();
+        Fixture::new()
+            .page_size(512)
+            .table("CREATE TABLE t (id INTEGER PRIMARY KEY, name)", rows)
+            .build()
+    };
+    let first = db(&[(1, "a"), (2, "b"), (3, "c")])?;
+    let second = db(&[(1, "a"), (2, "B"), (4, "d")])?;
+    let third = db(&[(1, "a")])?;
+
+    let path = std::env::temp_dir().join(format!("tail-{}.db", std::process::id()));
+    let wal_path = path.with_extension("db-wal");
+    std::fs::write(&path, &first)?;
+    std::fs::write(&wal_path, build_wal(512, &[&second]))?;
+    let mut tail = WalTail::open(&path)?;
+    let mut polls = vec![tail.poll(), tail.poll()];
+    // a transaction that isn't finished yet, then the rest of it
+    let wal = build_wal(512, &[&second, &third]);
+    std::fs::write(&wal_path, &wal[..wal.len() - 100])?;
+    polls.push(tail.poll());
+    std::fs::write(&wal_path, &wal)?;
+    polls.push(tail.poll());
+    // checkpointed, then the WAL deleted
+    std::fs::write(&path, &first)?;
+    std::fs::remove_file(&wal_path)?;
+    polls.push(tail.poll());
+    let _ = std::fs::remove_file(&path);
+
+    let polls: Vec<Vec<String>> = polls
+        .into_iter()
+        .map(|changes| {
+            let changes = changes?;
+            Ok(changes
+                .iter()
+                .map(|c| super::json::Json::from(c).to_string())
+                .collect())
+        })
+        .collect::<Result<_>>()?;
+    assert_eq!(
+        polls,
+        [
+            vec![
+                r#"{"table":"t","op":"update","rowid":2,"old":{"id":2,"name":"b"},"new":{"id":2,"name":"B"}}"#,
+                r#"{"table":"t","op":"delete","rowid":3,"old":{"id":3,"name":"c"}}"#,
+                r#"{"table":"t","op":"insert","rowid":4,"new":{"id":4,"name":"d"}}"#,
+            ],
+            vec![],
+            vec![],
+            vec![
+                r#"{"table":"t","op":"delete","rowid":2,"old":{"id":2,"name":"B"}}"#,
+                r#"{"table":"t","op":"delete","rowid":4,"old":{"id":4,"name":"d"}}"#,
+            ],
+            vec![
+                r#"{"table":"t","op":"insert","rowid":2,"new":{"id":2,"name":"b"}}"#,
+                r#"{"table":"t","op":"insert","rowid":3,"new":{"id":3,"name":"c"}}"#,
+            ],
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn open_with_wal() -> Result<()> {
+    use super::fixtures::Fixture;
+    use super::{OpenError, OpenOptions};
+    let image = Fixture::new()
+        .page_size(512)
+        .table(
+            "CREATE TABLE t (id INTEGER PRIMARY KEY)",
+            vec![vec![1.into()]],
+        )
+        .build()?;
+    let path = std::env::temp_dir().join(format!("open-wal-{}.db", std::process::id()));
+    let (wal_path, shm_path) = (path.with_extension("db-wal"), path.with_extension("db-shm"));
+    std::fs::write(&path, &image)?;
+    let mut opened = vec![];
+    // just switched to WAL mode, or checkpointed and truncated
+    std::fs::write(&wal_path, build_wal(512, &[]))?;
+    opened.push(OpenOptions::new().open(&path).is_ok());
+    std::fs::write(&wal_path, build_wal(512, &[&image]))?;
+    let pending = OpenOptions::new().open(&path);
+    // the index says the frames have all been copied back
+    let frames = (image.len() / 512) as u32;
+    let mut shm = vec![0; 136];
+    shm[96..100].copy_from_slice(&frames.to_ne_bytes());
+    std::fs::write(&shm_path, &shm)?;
+    opened.push(OpenOptions::new().open(&path).is_ok());
+    shm[96..100].copy_from_slice(&(frames - 1).to_ne_bytes());
+    std::fs::write(&shm_path, &shm)?;
+    opened.push(OpenOptions::new().open(&path).is_ok());
+    for p in [&path, &wal_path, &shm_path] {
+        let _ = std::fs::remove_file(p);
+    }
+    assert_eq!(opened, [true, true, false]);
+    let e = pending
+        .err()
+        .expect("opened a file with changes in its WAL");
+    assert!(matches!(e.downcast_ref(), Some(OpenError::Wal)));
+    Ok(())
+}